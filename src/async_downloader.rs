@@ -0,0 +1,276 @@
+//! Module with the asynchronous variant of the `Downloader` API,
+//! for programs that drive many downloads concurrently from their own runtime.
+//!
+//! The futures are those of the `futures` 0.1 crate, to be run by a Tokio 0.1 runtime
+//! (like the one that `tokio::run` sets up). The `Downloader` is the blocking facade of the same.
+//! Dropping a future aborts what it's doing, with nothing left behind, as the archives are only held in memory.
+
+use std::sync::{Arc, Mutex};
+
+use futures::{Future, Stream};
+use futures::future::{self, Either};
+use reqwest::async::{Client, Response};
+use sha2::{Digest, Sha256};
+
+use cancel::CancellationToken;
+use downloader::{DownloadError, ResolvedCrate, check_checksum, dl_template_in_config, resolve_in_index,
+                 warn_if_yanked};
+use index::{CRATES_INDEX_ROOT, download_url, index_path};
+use observer::{Observer, Phase, ProgressObserver};
+use resolve::VersionFilter;
+use spec::Crate;
+
+
+/// Asynchronous downloader of crates from a registry with a sparse index (crates.io by default).
+///
+/// It's cheap to clone, with the clones sharing their connections.
+#[derive(Debug, Clone)]
+pub struct AsyncDownloader {
+    client: Client,
+    /// Root URL of the registry's sparse index, without the `sparse+` prefix.
+    index_root: String,
+    /// Which kinds of versions the crates can be resolved to.
+    filter: VersionFilter,
+    /// Template of the download URLs from the index's `config.json`, once it's been fetched.
+    dl_template: Arc<Mutex<Option<String>>>,
+    /// Observer of the progress, if any.
+    observer: Observer,
+    /// Token that cancels the downloads.
+    cancellation: CancellationToken,
+}
+
+impl AsyncDownloader {
+    /// Create a downloader for the registry whose sparse index is at given root URL
+    /// (e.g. "https://index.crates.io", optionally with the `sparse+` prefix).
+    pub fn new<S: AsRef<str>>(index_root: S) -> AsyncDownloader {
+        let index_root = index_root.as_ref();
+        let index_root = index_root.strip_prefix("sparse+").unwrap_or(index_root);
+        AsyncDownloader{
+            client: Client::new(),
+            index_root: index_root.trim_end_matches('/').to_owned(),
+            filter: VersionFilter::default(),
+            dl_template: Arc::new(Mutex::new(None)),
+            observer: Observer::default(),
+            cancellation: CancellationToken::default(),
+        }
+    }
+
+    /// Create a downloader for crates.io.
+    #[inline]
+    pub fn crates_io() -> AsyncDownloader {
+        AsyncDownloader::new(CRATES_INDEX_ROOT)
+    }
+
+    /// Set which kinds of versions the crates can be resolved to, like `Downloader::set_filter`.
+    #[inline]
+    pub fn set_filter(&mut self, filter: VersionFilter) {
+        self.filter = filter;
+    }
+
+    /// Set the observer to be notified of the progress, like `Downloader::set_observer`.
+    #[inline]
+    pub fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.observer = Observer::new(observer);
+    }
+
+    /// Set the token that cancels the downloads, like `Downloader::set_cancellation_token`.
+    #[inline]
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    /// Resolve given crate to its newest version that matches the requirement and passes the filter.
+    pub fn resolve(&self, crate_: &Crate) -> impl Future<Item = ResolvedCrate, Error = DownloadError> {
+        self.observer.phase(&crate_.to_string(), Phase::Resolving);
+        let index_url = format!("{}/{}", self.index_root, index_path(crate_.name()));
+        let (crate_, filter, observer) = (crate_.clone(), self.filter, self.observer.clone());
+        get_text(&self.client, index_url.clone()).and_then(move |index_file| {
+            let resolved = resolve_in_index(&crate_, filter, index_url, &index_file)?;
+            warn_if_yanked(&observer, &resolved);
+            Ok(resolved)
+        })
+    }
+
+    /// Download the archive of given crate (a gzipped tarball),
+    /// which is only returned once it's been verified against its checksum from the registry index.
+    pub fn download(&self, crate_: &ResolvedCrate) -> impl Future<Item = Vec<u8>, Error = DownloadError> {
+        self.observer.phase(&crate_.to_string(), Phase::Downloading);
+        let client = self.client.clone();
+        let (crate_, observer, cancellation) = (crate_.clone(), self.observer.clone(), self.cancellation.clone());
+        self.dl_template().and_then(move |template| {
+            if cancellation.is_cancelled() {
+                return Either::A(future::err(DownloadError::Cancelled));
+            }
+            let url = download_url(&template, &crate_.name, &crate_.version, &crate_.checksum);
+            Either::B(get(&client, url)
+                .and_then(move |response| {
+                    let (id, total) = (crate_.to_string(), response.content_length());
+                    let chunk_observer = observer.clone();
+                    response.into_body()
+                        .from_err()
+                        .fold((Sha256::new(), vec![]), move |(mut hasher, mut body), chunk| {
+                            if cancellation.is_cancelled() {
+                                return Err(DownloadError::Cancelled);
+                            }
+                            hasher.update(&chunk);
+                            body.extend_from_slice(&chunk);
+                            chunk_observer.bytes_transferred(&id, body.len() as u64, total);
+                            Ok((hasher, body))
+                        })
+                        .and_then(move |(hasher, body)| {
+                            check_checksum(&crate_, hasher)?;
+                            observer.phase(&crate_.to_string(), Phase::Finished);
+                            Ok(body)
+                        })
+                }))
+        })
+    }
+
+    /// Template of the download URLs, fetched from the index's `config.json` the first time it's needed.
+    fn dl_template(&self) -> impl Future<Item = String, Error = DownloadError> {
+        if let Some(ref template) = *self.dl_template.lock().unwrap() {
+            return Either::A(future::ok(template.clone()));
+        }
+        let config_url = format!("{}/config.json", self.index_root);
+        let dl_template = self.dl_template.clone();
+        Either::B(get_text(&self.client, config_url.clone()).and_then(move |config| {
+            let template = dl_template_in_config(config_url, &config)?;
+            *dl_template.lock().unwrap() = Some(template.clone());
+            Ok(template)
+        }))
+    }
+}
+
+impl Default for AsyncDownloader {
+    fn default() -> Self {
+        AsyncDownloader::crates_io()
+    }
+}
+
+
+/// Make a GET request to given URL, failing unless it succeeds.
+fn get(client: &Client, url: String) -> impl Future<Item = Response, Error = DownloadError> {
+    client.get(&url).send().from_err().and_then(move |response| {
+        if !response.status().is_success() {
+            return Err(DownloadError::Status(url, response.status().as_u16()));
+        }
+        Ok(response)
+    })
+}
+
+/// Make a GET request to given URL, failing unless it succeeds, and read the response as text.
+fn get_text(client: &Client, url: String) -> impl Future<Item = String, Error = DownloadError> {
+    get(client, url).and_then(|mut response| response.text().from_err())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::str::FromStr;
+    use std::thread;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use sha2::{Digest, Sha256};
+    use tar;
+    use tokio::runtime::Runtime;
+    use downloader::DownloadError;
+    use index::index_path;
+    use spec::Crate;
+    use super::AsyncDownloader;
+
+    /// Serve the files that the function gives for the root URL over HTTP on a local port,
+    /// returning the root URL.
+    fn serve<F: FnOnce(&str) -> HashMap<String, Vec<u8>>>(files: F) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let root = format!("http://{}", listener.local_addr().unwrap());
+        let files = files(&root);
+        thread::spawn(move || for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            let request = String::from_utf8_lossy(&request).into_owned();
+            let (status, body) = match request.split(' ').nth(1).and_then(|path| files.get(path)) {
+                Some(body) => ("200 OK", &body[..]),
+                None => ("404 Not Found", &b""[..]),
+            };
+            let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                               status, body.len());
+            stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body)).unwrap();
+        });
+        root
+    }
+
+    /// Serve a sparse registry index with crate "hello", whose archive only matches the checksum of 1.0.0
+    /// (and not of 1.1.0). Returns the root URL of the index and the archive.
+    fn serve_registry() -> (String, Vec<u8>) {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::Default));
+        let mut header = tar::Header::new_gnu();
+        header.set_path("hello-1.0.0/hello.txt").unwrap();
+        header.set_size(6);
+        header.set_cksum();
+        builder.append(&header, &b"hello\n"[..]).unwrap();
+        let crate_bytes = builder.into_inner().unwrap().finish().unwrap();
+        let checksum: String = Sha256::digest(&crate_bytes).iter().map(|b| format!("{:02x}", b)).collect();
+
+        let index_file = format!(concat!(
+            r#"{{"name":"hello","vers":"1.0.0","deps":[],"cksum":"{}","yanked":false}}"#, "\n",
+            r#"{{"name":"hello","vers":"1.1.0","deps":[],"cksum":"{}","yanked":false}}"#, "\n"),
+            checksum, "0".repeat(64));
+        let archive = crate_bytes.clone();
+        let root = serve(move |root| {
+            let mut files = HashMap::new();
+            files.insert("/config.json".to_owned(), format!(r#"{{"dl":"{}/dl"}}"#, root).into_bytes());
+            files.insert(format!("/{}", index_path("hello")), index_file.into_bytes());
+            files.insert("/dl/hello/1.0.0/download".to_owned(), archive.clone());
+            files.insert("/dl/hello/1.1.0/download".to_owned(), archive);
+            files
+        });
+        (root, crate_bytes)
+    }
+
+    #[test]
+    fn resolve_and_download() {
+        let (root, crate_bytes) = serve_registry();
+        let downloader = AsyncDownloader::new(format!("sparse+{}/", root));
+        let mut runtime = Runtime::new().unwrap();
+
+        let crate_ = runtime.block_on(downloader.resolve(&Crate::from_str("hello==1.0.0").unwrap())).unwrap();
+        assert_eq!(crate_.to_string(), "hello==1.0.0");
+        assert!(!crate_.yanked);
+        assert_eq!(runtime.block_on(downloader.download(&crate_)).unwrap(), crate_bytes);
+        // The download URLs are only fetched from the index the first time.
+        assert!(downloader.dl_template.lock().unwrap().as_ref().unwrap().ends_with("/dl"));
+        assert_eq!(runtime.block_on(downloader.download(&crate_)).unwrap(), crate_bytes);
+
+        match runtime.block_on(downloader.resolve(&Crate::from_str("missing").unwrap())) {
+            Err(DownloadError::Status(ref url, 404)) => assert!(url.ends_with("/mi/ss/missing")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let (root, crate_bytes) = serve_registry();
+        let downloader = AsyncDownloader::new(root);
+        let mut runtime = Runtime::new().unwrap();
+
+        let crate_ = runtime.block_on(downloader.resolve(&Crate::from_str("hello=1").unwrap())).unwrap();
+        assert_eq!(crate_.to_string(), "hello==1.1.0");
+        match runtime.block_on(downloader.download(&crate_)) {
+            Err(DownloadError::ChecksumMismatch(ref c, ref expected, ref actual)) => {
+                assert_eq!(c, "hello==1.1.0");
+                assert_eq!(expected, &"0".repeat(64));
+                assert_eq!(actual, &Sha256::digest(&crate_bytes).iter()
+                    .map(|b| format!("{:02x}", b)).collect::<String>());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Module for reading the dependency info that `cargo auditable` embeds in compiled binaries.
+//!
+//! The info is a zlib-compressed JSON document in a dedicated section of the binary
+//! (`.dep-v0` in ELF and PE files, `__dep_v0` in Mach-O ones), listing every package
+//! that the binary has been built from, along with its exact version and source.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+use semver::Version;
+use serde_json;
+
+use spec::{Crate, CrateVersion};
+
+
+/// Names of the section that holds the dependency info.
+const SECTION_NAMES: &[&str] = &[".dep-v0", "__dep_v0"];
+
+/// Sources of the packages that come from a registry
+/// (as opposed to git repositories or local paths).
+const REGISTRY_SOURCES: &[&str] = &["crates.io", "registry"];
+
+/// Limit on the size of the decompressed dependency info, as a guard against zip bombs.
+const MAX_INFO_SIZE: u64 = 8 * 1024 * 1024;
+
+
+/// Dependency info embedded in a compiled binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditInfo {
+    /// Exact versions of the packages that come from a registry.
+    crates: Vec<Crate>,
+}
+
+impl AuditInfo {
+    /// Load the dependency info from the binary at given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<AuditInfo, AuditInfoError> {
+        let path = path.as_ref();
+        let binary = fs::read(path).map_err(|e| AuditInfoError::Io(path.to_owned(), e))?;
+        let section = SECTION_NAMES.iter()
+            .filter_map(|name| find_section(&binary, name))
+            .next()
+            .ok_or_else(|| AuditInfoError::Missing(path.to_owned()))?;
+        AuditInfo::from_section(section).map_err(|e| AuditInfoError::Parse(path.to_owned(), e))
+    }
+
+    /// Read the dependency info from the (compressed) content of its section.
+    pub fn from_section(section: &[u8]) -> Result<AuditInfo, io::Error> {
+        let mut json = vec![];
+        ZlibDecoder::new(section).take(MAX_INFO_SIZE).read_to_end(&mut json)?;
+        let raw: RawAuditInfo = serde_json::from_slice(&json)?;
+
+        let mut crates = vec![];
+        for package in raw.packages {
+            let from_registry = package.source.as_ref()
+                .is_some_and(|s| REGISTRY_SOURCES.contains(&s.as_str()));
+            if !from_registry {
+                continue;
+            }
+            let version = Version::parse(&package.version).map_err(|e| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid version `{}` of package `{}`: {}", package.version, package.name, e)))?;
+            let crate_ = Crate::new(package.name, CrateVersion::Exact(version))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            crates.push(crate_);
+        }
+        Ok(AuditInfo{crates})
+    }
+
+    /// The registry packages that the binary has been built from,
+    /// as crates with exact versions.
+    #[inline]
+    pub fn registry_crates(&self) -> &[Crate] {
+        &self.crates
+    }
+
+    #[inline]
+    pub fn into_registry_crates(self) -> Vec<Crate> {
+        self.crates
+    }
+}
+
+/// Subset of the dependency info format that we're interested in.
+#[derive(Deserialize)]
+struct RawAuditInfo {
+    packages: Vec<RawPackage>,
+}
+
+#[derive(Deserialize)]
+struct RawPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+
+/// Find the content of the section with given name in an ELF, PE, or (64-bit) Mach-O binary.
+fn find_section<'b>(binary: &'b [u8], name: &str) -> Option<&'b [u8]> {
+    match binary.get(..4)? {
+        b"\x7fELF" => find_elf_section(binary, name),
+        [b'M', b'Z', ..] => find_pe_section(binary, name),
+        b"\xcf\xfa\xed\xfe" => find_macho_section(binary, name),
+        _ => None,
+    }
+}
+
+fn find_elf_section<'b>(binary: &'b [u8], name: &str) -> Option<&'b [u8]> {
+    let is_64 = match *binary.get(4)? { 1 => false, 2 => true, _ => return None };
+    let be = match *binary.get(5)? { 1 => false, 2 => true, _ => return None };
+    let read = |offset: usize, size: usize| read_uint(binary, offset, size, be);
+    let word = if is_64 { 8 } else { 4 };
+
+    let (sh_off, sh_entsize, sh_num, sh_strndx) = if is_64 {
+        (read(0x28, 8)?, read(0x3a, 2)?, read(0x3c, 2)?, read(0x3e, 2)?)
+    } else {
+        (read(0x20, 4)?, read(0x2e, 2)?, read(0x30, 2)?, read(0x32, 2)?)
+    };
+    // Returns the name offset and the data of the section with given index.
+    let section = |index: u64| -> Option<(u64, &'b [u8])> {
+        let header = sh_off.checked_add(index.checked_mul(sh_entsize)?)? as usize;
+        let name_offset = read(header, 4)?;
+        let (offset, size) = (read(header + 8 + 2 * word, word)?, read(header + 8 + 3 * word, word)?);
+        Some((name_offset, slice(binary, offset, size)?))
+    };
+
+    let (_, names) = section(sh_strndx)?;
+    (0..sh_num).filter_map(&section)
+        .find(|&(name_offset, _)| {
+            names.get(name_offset as usize..).and_then(c_string) == Some(name.as_bytes())
+        })
+        .map(|(_, data)| data)
+}
+
+fn find_pe_section<'b>(binary: &'b [u8], name: &str) -> Option<&'b [u8]> {
+    let read = |offset: usize, size: usize| read_uint(binary, offset, size, false);
+    let coff = read(0x3c, 4)? as usize;
+    if binary.get(coff..coff + 4)? != b"PE\0\0" {
+        return None;
+    }
+    let num_sections = read(coff + 6, 2)? as usize;
+    let sections = coff + 24 + read(coff + 20, 2)? as usize;
+    (0..num_sections).map(|i| sections + 40 * i).find_map(|header| {
+        // Names are padded with NULs to 8 bytes.
+        let section_name = binary.get(header..header + 8)?;
+        if c_string(section_name).unwrap_or(section_name) != name.as_bytes() {
+            return None;
+        }
+        // The raw data is padded to the file alignment, unlike the virtual size.
+        let size = read(header + 8, 4)?.min(read(header + 16, 4)?);
+        slice(binary, read(header + 20, 4)?, size)
+    })
+}
+
+fn find_macho_section<'b>(binary: &'b [u8], name: &str) -> Option<&'b [u8]> {
+    const LC_SEGMENT_64: u64 = 0x19;
+    let read = |offset: usize, size: usize| read_uint(binary, offset, size, false);
+    let num_commands = read(16, 4)?;
+    let mut command = 32;
+    for _ in 0..num_commands {
+        let (cmd, cmd_size) = (read(command, 4)?, read(command + 4, 4)? as usize);
+        if cmd == LC_SEGMENT_64 {
+            let num_sections = read(command + 64, 4)? as usize;
+            for header in (0..num_sections).map(|i| command + 72 + 80 * i) {
+                let section_name = binary.get(header..header + 16)?;
+                if c_string(section_name).unwrap_or(section_name) == name.as_bytes() {
+                    return slice(binary, read(header + 48, 4)?, read(header + 40, 8)?);
+                }
+            }
+        }
+        if cmd_size == 0 {
+            return None;
+        }
+        command += cmd_size;
+    }
+    None
+}
+
+/// Read an unsigned integer of given size (in bytes) at given offset.
+fn read_uint(bytes: &[u8], offset: usize, size: usize, big_endian: bool) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(size)?)?;
+    let fold = |acc: u64, b: &u8| (acc << 8) | u64::from(*b);
+    Some(if big_endian { bytes.iter().fold(0, fold) } else { bytes.iter().rev().fold(0, fold) })
+}
+
+/// Take the slice of given size at given offset, if it's within bounds.
+fn slice(bytes: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+    let start = offset as usize;
+    bytes.get(start..start.checked_add(size as usize)?)
+}
+
+/// Take the NUL-terminated string at the start of given bytes.
+fn c_string(bytes: &[u8]) -> Option<&[u8]> {
+    bytes.iter().position(|&b| b == 0).map(|end| &bytes[..end])
+}
+
+
+/// Error that can occur while loading the dependency info of a binary.
+#[derive(Debug)]
+pub enum AuditInfoError {
+    /// Error reading the binary.
+    Io(PathBuf, io::Error),
+    /// The binary has no embedded dependency info
+    /// (or its format isn't supported).
+    Missing(PathBuf),
+    /// Malformed dependency info.
+    Parse(PathBuf, io::Error),
+}
+impl Error for AuditInfoError {
+    fn description(&self) -> &str { "invalid dependency info" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            AuditInfoError::Io(_, ref e) => Some(e),
+            AuditInfoError::Missing(_) => None,
+            AuditInfoError::Parse(_, ref e) => Some(e),
+        }
+    }
+}
+impl fmt::Display for AuditInfoError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuditInfoError::Io(ref p, ref e) =>
+                write!(fmt, "failed to read binary {}: {}", p.display(), e),
+            AuditInfoError::Missing(ref p) => write!(fmt, concat!(
+                "no dependency info found in binary {} ",
+                "(it has to be an ELF, PE, or Mach-O file built with `cargo auditable`)"), p.display()),
+            AuditInfoError::Parse(ref p, ref e) =>
+                write!(fmt, "malformed dependency info in binary {}: {}", p.display(), e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use super::{AuditInfo, find_section};
+
+    /// Build a minimal 64-bit little-endian ELF file with a single named section.
+    fn elf_with_section(name: &str, data: &[u8]) -> Vec<u8> {
+        let names = format!("\0{}\0.shstrtab\0", name).into_bytes();
+        let data_offset = 64;
+        let names_offset = data_offset + data.len();
+        let sh_off = names_offset + names.len();
+
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x28..0x30].copy_from_slice(&(sh_off as u64).to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        elf[0x3e..0x40].copy_from_slice(&2u16.to_le_bytes());
+        elf.extend_from_slice(data);
+        elf.extend_from_slice(&names);
+        for &(name_offset, offset, size) in &[(0, 0, 0),
+                                              (1, data_offset, data.len()),
+                                              (name.len() + 2, names_offset, names.len())] {
+            let mut header = vec![0u8; 64];
+            header[..4].copy_from_slice(&(name_offset as u32).to_le_bytes());
+            header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            elf.extend_from_slice(&header);
+        }
+        elf
+    }
+
+    #[test]
+    fn dependency_info() {
+        let json = concat!(
+            r#"{"packages":["#,
+            r#"{"name":"app","version":"0.1.0","source":"local","root":true,"dependencies":[1,2]},"#,
+            r#"{"name":"foo","version":"1.2.3","source":"crates.io"},"#,
+            r#"{"name":"bar","version":"0.4.0","source":"git"},"#,
+            r#"{"name":"cc","version":"1.0.83","source":"crates.io","kind":"build"}"#,
+            r#"]}"#);
+        let mut encoder = ZlibEncoder::new(vec![], Compression::Default);
+        encoder.write_all(json.as_bytes()).unwrap();
+        let section = encoder.finish().unwrap();
+
+        let elf = elf_with_section(".dep-v0", &section);
+        assert_eq!(find_section(&elf, ".dep-v0"), Some(&section[..]));
+        assert_eq!(find_section(&elf, ".text"), None);
+        assert_eq!(find_section(b"#!/bin/sh\n", ".dep-v0"), None);
+
+        let info = AuditInfo::from_section(&section).unwrap();
+        let crates: Vec<_> = info.registry_crates().iter().map(|c| c.to_string()).collect();
+        assert_eq!(crates, vec!["foo==1.2.3", "cc==1.0.83"]);
+        assert!(AuditInfo::from_section(b"garbage").is_err());
+    }
+}
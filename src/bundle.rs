@@ -0,0 +1,265 @@
+//! Module for bundling crates to carry them into an air-gapped environment (`--bundle`),
+//! and for importing such bundles there (`--import`).
+//!
+//! A bundle is a tar archive laid out like a Cargo local registry: the `.crate` archives
+//! at its top, and the index files of the crates (with the entries of the bundled versions only)
+//! under `index/`, so that it's usable with `local-registry` just by unpacking it.
+//! Its `bundle.json` manifest lists the bundled crate versions along with their checksums.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+use tar;
+
+use cache::Cache;
+use cargo_download::index_path;
+use checksum;
+
+
+/// Name of the manifest of a bundle.
+pub const MANIFEST_FILE: &str = "bundle.json";
+/// Directory of the index files, in a bundle and in the local registry imported from it.
+const INDEX_DIR: &str = "index";
+
+
+/// Crate version that's been bundled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BundledCrate {
+    pub name: String,
+    pub version: Version,
+    /// SHA-256 checksum of the crate's archive.
+    pub checksum: String,
+}
+
+impl BundledCrate {
+    /// Name of the crate's archive, both in a bundle and in a local registry.
+    fn file_name(&self) -> String {
+        format!("{}-{}.crate", self.name, self.version)
+    }
+}
+
+
+/// Writer of a bundle, which the crates are added to one by one.
+pub struct BundleWriter {
+    builder: tar::Builder<File>,
+    crates: Vec<BundledCrate>,
+    /// Index entries of the bundled crate versions, by crate name.
+    index: BTreeMap<String, Vec<String>>,
+}
+
+impl BundleWriter {
+    /// Create the bundle file at given path.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<BundleWriter> {
+        let file = File::create(path)?;
+        Ok(BundleWriter{builder: tar::Builder::new(file), crates: vec![], index: BTreeMap::new()})
+    }
+
+    /// Add given crate version to the bundle,
+    /// along with its entry from given content of the crate's index file.
+    pub fn add(&mut self, name: &str, version: &Version, crate_bytes: &[u8], index_file: &str) -> io::Result<()> {
+        let vers = version.to_string();
+        let entry = index_file.lines()
+            .find(|line| entry_version(line).as_deref() == Some(vers.as_str()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                format!("version {} of crate `{}` not found in its index file", version, name)))?;
+
+        let bundled = BundledCrate{
+            name: name.to_owned(),
+            version: version.clone(),
+            checksum: checksum::sha256_reader(crate_bytes)?,
+        };
+        append(&mut self.builder, &bundled.file_name(), crate_bytes)?;
+        self.crates.push(bundled);
+        self.index.entry(name.to_owned()).or_default().push(entry.to_owned());
+        Ok(())
+    }
+
+    /// Write the index files and the manifest to complete the bundle,
+    /// returning the crate versions it contains.
+    pub fn finish(mut self) -> io::Result<Vec<BundledCrate>> {
+        for (name, entries) in &self.index {
+            let content = entries.iter().map(|e| format!("{}\n", e)).collect::<String>();
+            append(&mut self.builder, &format!("{}/{}", INDEX_DIR, index_path(name)), content.as_bytes())?;
+        }
+        let manifest = json!({
+            "crates": self.crates.iter().map(|c| json!({
+                "name": c.name,
+                "version": c.version.to_string(),
+                "checksum": c.checksum,
+            })).collect::<Vec<_>>(),
+        });
+        append(&mut self.builder, MANIFEST_FILE, manifest.to_string().as_bytes())?;
+        self.builder.into_inner()?.sync_all()?;
+        Ok(self.crates)
+    }
+}
+
+/// Append a file with given path and content to the tar archive.
+fn append(builder: &mut tar::Builder<File>, path: &str, content: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(path)?;
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, content)
+}
+
+/// Version of the crate that given line of its index file is the entry of.
+fn entry_version(line: &str) -> Option<String> {
+    let entry: Json = serde_json::from_str(line).ok()?;
+    entry.get("vers")?.as_str().map(|v| v.to_owned())
+}
+
+
+/// Import the crates from the bundle at given path into the download cache,
+/// and/or into a local registry in given directory (which can already contain other crates),
+/// returning the crate versions that have been imported.
+///
+/// Every archive is verified against the checksum that the manifest records for it.
+pub fn import<P: AsRef<Path>>(path: P, cache: Option<&Cache>, dir: Option<&Path>) -> io::Result<Vec<BundledCrate>> {
+    let path = path.as_ref();
+
+    // The manifest is at the end of the bundle, so it takes a pass over the bundle to find it.
+    let mut manifest = None;
+    for entry in tar::Archive::new(File::open(path)?).entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_str() == Some(MANIFEST_FILE) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            manifest = Some(parse_manifest(&content)?);
+        }
+    }
+    let crates = manifest.ok_or_else(|| invalid_data(format!("no {} in the bundle", MANIFEST_FILE)))?;
+    let mut by_file_name: HashMap<_, _> = crates.iter().map(|c| (c.file_name(), c)).collect();
+    let by_index_path: HashMap<_, _> = crates.iter()
+        .map(|c| (format!("{}/{}", INDEX_DIR, index_path(&c.name)), c.name.as_str()))
+        .collect();
+
+    for entry in tar::Archive::new(File::open(path)?).entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_string_lossy().into_owned();
+        if let Some(bundled) = by_file_name.remove(&entry_path) {
+            let mut crate_bytes = vec![];
+            entry.read_to_end(&mut crate_bytes)?;
+            let digest = checksum::sha256_reader(&crate_bytes[..])?;
+            if !digest.eq_ignore_ascii_case(&bundled.checksum) {
+                return Err(invalid_data(format!(
+                    "archive of crate `{}=={}` is corrupted: its SHA-256 checksum is {}, but the manifest records {}",
+                    bundled.name, bundled.version, digest, bundled.checksum)));
+            }
+            if let Some(cache) = cache {
+                cache.put(&bundled.name, &bundled.version, &crate_bytes)?;
+            }
+            if let Some(dir) = dir {
+                fs::create_dir_all(dir)?;
+                fs::write(dir.join(&entry_path), &crate_bytes)?;
+            }
+        } else if let (Some(&name), Some(dir)) = (by_index_path.get(&entry_path), dir) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            merge_index_file(&dir.join(INDEX_DIR).join(index_path(name)), &content)?;
+        }
+    }
+    if let Some(missing) = by_file_name.keys().next() {
+        return Err(invalid_data(format!("{} is listed in the manifest, but missing from the bundle", missing)));
+    }
+    Ok(crates)
+}
+
+/// Parse the manifest of a bundle into the crate versions it lists.
+fn parse_manifest(content: &str) -> io::Result<Vec<BundledCrate>> {
+    let malformed = || invalid_data(format!("malformed {}", MANIFEST_FILE));
+    let manifest: Json = serde_json::from_str(content).map_err(|_| malformed())?;
+    let entries = manifest.get("crates").and_then(|c| c.as_array()).ok_or_else(malformed)?;
+    entries.iter().map(|entry| {
+        let field = |key| entry.get(key).and_then(|v| v.as_str()).ok_or_else(malformed);
+        let name = field("name")?;
+        // The names end up in paths, so they must not be able to point outside of the registry.
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(invalid_data(format!("invalid crate name `{}` in {}", name, MANIFEST_FILE)));
+        }
+        Ok(BundledCrate{
+            name: name.to_owned(),
+            version: Version::parse(field("version")?).map_err(|_| malformed())?,
+            checksum: field("checksum")?.to_owned(),
+        })
+    }).collect()
+}
+
+/// Add the entries from given content of an index file to the file at given path,
+/// skipping those that it has already.
+fn merge_index_file(path: &Path, content: &str) -> io::Result<()> {
+    let mut merged = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let existing: Vec<_> = merged.lines().filter_map(entry_version).collect();
+    for line in content.lines() {
+        if entry_version(line).is_some_and(|v| !existing.contains(&v)) {
+            if !merged.is_empty() && !merged.ends_with('\n') {
+                merged.push('\n');
+            }
+            merged.push_str(line);
+            merged.push('\n');
+        }
+    }
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, merged)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use semver::Version;
+
+    use cache::Cache;
+    use super::{BundleWriter, import};
+
+    #[test]
+    fn round_trip() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-bundle-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (v1, v2) = (Version::parse("1.0.0").unwrap(), Version::parse("1.1.0").unwrap());
+        let index_file = concat!(
+            r#"{"name":"foo","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":false}"#, "\n",
+            r#"{"name":"foo","vers":"1.1.0","deps":[],"cksum":"","features":{},"yanked":false}"#, "\n",
+            r#"{"name":"foo","vers":"2.0.0","deps":[],"cksum":"","features":{},"yanked":false}"#, "\n");
+
+        let bundle_path = dir.join("bundle.tar");
+        let mut bundle = BundleWriter::create(&bundle_path).unwrap();
+        bundle.add("foo", &v1, b"one", index_file).unwrap();
+        bundle.add("foo", &v2, b"two", index_file).unwrap();
+        assert!(bundle.add("foo", &Version::parse("3.0.0").unwrap(), b"three", index_file).is_err());
+        assert_eq!(bundle.finish().unwrap().len(), 2);
+
+        let cache = Cache::open(dir.join("cache"));
+        let registry = dir.join("registry");
+        let crates = import(&bundle_path, Some(&cache), Some(&registry)).unwrap();
+        assert_eq!(crates.len(), 2);
+        assert_eq!(cache.get("foo", &v2).unwrap(), Some(b"two".to_vec()));
+        assert_eq!(fs::read(registry.join("foo-1.0.0.crate")).unwrap(), b"one");
+        let imported_index = fs::read_to_string(registry.join("index/3/f/foo")).unwrap();
+        assert_eq!(imported_index.lines().count(), 2);
+        assert!(!imported_index.contains("2.0.0"));
+
+        // Importing again doesn't duplicate the index entries.
+        import(&bundle_path, None, Some(&registry)).unwrap();
+        assert_eq!(fs::read_to_string(registry.join("index/3/f/foo")).unwrap(), imported_index);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
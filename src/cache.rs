@@ -0,0 +1,645 @@
+//! Module implementing the local cache of downloaded crate archives.
+//!
+//! Every crate archive that's downloaded is also stored in the cache,
+//! so that subsequent requests for the same crate version can be served
+//! without talking to crates.io.
+//!
+//! Crates of different registries (or other sources) are kept apart, since crates of the same name
+//! and version may well differ between them: the archives are stored as
+//! `<registry>/<name>/<name>-<version>.crate`, where the registry's directory is named after
+//! a hash of its source ID.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use filetime::{self, FileTime};
+use semver::Version;
+use serde_json::{self, Value as Json};
+
+use cargo_download::CRATES_IO_SOURCE;
+use checksum;
+
+
+/// Extension of the archive files stored in the cache.
+const CRATE_EXT: &str = "crate";
+
+/// Extension added to the archive files while they're being downloaded.
+const PART_EXT: &str = "part";
+
+/// Time after which a partially downloaded archive that's not been written to is considered abandoned.
+const STALE_PART_AGE: Duration = Duration::from_secs(24 * 3600);
+
+/// Name of the file inside the cache directory that holds usage counters.
+const STATS_FILE: &str = "stats.json";
+
+/// Environment variable with the directory of the program's own state (the cache and the profiles),
+/// which is `$CARGO_HOME/cargo-download` by default.
+pub const STATE_DIR_VAR: &str = "CARGO_DOWNLOAD_HOME";
+
+/// Environment variable with the directory of the cache, like the `--cache-dir` option.
+pub const CACHE_DIR_VAR: &str = "CARGO_DOWNLOAD_CACHE_DIR";
+
+/// Whether the cache is only read from during the current run, because it's not writable.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Lock serializing the updates of the usage counters within the process.
+    static ref STATS_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Directory of the cache given for the current run, instead of the default one.
+    ///
+    /// It's global, because the cache is opened from deep within the call stack.
+    static ref DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+    /// Source ID of the registry that crates are downloaded from during the current run,
+    /// if it's not crates.io.
+    static ref REGISTRY: Mutex<Option<String>> = Mutex::new(None);
+}
+
+
+/// Use the cache in given directory for the rest of the run, instead of the default one.
+pub fn set_dir(dir: PathBuf) {
+    *DIR.lock().unwrap() = Some(dir);
+}
+
+/// Store and look up the crates of the registry with given source ID for the rest of the run,
+/// instead of those of crates.io.
+pub fn set_registry(source_id: &str) {
+    *REGISTRY.lock().unwrap() = Some(source_id.to_owned());
+}
+
+/// Only read from the cache for the rest of the run, never writing anything into it.
+pub fn set_read_only() {
+    READ_ONLY.store(true, Ordering::SeqCst);
+}
+
+
+/// Local cache of crate archives.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    root: PathBuf,
+    /// Name of the directory with the crates of the registry in use.
+    registry_dir: String,
+    read_only: bool,
+}
+
+impl Cache {
+    /// Open the cache at its default location, if one can be determined.
+    ///
+    /// This is `cache` inside of the program's state directory (see `state_dir`),
+    /// unless another directory has been given for the run with `set_dir`.
+    /// It holds the crates of the registry given with `set_registry`, or of crates.io.
+    pub fn open_default() -> Option<Cache> {
+        let read_only = READ_ONLY.load(Ordering::SeqCst);
+        let root = DIR.lock().unwrap().clone().or_else(default_dir)?;
+        let cache = match *REGISTRY.lock().unwrap() {
+            Some(ref source_id) => Cache::open_for(root, source_id),
+            None => Cache::open(root),
+        };
+        Some(Cache{read_only, ..cache})
+    }
+
+    /// Open the cache located in given directory, for the crates of crates.io.
+    /// The directory is created lazily, when something is stored in the cache.
+    pub fn open<P: Into<PathBuf>>(root: P) -> Cache {
+        Cache::open_for(root, CRATES_IO_SOURCE)
+    }
+
+    /// Open the cache located in given directory, for the crates of the registry with given source ID.
+    pub fn open_for<P: Into<PathBuf>>(root: P, source_id: &str) -> Cache {
+        Cache{root: root.into(), registry_dir: registry_dir(source_id), read_only: false}
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether the cache is only read from, with nothing stored in it.
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Check that files can be written into the cache directory, creating it if needed.
+    pub fn check_writable(&self) -> io::Result<()> {
+        let probe = self.root.join(format!(".probe-{}", std::process::id()));
+        fs::create_dir_all(&self.root)
+            .and_then(|_| fs::write(&probe, b""))
+            .and_then(|_| fs::remove_file(&probe))
+    }
+
+    /// Retrieve the archive of given crate version from the cache, if it's there.
+    pub fn get(&self, name: &str, version: &Version) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match self.open_entry(name, version)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+
+    /// Open the archive of given crate version in the cache for reading, if it's there.
+    pub fn open_entry(&self, name: &str, version: &Version) -> io::Result<Option<File>> {
+        let path = self.entry_path(name, version);
+        match File::open(&path) {
+            Ok(file) if self.read_only => Ok(Some(file)),
+            Ok(file) => {
+                // Bump the modification time, so that cache GC
+                // considers the entry as recently used.
+                if let Err(e) = filetime::set_file_mtime(&path, FileTime::now()) {
+                    debug!("Failed to update mtime of {}: {}", path.display(), e);
+                }
+                self.record_lookup(true);
+                Ok(Some(file))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                if !self.read_only {
+                    self.record_lookup(false);
+                }
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store the archive of given crate version in the cache.
+    pub fn put(&self, name: &str, version: &Version, bytes: &[u8]) -> io::Result<()> {
+        let path = self.entry_path(name, version);
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        // Write to a temporary file first, so that readers never see a partial entry.
+        let tmp_path = path.with_extension(format!("{}.tmp", CRATE_EXT));
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(bytes)?;
+        }
+        fs::rename(&tmp_path, &path)
+    }
+
+    /// Remove the archive of given crate version from the cache (if it's there),
+    /// e.g. after it's turned out to be corrupted.
+    pub fn evict(&self, name: &str, version: &Version) -> io::Result<()> {
+        match fs::remove_file(self.entry_path(name, version)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Copy the archive of given crate version from the cache to given path, if it's there
+    /// with given size and SHA-256 checksum (those of the archive as it's been downloaded),
+    /// returning whether it's been copied.
+    ///
+    /// An entry of the same size may still have been corrupted, so its checksum is always compared,
+    /// which means that the whole entry is read for every hit. Only the copy itself is left to the OS,
+    /// which can then clone the file rather than write out its content again (with `copy_file_range`
+    /// on Linux, `clonefile` on macOS, and block cloning on ReFS on Windows).
+    pub fn copy_to(&self, name: &str, version: &Version, size: u64, checksum: &str,
+                   path: &Path) -> io::Result<bool> {
+        let entry_path = self.entry_path(name, version);
+        match fs::metadata(&entry_path) {
+            Ok(ref metadata) if metadata.len() == size => {}
+            Ok(_) => return Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if !checksum::file_matches(&entry_path, checksum)? {
+            debug!("Cached archive of crate `{}=={}` doesn't match checksum {}", name, version, checksum);
+            return Ok(false);
+        }
+        fs::copy(&entry_path, path)?;
+        Ok(true)
+    }
+
+    /// Path of the file that given crate version's archive is downloaded into,
+    /// so that an interrupted download can be resumed.
+    pub fn part_path(&self, name: &str, version: &Version) -> PathBuf {
+        self.entry_path(name, version).with_extension(format!("{}.{}", CRATE_EXT, PART_EXT))
+    }
+
+    /// Store the completely downloaded archive of given crate version (see `part_path`)
+    /// in the cache, by moving it into place.
+    pub fn commit_part(&self, name: &str, version: &Version) -> io::Result<()> {
+        fs::rename(self.part_path(name, version), self.entry_path(name, version))
+    }
+
+    /// List the versions of given crate that are stored in the cache.
+    pub fn versions(&self, name: &str) -> io::Result<Vec<Version>> {
+        let prefix = format!("{}-", name);
+        let files = match fs::read_dir(self.root.join(&self.registry_dir).join(name)) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut versions = vec![];
+        for file in files {
+            let path = file?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(CRATE_EXT) {
+                continue;
+            }
+            let version = path.file_stem().and_then(|s| s.to_str())
+                .and_then(|s| s.strip_prefix(prefix.as_str()))
+                .and_then(|v| Version::parse(v).ok());
+            if let Some(v) = version {
+                versions.push(v);
+            }
+        }
+        Ok(versions)
+    }
+
+    /// List all entries currently stored in the cache, of all the registries.
+    ///
+    /// Entries stored before the crates were kept apart by registry (directly in crate directories
+    /// at the top of the cache) are listed too, so that they can be collected.
+    pub fn entries(&self) -> io::Result<Vec<CacheEntry>> {
+        self.files(CRATE_EXT)
+    }
+
+    /// List the partially downloaded archives (see `part_path`) that haven't been written to for a day,
+    /// i.e. whose downloads have been abandoned rather than interrupted to be resumed.
+    pub fn stale_parts(&self, now: SystemTime) -> io::Result<Vec<CacheEntry>> {
+        let mut parts = self.files(PART_EXT)?;
+        parts.retain(|p| now.duration_since(p.modified).unwrap_or_default() > STALE_PART_AGE);
+        Ok(parts)
+    }
+
+    /// Remove given entry from the cache, along with its crate's and registry's directories
+    /// if they've become empty (but never the cache directory itself).
+    pub fn remove(&self, entry: &CacheEntry) -> io::Result<()> {
+        fs::remove_file(&entry.path)?;
+        for dir in entry.path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) || dir == self.root || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// List the files with given extension in the crate directories of all the registries
+    /// (and in those from before the registries were kept apart).
+    fn files(&self, ext: &str) -> io::Result<Vec<CacheEntry>> {
+        let mut entries = vec![];
+        let dirs = match fs::read_dir(&self.root) {
+            Ok(d) => d,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e),
+        };
+        for dir in dirs {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                if file.file_type()?.is_dir() {
+                    for file in fs::read_dir(file.path())? {
+                        push_entry(file?, ext, &mut entries)?;
+                    }
+                } else {
+                    push_entry(file, ext, &mut entries)?;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Read the usage counters of the cache.
+    pub fn counters(&self) -> CacheCounters {
+        let path = self.root.join(STATS_FILE);
+        let json: Json = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Ignoring malformed cache stats file {}: {}", path.display(), e);
+                Json::Null
+            }),
+            Err(_) => Json::Null,
+        };
+        CacheCounters{
+            hits: json.get("hits").and_then(|h| h.as_u64()).unwrap_or(0),
+            misses: json.get("misses").and_then(|m| m.as_u64()).unwrap_or(0),
+        }
+    }
+
+    /// Record a cache hit or miss in the usage counters.
+    fn record_lookup(&self, hit: bool) {
+        // Crates may be looked up by several threads at once (with --jobs).
+        let _guard = STATS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut counters = self.counters();
+        if hit {
+            counters.hits += 1;
+        } else {
+            counters.misses += 1;
+        }
+        let json = json!({"hits": counters.hits, "misses": counters.misses});
+        let result = fs::create_dir_all(&self.root)
+            .and_then(|_| fs::write(self.root.join(STATS_FILE), json.to_string()));
+        if let Err(e) = result {
+            debug!("Failed to update cache stats in {}/: {}", self.root.display(), e);
+        }
+    }
+
+    /// Path to the file where given crate version is stored.
+    fn entry_path(&self, name: &str, version: &Version) -> PathBuf {
+        self.root.join(&self.registry_dir).join(name).join(format!("{}-{}.{}", name, version, CRATE_EXT))
+    }
+}
+
+/// Name of the directory with the crates of the registry with given source ID,
+/// i.e. the host of the registry (if any) followed by a hash of the ID.
+fn registry_dir(source_id: &str) -> String {
+    let url = source_id.split("://").nth(1).unwrap_or("local");
+    let host = url.split('/').next().unwrap_or(url);
+    let host = if host.is_empty() { "local" } else { host };
+    let hash = checksum::sha256_reader(source_id.as_bytes()).expect("hashing in memory");
+    format!("{}-{}", host, &hash[..16])
+}
+
+/// Add the cache entry of given directory entry, if it's a file with given extension.
+fn push_entry(file: fs::DirEntry, ext: &str, entries: &mut Vec<CacheEntry>) -> io::Result<()> {
+    let path = file.path();
+    if path.extension().and_then(|e| e.to_str()) != Some(ext) {
+        return Ok(());
+    }
+    let metadata = file.metadata()?;
+    entries.push(CacheEntry{
+        path,
+        size: metadata.len(),
+        modified: metadata.modified()?,
+    });
+    Ok(())
+}
+
+
+/// Usage counters of the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheCounters {
+    /// How many times a crate was found in the cache.
+    pub hits: u64,
+    /// How many times a crate had to be downloaded because it wasn't in the cache.
+    pub misses: u64,
+}
+
+impl CacheCounters {
+    /// Fraction of lookups that were served from the cache, if there were any.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        if total > 0 { Some(self.hits as f64 / total as f64) } else { None }
+    }
+}
+
+
+/// Single crate archive stored in the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// Path to the archive file.
+    pub path: PathBuf,
+    /// Size of the archive in bytes.
+    pub size: u64,
+    /// When was the entry last stored or used.
+    pub modified: SystemTime,
+}
+
+impl CacheEntry {
+    /// Name of the entry, i.e. the archive's file name without extension.
+    pub fn name(&self) -> String {
+        self.path.file_stem().map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+
+/// Select the cache entries that should be removed during garbage collection.
+///
+/// First, all entries older than `older_than` are selected.
+/// Then, if the remaining entries exceed `max_size` bytes in total,
+/// the least recently used ones are selected until they fit.
+pub fn select_garbage(entries: Vec<CacheEntry>, now: SystemTime,
+                      older_than: Option<Duration>, max_size: Option<u64>) -> Vec<CacheEntry> {
+    let mut entries = entries;
+    entries.sort_by_key(|e| e.modified);  // oldest first
+
+    let mut garbage = vec![];
+    let mut kept = vec![];
+    for entry in entries {
+        let age = now.duration_since(entry.modified).unwrap_or_default();
+        match older_than {
+            Some(max_age) if age > max_age => garbage.push(entry),
+            _ => kept.push(entry),
+        }
+    }
+
+    if let Some(max_size) = max_size {
+        let mut total: u64 = kept.iter().map(|e| e.size).sum();
+        let mut kept = kept.into_iter();
+        while total > max_size {
+            match kept.next() {
+                Some(entry) => {
+                    total -= entry.size;
+                    garbage.push(entry);
+                }
+                None => break,
+            }
+        }
+    }
+    garbage
+}
+
+
+/// Write the archive of given crate version to given path,
+/// copying it from the cache if it's there (see `Cache::copy_to`), and from memory otherwise.
+pub fn write_archive(name: &str, version: &Version, crate_bytes: &[u8], path: &Path) -> io::Result<()> {
+    if let Some(cache) = Cache::open_default() {
+        let checksum = checksum::sha256_reader(crate_bytes)?;
+        match cache.copy_to(name, version, crate_bytes.len() as u64, &checksum, path) {
+            Ok(true) => {
+                debug!("Archive of crate `{}=={}` copied from the cache to {}", name, version, path.display());
+                return Ok(());
+            }
+            Ok(false) => {}
+            Err(e) => debug!("Failed to copy crate `{}=={}` from the cache to {}, writing it instead: {}",
+                name, version, path.display(), e),
+        }
+    }
+    fs::write(path, crate_bytes)
+}
+
+/// Check whether given crate version is in Cargo's own download cache,
+/// i.e. `$CARGO_HOME/registry/cache/<registry>/`, for any of the registries,
+/// with given SHA-256 checksum if there's one.
+pub fn in_cargo_cache(name: &str, version: &Version, checksum: Option<&str>) -> bool {
+    let cache_dir = match cargo_home() {
+        Some(home) => home.join("registry").join("cache"),
+        None => return false,
+    };
+    let registry_dirs = match fs::read_dir(cache_dir) {
+        Ok(d) => d,
+        Err(_) => return false,
+    };
+    let file_name = format!("{}-{}.{}", name, version, CRATE_EXT);
+    registry_dirs.filter_map(|d| d.ok())
+        .any(|d| checksum::file_present(d.path().join(&file_name), checksum).unwrap_or(false))
+}
+
+/// Determine the default location of the cache.
+fn default_dir() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("cache"))
+}
+
+/// Determine the directory of the program's own state,
+/// honoring the `$CARGO_DOWNLOAD_HOME` variable.
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(STATE_DIR_VAR) {
+        return Some(dir.into());
+    }
+    cargo_home().map(|home| home.join("cargo-download"))
+}
+
+/// Determine Cargo's home directory, honoring the $CARGO_HOME variable.
+pub fn cargo_home() -> Option<PathBuf> {
+    if let Some(home) = env::var_os("CARGO_HOME") {
+        return Some(home.into());
+    }
+    #[allow(deprecated)]  // it's fine for our purposes
+    env::home_dir().map(|home| home.join(".cargo"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+    use filetime::{self, FileTime};
+    use semver::Version;
+    use checksum;
+    use super::{Cache, CacheEntry, select_garbage};
+
+    fn entry(name: &str, size: u64, age_days: u64, now: SystemTime) -> CacheEntry {
+        CacheEntry{
+            path: PathBuf::from(format!("{}.crate", name)),
+            size,
+            modified: now - Duration::from_secs(age_days * 24 * 3600),
+        }
+    }
+
+    #[test]
+    fn gc_by_age() {
+        let now = SystemTime::now();
+        let entries = vec![entry("a", 10, 40, now), entry("b", 10, 5, now)];
+        let garbage = select_garbage(entries, now, Some(Duration::from_secs(30 * 24 * 3600)), None);
+        assert_eq!(garbage.iter().map(|e| e.name()).collect::<Vec<_>>(), vec!["a"]);
+    }
+
+    #[test]
+    fn gc_by_size_removes_oldest_first() {
+        let now = SystemTime::now();
+        let entries = vec![
+            entry("new", 100, 1, now), entry("old", 100, 3, now), entry("mid", 100, 2, now),
+        ];
+        let garbage = select_garbage(entries, now, None, Some(150));
+        assert_eq!(garbage.iter().map(|e| e.name()).collect::<Vec<_>>(), vec!["old", "mid"]);
+    }
+
+    #[test]
+    fn gc_nothing_to_do() {
+        let now = SystemTime::now();
+        let entries = vec![entry("a", 10, 1, now)];
+        assert!(select_garbage(entries, now, None, None).is_empty());
+    }
+
+    #[test]
+    fn copying_entries() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-cache-copy-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = Cache::open(dir.join("cache"));
+        let version = Version::parse("1.0.0").unwrap();
+        cache.put("foo", &version, b"archive").unwrap();
+        let sha256 = checksum::sha256_reader(&b"archive"[..]).unwrap();
+
+        let path = dir.join("foo.crate");
+        assert!(cache.copy_to("foo", &version, 7, &sha256, &path).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"archive");
+        // An entry of another size isn't the same archive.
+        assert!(!cache.copy_to("foo", &version, 8, &sha256, &dir.join("other.crate")).unwrap());
+        assert!(!cache.copy_to("bar", &version, 7, &sha256, &dir.join("other.crate")).unwrap());
+        // Nor is a corrupted one of the same size.
+        cache.put("foo", &version, b"arch1ve").unwrap();
+        assert!(!cache.copy_to("foo", &version, 7, &sha256, &dir.join("other.crate")).unwrap());
+        assert!(!dir.join("other.crate").exists());
+
+        // A corrupted entry can be evicted, and then isn't found anymore.
+        cache.evict("foo", &version).unwrap();
+        assert_eq!(cache.get("foo", &version).unwrap(), None);
+        cache.evict("foo", &version).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn registries_kept_apart() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-cache-registries-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let crates_io = Cache::open(&dir);
+        let other = Cache::open_for(&dir, "sparse+https://registry.example.com/index/");
+        let version = Version::parse("1.0.0").unwrap();
+        crates_io.put("foo", &version, b"crates.io").unwrap();
+        other.put("foo", &version, b"other").unwrap();
+
+        assert_eq!(crates_io.get("foo", &version).unwrap(), Some(b"crates.io".to_vec()));
+        assert_eq!(other.get("foo", &version).unwrap(), Some(b"other".to_vec()));
+        other.put("foo", &Version::parse("2.0.0").unwrap(), b"other").unwrap();
+        assert_eq!(crates_io.versions("foo").unwrap(), vec![version.clone()]);
+        assert_eq!(other.versions("foo").unwrap().len(), 2);
+
+        // Entries from before the registries were kept apart are listed too.
+        fs::create_dir_all(dir.join("bar")).unwrap();
+        fs::write(dir.join("bar").join("bar-1.0.0.crate"), b"legacy").unwrap();
+        let mut entries = crates_io.entries().unwrap();
+        assert_eq!(entries.len(), 4);
+        entries.sort_by_key(|e| e.name());
+        assert_eq!(entries[0].name(), "bar-1.0.0");
+        for entry in &entries {
+            crates_io.remove(entry).unwrap();
+        }
+        assert!(crates_io.entries().unwrap().is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().map(|f| f.unwrap().file_name()).collect::<Vec<_>>(),
+                   vec!["stats.json"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stale_parts() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-cache-parts-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = Cache::open(&dir);
+        let version = Version::parse("1.0.0").unwrap();
+        for name in &["foo", "bar"] {
+            let part = cache.part_path(name, &version);
+            fs::create_dir_all(part.parent().unwrap()).unwrap();
+            fs::write(&part, b"partial").unwrap();
+        }
+        let now = SystemTime::now();
+        let two_days_ago = now - Duration::from_secs(2 * 24 * 3600);
+        let two_days_ago = FileTime::from_system_time(two_days_ago);
+        filetime::set_file_mtime(cache.part_path("foo", &version), two_days_ago).unwrap();
+
+        // Only the download that's not been written to for a while is abandoned, and neither is an entry.
+        let stale = cache.stale_parts(now).unwrap();
+        assert_eq!(stale.iter().map(|p| p.name()).collect::<Vec<_>>(), vec!["foo-1.0.0.crate"]);
+        assert!(cache.entries().unwrap().is_empty());
+        cache.remove(&stale[0]).unwrap();
+        assert!(!cache.part_path("foo", &version).parent().unwrap().exists());
+        assert!(cache.part_path("bar", &version).exists());
+
+        // Once everything's been removed, the (now empty) cache directory is left in place.
+        for part in cache.stale_parts(now + Duration::from_secs(2 * 24 * 3600)).unwrap() {
+            cache.remove(&part).unwrap();
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
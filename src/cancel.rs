@@ -0,0 +1,87 @@
+//! Module for cancelling downloads from another thread,
+//! so that programs embedding the library can abort them cleanly (e.g. when the user asks to).
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+/// Token for cancelling the downloads of a downloader that it's been given to.
+///
+/// It's cheap to clone, with the clones sharing whether it's been cancelled,
+/// so one can be kept to cancel the downloads while the downloader (on any thread) holds another.
+/// Once cancelled, the token stays so, and the downloader fails whatever it's doing
+/// with `DownloadError::Cancelled`, without leaving partially downloaded or extracted files behind
+/// (only writers given to `Downloader::download` can be left with a part of an archive).
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    #[inline]
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancel the downloads of the downloaders that have been given this token.
+    #[inline]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "CancellationToken({})", if self.is_cancelled() { "cancelled" } else { "active" })
+    }
+}
+
+
+/// Reader that fails once given token has been cancelled,
+/// so that whatever is reading a crate's archive through it stops.
+pub struct CancellableReader<R> {
+    inner: R,
+    token: CancellationToken,
+}
+
+impl<R: Read> CancellableReader<R> {
+    pub fn new(inner: R, token: CancellationToken) -> CancellableReader<R> {
+        CancellableReader{inner, token}
+    }
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.token.is_cancelled() {
+            return Err(io::Error::other("the download has been cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{CancellableReader, CancellationToken};
+
+    #[test]
+    fn cancelled_reading() {
+        let token = CancellationToken::new();
+        let mut reader = CancellableReader::new(&b"hello"[..], token.clone());
+        let mut buf = [0; 2];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+
+        token.cancel();
+        assert!(token.clone().is_cancelled());
+        assert!(reader.read(&mut buf).is_err());
+        assert!(reader.read_to_end(&mut vec![]).is_err());
+    }
+}
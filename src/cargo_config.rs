@@ -0,0 +1,203 @@
+//! Module for reading Cargo's configuration files.
+//!
+//! Just like Cargo itself, we look for `.cargo/config.toml` (or `.cargo/config`)
+//! in the current directory and all its ancestors, and finally in `$CARGO_HOME`.
+//! Files closer to the current directory take precedence.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml;
+
+use cache::cargo_home;
+
+
+/// Names of the config files inside a `.cargo` directory, in order of preference.
+const CONFIG_FILES: &[&str] = &["config.toml", "config"];
+
+
+/// Cargo configuration, possibly coming from multiple files.
+#[derive(Debug, Clone, Default)]
+pub struct CargoConfig {
+    /// Parsed config files, in order of decreasing precedence.
+    files: Vec<ConfigFile>,
+}
+
+/// Single parsed config file.
+#[derive(Debug, Clone)]
+struct ConfigFile {
+    path: PathBuf,
+    value: toml::Value,
+}
+
+impl CargoConfig {
+    /// Load the configuration that applies to the current directory.
+    pub fn load() -> Result<CargoConfig, ConfigError> {
+        let cwd = env::current_dir().map_err(|e| ConfigError::Io(PathBuf::from("."), e))?;
+        CargoConfig::load_for(&cwd, cargo_home().as_deref())
+    }
+
+    /// Load the configuration that applies to given directory,
+    /// with the Cargo home directory as the final fallback.
+    pub fn load_for(dir: &Path, cargo_home: Option<&Path>) -> Result<CargoConfig, ConfigError> {
+        let mut config_dirs: Vec<PathBuf> = dir.ancestors().map(|d| d.join(".cargo")).collect();
+        if let Some(home) = cargo_home {
+            if !config_dirs.iter().any(|d| d == home) {
+                config_dirs.push(home.to_owned());
+            }
+        }
+
+        let mut files = vec![];
+        for config_dir in config_dirs {
+            let path = CONFIG_FILES.iter().map(|f| config_dir.join(f)).find(|p| p.is_file());
+            if let Some(path) = path {
+                trace!("Reading Cargo config file {}", path.display());
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| ConfigError::Io(path.clone(), e))?;
+                let value = content.parse::<toml::Value>()
+                    .map_err(|e| ConfigError::Parse(path.clone(), e))?;
+                files.push(ConfigFile{path, value});
+            }
+        }
+        Ok(CargoConfig{files})
+    }
+
+    /// Look up a value by its dotted key (e.g. "http.proxy").
+    pub fn get(&self, key: &str) -> Option<ConfigValue<'_>> {
+        self.files.iter().filter_map(|file| {
+            let value = key.split('.')
+                .try_fold(&file.value, |v, k| v.as_table().and_then(|t| t.get(k)))?;
+            Some(ConfigValue{value, file: &file.path})
+        }).next()
+    }
+
+    pub fn get_str(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        self.get(key).map(|v| v.as_str().map(|s| s.to_owned())).transpose()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        self.get(key).map(|v| v.as_bool()).transpose()
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>, ConfigError> {
+        self.get(key).map(|v| v.as_int()).transpose()
+    }
+
+    /// Look up a path value, resolving it relative to the directory
+    /// that contains the `.cargo` directory it was defined in.
+    pub fn get_path(&self, key: &str) -> Result<Option<PathBuf>, ConfigError> {
+        self.get(key).map(|v| v.as_path()).transpose()
+    }
+}
+
+
+/// Value from the configuration, along with the file it was found in.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigValue<'c> {
+    value: &'c toml::Value,
+    file: &'c Path,
+}
+
+impl<'c> ConfigValue<'c> {
+    pub fn as_str(&self) -> Result<&'c str, ConfigError> {
+        self.value.as_str().ok_or_else(|| self.type_error("a string"))
+    }
+
+    pub fn as_bool(&self) -> Result<bool, ConfigError> {
+        self.value.as_bool().ok_or_else(|| self.type_error("a boolean"))
+    }
+
+    pub fn as_int(&self) -> Result<i64, ConfigError> {
+        self.value.as_integer().ok_or_else(|| self.type_error("an integer"))
+    }
+
+    pub fn as_path(&self) -> Result<PathBuf, ConfigError> {
+        let path = Path::new(self.as_str()?);
+        if path.is_absolute() {
+            return Ok(path.to_owned());
+        }
+        // The config file is at $DIR/.cargo/config.toml, and paths are relative to $DIR.
+        let base = self.file.parent().and_then(|d| d.parent()).unwrap_or_else(|| Path::new("."));
+        Ok(base.join(path))
+    }
+
+    fn type_error(&self, expected: &str) -> ConfigError {
+        ConfigError::Type(self.file.to_owned(), format!("expected {}, got `{}`", expected, self.value))
+    }
+}
+
+
+/// Error that can occur while reading Cargo's configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Error reading a config file.
+    Io(PathBuf, io::Error),
+    /// Syntax error in a config file.
+    Parse(PathBuf, toml::de::Error),
+    /// Config value of unexpected type.
+    Type(PathBuf, String),
+}
+impl Error for ConfigError {
+    fn description(&self) -> &str { "invalid Cargo configuration" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            ConfigError::Io(_, ref e) => Some(e),
+            ConfigError::Parse(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref p, ref e) =>
+                write!(fmt, "failed to read config file {}: {}", p.display(), e),
+            ConfigError::Parse(ref p, ref e) =>
+                write!(fmt, "failed to parse config file {}: {}", p.display(), e),
+            ConfigError::Type(ref p, ref msg) =>
+                write!(fmt, "invalid value in config file {}: {}", p.display(), msg),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::env;
+    use super::CargoConfig;
+
+    fn write_config(dir: &Path, content: &str) {
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(dir.join(".cargo").join("config.toml"), content).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cargo-download-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn nested_configs_take_precedence() {
+        let root = temp_dir("config-precedence");
+        let nested = root.join("project");
+        write_config(&root, "[http]\ntimeout = 10\nproxy = \"http://outer:3128\"\n");
+        write_config(&nested, "[http]\nproxy = \"http://inner:3128\"\ncainfo = \"certs/ca.pem\"\n");
+
+        let config = CargoConfig::load_for(&nested, None).unwrap();
+        assert_eq!(config.get_str("http.proxy").unwrap().unwrap(), "http://inner:3128");
+        assert_eq!(config.get_int("http.timeout").unwrap(), Some(10));
+        assert_eq!(config.get_path("http.cainfo").unwrap().unwrap(), nested.join("certs/ca.pem"));
+        assert_eq!(config.get_bool("http.check-revoke").unwrap(), None);
+        assert!(config.get_int("http.proxy").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
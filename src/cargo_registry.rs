@@ -0,0 +1,325 @@
+//! Module for populating Cargo's own registry cache (`--cache`),
+//! so that a subsequent `cargo build --offline` finds the downloaded crates.
+//! In offline mode, the crates (and index files) that Cargo has cached are read from there in turn.
+//!
+//! Cargo keeps the data of every registry in `$CARGO_HOME/registry/{cache,index,src}/<dir>`,
+//! where the directory is named after the host of the index and a hash of its URL.
+//! The hash changed in Cargo 1.85, so if the directory of the older scheme exists already
+//! (i.e. it's in use by an older Cargo), it's populated instead of the current one.
+
+use std::fs::{self, File};
+#[allow(deprecated)]  // it's exactly the hash that older Cargo used
+use std::hash::SipHasher;
+use std::hash::Hasher;
+use std::io;
+use std::path::PathBuf;
+use std::str;
+
+use fs2::FileExt;
+use reqwest::Url;
+use semver::Version;
+use serde_json::{self, Value as Json};
+use tar;
+
+use cache::{self, cargo_home};
+use cargo_download::{decompress, index_path};
+use sparse_index::SparseIndex;
+
+
+/// Name of the file that Cargo locks while it's downloading crates.
+const PACKAGE_CACHE_LOCK: &str = ".package-cache";
+
+/// Name of the index's configuration file.
+const INDEX_CONFIG_FILE: &str = "config.json";
+
+/// Version of the format of Cargo's cache of index files.
+const INDEX_CACHE_VERSION: u8 = 3;
+/// Version of the index format that Cargo's cache of index files is for.
+const INDEX_FORMAT_VERSION: u32 = 2;
+/// What Cargo records as the version of an index file when the server didn't report one.
+const UNKNOWN_INDEX_VERSION: &str = "Unknown";
+
+/// Content of the marker file that Cargo puts into the directories of unpacked crates.
+const CARGO_OK: &str = "{\"v\":1}";
+
+/// Discriminant of the sparse registries among the kinds of sources in Cargo,
+/// which goes into the hash of their URLs.
+const SPARSE_REGISTRY_KIND: u8 = 3;
+
+
+/// Directories of a registry in Cargo's home directory.
+#[derive(Debug)]
+pub struct CargoRegistry {
+    home: PathBuf,
+    /// Name of the registry's directories under `registry/{cache,index,src}`.
+    dir_name: String,
+}
+
+impl CargoRegistry {
+    /// Locate the directories of the registry with given index in Cargo's home directory,
+    /// if there's one.
+    pub fn locate(index: &SparseIndex) -> Option<CargoRegistry> {
+        let home = cargo_home()?;
+        let url = index.to_string();
+        let current = dir_name(&url, HashScheme::Current);
+        let legacy = dir_name(&url, HashScheme::Legacy);
+        let in_use = |name: &str| home.join("registry").join("index").join(name).is_dir();
+        let dir_name = if !in_use(&current) && in_use(&legacy) { legacy } else { current };
+        Some(CargoRegistry{home, dir_name})
+    }
+
+    /// Path of given crate version's archive in Cargo's cache.
+    pub fn crate_path(&self, name: &str, version: &Version) -> PathBuf {
+        self.dir("cache").join(format!("{}-{}.crate", name, version))
+    }
+
+    /// Lock Cargo's package cache for writing, like Cargo does while it's downloading,
+    /// until the returned file is dropped.
+    pub fn lock(&self) -> io::Result<File> {
+        fs::create_dir_all(&self.home)?;
+        let path = self.home.join(PACKAGE_CACHE_LOCK);
+        let file = fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(false)
+            .open(&path)?;
+        if file.try_lock_exclusive().is_err() {
+            info!("Waiting for the lock on Cargo's package cache {}", path.display());
+            file.lock_exclusive()?;
+        }
+        Ok(file)
+    }
+
+    /// Read the archive of given crate version from Cargo's cache, if it's there.
+    pub fn read_crate(&self, name: &str, version: &Version) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.crate_path(name, version)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check whether given crate version has been unpacked where Cargo keeps the crate sources.
+    pub fn has_src(&self, name: &str, version: &Version) -> bool {
+        self.src_path(name, version).join(".cargo-ok").is_file()
+    }
+
+    /// Put the archive of given crate version into Cargo's cache, returning its path.
+    pub fn put_crate(&self, name: &str, version: &Version, crate_bytes: &[u8]) -> io::Result<PathBuf> {
+        let path = self.crate_path(name, version);
+        fs::create_dir_all(self.dir("cache"))?;
+        cache::write_archive(name, version, crate_bytes, &path)?;
+        Ok(path)
+    }
+
+    /// Unpack the archive of given crate version where Cargo keeps the crate sources,
+    /// returning the crate's directory.
+    pub fn put_src(&self, name: &str, version: &Version, crate_bytes: &[u8]) -> io::Result<PathBuf> {
+        let src_dir = self.src_dir();
+        let crate_dir = self.src_path(name, version);
+        if crate_dir.exists() {
+            fs::remove_dir_all(&crate_dir)?;
+        }
+        fs::create_dir_all(&src_dir)?;
+        // Archives have a single top-level directory named $CRATE-$VERSION.
+        tar::Archive::new(decompress(crate_bytes)?).unpack(&src_dir)?;
+        // Without the marker, Cargo would consider the unpacking unfinished and redo it.
+        fs::write(crate_dir.join(".cargo-ok"), CARGO_OK)?;
+        Ok(crate_dir)
+    }
+
+    /// Put the index file of given crate into Cargo's cache of the index,
+    /// which Cargo resolves the dependencies from when it's offline.
+    pub fn put_index_file(&self, name: &str, content: &str) -> io::Result<()> {
+        let path = self.index_dir().join(".cache").join(index_path(name));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, index_cache(content)?)
+    }
+
+    /// Read the index file of given crate from Cargo's cache of the index, if it's there
+    /// (in a format that's understood).
+    pub fn read_index_file(&self, name: &str) -> io::Result<Option<String>> {
+        let path = self.index_dir().join(".cache").join(index_path(name));
+        match fs::read(&path) {
+            Ok(cache) => Ok(index_file(&cache)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check whether Cargo's copy of the index has the index's configuration.
+    pub fn has_index_config(&self) -> bool {
+        self.index_dir().join(INDEX_CONFIG_FILE).is_file()
+    }
+
+    /// Put the configuration of the index (its `config.json`) into Cargo's copy of the index,
+    /// which Cargo refuses to use without it.
+    pub fn put_index_config(&self, content: &str) -> io::Result<()> {
+        fs::create_dir_all(self.index_dir())?;
+        fs::write(self.index_dir().join(INDEX_CONFIG_FILE), content)
+    }
+
+    /// Directory of Cargo's copy of the registry's index.
+    pub fn index_dir(&self) -> PathBuf {
+        self.dir("index")
+    }
+
+    /// Directory where Cargo keeps the unpacked crate sources.
+    pub fn src_dir(&self) -> PathBuf {
+        self.dir("src")
+    }
+
+    fn src_path(&self, name: &str, version: &Version) -> PathBuf {
+        self.dir("src").join(format!("{}-{}", name, version))
+    }
+
+    fn dir(&self, kind: &str) -> PathBuf {
+        self.home.join("registry").join(kind).join(&self.dir_name)
+    }
+}
+
+/// Convert an index file to the format of Cargo's cache of the index,
+/// which is a binary header followed by NUL-terminated versions and their index entries.
+fn index_cache(content: &str) -> io::Result<Vec<u8>> {
+    let mut cache = vec![INDEX_CACHE_VERSION];
+    cache.extend(&INDEX_FORMAT_VERSION.to_le_bytes());
+    cache.extend(UNKNOWN_INDEX_VERSION.as_bytes());
+    cache.push(0);
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        let entry: Json = serde_json::from_str(line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let version = entry.get("vers").and_then(|v| v.as_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "index entry without a version"))?;
+        cache.extend(version.as_bytes());
+        cache.push(0);
+        cache.extend(line.as_bytes());
+        cache.push(0);
+    }
+    Ok(cache)
+}
+
+/// Convert a file of Cargo's cache of the index back to the index file,
+/// unless it's in a different version of the format.
+fn index_file(cache: &[u8]) -> Option<String> {
+    if cache.first() != Some(&INDEX_CACHE_VERSION) {
+        return None;
+    }
+    // After the versions of the formats come the version of the index file and then the entries,
+    // each following its crate version, all terminated by NULs.
+    let mut fields = cache.get(5..)?.split(|&b| b == 0).skip(1);
+    let mut content = String::new();
+    while let (Some(_), Some(entry)) = (fields.next(), fields.next()) {
+        content.push_str(str::from_utf8(entry).ok()?);
+        content.push('\n');
+    }
+    Some(content)
+}
+
+
+/// Scheme of hashing the registry URLs into the names of their directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashScheme {
+    /// 128-bit SipHash-1-3 folded to 64 bits, used since Cargo 1.85.
+    Current,
+    /// 64-bit SipHash-2-4, used by older versions of Cargo.
+    Legacy,
+}
+
+/// Name of the directories of the sparse registry with given index URL (including `sparse+`),
+/// i.e. the host of the index followed by the hash of its URL.
+fn dir_name(url: &str, scheme: HashScheme) -> String {
+    let host = Url::parse(url).ok()
+        .and_then(|u| u.host_str().map(|h| h.to_owned()))
+        .unwrap_or_default();
+    // This is what Cargo's source IDs hash to: the kind of the source, then the URL
+    // (with the terminator that Rust appends to hashed strings).
+    let mut data = vec![];
+    match scheme {
+        // Small integers are hashed as a single byte by Cargo's stable hasher.
+        HashScheme::Current => data.push(SPARSE_REGISTRY_KIND),
+        HashScheme::Legacy => data.extend(&(SPARSE_REGISTRY_KIND as u64).to_le_bytes()),
+    }
+    data.extend(url.as_bytes());
+    data.push(0xff);
+    let hash = match scheme {
+        HashScheme::Current => {
+            let (lo, hi) = sip13_128(&data);
+            lo.wrapping_mul(3).wrapping_add(hi)
+        }
+        HashScheme::Legacy => {
+            #[allow(deprecated)]
+            let mut hasher = SipHasher::new();
+            hasher.write(&data);
+            hasher.finish()
+        }
+    };
+    let hex: String = hash.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", host, hex)
+}
+
+/// Compute the 128-bit SipHash-1-3 (with zero keys) of given data, as two halves.
+fn sip13_128(data: &[u8]) -> (u64, u64) {
+    let mut v = [0x736f6d6570736575u64, 0x646f72616e646f6d ^ 0xee, 0x6c7967656e657261, 0x7465646279746573];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]); v[1] = v[1].rotate_left(13); v[1] ^= v[0]; v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]); v[3] = v[3].rotate_left(16); v[3] ^= v[2];
+        v[0] = v[0].wrapping_add(v[3]); v[3] = v[3].rotate_left(21); v[3] ^= v[0];
+        v[2] = v[2].wrapping_add(v[1]); v[1] = v[1].rotate_left(17); v[1] ^= v[2]; v[2] = v[2].rotate_left(32);
+    };
+    let compress = |v: &mut [u64; 4], m: u64| {
+        v[3] ^= m;
+        round(v);
+        v[0] ^= m;
+    };
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        compress(&mut v, u64::from_le_bytes(word));
+    }
+    let mut last = ((data.len() as u64) & 0xff) << 56;
+    for (i, &byte) in chunks.remainder().iter().enumerate() {
+        last |= (byte as u64) << (8 * i);
+    }
+    compress(&mut v, last);
+
+    v[2] ^= 0xee;
+    for _ in 0..3 {
+        round(&mut v);
+    }
+    let lo = v[0] ^ v[1] ^ v[2] ^ v[3];
+    v[1] ^= 0xdd;
+    for _ in 0..3 {
+        round(&mut v);
+    }
+    let hi = v[0] ^ v[1] ^ v[2] ^ v[3];
+    (lo, hi)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{HashScheme, dir_name, index_cache, index_file};
+
+    #[test]
+    fn registry_dir_names() {
+        let url = "sparse+https://index.crates.io/";
+        assert_eq!(dir_name(url, HashScheme::Current), "index.crates.io-1949cf8c6b5b557f");
+        assert_eq!(dir_name(url, HashScheme::Legacy), "index.crates.io-6f17d22bba15001f");
+
+        let cache = index_cache("{\"name\":\"foo\",\"vers\":\"0.1.0\"}\n").unwrap();
+        assert_eq!(&cache[..13], b"\x03\x02\x00\x00\x00Unknown\x00");
+        assert_eq!(&cache[13..], &b"0.1.0\x00{\"name\":\"foo\",\"vers\":\"0.1.0\"}\x00"[..]);
+    }
+
+    #[test]
+    fn index_cache_roundtrip() {
+        let content = concat!(r#"{"name":"foo","vers":"0.1.0"}"#, "\n", r#"{"name":"foo","vers":"0.2.0"}"#, "\n");
+        assert_eq!(index_file(&index_cache(content).unwrap()).as_deref(), Some(content));
+        // The version of the index file is whatever the server reported, e.g. an ETag.
+        let cache = b"\x03\x02\x00\x00\x00etag: \"abc\"\x000.1.0\x00{}\x00";
+        assert_eq!(index_file(cache).as_deref(), Some("{}\n"));
+        assert_eq!(index_file(b"\x01\x00"), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Module for computing and verifying checksums of crate archives.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+
+/// Compute the SHA-256 digest of everything that can be read from given reader,
+/// as a lowercase hex string.
+pub fn sha256_reader<R: Read>(reader: R) -> io::Result<String> {
+    copy_with_sha256(reader, &mut io::sink())
+}
+
+/// Copy everything that can be read from given reader into given writer,
+/// returning the SHA-256 digest of it as a lowercase hex string.
+pub fn copy_with_sha256<R: Read, W: Write>(mut reader: R, writer: &mut W) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Check whether the file at given path exists and has the expected SHA-256 digest.
+pub fn file_matches<P: AsRef<Path>>(path: P, expected: &str) -> io::Result<bool> {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let actual = sha256_reader(io::BufReader::new(file))?;
+    Ok(actual.eq_ignore_ascii_case(expected))
+}
+
+/// Check whether the file at given path exists, with the expected SHA-256 digest if there's one.
+pub fn file_present<P: AsRef<Path>>(path: P, expected: Option<&str>) -> io::Result<bool> {
+    match expected {
+        Some(expected) => file_matches(path, expected),
+        None => Ok(path.as_ref().is_file()),
+    }
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use super::{file_present, sha256_reader};
+
+    #[test]
+    fn empty_digest() {
+        assert_eq!(sha256_reader(&b""[..]).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn digest_spanning_buffers() {
+        let data = vec![b'a'; 1000 * 1000];
+        assert_eq!(sha256_reader(&data[..]).unwrap(),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn corrupted_file_not_present() {
+        let path = env::temp_dir().join(format!("cargo-download-test-checksum-{}.crate", process::id()));
+        let expected = sha256_reader(&b"archive"[..]).unwrap();
+        assert!(!file_present(&path, None).unwrap());
+        assert!(!file_present(&path, Some(&expected)).unwrap());
+
+        fs::write(&path, b"archive").unwrap();
+        assert!(file_present(&path, Some(&expected.to_uppercase())).unwrap());
+        // A file of the same size with different content is there, but not as expected.
+        fs::write(&path, b"arch1ve").unwrap();
+        assert!(file_present(&path, None).unwrap());
+        assert!(!file_present(&path, Some(&expected)).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
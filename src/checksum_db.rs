@@ -0,0 +1,101 @@
+//! Module for the database of checksums of downloaded crates (`--checksum-db`).
+//!
+//! The database records the SHA-256 digest of every crate version the first time
+//! it's downloaded, and later downloads of the same version must match it.
+//! This is trust-on-first-use protection against the registry serving
+//! different content for a version that has already been published,
+//! e.g. because its index has been rewritten.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+use serde_json;
+
+
+/// Database of the checksums of downloaded crates, stored in a JSON file
+/// that maps crate names to their versions and those to the SHA-256 digests.
+#[derive(Debug)]
+pub struct ChecksumDb {
+    path: PathBuf,
+    checksums: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Result of checking a crate's digest against the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// The crate version hasn't been seen before, and its digest has been recorded.
+    Recorded,
+    /// The digest matches the one recorded before.
+    Matches,
+    /// The digest differs from the one recorded before, which is given.
+    Differs(String),
+}
+
+impl ChecksumDb {
+    /// Load the database from given file, which doesn't need to exist yet.
+    pub fn load<P: Into<PathBuf>>(path: P) -> io::Result<ChecksumDb> {
+        let path = path.into();
+        let checksums = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} is not a valid checksum database: {}", path.display(), e)))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(ChecksumDb{path, checksums})
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check the SHA-256 digest (as a hex string) of given crate version against the database,
+    /// recording it if the version is new.
+    pub fn check(&mut self, name: &str, version: &Version, digest: &str) -> Check {
+        let versions = self.checksums.entry(name.to_owned()).or_default();
+        match versions.get(&version.to_string()) {
+            Some(known) if known.eq_ignore_ascii_case(digest) => Check::Matches,
+            Some(known) => Check::Differs(known.clone()),
+            None => {
+                versions.insert(version.to_string(), digest.to_lowercase());
+                Check::Recorded
+            }
+        }
+    }
+
+    /// Save the database to its file.
+    pub fn save(&self) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.checksums)?;
+        fs::write(&self.path, json + "\n")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use semver::Version;
+    use super::{Check, ChecksumDb};
+
+    #[test]
+    fn trust_on_first_use() {
+        let path = env::temp_dir().join(format!("cargo-download-test-checksum-db-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let version = Version::parse("1.0.0").unwrap();
+
+        let mut db = ChecksumDb::load(&path).unwrap();
+        assert_eq!(db.check("foo", &version, "ABCD"), Check::Recorded);
+        db.save().unwrap();
+
+        let mut db = ChecksumDb::load(&path).unwrap();
+        assert_eq!(db.check("foo", &version, "abcd"), Check::Matches);
+        assert_eq!(db.check("foo", &version, "ef01"), Check::Differs("abcd".into()));
+        assert_eq!(db.check("foo", &Version::parse("1.0.1").unwrap(), "ef01"), Check::Recorded);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
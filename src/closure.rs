@@ -0,0 +1,473 @@
+//! Module for determining the closures of a crate's dependencies.
+//!
+//! The build closure consists of the dependencies needed to expand the crate's
+//! procedural macros and run its build script: its build-dependencies and proc-macro
+//! dependencies, along with everything that they in turn need to be built on the host.
+//! Optional dependencies are left out of it, since features aren't resolved.
+//!
+//! The dependency closure consists of everything needed to build the crate.
+//! Cargo resolves the dependencies for all targets at once, so it includes
+//! the target-specific dependencies too, along with the optional ones
+//! that are needed by the enabled features.
+//!
+//! Renamed dependencies (`alias = { package = "real-name", ... }`) are resolved
+//! and downloaded as the actual crates, with their aliases only used to match them
+//! against the features. A crate may depend on the same crate more than once,
+//! e.g. in two semver-incompatible versions, in which case both are included.
+//!
+//! The crates of a dependency graph can be put in dependency order (see `leaves_first`),
+//! so that every crate comes after the crates it depends on.
+//!
+//! Along with the crates of a closure, how each of them is depended on is determined
+//! (by which crates, with which kind of dependency, and whether it's optional),
+//! so that the outputs of the run can tell why the crate is included.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+
+use semver::Version;
+use serde_json::Value as Json;
+
+use cargo_download::{self, Crate, CrateVersion, Dependency, DependencyKind, LockedPackage, ResolvedVersion,
+                     VersionFilter};
+
+use registry::Registry;
+use super::{download_crate, read_manifest};
+
+
+/// Crate version that depends on a crate in a closure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependent {
+    /// The depending crate version, as `name==version`.
+    pub crate_: String,
+    pub kind: DependencyKind,
+    /// Whether the dependency is optional, i.e. only enabled by a feature.
+    pub optional: bool,
+}
+
+impl Dependent {
+    pub fn to_json(&self) -> Json {
+        json!({"crate": self.crate_, "kind": self.kind.to_string(), "optional": self.optional})
+    }
+}
+
+/// Dependents of the crates in a closure, by the crates as `name==version`.
+pub type Dependents = HashMap<String, Vec<Dependent>>;
+
+/// Note that given crate version depends on another one, with given dependency.
+fn add_dependent(dependents: &mut Dependents, name: &str, version: &Version, dep: &Dependency,
+                 dep_version: &Version) {
+    let dependent = Dependent{crate_: format!("{}=={}", name, version), kind: dep.kind, optional: dep.optional};
+    let list = dependents.entry(format!("{}=={}", dep.name, dep_version)).or_default();
+    // A crate may declare the same dependency more than once, e.g. for different targets.
+    if !list.contains(&dependent) {
+        list.push(dependent);
+    }
+}
+
+/// Determine the build closure of given crate version, as crates with exact versions,
+/// along with their dependents.
+pub fn build_closure(registry: &Registry, name: &str,
+                     version: &Version) -> Result<(Vec<Crate>, Dependents), Box<dyn Error>> {
+    let mut dependents = Dependents::new();
+    // Normal dependencies only need to be built on the host if they are proc-macros,
+    // which is only known from their manifests.
+    let mut host_queue = VecDeque::new();
+    for dep in required_deps(registry, name, version)? {
+        let dep_version = resolve(registry, &dep)?;
+        let on_host = match dep.kind {
+            DependencyKind::Build => true,
+            DependencyKind::Normal => is_proc_macro(registry, &dep.name, &dep_version)?,
+            DependencyKind::Dev => false,
+        };
+        if on_host {
+            add_dependent(&mut dependents, name, version, &dep, &dep_version);
+            host_queue.push_back((dep.name, dep_version));
+        }
+    }
+
+    // Everything that a host crate depends on has to be built on the host as well.
+    let mut closure = vec![];
+    let mut seen = HashSet::new();
+    while let Some((name, version)) = host_queue.pop_front() {
+        // Version equality disregards build metadata, so compare the full strings.
+        if !seen.insert((name.clone(), version.to_string())) {
+            continue;
+        }
+        for dep in required_deps(registry, &name, &version)? {
+            if dep.kind != DependencyKind::Dev {
+                let dep_version = resolve(registry, &dep)?;
+                add_dependent(&mut dependents, &name, &version, &dep, &dep_version);
+                host_queue.push_back((dep.name, dep_version));
+            }
+        }
+        closure.push(Crate::new(name, CrateVersion::Exact(version))?);
+    }
+    Ok((closure, dependents))
+}
+
+/// Determine the dependency closure of given crate version (with its default features),
+/// as crates with exact versions, not including the crate itself.
+pub fn dependency_closure(registry: &Registry, name: &str,
+                          version: &Version) -> Result<Vec<Crate>, Box<dyn Error>> {
+    let packages = Resolver::new(registry).resolve(name, version)?;
+    let mut closure = vec![];
+    for (name, version) in packages.into_iter().skip(1) {
+        closure.push(Crate::new(name, CrateVersion::Exact(version))?);
+    }
+    Ok(closure)
+}
+
+/// Determine the dependency graph of given crate version (with its default features),
+/// as the packages of a lockfile, starting with the crate itself, along with their dependents.
+pub fn dependency_graph(registry: &Registry, name: &str,
+                        version: &Version) -> Result<(Vec<LockedPackage>, Dependents), Box<dyn Error>> {
+    let mut resolver = Resolver::new(registry);
+    let mut dependents = Dependents::new();
+    let packages = resolver.resolve(name, version)?.into_iter()
+        .map(|(name, version)| resolver.locked_package(name, version, &mut dependents))
+        .collect::<Result<_, _>>()?;
+    Ok((packages, dependents))
+}
+
+/// Order the packages of a dependency graph so that every package comes after its dependencies,
+/// starting with the leaves, and otherwise keeping their order. Duplicate packages are left out.
+///
+/// Dependency graphs without dev-dependencies are acyclic, but should there be a cycle anyway,
+/// it's broken where it's been entered.
+pub fn leaves_first(packages: &[LockedPackage]) -> Vec<&LockedPackage> {
+    // Version equality disregards build metadata, so compare the full strings.
+    let key = |name: &str, version: &Version| (name.to_owned(), version.to_string());
+    let by_key: HashMap<_, _> = packages.iter().map(|p| (key(&p.name, &p.version), p)).collect();
+
+    let mut order = vec![];
+    let mut visited = HashSet::new();
+    // Depth-first traversal, with each package added once all of its dependencies have been.
+    let mut stack: Vec<(&LockedPackage, usize)> = vec![];
+    for package in packages {
+        if !visited.insert(key(&package.name, &package.version)) {
+            continue;
+        }
+        stack.push((package, 0));
+        while let Some((package, next_dep)) = stack.pop() {
+            match package.dependencies.get(next_dep) {
+                Some((name, version)) => {
+                    stack.push((package, next_dep + 1));
+                    let dep_key = key(name, version);
+                    if let Some(dep) = by_key.get(&dep_key) {
+                        if visited.insert(dep_key) {
+                            stack.push((dep, 0));
+                        }
+                    }
+                }
+                None => order.push(package),
+            }
+        }
+    }
+    order
+}
+
+/// Fetch the non-optional dependencies of given crate version.
+fn required_deps(registry: &Registry, name: &str, version: &Version) -> Result<Vec<Dependency>, Box<dyn Error>> {
+    let deps = registry.fetch_dependencies(name, version)?;
+    Ok(deps.into_iter().filter(|d| !d.optional).collect())
+}
+
+/// Resolve given dependency to the newest matching version, like Cargo would.
+fn resolve(registry: &Registry, dep: &Dependency) -> Result<Version, Box<dyn Error>> {
+    let crate_ = Crate::new(dep.name.clone(), CrateVersion::Other(dep.req.clone()))?;
+    let versions = registry.fetch_index_versions(&dep.name)?;
+    let newest = cargo_download::resolve_versions(&crate_, versions, VersionFilter::default()).next();
+    newest.map(|v| v.version)
+        .ok_or_else(|| format!("no version of crate `{}` matches `{}`", dep.name, dep.req).into())
+}
+
+/// Check whether given crate version is a procedural macro, by downloading its archive.
+fn is_proc_macro(registry: &Registry, name: &str, version: &Version) -> Result<bool, Box<dyn Error>> {
+    let crate_bytes = download_crate(registry, name, version)?;
+    let manifest = read_manifest(name, version, &crate_bytes)?;
+    Ok(cargo_download::is_proc_macro(&manifest)?)
+}
+
+
+/// Resolver of the dependency closure of a crate, with its features taken into account.
+///
+/// Each dependency is resolved to the newest version that matches it, like Cargo would,
+/// and the features enabled on a crate version are the union of what all its dependents enable.
+struct Resolver<'r> {
+    registry: &'r Registry,
+    /// Contents of the index files fetched so far, by crate name.
+    index_files: HashMap<String, String>,
+    /// Crate versions in the closure so far, by name and version.
+    packages: HashMap<(String, String), Package>,
+    /// Crate versions (and features of them) that have yet to be activated.
+    queue: VecDeque<(String, Version, Option<String>)>,
+}
+
+/// Crate version in the closure.
+struct Package {
+    /// Non-dev dependencies of the crate version.
+    deps: Vec<Dependency>,
+    /// Features of the crate version, mapped to what they enable.
+    features: HashMap<String, Vec<String>>,
+    enabled_features: HashSet<String>,
+    /// Names of the enabled optional dependencies (as used in the features).
+    enabled_deps: HashSet<String>,
+}
+
+impl<'r> Resolver<'r> {
+    fn new(registry: &'r Registry) -> Resolver<'r> {
+        Resolver{
+            registry,
+            index_files: HashMap::new(),
+            packages: HashMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Resolve the closure of given crate version (with its default features),
+    /// as a list of names and versions that starts with the crate itself.
+    fn resolve(&mut self, name: &str, version: &Version) -> Result<Vec<(String, Version)>, Box<dyn Error>> {
+        self.queue.push_back((name.to_owned(), version.clone(), None));
+        self.queue.push_back((name.to_owned(), version.clone(), Some("default".to_owned())));
+        let mut closure = vec![];
+        while let Some((name, version, feature)) = self.queue.pop_front() {
+            let key = (name.clone(), version.to_string());
+            if !self.packages.contains_key(&key) {
+                self.add_package(&name, &version)?;
+                closure.push((name.clone(), version.clone()));
+            }
+            if let Some(feature) = feature {
+                self.enable_feature(&key, &feature)?;
+            }
+        }
+        Ok(closure)
+    }
+
+    /// Add given crate version to the closure, along with its required dependencies.
+    fn add_package(&mut self, name: &str, version: &Version) -> Result<(), Box<dyn Error>> {
+        let content = self.index_file(name)?.to_owned();
+        let deps: Vec<_> = Dependency::list_from_index(&content, version)
+            .ok_or_else(|| format!("crate `{}=={}` not found or malformed in the index", name, version))?
+            .into_iter().filter(|d| d.kind != DependencyKind::Dev).collect();
+        let features = cargo_download::features_from_index(&content, version).unwrap_or_default();
+        for dep in deps.iter().filter(|d| !d.optional) {
+            self.use_dep(dep)?;
+        }
+        self.packages.insert((name.to_owned(), version.to_string()), Package{
+            deps, features,
+            enabled_features: HashSet::new(),
+            enabled_deps: HashSet::new(),
+        });
+        Ok(())
+    }
+
+    /// Enable given feature of a crate version in the closure.
+    fn enable_feature(&mut self, key: &(String, String), feature: &str) -> Result<(), Box<dyn Error>> {
+        let package = self.packages.get_mut(key).unwrap();
+        if !package.enabled_features.insert(feature.to_owned()) {
+            return Ok(());
+        }
+        if let Some(values) = package.features.get(feature).cloned() {
+            for value in values {
+                self.enable_feature_value(key, &value)?;
+            }
+        } else {
+            // Optional dependencies are implicitly features of their own.
+            self.enable_dep(key, feature)?;
+        }
+        Ok(())
+    }
+
+    /// Enable what given value from a feature's definition refers to.
+    fn enable_feature_value(&mut self, key: &(String, String), value: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(dep) = value.strip_prefix("dep:") {
+            return self.enable_dep(key, dep);
+        }
+        let (dep_name, dep_feature) = match value.find('/') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => return self.enable_feature(key, value),
+        };
+        // A "foo?/bar" feature doesn't enable the optional dependency foo by itself,
+        // but it's taken into account once something else enables it.
+        let weak = dep_name.ends_with('?');
+        let dep_name = dep_name.trim_end_matches('?');
+        if !weak {
+            self.enable_dep(key, dep_name)?;
+        }
+        let package = &self.packages[key];
+        let deps: Vec<_> = package.deps.iter()
+            .filter(|d| d.used_name() == dep_name)
+            .filter(|d| !d.optional || package.enabled_deps.contains(dep_name))
+            .cloned().collect();
+        for dep in deps {
+            let version = self.resolve_dep(&dep)?;
+            self.queue.push_back((dep.name, version, Some(dep_feature.to_owned())));
+        }
+        Ok(())
+    }
+
+    /// Enable the optional dependency of a crate version with given name (as used in the features).
+    fn enable_dep(&mut self, key: &(String, String), dep_name: &str) -> Result<(), Box<dyn Error>> {
+        let package = self.packages.get_mut(key).unwrap();
+        let deps: Vec<_> = package.deps.iter()
+            .filter(|d| d.optional && d.used_name() == dep_name)
+            .cloned().collect();
+        if deps.is_empty() || !package.enabled_deps.insert(dep_name.to_owned()) {
+            return Ok(());
+        }
+        let weak_prefix = format!("{}?/", dep_name);
+        let weak_features: Vec<_> = package.enabled_features.iter()
+            .flat_map(|f| package.features.get(f).into_iter().flatten())
+            .filter_map(|v| v.strip_prefix(&weak_prefix))
+            .map(|f| f.to_owned())
+            .collect();
+        for dep in deps {
+            self.use_dep(&dep)?;
+            let version = self.resolve_dep(&dep)?;
+            for feature in &weak_features {
+                self.queue.push_back((dep.name.clone(), version.clone(), Some(feature.clone())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the crate version that given dependency resolves to,
+    /// with the features that the dependency enables.
+    fn use_dep(&mut self, dep: &Dependency) -> Result<(), Box<dyn Error>> {
+        let version = self.resolve_dep(dep)?;
+        self.queue.push_back((dep.name.clone(), version.clone(), None));
+        let default = Some("default".to_owned()).filter(|_| dep.default_features);
+        for feature in dep.features.iter().cloned().chain(default) {
+            self.queue.push_back((dep.name.clone(), version.clone(), Some(feature)));
+        }
+        Ok(())
+    }
+
+    /// Resolve given dependency to the newest matching version.
+    fn resolve_dep(&mut self, dep: &Dependency) -> Result<Version, Box<dyn Error>> {
+        let crate_ = Crate::new(dep.name.clone(), CrateVersion::Other(dep.req.clone()))?;
+        let versions = ResolvedVersion::list_from_index(self.index_file(&dep.name)?)
+            .ok_or_else(|| format!("malformed index file of crate `{}`", dep.name))?;
+        let newest = cargo_download::resolve_versions(&crate_, versions, VersionFilter::default()).next();
+        newest.map(|v| v.version)
+            .ok_or_else(|| format!("no version of crate `{}` matches `{}`", dep.name, dep.req).into())
+    }
+
+    /// Describe given crate version in the closure as a package of a lockfile,
+    /// with the dependencies that are enabled on it, which it's added to the dependents of.
+    fn locked_package(&mut self, name: String, version: Version,
+                      dependents: &mut Dependents) -> Result<LockedPackage, Box<dyn Error>> {
+        let package = &self.packages[&(name.clone(), version.to_string())];
+        let deps: Vec<_> = package.deps.iter()
+            .filter(|d| !d.optional || package.enabled_deps.contains(d.used_name()))
+            .cloned().collect();
+        let mut dependencies = vec![];
+        for dep in deps {
+            let dep_version = self.resolve_dep(&dep)?;
+            add_dependent(dependents, &name, &version, &dep, &dep_version);
+            dependencies.push((dep.name, dep_version));
+        }
+        let checksum = ResolvedVersion::list_from_index(self.index_file(&name)?)
+            .and_then(|versions| versions.into_iter().find(|v| v.version.to_string() == version.to_string()))
+            .and_then(|v| v.checksum);
+        Ok(LockedPackage{name, version, checksum, dependencies})
+    }
+
+    /// Fetch the index file of given crate, unless it's been fetched already.
+    fn index_file(&mut self, name: &str) -> Result<&str, Box<dyn Error>> {
+        if !self.index_files.contains_key(name) {
+            let (_, content) = self.registry.fetch_index_file(name)?;
+            self.index_files.insert(name.to_owned(), content);
+        }
+        Ok(&self.index_files[name])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use cargo_download::{DependencyKind, LockedPackage};
+    use http::Client;
+    use registry::Registry;
+    use super::{Dependent, Dependents, Resolver, leaves_first};
+
+    #[test]
+    fn renamed_and_duplicated_deps() {
+        let registry = Registry::new(Client::offline(), None, None);
+        let mut resolver = Resolver::new(&registry);
+        // The renamed dependencies are only known by their aliases in the features,
+        // and "rand" is depended on twice (in two versions, and as a build-dependency).
+        let index_files = vec![
+            ("app", concat!(
+                r#"{"name":"app","vers":"1.0.0","deps":["#,
+                r#"{"name":"rand07","package":"rand","req":"^0.7","optional":false,"kind":"normal"},"#,
+                r#"{"name":"rand","req":"^0.8","optional":false,"kind":"normal","features":["std"]},"#,
+                r#"{"name":"rand","req":"^0.8.1","optional":false,"kind":"build"},"#,
+                r#"{"name":"ser","package":"serde","req":"^1","optional":true,"kind":"normal"},"#,
+                r#"{"name":"json","package":"serde_json","req":"^1","optional":true,"kind":"normal"}"#,
+                r#"],"features":{"default":["ser","rand07/small"]},"cksum":"aa"}"#)),
+            ("rand", concat!(
+                r#"{"name":"rand","vers":"0.7.3","deps":[],"features":{"small":[]},"cksum":"bb"}"#, "\n",
+                r#"{"name":"rand","vers":"0.8.5","deps":[],"features":{"std":[]},"cksum":"cc"}"#)),
+            ("serde", r#"{"name":"serde","vers":"1.0.0","deps":[],"features":{},"cksum":"dd"}"#),
+        ];
+        for (name, content) in index_files {
+            resolver.index_files.insert(name.to_owned(), content.to_owned());
+        }
+
+        let version = Version::parse("1.0.0").unwrap();
+        let closure = resolver.resolve("app", &version).unwrap();
+        let mut closure: Vec<_> = closure.iter().map(|(n, v)| format!("{}=={}", n, v)).collect();
+        closure.sort();
+        assert_eq!(closure, vec!["app==1.0.0", "rand==0.7.3", "rand==0.8.5", "serde==1.0.0"]);
+        assert!(resolver.packages[&("rand".to_owned(), "0.7.3".to_owned())].enabled_features.contains("small"));
+        assert!(resolver.packages[&("rand".to_owned(), "0.8.5".to_owned())].enabled_features.contains("std"));
+
+        let mut dependents = Dependents::new();
+        let package = resolver.locked_package("app".to_owned(), version, &mut dependents).unwrap();
+        let mut deps: Vec<_> = package.dependencies.iter().map(|(n, v)| format!("{}=={}", n, v)).collect();
+        deps.sort();
+        deps.dedup();
+        assert_eq!(deps, vec!["rand==0.7.3", "rand==0.8.5", "serde==1.0.0"]);
+        assert_eq!(package.checksum.as_deref(), Some("aa"));
+
+        // rand 0.8 is both a normal and a build-dependency, and serde is only there because of a feature.
+        let dependent = |kind, optional| Dependent{crate_: "app==1.0.0".to_owned(), kind, optional};
+        assert_eq!(dependents["rand==0.8.5"],
+                   vec![dependent(DependencyKind::Normal, false), dependent(DependencyKind::Build, false)]);
+        assert_eq!(dependents["rand==0.7.3"], vec![dependent(DependencyKind::Normal, false)]);
+        assert_eq!(dependents["serde==1.0.0"], vec![dependent(DependencyKind::Normal, true)]);
+        assert!(!dependents.contains_key("serde_json==1.0.0"));
+        assert_eq!(dependents["serde==1.0.0"][0].to_json(),
+                   json!({"crate": "app==1.0.0", "kind": "normal", "optional": true}));
+    }
+
+    #[test]
+    fn dependency_order() {
+        let package = |name: &str, deps: &[&str]| LockedPackage{
+            name: name.to_owned(),
+            version: Version::new(1, 0, 0),
+            checksum: None,
+            dependencies: deps.iter().map(|d| (d.to_string(), Version::new(1, 0, 0))).collect(),
+        };
+        let packages = vec![
+            package("app", &["log", "serde"]),
+            package("serde", &["serde_derive", "libc"]),
+            package("serde_derive", &["proc-macro2"]),
+            package("proc-macro2", &[]),
+            package("log", &["libc"]),
+            package("libc", &[]),
+            package("serde", &["serde_derive", "libc"]),
+        ];
+        let order: Vec<_> = leaves_first(&packages).iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(order, vec!["libc", "log", "proc-macro2", "serde_derive", "serde", "app"]);
+
+        // A cycle doesn't stop the packages from being ordered.
+        let packages = vec![package("a", &["b"]), package("b", &["a"])];
+        let order: Vec<_> = leaves_first(&packages).iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(order, vec!["b", "a"]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Module for comparing the crates of a run against those of a previous run (`--compare-report`),
+//! as listed in the summaries written with `--summary-file`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+
+
+/// Crates listed in the summary of a run, with the checksums of their archives (if they've been downloaded).
+#[derive(Debug, Default)]
+pub struct Report {
+    crates: BTreeMap<String, BTreeMap<Version, Option<String>>>,
+}
+
+impl Report {
+    /// Load the report from the summary file of a run.
+    pub fn load(path: &Path) -> Result<Report, Box<dyn Error>> {
+        let json: Json = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Report::from_json(&json)?)
+    }
+
+    /// Read the report from the JSON summary of a run.
+    pub fn from_json(json: &Json) -> Result<Report, String> {
+        let crates = json.get("crates").and_then(|c| c.as_array())
+            .ok_or("the summary doesn't list any crates (it may be from an older version)")?;
+        let mut report = Report::default();
+        for crate_ in crates {
+            let name = crate_.get("name").and_then(|n| n.as_str());
+            let version = crate_.get("version").and_then(|v| v.as_str()).and_then(|v| Version::parse(v).ok());
+            let (name, version) = match (name, version) {
+                (Some(n), Some(v)) => (n, v),
+                _ => return Err(format!("invalid crate in the summary: {}", crate_)),
+            };
+            let checksum = crate_.get("checksum").and_then(|c| c.as_str()).map(|c| c.to_lowercase());
+            report.crates.entry(name.to_owned()).or_default().insert(version, checksum);
+        }
+        Ok(report)
+    }
+}
+
+
+/// Changes in the crates of a run since a previous one.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    /// Crates that weren't there before, with their versions.
+    pub added: Vec<(String, Vec<Version>)>,
+    /// Crates whose versions are different than before, with the old and the new versions.
+    pub changed: Vec<(String, Vec<Version>, Vec<Version>)>,
+    /// Crate versions whose archives have a different checksum than before, with the old and the new one.
+    pub checksums: Vec<(String, Version, String, String)>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.checksums.is_empty()
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (name, versions) in &self.added {
+            writeln!(fmt, "added: {} {}", name, join(versions))?;
+        }
+        for (name, old, new) in &self.changed {
+            writeln!(fmt, "changed: {} {} -> {}", name, join(old), join(new))?;
+        }
+        for (name, version, old, new) in &self.checksums {
+            writeln!(fmt, "checksum changed: {} {} ({} -> {})", name, version, old, new)?;
+        }
+        Ok(())
+    }
+}
+
+fn join(versions: &[Version]) -> String {
+    versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+
+/// Compare the crates of a run against those of a previous one.
+pub fn compare(old: &Report, new: &Report) -> Changes {
+    let mut changes = Changes::default();
+    for (name, new_versions) in &new.crates {
+        let old_versions = match old.crates.get(name) {
+            Some(v) => v,
+            None => {
+                changes.added.push((name.clone(), new_versions.keys().cloned().collect()));
+                continue;
+            }
+        };
+        if old_versions.keys().ne(new_versions.keys()) {
+            changes.changed.push((name.clone(), old_versions.keys().cloned().collect(),
+                                  new_versions.keys().cloned().collect()));
+        }
+        for (version, new_checksum) in new_versions {
+            if let (Some(Some(old_checksum)), Some(new_checksum)) = (old_versions.get(version), new_checksum) {
+                if old_checksum != new_checksum {
+                    changes.checksums.push((name.clone(), version.clone(),
+                                            old_checksum.clone(), new_checksum.clone()));
+                }
+            }
+        }
+    }
+    changes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use super::{Report, compare};
+
+    #[test]
+    fn changes() {
+        let old = Report::from_json(&json!({"crates": [
+            {"name": "foo", "version": "1.0.0", "checksum": "aa", "outcome": "downloaded"},
+            {"name": "bar", "version": "0.1.0", "checksum": "bb", "outcome": "downloaded"},
+            {"name": "baz", "version": "2.0.0", "checksum": null, "outcome": "skipped"},
+        ]})).unwrap();
+        let new = Report::from_json(&json!({"crates": [
+            {"name": "foo", "version": "1.1.0", "checksum": "cc", "outcome": "downloaded"},
+            {"name": "bar", "version": "0.1.0", "checksum": "dd", "outcome": "downloaded"},
+            {"name": "baz", "version": "2.0.0", "checksum": "ee", "outcome": "downloaded"},
+            {"name": "qux", "version": "0.0.1", "checksum": "ff", "outcome": "downloaded"},
+        ]})).unwrap();
+        let v = |s| Version::parse(s).unwrap();
+
+        let changes = compare(&old, &new);
+        assert_eq!(changes.added, vec![("qux".to_owned(), vec![v("0.0.1")])]);
+        assert_eq!(changes.changed, vec![("foo".to_owned(), vec![v("1.0.0")], vec![v("1.1.0")])]);
+        // The checksum of baz isn't known from before, so it cannot have changed.
+        assert_eq!(changes.checksums, vec![("bar".to_owned(), v("0.1.0"), "bb".to_owned(), "dd".to_owned())]);
+        assert!(compare(&new, &new).is_empty());
+        assert!(Report::from_json(&json!({"succeeded": 1})).is_err());
+    }
+}
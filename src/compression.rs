@@ -0,0 +1,121 @@
+//! Module for decompressing crate archives.
+//!
+//! Crate archives are gzipped tarballs, but their compression is detected from their first bytes
+//! rather than assumed, so that archives compressed with zstd (should registries adopt it)
+//! or served decompressed already (e.g. by a proxy that decoded a `Content-Encoding`) can be read too.
+
+use std::io::{self, Cursor, Read};
+
+use flate2::read::GzDecoder;
+use ruzstd::StreamingDecoder;
+
+
+/// Magic bytes that a gzip stream starts with.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// Magic bytes that a zstd frame starts with.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+/// Magic string of the POSIX (and GNU) tar headers, along with its offset in the header.
+const TAR_MAGIC: (usize, &[u8]) = (257, b"ustar");
+
+
+/// Compression of a crate archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    /// Plain tarball.
+    None,
+}
+
+impl Compression {
+    /// Detect the compression of a crate archive from its first bytes (at least 262 of them for a plain tarball),
+    /// returning `None` if they don't look like a crate archive at all.
+    pub fn detect(bytes: &[u8]) -> Option<Compression> {
+        let (tar_offset, tar_magic) = TAR_MAGIC;
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else if bytes.get(tar_offset..tar_offset + tar_magic.len()) == Some(tar_magic) {
+            Some(Compression::None)
+        } else {
+            None
+        }
+    }
+}
+
+
+/// Wrap a reader of a crate archive with one that decompresses it into a tarball,
+/// with the compression detected from the archive's first bytes.
+///
+/// Fails with an `InvalidData` error if the compression isn't recognized.
+pub fn decompress<'r, R: Read + 'r>(mut reader: R) -> io::Result<Box<dyn Read + 'r>> {
+    let (tar_offset, tar_magic) = TAR_MAGIC;
+    let mut head = vec![];
+    (&mut reader).take((tar_offset + tar_magic.len()) as u64).read_to_end(&mut head)?;
+    let compression = Compression::detect(&head).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData, "unrecognized compression of the crate archive (expected gzip or zstd)"))?;
+    let reader = Cursor::new(head).chain(reader);
+    Ok(match compression {
+        Compression::Gzip => Box::new(GzDecoder::new(reader)?),
+        Compression::Zstd => Box::new(StreamingDecoder::new(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?),
+        Compression::None => Box::new(reader),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use flate2;
+    use flate2::write::GzEncoder;
+    use tar;
+    use super::{Compression, decompress};
+
+    /// Make a tarball with a single file.
+    fn tarball() -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_path("foo-1.0.0/Cargo.toml").unwrap();
+        header.set_size(6);
+        header.set_cksum();
+        builder.append(&header, &b"[lib]\n"[..]).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    /// Wrap given content (of 256 to 65791 bytes) in a zstd frame with a single uncompressed block.
+    fn zstd_frame(content: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd];
+        // Single segment, with the content size in 2 bytes (offset by 256).
+        frame.push(0x60);
+        let size = content.len() as u32;
+        frame.extend_from_slice(&((size - 256) as u16).to_le_bytes());
+        // Last block, of the raw type.
+        let block_header = 1 | (size << 3);
+        frame.extend_from_slice(&block_header.to_le_bytes()[..3]);
+        frame.extend_from_slice(content);
+        frame
+    }
+
+    #[test]
+    fn detection() {
+        let tarball = tarball();
+        let mut gzip = GzEncoder::new(vec![], flate2::Compression::Default);
+        gzip.write_all(&tarball).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd_frame(&tarball);
+
+        assert_eq!(Compression::detect(&gzip), Some(Compression::Gzip));
+        assert_eq!(Compression::detect(&zstd), Some(Compression::Zstd));
+        assert_eq!(Compression::detect(&tarball), Some(Compression::None));
+        assert_eq!(Compression::detect(b"<html></html>"), None);
+
+        for archive in &[gzip, zstd, tarball.clone()] {
+            let mut decompressed = vec![];
+            decompress(&archive[..]).unwrap().read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, tarball);
+        }
+        assert!(decompress(&b"<html></html>"[..]).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Module for reading the dependencies of crate versions.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use semver::{Version, VersionReq};
+use serde_json::{self, Value as Json};
+use toml::{self, Value as Toml};
+
+
+/// Kind of a dependency, i.e. which section of the manifest it's declared in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// Dependency from `[dependencies]`.
+    Normal,
+    /// Dependency from `[build-dependencies]`.
+    Build,
+    /// Dependency from `[dev-dependencies]`.
+    Dev,
+}
+
+impl fmt::Display for DependencyKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match *self {
+            DependencyKind::Normal => "normal",
+            DependencyKind::Build => "build",
+            DependencyKind::Dev => "dev",
+        };
+        write!(fmt, "{}", kind)
+    }
+}
+
+/// Dependency of a crate version, as listed in the registry index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// Name of the depended-on crate (which may differ from the name it's used under).
+    pub name: String,
+    /// Requirement on the version of the depended-on crate.
+    pub req: VersionReq,
+    pub kind: DependencyKind,
+    /// Whether the dependency is only enabled by a feature.
+    pub optional: bool,
+    /// Name that the dependency is used under, if it's been renamed.
+    pub rename: Option<String>,
+    /// Features of the depended-on crate that are enabled by the dependency.
+    pub features: Vec<String>,
+    /// Whether the default features of the depended-on crate are enabled.
+    pub default_features: bool,
+}
+
+impl Dependency {
+    /// Read the dependencies of given version from a crate's file in the registry index.
+    ///
+    /// Returns `None` if the version isn't in the file, or its entry is malformed.
+    pub fn list_from_index(content: &str, version: &Version) -> Option<Vec<Dependency>> {
+        let entry = index_entry(content, version)?;
+        entry.get("deps")?.as_array()?.iter().map(|dep| {
+            // Renamed dependencies have the actual crate name under "package".
+            let key = dep.get("name")?.as_str()?;
+            let name = match dep.get("package") {
+                Some(package) => package.as_str()?,
+                None => key,
+            };
+            let req = dep.get("req")?.as_str().and_then(|r| VersionReq::parse(r).ok())?;
+            let kind = match dep.get("kind").and_then(|k| k.as_str()) {
+                None | Some("normal") => DependencyKind::Normal,
+                Some("build") => DependencyKind::Build,
+                Some("dev") => DependencyKind::Dev,
+                Some(_) => return None,
+            };
+            Some(Dependency{
+                name: name.to_owned(),
+                req,
+                kind,
+                optional: dep.get("optional").and_then(|o| o.as_bool()).unwrap_or(false),
+                rename: Some(key.to_owned()).filter(|k| k != name),
+                features: dep.get("features").and_then(|f| f.as_array())
+                    .map(|f| f.iter().filter_map(|f| f.as_str()).map(|f| f.to_owned()).collect())
+                    .unwrap_or_default(),
+                default_features: dep.get("default_features").and_then(|d| d.as_bool()).unwrap_or(true),
+            })
+        }).collect()
+    }
+
+    /// Name that the dependency is referred to by in the crate's features.
+    #[inline]
+    pub fn used_name(&self) -> &str {
+        self.rename.as_deref().unwrap_or(&self.name)
+    }
+}
+
+
+/// Read the features of given version from a crate's file in the registry index,
+/// as a map from feature names to what they enable.
+///
+/// Returns `None` if the version isn't in the file, or its entry is malformed.
+pub fn features_from_index(content: &str, version: &Version) -> Option<HashMap<String, Vec<String>>> {
+    let entry = index_entry(content, version)?;
+    let mut features = HashMap::new();
+    // Features using the newer syntax (like "dep:foo") are listed separately.
+    for key in &["features", "features2"] {
+        let map = match entry.get(key) {
+            Some(&Json::Null) | None => continue,
+            Some(m) => m.as_object()?,
+        };
+        for (name, values) in map {
+            let values = values.as_array()?.iter()
+                .map(|v| v.as_str().map(|v| v.to_owned()))
+                .collect::<Option<Vec<_>>>()?;
+            features.insert(name.clone(), values);
+        }
+    }
+    Some(features)
+}
+
+/// Read the minimum supported Rust version of given version from a crate's file in the registry index.
+///
+/// Returns `None` if the version isn't in the file, or it doesn't declare one.
+pub fn rust_version_from_index(content: &str, version: &Version) -> Option<String> {
+    let entry = index_entry(content, version)?;
+    entry.get("rust_version")?.as_str().map(|v| v.to_owned())
+}
+
+/// Find the entry of given version in a crate's file in the registry index.
+fn index_entry(content: &str, version: &Version) -> Option<Json> {
+    content.lines()
+        .filter_map(|line| serde_json::from_str::<Json>(line).ok())
+        .find(|e| e.get("vers").and_then(|v| v.as_str())
+            .and_then(|v| Version::parse(v).ok())
+            .is_some_and(|v| &v == version && v.build == version.build))
+}
+
+
+/// Check whether the crate with given Cargo.toml content is a procedural macro.
+pub fn is_proc_macro(manifest: &str) -> Result<bool, toml::de::Error> {
+    let manifest: Toml = toml::from_str(manifest)?;
+    let lib = manifest.get("lib");
+    Ok(["proc-macro", "proc_macro"].iter()
+        .any(|key| lib.and_then(|l| l.get(key)).and_then(|p| p.as_bool()) == Some(true)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::{Version, VersionReq};
+    use super::{Dependency, DependencyKind, features_from_index, is_proc_macro, rust_version_from_index};
+
+    #[test]
+    fn dependencies_from_index() {
+        let content = concat!(
+            r#"{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aa","yanked":false}"#, "\n",
+            r#"{"name":"foo","vers":"0.2.0","deps":["#,
+            r#"{"name":"bar","req":"^1.0","optional":false,"kind":"normal"},"#,
+            r#"{"name":"cc","req":"^1","optional":false,"kind":"build"},"#,
+            r#"{"name":"baz2","package":"baz","req":"=2.0.0","optional":true,"kind":null,"#,
+            r#""features":["std"],"default_features":false}"#,
+            r#"],"features":{"default":["bar/x"]},"features2":{"baz":["dep:baz2"]},"#,
+            r#""cksum":"bb","yanked":false,"rust_version":"1.60"}"#, "\n");
+
+        let deps = Dependency::list_from_index(content, &Version::parse("0.2.0").unwrap()).unwrap();
+        assert_eq!(deps.len(), 3);
+        assert_eq!(deps[0].req, VersionReq::parse("^1.0").unwrap());
+        assert_eq!(deps[1].kind, DependencyKind::Build);
+        assert_eq!(deps[2].name, "baz");
+        assert!(deps[2].optional);
+        assert_eq!(deps[2].kind, DependencyKind::Normal);
+        assert_eq!(deps[2].used_name(), "baz2");
+        assert_eq!(deps[2].features, vec!["std"]);
+        assert!(!deps[2].default_features && deps[0].default_features);
+
+        let features = features_from_index(content, &Version::parse("0.2.0").unwrap()).unwrap();
+        assert_eq!(features["default"], vec!["bar/x"]);
+        assert_eq!(features["baz"], vec!["dep:baz2"]);
+
+        assert_eq!(rust_version_from_index(content, &Version::parse("0.2.0").unwrap()).unwrap(), "1.60");
+        assert!(rust_version_from_index(content, &Version::parse("0.1.0").unwrap()).is_none());
+
+        assert!(Dependency::list_from_index(content, &Version::parse("0.1.0").unwrap()).unwrap().is_empty());
+        assert!(Dependency::list_from_index(content, &Version::parse("0.3.0").unwrap()).is_none());
+    }
+
+    #[test]
+    fn proc_macros() {
+        assert!(is_proc_macro("[package]\nname = \"foo\"\n[lib]\nproc-macro = true\n").unwrap());
+        assert!(!is_proc_macro("[package]\nname = \"foo\"\n").unwrap());
+    }
+}
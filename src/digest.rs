@@ -0,0 +1,117 @@
+//! Module for listing the files inside crate archives along with their digests.
+//!
+//! The listing can be written as JSON or CSV, for diffing and allow-listing
+//! the contents of crates without extracting them to disk.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use semver::Version;
+use serde_json;
+use tar;
+
+use cargo_download::decompress;
+use checksum;
+
+
+/// Format of the file listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// Determine the format from the extension of given path (JSON unless it's `.csv`).
+    pub fn for_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Json,
+        }
+    }
+}
+
+
+/// Regular file inside a crate archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDigest {
+    /// Name of the crate the file belongs to.
+    pub name: String,
+    pub version: Version,
+    /// Path of the file inside the archive.
+    pub path: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// SHA-256 digest of the file's content, as a hex string.
+    pub sha256: String,
+}
+
+/// List the regular files inside given crate archive, along with their digests.
+pub fn list_files(name: &str, version: &Version, crate_bytes: &[u8]) -> io::Result<Vec<FileDigest>> {
+    let mut archive = tar::Archive::new(decompress(crate_bytes)?);
+    let mut files = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let size = entry.header().size()?;
+        let sha256 = checksum::sha256_reader(entry)?;
+        files.push(FileDigest{name: name.to_owned(), version: version.clone(), path, size, sha256});
+    }
+    Ok(files)
+}
+
+/// Write the listing of given files to given path.
+pub fn write_listing(path: &Path, files: &[FileDigest]) -> io::Result<()> {
+    let mut out = vec![];
+    match Format::for_path(path) {
+        Format::Json => {
+            let json: Vec<_> = files.iter().map(|f| json!({
+                "crate": f.name,
+                "version": f.version.to_string(),
+                "path": f.path,
+                "size": f.size,
+                "sha256": f.sha256,
+            })).collect();
+            serde_json::to_writer_pretty(&mut out, &json)?;
+            writeln!(&mut out)?;
+        }
+        Format::Csv => {
+            writeln!(&mut out, "crate,version,path,size,sha256")?;
+            for f in files {
+                writeln!(&mut out, "{},{},{},{},{}",
+                    f.name, f.version, csv_field(&f.path), f.size, f.sha256)?;
+            }
+        }
+    }
+    fs::write(path, out)
+}
+
+/// Quote a CSV field if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::{Format, csv_field};
+
+    #[test]
+    fn formats() {
+        assert_eq!(Format::for_path(Path::new("files.CSV")), Format::Csv);
+        assert_eq!(Format::for_path(Path::new("files.json")), Format::Json);
+        assert_eq!(Format::for_path(Path::new("files")), Format::Json);
+
+        assert_eq!(csv_field("src/lib.rs"), "src/lib.rs");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+}
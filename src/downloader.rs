@@ -0,0 +1,515 @@
+//! Module with the API for downloading crates from a registry,
+//! for programs that embed the functionality of the command line tool.
+//!
+//! Unlike the tool, it never exits the process:
+//! every failure is reported as a `DownloadError`.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use reqwest;
+use semver::Version;
+use serde_json::{self, Value as Json};
+use sha2::{Digest, Sha256};
+use tar;
+
+use cancel::{CancellableReader, CancellationToken};
+use compression::decompress;
+use index::{CRATES_INDEX_ROOT, download_url, index_path};
+use observer::{ObservedReader, Observer, Phase, ProgressObserver};
+use resolve::{ResolvedVersion, VersionFilter, resolve_versions};
+use spec::Crate;
+
+
+/// Downloader of crates from a registry with a sparse index (crates.io by default).
+#[derive(Debug)]
+pub struct Downloader {
+    client: reqwest::Client,
+    /// Root URL of the registry's sparse index, without the `sparse+` prefix.
+    index_root: String,
+    /// Which kinds of versions the crates can be resolved to.
+    filter: VersionFilter,
+    /// Template of the download URLs from the index's `config.json`, once it's been fetched.
+    dl_template: Mutex<Option<String>>,
+    /// Observer of the progress, if any.
+    observer: Observer,
+    /// Token that cancels the downloads.
+    cancellation: CancellationToken,
+}
+
+impl Downloader {
+    /// Create a downloader for the registry whose sparse index is at given root URL
+    /// (e.g. "https://index.crates.io", optionally with the `sparse+` prefix).
+    pub fn new<S: AsRef<str>>(index_root: S) -> Downloader {
+        let index_root = index_root.as_ref();
+        let index_root = index_root.strip_prefix("sparse+").unwrap_or(index_root);
+        Downloader{
+            client: reqwest::Client::new(),
+            index_root: index_root.trim_end_matches('/').to_owned(),
+            filter: VersionFilter::default(),
+            dl_template: Mutex::new(None),
+            observer: Observer::default(),
+            cancellation: CancellationToken::default(),
+        }
+    }
+
+    /// Create a downloader for crates.io.
+    #[inline]
+    pub fn crates_io() -> Downloader {
+        Downloader::new(CRATES_INDEX_ROOT)
+    }
+
+    /// Set which kinds of versions the crates can be resolved to.
+    ///
+    /// By default, yanked versions and pre-releases are excluded, like the command line tool does.
+    /// Crates given with an exact version are resolved to it even if it's been yanked.
+    #[inline]
+    pub fn set_filter(&mut self, filter: VersionFilter) {
+        self.filter = filter;
+    }
+
+    /// Set the observer to be notified of the progress of resolving and downloading crates.
+    #[inline]
+    pub fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.observer = Observer::new(observer);
+    }
+
+    /// Set the token that cancels the downloads once it's cancelled (from any thread),
+    /// failing them with `DownloadError::Cancelled`.
+    #[inline]
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    /// Resolve given crate to its newest version that matches the requirement and passes the filter.
+    pub fn resolve(&self, crate_: &Crate) -> Result<ResolvedCrate, DownloadError> {
+        self.observer.phase(&crate_.to_string(), Phase::Resolving);
+        let index_url = format!("{}/{}", self.index_root, index_path(crate_.name()));
+        let index_file = self.get(&index_url)?.text()?;
+        let resolved = resolve_in_index(crate_, self.filter, index_url, &index_file)?;
+        warn_if_yanked(&self.observer, &resolved);
+        Ok(resolved)
+    }
+
+    /// Download the archive of given crate (a gzipped tarball) into given writer,
+    /// returning its size in bytes.
+    ///
+    /// The archive is verified against its checksum from the registry index,
+    /// but only once it's been written in full, so on a mismatch,
+    /// whatever the writer received should be discarded.
+    pub fn download<W: Write>(&self, crate_: &ResolvedCrate, output: &mut W) -> Result<u64, DownloadError> {
+        self.observer.phase(&crate_.to_string(), Phase::Downloading);
+        let template = self.dl_template()?;
+        let url = download_url(&template, &crate_.name, &crate_.version, &crate_.checksum);
+        let response = self.get(&url)?;
+        let total = response.content_length();
+        let reader = ObservedReader::new(response, self.observer.clone(), crate_.to_string(), total);
+        let mut reader = CancellableReader::new(reader, self.cancellation.clone());
+        let size = self.unless_cancelled(copy_verified(&mut reader, output, crate_))?;
+        self.observer.phase(&crate_.to_string(), Phase::Finished);
+        Ok(size)
+    }
+
+    /// Download the archive of given crate into the file at given path, returning its size in bytes.
+    ///
+    /// The archive is written into a temporary file next to it, which is only moved into place
+    /// once it's been verified against its checksum, and removed if the download fails or is cancelled.
+    pub fn download_to_file<P: AsRef<Path>>(&self, crate_: &ResolvedCrate,
+                                            path: P) -> Result<u64, DownloadError> {
+        let path = path.as_ref();
+        let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} isn't a path of a file", path.display())))?;
+        let partial = path.with_file_name(format!(".{}.partial", file_name.to_string_lossy()));
+        let downloaded = File::create(&partial).map_err(DownloadError::from).and_then(|mut file| {
+            let size = self.download(crate_, &mut file)?;
+            file.sync_all()?;
+            fs::rename(&partial, path)?;
+            Ok(size)
+        });
+        if downloaded.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        downloaded
+    }
+
+    /// Download the archive of given crate and extract it into given directory,
+    /// returning the path of the crate's own directory in there (`<name>-<version>`).
+    ///
+    /// The archive is extracted as it's being received, without holding all of it in memory,
+    /// into a staging directory that's only moved into place once the archive
+    /// has been verified against its checksum from the registry index.
+    pub fn extract<P: AsRef<Path>>(&self, crate_: &ResolvedCrate, dir: P) -> Result<PathBuf, DownloadError> {
+        let dir = dir.as_ref();
+        self.observer.phase(&crate_.to_string(), Phase::Extracting);
+        let template = self.dl_template()?;
+        let url = download_url(&template, &crate_.name, &crate_.version, &crate_.checksum);
+        let response = self.get(&url)?;
+        let total = response.content_length();
+        let response = ObservedReader::new(response, self.observer.clone(), crate_.to_string(), total);
+        let response = CancellableReader::new(response, self.cancellation.clone());
+
+        let crate_dir_name = format!("{}-{}", crate_.name, crate_.version);
+        let staging_dir = dir.join(format!(".{}.partial", crate_dir_name));
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        fs::create_dir_all(&staging_dir)?;
+        let moved = unpack_verified(response, &staging_dir, crate_).and_then(|()| {
+            // Archives have a single top-level directory named $CRATE-$VERSION.
+            let staged = staging_dir.join(&crate_dir_name);
+            if !staged.is_dir() {
+                return Err(DownloadError::Malformed(url));
+            }
+            let crate_dir = dir.join(&crate_dir_name);
+            if crate_dir.exists() {
+                fs::remove_dir_all(&crate_dir)?;
+            }
+            fs::rename(staged, &crate_dir)?;
+            Ok(crate_dir)
+        });
+        let _ = fs::remove_dir_all(&staging_dir);
+        let moved = self.unless_cancelled(moved);
+        if moved.is_ok() {
+            self.observer.phase(&crate_.to_string(), Phase::Finished);
+        }
+        moved
+    }
+
+    /// Template of the download URLs, fetched from the index's `config.json` the first time it's needed.
+    fn dl_template(&self) -> Result<String, DownloadError> {
+        let mut dl_template = self.dl_template.lock().unwrap();
+        if let Some(ref template) = *dl_template {
+            return Ok(template.clone());
+        }
+        let config_url = format!("{}/config.json", self.index_root);
+        let config = self.get(&config_url)?.text()?;
+        let template = dl_template_in_config(config_url, &config)?;
+        *dl_template = Some(template.clone());
+        Ok(template)
+    }
+
+    /// Make a GET request to given URL, failing unless it succeeds.
+    fn get(&self, url: &str) -> Result<reqwest::Response, DownloadError> {
+        if self.cancellation.is_cancelled() {
+            return Err(DownloadError::Cancelled);
+        }
+        let response = self.client.get(url).send()?;
+        if !response.status().is_success() {
+            return Err(DownloadError::Status(url.to_owned(), response.status().as_u16()));
+        }
+        Ok(response)
+    }
+
+    /// Report the failure of an operation as the cancellation that caused it, if it's been cancelled.
+    fn unless_cancelled<T>(&self, result: Result<T, DownloadError>) -> Result<T, DownloadError> {
+        match result {
+            Err(_) if self.cancellation.is_cancelled() => Err(DownloadError::Cancelled),
+            result => result,
+        }
+    }
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Downloader::crates_io()
+    }
+}
+
+
+/// Crate resolved to an exact version in the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedCrate {
+    pub name: String,
+    pub version: Version,
+    /// SHA-256 checksum of the crate's archive, as a hex string.
+    pub checksum: String,
+    /// Whether the version has been yanked from the registry.
+    pub yanked: bool,
+}
+
+impl fmt::Display for ResolvedCrate {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}=={}", self.name, self.version)
+    }
+}
+
+
+/// Resolve given crate to its newest version in the crate's file from the registry index
+/// (fetched from given URL) that matches the requirement and passes the filter.
+///
+/// Crates given with an exact version are resolved to it even if it's been yanked.
+pub fn resolve_in_index(crate_: &Crate, filter: VersionFilter,
+                        index_url: String, index_file: &str) -> Result<ResolvedCrate, DownloadError> {
+    let versions = ResolvedVersion::list_from_index(index_file)
+        .ok_or_else(|| DownloadError::Malformed(index_url.clone()))?;
+    let exact = crate_.exact_version().is_some();
+    let filter = VersionFilter{yanked: filter.yanked || exact, ..filter};
+    let resolved = resolve_versions(crate_, versions, filter).next()
+        .ok_or_else(|| DownloadError::NoMatchingVersion(crate_.to_string()))?;
+    let checksum = resolved.checksum.ok_or(DownloadError::Malformed(index_url))?;
+    Ok(ResolvedCrate{
+        name: crate_.name().to_owned(),
+        version: resolved.version,
+        checksum,
+        yanked: resolved.yanked,
+    })
+}
+
+/// Warn the observer if given crate has been resolved to a yanked version
+/// (which only happens if it's been asked for, explicitly or by the filter).
+pub fn warn_if_yanked(observer: &Observer, crate_: &ResolvedCrate) {
+    if crate_.yanked {
+        observer.warning(&crate_.to_string(), &format!("version {} of crate `{}` has been yanked",
+            crate_.version, crate_.name));
+    }
+}
+
+/// Read the template of the download URLs from the index's `config.json` (fetched from given URL).
+pub fn dl_template_in_config(config_url: String, config: &str) -> Result<String, DownloadError> {
+    let config: Json = serde_json::from_str(config)
+        .map_err(|_| DownloadError::Malformed(config_url.clone()))?;
+    config.get("dl").and_then(|d| d.as_str())
+        .map(|t| t.to_owned())
+        .ok_or(DownloadError::Malformed(config_url))
+}
+
+/// Copy the archive of given crate from the reader to the writer,
+/// checking its SHA-256 checksum at the end.
+fn copy_verified<R: Read, W: Write>(reader: &mut R, writer: &mut W,
+                                    crate_: &ResolvedCrate) -> Result<u64, DownloadError> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    check_checksum(crate_, hasher)?;
+    Ok(size)
+}
+
+/// Unpack the archive of given crate from the reader into given directory as it's being read,
+/// checking its SHA-256 checksum at the end.
+///
+/// What's been unpacked should be discarded if the checksum doesn't match.
+fn unpack_verified<R: Read>(reader: R, dir: &Path, crate_: &ResolvedCrate) -> Result<(), DownloadError> {
+    let ((), _, actual) = unpack_hashed(reader, |archive| archive.unpack(dir))?;
+    if !actual.eq_ignore_ascii_case(&crate_.checksum) {
+        return Err(DownloadError::ChecksumMismatch(crate_.to_string(), crate_.checksum.clone(), actual));
+    }
+    Ok(())
+}
+
+/// Unpack a crate archive from the reader with given function as it's being read,
+/// returning what the function returns along with the size of the whole archive
+/// and its SHA-256 checksum (as a hex string).
+///
+/// The checksum is only known at the end, so what's been unpacked should be discarded
+/// if it isn't the expected one.
+pub fn unpack_hashed<R, T, F>(reader: R, unpack: F) -> io::Result<(T, u64, String)>
+    where R: Read, F: for<'a> FnOnce(&mut tar::Archive<Box<dyn Read + 'a>>) -> io::Result<T>
+{
+    let mut reader = HashingReader::new(reader);
+    let unpacked = {
+        let mut archive = tar::Archive::new(decompress(&mut reader)?);
+        let unpacked = unpack(&mut archive)?;
+        // The compressed stream goes on past the end of the tarball, and all of it is checksummed.
+        io::copy(&mut archive.into_inner(), &mut io::sink())?;
+        unpacked
+    };
+    io::copy(&mut reader, &mut io::sink())?;
+    let (size, checksum) = reader.finish();
+    Ok((unpacked, size, checksum))
+}
+
+/// Reader that feeds everything read through it to a SHA-256 hasher.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader{inner, hasher: Sha256::new(), size: 0}
+    }
+
+    /// Finish hashing, returning the number of bytes read and their SHA-256 checksum (as a hex string).
+    pub fn finish(self) -> (u64, String) {
+        (self.size, self.hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+/// Check that the archive of given crate, whose content has been fed to the hasher,
+/// matches the checksum from the registry index.
+pub fn check_checksum(crate_: &ResolvedCrate, hasher: Sha256) -> Result<(), DownloadError> {
+    let actual: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    if !actual.eq_ignore_ascii_case(&crate_.checksum) {
+        return Err(DownloadError::ChecksumMismatch(crate_.to_string(), crate_.checksum.clone(), actual));
+    }
+    Ok(())
+}
+
+
+/// Error that can occur while resolving, downloading, or extracting a crate.
+#[derive(Debug)]
+pub enum DownloadError {
+    /// Error while performing a request.
+    Request(reqwest::Error),
+    /// Given URL responded with given (unsuccessful) HTTP status.
+    Status(String, u16),
+    /// Malformed response from given URL.
+    Malformed(String),
+    /// No version of given crate matches the requirement.
+    NoMatchingVersion(String),
+    /// Archive of given crate version doesn't have the expected checksum from the registry index,
+    /// but the other one given.
+    ChecksumMismatch(String, String, String),
+    /// Error while writing the archive, or extracting it.
+    Io(io::Error),
+    /// The download has been cancelled with the downloader's `CancellationToken`.
+    Cancelled,
+}
+impl From<reqwest::Error> for DownloadError {
+    fn from(input: reqwest::Error) -> Self {
+        DownloadError::Request(input)
+    }
+}
+impl From<io::Error> for DownloadError {
+    fn from(input: io::Error) -> Self {
+        DownloadError::Io(input)
+    }
+}
+impl Error for DownloadError {
+    fn description(&self) -> &str { "failed to download crate" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            DownloadError::Request(ref e) => Some(e),
+            DownloadError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for DownloadError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DownloadError::Request(ref e) => write!(fmt, "{}", e),
+            DownloadError::Status(ref url, status) => write!(fmt, "{} responded with {}", url, status),
+            DownloadError::Malformed(ref url) => write!(fmt, "malformed response from {}", url),
+            DownloadError::NoMatchingVersion(ref crate_) =>
+                write!(fmt, "no version of crate {} found", crate_),
+            DownloadError::ChecksumMismatch(ref crate_, ref expected, ref actual) =>
+                write!(fmt, "checksum of the archive of crate `{}` is {}, but the registry says {}",
+                    crate_, actual, expected),
+            DownloadError::Io(ref e) => write!(fmt, "{}", e),
+            DownloadError::Cancelled => write!(fmt, "the download has been cancelled"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::str::FromStr;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use semver::Version;
+    use sha2::{Digest, Sha256};
+    use tar;
+    use resolve::VersionFilter;
+    use spec::Crate;
+    use super::{DownloadError, ResolvedCrate, copy_verified, resolve_in_index, unpack_hashed, unpack_verified};
+
+    // SHA-256 of "hello\n".
+    const HELLO_SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    #[test]
+    fn verified_copy() {
+        let crate_ = ResolvedCrate{
+            name: "hello".into(),
+            version: Version::parse("1.0.0").unwrap(),
+            checksum: HELLO_SHA256.into(),
+            yanked: false,
+        };
+        let mut output = vec![];
+        assert_eq!(copy_verified(&mut &b"hello\n"[..], &mut output, &crate_).unwrap(), 6);
+        assert_eq!(output, b"hello\n");
+
+        match copy_verified(&mut &b"hell0\n"[..], &mut vec![], &crate_) {
+            Err(DownloadError::ChecksumMismatch(ref c, _, _)) => assert_eq!(c, "hello==1.0.0"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verified_unpacking() {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::Default));
+        let mut header = tar::Header::new_gnu();
+        header.set_path("hello-1.0.0/hello.txt").unwrap();
+        header.set_size(6);
+        header.set_cksum();
+        builder.append(&header, &b"hello\n"[..]).unwrap();
+        let crate_bytes = builder.into_inner().unwrap().finish().unwrap();
+        let crate_ = ResolvedCrate{
+            name: "hello".into(),
+            version: Version::parse("1.0.0").unwrap(),
+            checksum: Sha256::digest(&crate_bytes).iter().map(|b| format!("{:02x}", b)).collect(),
+            yanked: false,
+        };
+
+        let dir = env::temp_dir().join(format!("cargo-download-test-downloader-{}", process::id()));
+        unpack_verified(&crate_bytes[..], &dir, &crate_).unwrap();
+        assert_eq!(fs::read(dir.join("hello-1.0.0/hello.txt")).unwrap(), b"hello\n");
+
+        let mut corrupted = crate_bytes.clone();
+        corrupted.push(0);
+        match unpack_verified(&corrupted[..], &dir, &crate_) {
+            Err(DownloadError::ChecksumMismatch(..)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Whatever follows the tarball is hashed too.
+        let (count, size, checksum) = unpack_hashed(&corrupted[..], |archive| Ok(archive.entries()?.count()))
+            .unwrap();
+        assert_eq!((count, size), (1, corrupted.len() as u64));
+        assert_eq!(checksum, Sha256::digest(&corrupted).iter().map(|b| format!("{:02x}", b)).collect::<String>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolution_in_index() {
+        let index_file = concat!(
+            r#"{"name":"foo","vers":"1.0.0","deps":[],"cksum":"aa","yanked":false}"#, "\n",
+            r#"{"name":"foo","vers":"1.1.0","deps":[],"cksum":"bb","yanked":true}"#, "\n");
+        let resolve = |spec| resolve_in_index(&Crate::from_str(spec).unwrap(), VersionFilter::default(),
+                                              "index".into(), index_file);
+
+        let newest = resolve("foo=1").unwrap();
+        assert_eq!((newest.version.to_string(), newest.checksum), ("1.0.0".into(), "aa".into()));
+        // Yanked versions are only resolved to when asked for explicitly.
+        assert!(resolve("foo==1.1.0").unwrap().yanked);
+        match resolve("foo=2") {
+            Err(DownloadError::NoMatchingVersion(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
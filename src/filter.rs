@@ -0,0 +1,192 @@
+//! Module for extracting only some of the files of a crate archive (`--filter`),
+//! or extracting them without the archive's top-level directory (`--strip-prefix`).
+//!
+//! The glob patterns are matched against the paths of the files relative to the crate's root
+//! (like `Cargo.toml` or `src/**/*.rs`), with `*` not matching across directories.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use tar;
+
+
+/// How the patterns are matched against the paths of the files.
+const MATCH_OPTIONS: MatchOptions = MatchOptions{
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+
+/// Check whether given path of a file in a crate archive (including the crate's top-level directory)
+/// matches any of the patterns.
+pub fn is_selected(path: &Path, patterns: &[Pattern]) -> bool {
+    let path = strip_top_level(path);
+    // Nothing is left of the top-level directory itself, which a wildcard would match.
+    !path.as_os_str().is_empty() && patterns.iter().any(|p| p.matches_path_with(&path, MATCH_OPTIONS))
+}
+
+/// Strip the top-level directory from given path of a file in a crate archive.
+pub fn strip_top_level(path: &Path) -> PathBuf {
+    // Archives have a single top-level directory named $CRATE-$VERSION.
+    path.components().skip_while(|c| *c == Component::CurDir).skip(1).collect()
+}
+
+/// Unpack the files of given archive whose paths match any of the patterns (or all of them if there are none)
+/// into given directory, along with the directories they're in, returning how many files have been unpacked.
+///
+/// With `strip_prefix`, the archive's top-level directory is left out, so the files end up right in `dir`.
+pub fn unpack<R: Read>(archive: &mut tar::Archive<R>, dir: &Path, patterns: &[Pattern],
+                       strip_prefix: bool) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let is_dir = entry.header().entry_type().is_dir();
+        if !patterns.is_empty() && (is_dir || !is_selected(&path, patterns)) {
+            continue;
+        }
+        let unpacked = if strip_prefix {
+            // Like `unpack_in`, refuse to write outside of the directory.
+            let path = strip_top_level(&path);
+            if path.as_os_str().is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+                continue;
+            }
+            let path = dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&path)?;
+            true
+        } else {
+            // This refuses to write outside of the directory, just like unpacking the whole archive.
+            entry.unpack_in(dir)?
+        };
+        if unpacked && !is_dir {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::{Path, PathBuf};
+    use glob::Pattern;
+    use tar;
+    use super::{is_selected, strip_top_level, unpack};
+
+    /// Build a tarball of given paths (with a trailing slash for directories), each file containing "foo".
+    ///
+    /// The paths are put into the headers as they are, so that malicious ones can be given too.
+    fn tarball(paths: &[&str]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for path in paths {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            if path.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, &b""[..]).unwrap();
+            } else {
+                header.set_size(3);
+                header.set_cksum();
+                builder.append(&header, &b"foo"[..]).unwrap();
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cargo-download-test-filter-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn selection() {
+        let patterns: Vec<_> = ["Cargo.toml", "src/*.rs"].iter().map(|p| Pattern::new(p).unwrap()).collect();
+        let selected = |path| is_selected(Path::new(path), &patterns);
+        assert!(selected("foo-1.0.0/Cargo.toml"));
+        assert!(selected("./foo-1.0.0/src/lib.rs"));
+        assert!(!selected("foo-1.0.0/Cargo.toml.orig"));
+        assert!(!selected("foo-1.0.0/src/bin/main.rs"));
+        assert!(!selected("foo-1.0.0/tests/Cargo.toml"));
+
+        let patterns = vec![Pattern::new("src/**/*.rs").unwrap()];
+        assert!(is_selected(Path::new("foo-1.0.0/src/bin/main.rs"), &patterns));
+    }
+
+    #[test]
+    fn selection_edge_cases() {
+        let patterns = vec![Pattern::new("*").unwrap()];
+        // Hidden files are matched by wildcards, but files in subdirectories aren't.
+        assert!(is_selected(Path::new("foo-1.0.0/.cargo_vcs_info.json"), &patterns));
+        assert!(!is_selected(Path::new("foo-1.0.0/src/lib.rs"), &patterns));
+        // Nothing is left to match of the top-level directory itself (nor of a file in its place).
+        assert!(!is_selected(Path::new("foo-1.0.0"), &patterns));
+        // The patterns are case-sensitive, and no patterns select nothing.
+        assert!(!is_selected(Path::new("foo-1.0.0/Cargo.toml"), &[Pattern::new("cargo.toml").unwrap()]));
+        assert!(!is_selected(Path::new("foo-1.0.0/Cargo.toml"), &[]));
+
+        assert_eq!(strip_top_level(Path::new("./foo-1.0.0/src/lib.rs")), Path::new("src/lib.rs"));
+        assert_eq!(strip_top_level(Path::new("foo-1.0.0")), Path::new(""));
+    }
+
+    #[test]
+    fn stripped_unpacking() {
+        let tarball = tarball(&["foo-1.0.0/Cargo.toml", "foo-1.0.0/src/lib.rs"]);
+        let dir = temp_dir("strip");
+        let count = unpack(&mut tar::Archive::new(&tarball[..]), &dir, &[], true).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(fs::read(dir.join("Cargo.toml")).unwrap(), b"foo");
+        assert!(dir.join("src/lib.rs").is_file());
+        assert!(!dir.join("foo-1.0.0").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filtered_unpacking() {
+        let tarball = tarball(&[
+            "foo-1.0.0/", "foo-1.0.0/Cargo.toml", "foo-1.0.0/src/", "foo-1.0.0/src/lib.rs", "foo-1.0.0/README.md",
+        ]);
+        let patterns = vec![Pattern::new("src/*.rs").unwrap()];
+        let dir = temp_dir("select");
+        // Only the selected files are unpacked, along with the directories they're in (which aren't counted).
+        let count = unpack(&mut tar::Archive::new(&tarball[..]), &dir, &patterns, false).unwrap();
+        assert_eq!(count, 1);
+        assert!(dir.join("foo-1.0.0/src/lib.rs").is_file());
+        assert!(!dir.join("foo-1.0.0/Cargo.toml").exists());
+        assert!(!dir.join("foo-1.0.0/README.md").exists());
+
+        // A pattern that matches nothing unpacks nothing.
+        let patterns = vec![Pattern::new("benches/*.rs").unwrap()];
+        let empty = temp_dir("select-none");
+        assert_eq!(unpack(&mut tar::Archive::new(&tarball[..]), &empty, &patterns, false).unwrap(), 0);
+        assert_eq!(fs::read_dir(&empty).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&empty).unwrap();
+    }
+
+    #[test]
+    fn stripped_unpacking_stays_inside() {
+        let tarball = tarball(&["foo-1.0.0/", "foo-1.0.0/../evil.rs", "foo-1.0.0/src/../../evil.rs", "lib.rs"]);
+        let dir = temp_dir("strip-inside");
+        let root = dir.join("out");
+        // The top-level directory and the files escaping the directory are skipped,
+        // while a file without a top-level directory is too short to strip.
+        let count = unpack(&mut tar::Archive::new(&tarball[..]), &root, &[], true).unwrap();
+        assert_eq!(count, 0);
+        assert!(!dir.join("evil.rs").exists());
+        assert!(!root.join("evil.rs").exists());
+        assert!(!root.join("lib.rs").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Module for reading registry indices over the git protocol,
+//! where the index is a git repository with a file of index entries for every crate.
+//!
+//! The repository is fetched (shallowly, at its current commit) into the program's state directory
+//! once per run, and the files are then read right from the fetched commit, without a checkout.
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use cache::state_dir;
+use cargo_download::index_path;
+use index_sync::git;
+use sparse_index::IndexError;
+
+
+/// URL of the git repository with the index of crates.io.
+pub const CRATES_IO_GIT_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+
+/// Commit of the index that's been fetched last, in the local repository.
+const FETCHED: &str = "FETCH_HEAD";
+
+
+lazy_static! {
+    /// Local repositories that have been fetched into during this run.
+    ///
+    /// The lock is held while the repositories are used, so that git doesn't run concurrently on them.
+    static ref FETCHED_REPOS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+
+/// Registry index served over the git protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitIndex {
+    /// URL of the index's git repository.
+    url: String,
+    /// Local (bare) repository that the index is fetched into.
+    dir: PathBuf,
+}
+
+impl GitIndex {
+    /// The git index of crates.io.
+    pub fn crates_io() -> GitIndex {
+        GitIndex::new(CRATES_IO_GIT_INDEX)
+    }
+
+    /// Create the index whose repository is at given URL.
+    pub fn new(url: &str) -> GitIndex {
+        let url = url.trim_end_matches('/').to_owned();
+        // Name the local repository after the URL, in a readable way.
+        let dir_name: String = url.split("://").last().unwrap_or(&url).chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c } else { '-' })
+            .collect();
+        let root = state_dir().unwrap_or_else(|| env::temp_dir().join("cargo-download"));
+        GitIndex{dir: root.join("git-index").join(dir_name), url}
+    }
+
+    /// URL of the index's git repository.
+    #[inline]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Fetch the file with the index entries of given crate, returning its URL and content.
+    ///
+    /// The index is only fetched if it hasn't been during this run already (nor offline).
+    pub fn fetch_file(&self, offline: bool, name: &str) -> Result<(String, String), IndexError> {
+        let path = index_path(name);
+        debug!("Reading index entries of crate `{}` from the git index at {}", name, self.url);
+        let content = self.read(offline, &path)?;
+        Ok((format!("{}/{}", self.url, path), content))
+    }
+
+    /// Fetch the index's `config.json`, returning its content.
+    pub fn fetch_config(&self, offline: bool) -> Result<String, IndexError> {
+        debug!("Reading the configuration of the git index at {}", self.url);
+        self.read(offline, "config.json")
+    }
+
+    /// Read the file at given path in the index, fetching the index first if needed.
+    fn read(&self, offline: bool, path: &str) -> Result<String, IndexError> {
+        let mut fetched = FETCHED_REPOS.lock().unwrap();
+        if !fetched.contains(&self.dir) {
+            self.fetch(offline).map_err(|e| IndexError::Git(self.url.clone(), e))?;
+            fetched.insert(self.dir.clone());
+        }
+        git(&self.dir, &["cat-file", "blob", &format!("{}:{}", FETCHED, path)])
+            .map_err(|_| IndexError::NotFound(format!("{}/{}", self.url, path)))
+    }
+
+    /// Fetch the current commit of the index into the local repository.
+    ///
+    /// Offline, the commit fetched before is used, if there's one.
+    fn fetch(&self, offline: bool) -> io::Result<()> {
+        if offline {
+            if self.dir.join(FETCHED).is_file() {
+                return Ok(());
+            }
+            return Err(io::Error::other("it hasn't been fetched before, and network access is disabled"));
+        }
+        let created = !self.dir.join("HEAD").is_file();
+        if created {
+            fs::create_dir_all(&self.dir)?;
+            git(&self.dir, &["init", "--bare", "--quiet"])?;
+        }
+        info!("Fetching the git index at {} into {}/", self.url, self.dir.display());
+        let fetched = git(&self.dir, &["fetch", "--quiet", "--depth", "1", &self.url, "HEAD"]);
+        if fetched.is_err() && created {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+        fetched.map(|_| ())
+    }
+}
+
+impl fmt::Display for GitIndex {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.url)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use index_sync::git;
+    use sparse_index::IndexError;
+    use super::GitIndex;
+
+    #[test]
+    fn reading_files() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-git-index-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = dir.join("index");
+        fs::create_dir_all(repo.join("3/f")).unwrap();
+        fs::write(repo.join("config.json"), r#"{"dl":"https://example.com/dl"}"#).unwrap();
+        fs::write(repo.join("3/f/foo"), "{\"name\":\"foo\",\"vers\":\"1.0.0\"}\n").unwrap();
+        git(&repo, &["init", "--quiet"]).unwrap();
+        git(&repo, &["add", "."]).unwrap();
+        git(&repo, &["-c", "user.name=test", "-c", "user.email=test@example.com",
+                     "commit", "--quiet", "-m", "index"]).unwrap();
+
+        let url = format!("file://{}", repo.display());
+        let index = GitIndex{url: url.clone(), dir: dir.join("fetched")};
+        assert!(index.fetch_config(true).is_err());
+        assert_eq!(index.fetch_config(false).unwrap(), r#"{"dl":"https://example.com/dl"}"#);
+        let (file_url, content) = index.fetch_file(false, "Foo").unwrap();
+        assert_eq!(file_url, format!("{}/3/f/foo", url));
+        assert!(content.contains("1.0.0"));
+        match index.fetch_file(false, "bar") {
+            Err(IndexError::NotFound(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert_eq!(GitIndex::new("https://github.com/rust-lang/crates.io-index/").url(),
+                   "https://github.com/rust-lang/crates.io-index");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
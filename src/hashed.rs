@@ -0,0 +1,73 @@
+//! Module for content-addressed naming of crate archives.
+//!
+//! With `--name-by-hash`, archives are named after their SHA-256 digest,
+//! and a mapping file in the same directory records which crate version
+//! each of them contains.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use semver::Version;
+use serde_json::{self, Map, Value as Json};
+
+
+/// Name of the file that maps content-addressed archive names to crate versions.
+pub const MAPPING_FILE: &str = "sha256-map.json";
+
+
+/// Name of the archive file with given SHA-256 digest (as a hex string).
+pub fn file_name(digest: &str) -> String {
+    format!("sha256-{}.crate", digest.to_lowercase())
+}
+
+/// Record in the mapping file of given directory that the archive
+/// with given file name contains given crate version.
+///
+/// Existing entries of the mapping file are preserved.
+pub fn record(dir: &Path, file_name: &str, name: &str, version: &Version) -> io::Result<()> {
+    let path = dir.join(MAPPING_FILE);
+    let mut mapping = match fs::read(&path) {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(Json::Object(m)) => m,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} is not a JSON object", path.display()))),
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+        Err(e) => return Err(e),
+    };
+    mapping.insert(file_name.to_owned(), json!({
+        "name": name,
+        "version": version.to_string(),
+    }));
+    let json = serde_json::to_string_pretty(&Json::Object(mapping))?;
+    fs::write(&path, json)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use semver::Version;
+    use serde_json::{self, Value as Json};
+    use super::{MAPPING_FILE, file_name, record};
+
+    #[test]
+    fn mapping_accumulates() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-hashed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = file_name("ABCD");
+        assert_eq!(first, "sha256-abcd.crate");
+        record(&dir, &first, "foo", &Version::parse("1.0.0").unwrap()).unwrap();
+        record(&dir, &file_name("ef01"), "bar", &Version::parse("0.2.0").unwrap()).unwrap();
+
+        let mapping: Json = serde_json::from_slice(&fs::read(dir.join(MAPPING_FILE)).unwrap()).unwrap();
+        assert_eq!(mapping["sha256-abcd.crate"]["name"], "foo");
+        assert_eq!(mapping["sha256-ef01.crate"]["version"], "0.2.0");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+//! Module describing the layout of registry indices.
+
+use semver::Version;
+
+
+/// Root of the crates.io index, served over the sparse HTTP protocol.
+pub const CRATES_INDEX_ROOT: &str = "https://index.crates.io";
+
+
+/// Path of the file with given crate's entries, relative to the index root.
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    format!("{}/{}", index_prefix(&name), name)
+}
+
+/// Directory of the file with given crate's entries, relative to the index root,
+/// keeping the case of the crate name.
+pub fn index_prefix(name: &str) -> String {
+    match name.len() {
+        1 => "1".to_owned(),
+        2 => "2".to_owned(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Format a crate version for use as a URL path segment or in a file name.
+///
+/// The `+` preceding build metadata must be escaped, because some servers
+/// (notably S3, which backs crates.io downloads) would decode it as a space.
+/// File names are escaped the same way, so that they match the download URLs.
+pub fn url_version(version: &Version) -> String {
+    version.to_string().replace('+', "%2B")
+}
+
+/// Fill in the download URL template from the registry's `config.json` for given crate version.
+///
+/// Like in Cargo, a template without any markers is the root that
+/// `/$CRATE/$VERSION/download` is appended to.
+pub fn download_url(template: &str, name: &str, version: &Version, checksum: &str) -> String {
+    let version = url_version(version);
+    const MARKERS: &[&str] = &["{crate}", "{version}", "{prefix}", "{lowerprefix}", "{sha256-checksum}"];
+    if !MARKERS.iter().any(|m| template.contains(m)) {
+        return format!("{}/{}/{}/download", template.trim_end_matches('/'), name, version);
+    }
+    let prefix = index_prefix(name);
+    template
+        .replace("{crate}", name)
+        .replace("{version}", &version)
+        .replace("{prefix}", &prefix)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{sha256-checksum}", checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use super::{download_url, index_path, index_prefix, url_version};
+
+    #[test]
+    fn index_paths() {
+        assert_eq!(index_path("a"), "1/a");
+        assert_eq!(index_path("ab"), "2/ab");
+        assert_eq!(index_path("foo"), "3/f/foo");
+        assert_eq!(index_path("Serde_JSON"), "se/rd/serde_json");
+        assert_eq!(index_prefix("Serde_JSON"), "Se/rd");
+    }
+
+    #[test]
+    fn download_urls() {
+        let version = Version::parse("1.0.0+meta").unwrap();
+        assert_eq!(download_url("https://dl.example.com/crates/", "Foo", &version, "ab"),
+                   "https://dl.example.com/crates/Foo/1.0.0%2Bmeta/download");
+        assert_eq!(download_url("https://s3/{prefix}/{lowerprefix}/{crate}-{version}.{sha256-checksum}",
+                                "Serde", &version, "ab"),
+                   "https://s3/Se/rd/se/rd/Serde-1.0.0%2Bmeta.ab");
+        assert_eq!(url_version(&Version::parse("1.0.0").unwrap()), "1.0.0");
+    }
+}
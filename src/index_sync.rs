@@ -0,0 +1,126 @@
+//! Module for incremental syncing of a mirror from the history of a git registry index.
+//!
+//! Every crate version published to the registry is a new line in the index,
+//! so the versions added since the last sync can be read off of the `git diff`
+//! between the index commit that was synced last and the current one.
+//! The former is recorded in a state file inside the mirror's directory.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+
+use cargo_download::{Crate, CrateVersion};
+
+
+/// Name of the file inside the mirror's directory that records the last synced index commit.
+pub const STATE_FILE: &str = ".cargo-download-index-sync";
+
+/// Seconds that git's transfers may stall for before they're aborted (0 if they may indefinitely).
+static STALL_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+
+/// Read the index commit that the mirror in given directory was last synced to, if any.
+pub fn last_synced_commit(dir: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(dir.join(STATE_FILE)) {
+        Ok(content) => Ok(Some(content.trim().to_owned()).filter(|c| !c.is_empty())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Record that the mirror in given directory has been synced to given index commit.
+pub fn record_synced_commit(dir: &Path, commit: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(STATE_FILE), format!("{}\n", commit))
+}
+
+/// Determine the current (HEAD) commit of the index repository at given path.
+pub fn head_commit(index: &Path) -> io::Result<String> {
+    git(index, &["rev-parse", "--verify", "HEAD"]).map(|out| out.trim().to_owned())
+}
+
+/// List the crate versions added to the index between two commits.
+pub fn added_crates(index: &Path, from: &str, to: &str) -> io::Result<Vec<Crate>> {
+    let diff = git(index, &["diff", "--no-renames", "--no-color", "-U0", from, to, "--", "."])?;
+    Ok(added_in_diff(&diff))
+}
+
+/// Parse the crate versions added in a (unified) diff of the index.
+///
+/// Lines that are changed rather than added (e.g. when a version is yanked)
+/// don't count as added versions.
+fn added_in_diff(diff: &str) -> Vec<Crate> {
+    let entry = |line: &str| -> Option<(String, Version)> {
+        let json: Json = serde_json::from_str(line).ok()?;
+        let name = json.get("name")?.as_str()?;
+        let version = json.get("vers")?.as_str().and_then(|v| Version::parse(v).ok())?;
+        Some((name.to_owned(), version))
+    };
+    let is_content = |line: &&str| !line.starts_with("+++") && !line.starts_with("---");
+
+    let removed: HashSet<_> = diff.lines().filter(is_content)
+        .filter_map(|l| l.strip_prefix('-')).filter_map(entry)
+        .map(|(name, version)| (name, version.to_string()))
+        .collect();
+    diff.lines().filter(is_content)
+        .filter_map(|l| l.strip_prefix('+')).filter_map(entry)
+        .filter(|(name, version)| !removed.contains(&(name.clone(), version.to_string())))
+        .filter_map(|(name, version)| Crate::new(name, CrateVersion::Exact(version)).ok())
+        .collect()
+}
+
+/// Abort the transfers of git commands that stall (receive nothing) for given time.
+pub fn set_stall_timeout(timeout: Duration) {
+    STALL_TIMEOUT.store(timeout.as_secs().max(1), Ordering::SeqCst);
+}
+
+/// Run a git command in given repository, returning its standard output.
+pub fn git(repo: &Path, args: &[&str]) -> io::Result<String> {
+    trace!("Running git {} in {}", args.join(" "), repo.display());
+    // Nothing here is interactive, so git mustn't wait for credentials (e.g. of a missing repository).
+    let mut command = Command::new("git");
+    command.arg("-C").arg(repo).args(args).env("GIT_TERMINAL_PROMPT", "0");
+    let stall_timeout = STALL_TIMEOUT.load(Ordering::SeqCst);
+    if stall_timeout > 0 {
+        command.env("GIT_HTTP_LOW_SPEED_LIMIT", "1").env("GIT_HTTP_LOW_SPEED_TIME", stall_timeout.to_string());
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`git {}` failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::added_in_diff;
+
+    #[test]
+    fn added_versions() {
+        let diff = concat!(
+            "diff --git a/3/f/foo b/3/f/foo\n",
+            "--- a/3/f/foo\n+++ b/3/f/foo\n",
+            "@@ -1 +1 @@\n",
+            r#"-{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aa","yanked":false}"#, "\n",
+            r#"+{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aa","yanked":true}"#, "\n",
+            "@@ -1,0 +2 @@\n",
+            r#"+{"name":"foo","vers":"0.2.0","deps":[],"cksum":"bb","yanked":false}"#, "\n",
+            "diff --git a/ba/r_/bar_baz b/ba/r_/bar_baz\n",
+            "new file mode 100644\n",
+            "--- /dev/null\n+++ b/ba/r_/bar_baz\n",
+            "@@ -0,0 +1 @@\n",
+            r#"+{"name":"bar_baz","vers":"1.0.0-rc.1","deps":[],"cksum":"cc","yanked":false}"#, "\n",
+        );
+        let added: Vec<_> = added_in_diff(diff).iter().map(|c| c.to_string()).collect();
+        assert_eq!(added, vec!["foo==0.2.0", "bar_baz==1.0.0-rc.1"]);
+    }
+}
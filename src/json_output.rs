@@ -0,0 +1,143 @@
+//! Module for the machine-readable output of a download run (`--json`).
+//!
+//! Every crate is reported as a JSON object on its own line of stdout once it's been processed,
+//! and so is a failure that stops the run, so that build tooling can consume the outcome
+//! without parsing the logs (which still go to stderr).
+
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use semver::Version;
+use serde_json::Value as Json;
+
+use checksum;
+use closure::Dependent;
+use summary::{FailureClass, Outcome};
+
+
+/// Whether the records are being output.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// What's been found out about the crate that's currently being processed.
+    ///
+    /// It's global, like the summary of the run, because it's gathered from deep within the call stack.
+    static ref CURRENT: Mutex<Record> = Mutex::new(Record::default());
+}
+
+
+/// Start outputting the records.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Note the version that the current crate has been resolved to,
+/// and whether it's been yanked (if that's known).
+pub fn set_version(version: &Version, yanked: Option<bool>) {
+    with_current(|r| {
+        r.version = Some(version.clone());
+        r.yanked = yanked;
+    });
+}
+
+/// Note where the current crate is written to (or was present already).
+pub fn set_path(path: &Path) {
+    with_current(|r| r.path = Some(path.display().to_string()));
+}
+
+/// Note the downloaded archive of the current crate.
+pub fn set_archive(crate_bytes: &[u8]) {
+    with_current(|r| {
+        r.checksum = checksum::sha256_reader(crate_bytes).ok();
+        r.size = Some(crate_bytes.len() as u64);
+    });
+}
+
+/// Note the size and SHA-256 checksum of the archive of the current crate,
+/// which has been extracted as it was received.
+pub fn set_archive_digest(size: u64, checksum: &str) {
+    with_current(|r| {
+        r.checksum = Some(checksum.to_owned());
+        r.size = Some(size);
+    });
+}
+
+/// Note the crates that depend on the current crate, which is part of the closure of another one.
+pub fn set_dependents(dependents: &[Dependent]) {
+    with_current(|r| r.dependents = dependents.to_vec());
+}
+
+/// Output the record of the current crate (with given name), now that it's been processed.
+pub fn crate_processed(name: &str, outcome: Outcome) {
+    if !is_enabled() {
+        return;
+    }
+    let record = mem::take(&mut *CURRENT.lock().unwrap());
+    let outcome = match outcome {
+        Outcome::Succeeded(_) => "downloaded",
+        Outcome::Skipped => "skipped",
+        Outcome::Unfinished => "unfinished",
+    };
+    emit(&json!({
+        "type": "crate",
+        "name": name,
+        "version": record.version.map(|v| v.to_string()),
+        "outcome": outcome,
+        "yanked": record.yanked,
+        "checksum": record.checksum,
+        "size": record.size,
+        "path": record.path,
+        "dependents": record.dependents.iter().map(|d| d.to_json()).collect::<Vec<_>>(),
+    }));
+}
+
+/// Output the record of a failure that stops the run,
+/// which happened while processing given crate (if any).
+pub fn failure(crate_: Option<&str>, class: FailureClass, message: &str) {
+    if !is_enabled() {
+        return;
+    }
+    emit(&json!({
+        "type": "error",
+        "crate": crate_,
+        "class": class.to_string(),
+        "message": message,
+    }));
+}
+
+fn emit(record: &Json) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    // If stdout is gone (e.g. a closed pipe), there's no one to tell about it.
+    let _ = writeln!(stdout, "{}", record);
+    let _ = stdout.flush();
+}
+
+fn with_current<F: FnOnce(&mut Record)>(f: F) {
+    if is_enabled() {
+        f(&mut CURRENT.lock().unwrap());
+    }
+}
+
+
+/// What's known about a processed crate.
+#[derive(Debug, Default)]
+struct Record {
+    version: Option<Version>,
+    yanked: Option<bool>,
+    /// SHA-256 checksum of the downloaded archive, as a hex string.
+    checksum: Option<String>,
+    /// Size of the downloaded archive in bytes.
+    size: Option<u64>,
+    path: Option<String>,
+    /// Crates that depend on the crate, if it's part of a closure.
+    dependents: Vec<Dependent>,
+}
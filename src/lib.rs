@@ -0,0 +1,60 @@
+//!
+//! cargo-download
+//!
+//! Library for downloading crate sources from crates.io.
+//! The `Downloader` is the place to start, or the `AsyncDownloader` for use with Tokio.
+//!
+
+             extern crate conv;
+#[macro_use] extern crate derive_error;
+             extern crate flate2;
+             extern crate futures;
+             extern crate reqwest;
+             extern crate ruzstd;
+             extern crate semver;
+             extern crate serde;
+#[macro_use] extern crate serde_derive;
+#[cfg_attr(test, macro_use)] extern crate serde_json;
+             extern crate sha2;
+             extern crate tar;
+             extern crate time;
+#[cfg(test)] extern crate tokio;
+             extern crate toml;
+
+
+mod async_downloader;
+mod auditable;
+mod cancel;
+mod compression;
+mod deps;
+mod downloader;
+mod index;
+mod license;
+mod lint;
+mod lockfile;
+mod observer;
+mod output;
+mod profile;
+mod resolve;
+mod spec;
+mod staleness;
+mod stream;
+
+pub use async_downloader::AsyncDownloader;
+pub use auditable::{AuditInfo, AuditInfoError};
+pub use cancel::CancellationToken;
+pub use compression::{Compression, decompress};
+pub use deps::{Dependency, DependencyKind, features_from_index, is_proc_macro, rust_version_from_index};
+pub use downloader::{DownloadError, Downloader, HashingReader, ResolvedCrate, unpack_hashed};
+pub use index::{CRATES_INDEX_ROOT, download_url, index_path, url_version};
+pub use license::{license_allowed, manifest_license};
+pub use lint::{ManifestIssue, lint_manifest};
+pub use lockfile::{CRATES_IO_SOURCE, LockedPackage, Lockfile, LockfileError, LockfileFormat, format_lockfile};
+pub use observer::{Phase, ProgressObserver};
+pub use output::Output;
+pub use profile::{DownloadConfig, ProfileError};
+pub use resolve::{ResolvedVersion, VersionFilter, resolve_versions};
+pub use spec::{Crate, CrateError, CrateVersion, CrateVersionError, SpecError,
+               dedup_crates, expand_spec, normalize_spec, validate_spec};
+pub use staleness::newer_major_version;
+pub use stream::{StreamError, VerifyingReader, open_crate_stream};
@@ -0,0 +1,111 @@
+//! Module for checking the licenses of crates against an allow-list.
+//!
+//! Crates declare their licenses as SPDX expressions, e.g. `MIT OR Apache-2.0`,
+//! which are satisfied by the allow-list if it allows any of the alternatives
+//! joined with OR, but only if it allows all of the licenses joined with AND.
+
+use toml::{self, Value as Toml};
+
+
+/// Read the license expression declared in the content of a Cargo.toml file, if any.
+pub fn manifest_license(content: &str) -> Result<Option<String>, toml::de::Error> {
+    let manifest: Toml = toml::from_str(content)?;
+    Ok(manifest.get("package")
+        .and_then(|p| p.get("license"))
+        .and_then(|l| l.as_str())
+        .map(|l| l.to_owned()))
+}
+
+/// Check whether given SPDX license expression is satisfied by the allowed licenses.
+///
+/// License identifiers are compared case-insensitively, ignoring the `+` suffix,
+/// and a license with an exception (`... WITH ...`) is allowed if the license itself is.
+/// The obsolete `/` separator counts as OR, and malformed expressions aren't allowed.
+pub fn license_allowed(expr: &str, allowed: &[String]) -> bool {
+    let spaced = expr.replace('(', " ( ").replace(')', " ) ").replace('/', " OR ");
+    let tokens: Vec<_> = spaced.split_whitespace().collect();
+    let mut parser = Parser{tokens: &tokens, pos: 0, allowed};
+    match parser.or_expr() {
+        Some(result) if parser.pos == tokens.len() => result,
+        _ => false,
+    }
+}
+
+
+/// Recursive descent evaluator of SPDX license expressions.
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    allowed: &'a [String],
+}
+
+impl<'a> Parser<'a> {
+    fn or_expr(&mut self) -> Option<bool> {
+        let mut result = self.and_expr()?;
+        while self.eat("OR") {
+            result |= self.and_expr()?;
+        }
+        Some(result)
+    }
+
+    fn and_expr(&mut self) -> Option<bool> {
+        let mut result = self.atom()?;
+        while self.eat("AND") {
+            result &= self.atom()?;
+        }
+        Some(result)
+    }
+
+    fn atom(&mut self) -> Option<bool> {
+        if self.eat("(") {
+            let result = self.or_expr()?;
+            return if self.eat(")") { Some(result) } else { None };
+        }
+        let license = *self.tokens.get(self.pos)?;
+        if ["(", ")", "OR", "AND", "WITH"].iter().any(|t| t.eq_ignore_ascii_case(license)) {
+            return None;
+        }
+        self.pos += 1;
+        if self.eat("WITH") {
+            self.tokens.get(self.pos)?;
+            self.pos += 1;
+        }
+        let license = license.trim_end_matches('+');
+        Some(self.allowed.iter().any(|a| a.trim_end_matches('+').eq_ignore_ascii_case(license)))
+    }
+
+    /// Consume the next token if it's given operator (or parenthesis).
+    fn eat(&mut self, token: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(t) if t.eq_ignore_ascii_case(token) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{license_allowed, manifest_license};
+
+    #[test]
+    fn allow_list() {
+        let allowed = vec!["MIT".to_owned(), "Apache-2.0".to_owned()];
+        assert!(license_allowed("MIT", &allowed));
+        assert!(license_allowed("MIT OR GPL-3.0", &allowed));
+        assert!(license_allowed("mit/Apache-2.0", &allowed));
+        assert!(license_allowed("Apache-2.0 WITH LLVM-exception", &allowed));
+        assert!(license_allowed("(MIT OR Zlib) AND Apache-2.0+", &allowed));
+        assert!(!license_allowed("MIT AND GPL-3.0", &allowed));
+        assert!(!license_allowed("Unlicense", &allowed));
+        assert!(!license_allowed("MIT OR", &allowed));
+        assert!(!license_allowed("(MIT", &allowed));
+
+        let manifest = "[package]\nname = \"foo\"\nlicense = \"MIT OR Apache-2.0\"\n";
+        assert_eq!(manifest_license(manifest).unwrap(), Some("MIT OR Apache-2.0".into()));
+        assert_eq!(manifest_license("[package]\nlicense-file = \"LICENSE\"\n").unwrap(), None);
+    }
+}
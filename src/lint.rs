@@ -0,0 +1,112 @@
+//! Module for spotting dependency hygiene red flags in a crate's manifest.
+
+use std::fmt;
+
+use toml::{self, Value as Toml};
+
+
+/// Sections of the manifest that list dependencies.
+const DEPENDENCY_SECTIONS: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+
+/// Red flag found in a crate's manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestIssue {
+    /// Dependency which accepts any version (`*`).
+    WildcardRequirement(String),
+    /// Dependency fetched from a git repository.
+    GitDependency(String),
+    /// Dependency taken from a local path.
+    PathDependency(String),
+    /// The package declares neither `license` nor `license-file`.
+    MissingLicense,
+}
+
+impl fmt::Display for ManifestIssue {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ManifestIssue::WildcardRequirement(ref dep) =>
+                write!(fmt, "wildcard version requirement on `{}`", dep),
+            ManifestIssue::GitDependency(ref dep) => write!(fmt, "git dependency `{}`", dep),
+            ManifestIssue::PathDependency(ref dep) => write!(fmt, "path dependency `{}`", dep),
+            ManifestIssue::MissingLicense => write!(fmt, "no license or license-file"),
+        }
+    }
+}
+
+
+/// Analyze the content of a Cargo.toml file, returning the red flags found in it.
+///
+/// Dependencies are referred to by their section, e.g. `dev-dependencies.foo`,
+/// including the `target.<cfg>.` prefix for platform-specific ones.
+pub fn lint_manifest(content: &str) -> Result<Vec<ManifestIssue>, toml::de::Error> {
+    let manifest: Toml = toml::from_str(content)?;
+    let mut issues = vec![];
+
+    let package = manifest.get("package");
+    let has_license = ["license", "license-file"].iter()
+        .any(|key| package.and_then(|p| p.get(key)).is_some());
+    if !has_license {
+        issues.push(ManifestIssue::MissingLicense);
+    }
+
+    lint_dependencies(&manifest, "", &mut issues);
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        for (target, table) in targets {
+            lint_dependencies(table, &format!("target.{}.", target), &mut issues);
+        }
+    }
+    Ok(issues)
+}
+
+fn lint_dependencies(table: &Toml, prefix: &str, issues: &mut Vec<ManifestIssue>) {
+    for section in DEPENDENCY_SECTIONS {
+        let deps = match table.get(section).and_then(|d| d.as_table()) {
+            Some(deps) => deps,
+            None => continue,
+        };
+        for (name, spec) in deps {
+            let dep = format!("{}{}.{}", prefix, section, name);
+            let version = match *spec {
+                Toml::String(ref v) => Some(v.as_str()),
+                _ => spec.get("version").and_then(|v| v.as_str()),
+            };
+            if version.map(|v| v.trim()) == Some("*") {
+                issues.push(ManifestIssue::WildcardRequirement(dep.clone()));
+            }
+            if spec.get("git").is_some() {
+                issues.push(ManifestIssue::GitDependency(dep.clone()));
+            }
+            if spec.get("path").is_some() {
+                issues.push(ManifestIssue::PathDependency(dep));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ManifestIssue, lint_manifest};
+
+    #[test]
+    fn red_flags() {
+        let issues = lint_manifest(concat!(
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+            "[dependencies]\nbar = \"*\"\nbaz = { git = \"https://example.com/baz\" }\n",
+            "qux = \"1.0\"\n",
+            "[target.'cfg(unix)'.dev-dependencies]\nquux = { path = \"../quux\", version = \"*\" }\n",
+        )).unwrap();
+        assert_eq!(issues, vec![
+            ManifestIssue::MissingLicense,
+            ManifestIssue::WildcardRequirement("dependencies.bar".into()),
+            ManifestIssue::GitDependency("dependencies.baz".into()),
+            ManifestIssue::WildcardRequirement("target.cfg(unix).dev-dependencies.quux".into()),
+            ManifestIssue::PathDependency("target.cfg(unix).dev-dependencies.quux".into()),
+        ]);
+
+        let clean = "[package]\nname = \"foo\"\nlicense = \"MIT\"\n[dependencies]\nbar = \"1\"\n";
+        assert!(lint_manifest(clean).unwrap().is_empty());
+        assert!(lint_manifest("[package").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Module for listing the content of crate archives without unpacking them (`--list`).
+
+use std::fmt;
+use std::io;
+
+use serde_json::Value as Json;
+use tar::{self, EntryType};
+use time;
+
+use cargo_download::decompress;
+
+
+/// Entry of a crate archive, as listed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    /// Permission bits of the entry, like 0o644.
+    pub mode: u32,
+    /// Size of the entry's content in bytes.
+    pub size: u64,
+    /// Modification time of the entry, as a Unix time.
+    pub mtime: u64,
+    /// Target of the entry if it's a link.
+    pub link: Option<String>,
+}
+
+/// Kind of an entry of a crate archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    HardLink,
+    Other,
+}
+
+impl EntryKind {
+    fn from_entry_type(entry_type: EntryType) -> EntryKind {
+        match entry_type {
+            EntryType::Regular | EntryType::Continuous => EntryKind::File,
+            EntryType::Directory => EntryKind::Directory,
+            EntryType::Symlink => EntryKind::Symlink,
+            EntryType::Link => EntryKind::HardLink,
+            _ => EntryKind::Other,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Directory => "directory",
+            EntryKind::Symlink => "symlink",
+            EntryKind::HardLink => "hardlink",
+            EntryKind::Other => "other",
+        }
+    }
+
+    /// Character that `ls -l` and `tar -tv` show for this kind of entry.
+    fn symbol(self) -> char {
+        match self {
+            EntryKind::File => '-',
+            EntryKind::Directory => 'd',
+            EntryKind::Symlink => 'l',
+            EntryKind::HardLink => 'h',
+            EntryKind::Other => '?',
+        }
+    }
+}
+
+
+/// Read the entries of given crate archive, in the order they're stored.
+pub fn read_entries(crate_bytes: &[u8]) -> io::Result<Vec<ArchiveEntry>> {
+    let mut archive = tar::Archive::new(decompress(crate_bytes)?);
+    let mut entries = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        entries.push(ArchiveEntry{
+            path: entry.path()?.to_string_lossy().into_owned(),
+            kind: EntryKind::from_entry_type(header.entry_type()),
+            mode: header.mode()? & 0o7777,
+            size: header.size()?,
+            mtime: header.mtime()?,
+            link: entry.link_name()?.map(|l| l.to_string_lossy().into_owned()),
+        });
+    }
+    Ok(entries)
+}
+
+impl ArchiveEntry {
+    /// Permissions of the entry in the symbolic form, like `-rw-r--r--`.
+    pub fn permissions(&self) -> String {
+        let mut perms = String::with_capacity(10);
+        perms.push(self.kind.symbol());
+        for shift in &[6, 3, 0] {
+            let bits = (self.mode >> shift) & 0o7;
+            perms.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            perms.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            perms.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        perms
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "path": self.path,
+            "type": self.kind.name(),
+            "mode": format!("{:04o}", self.mode),
+            "size": self.size,
+            "mtime": self.mtime,
+            "link": self.link,
+        })
+    }
+}
+
+/// Formats the entry like a line of `tar -tv`, without the owner (which is meaningless for crates).
+impl fmt::Display for ArchiveEntry {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mtime = time::at_utc(time::Timespec::new(self.mtime as i64, 0));
+        write!(fmt, "{} {:>9} {} {}", self.permissions(), self.size,
+               mtime.strftime("%Y-%m-%d %H:%M").unwrap(), self.path)?;
+        if let Some(ref link) = self.link {
+            write!(fmt, " -> {}", link)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tar::{self, EntryType};
+    use super::{EntryKind, read_entries};
+
+    #[test]
+    fn entries() {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::Default));
+        let mut header = tar::Header::new_gnu();
+        header.set_path("foo-1.0.0/build.sh").unwrap();
+        header.set_size(3);
+        header.set_mode(0o755);
+        header.set_mtime(86400);
+        header.set_cksum();
+        builder.append(&header, &b"ls\n"[..]).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_path("foo-1.0.0/README").unwrap();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_link_name("README.md").unwrap();
+        header.set_size(0);
+        header.set_mode(0o777);
+        header.set_cksum();
+        builder.append(&header, &[][..]).unwrap();
+        let crate_bytes = builder.into_inner().unwrap().finish().unwrap();
+
+        let entries = read_entries(&crate_bytes).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].kind, EntryKind::File);
+        assert_eq!(entries[0].to_string(), "-rwxr-xr-x         3 1970-01-02 00:00 foo-1.0.0/build.sh");
+        assert_eq!(entries[0].to_json()["mode"], "0755");
+        assert_eq!(entries[1].permissions(), "lrwxrwxrwx");
+        assert!(entries[1].to_string().ends_with("foo-1.0.0/README -> README.md"));
+    }
+}
@@ -0,0 +1,286 @@
+//! Module for reading crates from the local sources that Cargo's source replacement can point to:
+//!
+//! - local registries (`local-registry = "DIR"`), with the index files in `DIR/index`
+//!   (laid out like in a registry index) and the archives as `DIR/$CRATE-$VERSION.crate`,
+//! - directories of vendored crates (`directory = "DIR"`), like those that `cargo vendor` creates,
+//!   with every crate unpacked into `DIR/$CRATE` or `DIR/$CRATE-$VERSION`.
+//!
+//! Vendored crates have no index, so their index entries are made up from their manifests,
+//! and their archives are packed again from the unpacked files.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use flate2;
+use flate2::write::GzEncoder;
+use semver::Version;
+use serde_json::{self, Value as Json};
+use tar;
+use toml::{self, Value as Toml};
+
+use cargo_download::index_path;
+use vendor::{self, CHECKSUM_FILE};
+
+
+/// Local source of crates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalSource {
+    /// Local registry in given directory.
+    Registry(PathBuf),
+    /// Directory of vendored crates.
+    Directory(PathBuf),
+}
+
+impl LocalSource {
+    /// Whether the archives of the source are the published ones,
+    /// i.e. they have the checksums listed in its index.
+    ///
+    /// That's not the case for vendored crates, whose archives are packed again.
+    #[inline]
+    pub fn has_published_archives(&self) -> bool {
+        match *self {
+            LocalSource::Registry(_) => true,
+            LocalSource::Directory(_) => false,
+        }
+    }
+
+    /// Read the index file of given crate, returning its path and content,
+    /// or `None` if the source doesn't have the crate.
+    pub fn index_file(&self, name: &str) -> io::Result<Option<(PathBuf, String)>> {
+        match *self {
+            LocalSource::Registry(ref dir) => {
+                let path = dir.join("index").join(index_path(name));
+                match fs::read_to_string(&path) {
+                    Ok(content) => Ok(Some((path, content))),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            LocalSource::Directory(ref dir) => {
+                let crates = vendored_crates(dir, name)?;
+                if crates.is_empty() {
+                    return Ok(None);
+                }
+                let mut content = String::new();
+                for (crate_dir, manifest) in crates {
+                    let entry = index_entry(&manifest, vendor::package_checksum(&crate_dir))
+                        .ok_or_else(|| invalid_data(format!("malformed manifest in {}", crate_dir.display())))?;
+                    content.push_str(&entry.to_string());
+                    content.push('\n');
+                }
+                Ok(Some((dir.clone(), content)))
+            }
+        }
+    }
+
+    /// Read the archive of given crate version, or `None` if the source doesn't have it.
+    pub fn read_crate(&self, name: &str, version: &Version) -> io::Result<Option<Vec<u8>>> {
+        match *self {
+            LocalSource::Registry(ref dir) => match fs::read(dir.join(format!("{}-{}.crate", name, version))) {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            },
+            LocalSource::Directory(ref dir) => {
+                let crate_dir = vendored_crates(dir, name)?.into_iter()
+                    .find(|(_, manifest)| manifest_version(manifest).is_some_and(|v| {
+                        // Version equality disregards build metadata, but it's significant here.
+                        &v == version && v.build == version.build
+                    }))
+                    .map(|(crate_dir, _)| crate_dir);
+                match crate_dir {
+                    Some(crate_dir) => pack(name, version, &crate_dir).map(Some),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for LocalSource {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LocalSource::Registry(ref dir) => write!(fmt, "local registry {}", dir.display()),
+            LocalSource::Directory(ref dir) => write!(fmt, "directory {}", dir.display()),
+        }
+    }
+}
+
+
+/// Find the vendored crates with given name in given directory,
+/// returning their directories along with their manifests.
+fn vendored_crates(dir: &Path, name: &str) -> io::Result<Vec<(PathBuf, Toml)>> {
+    let mut crates = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let dir_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        // The directories are named after the crates, possibly with a version suffix.
+        if !dir_name.starts_with(name) || !path.join(CHECKSUM_FILE).is_file() {
+            continue;
+        }
+        let manifest = match fs::read_to_string(path.join("Cargo.toml")) {
+            Ok(manifest) => manifest,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let manifest: Toml = toml::from_str(&manifest)
+            .map_err(|e| invalid_data(format!("failed to parse the manifest in {}: {}", path.display(), e)))?;
+        if manifest.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str()) == Some(name) {
+            crates.push((path, manifest));
+        }
+    }
+    Ok(crates)
+}
+
+/// Version declared in given crate manifest.
+fn manifest_version(manifest: &Toml) -> Option<Version> {
+    manifest.get("package")?.get("version")?.as_str().and_then(|v| Version::parse(v).ok())
+}
+
+/// Make up the index entry of the crate with given manifest (and archive checksum, if known).
+fn index_entry(manifest: &Toml, checksum: Option<String>) -> Option<Json> {
+    let package = manifest.get("package")?;
+    let mut deps = vec![];
+    dependencies(manifest, None, &mut deps)?;
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        for (target, table) in targets {
+            dependencies(table, Some(target), &mut deps)?;
+        }
+    }
+    let features = manifest.get("features").map(toml_to_json).unwrap_or_else(|| json!({}));
+    Some(json!({
+        "name": package.get("name")?.as_str()?,
+        "vers": manifest_version(manifest)?.to_string(),
+        "deps": deps,
+        "cksum": checksum.unwrap_or_default(),
+        "features": features,
+        "yanked": false,
+        "rust_version": package.get("rust-version").and_then(|v| v.as_str()),
+    }))
+}
+
+/// Collect the dependencies declared in given manifest table (for given target, if any)
+/// as index entries.
+fn dependencies(table: &Toml, target: Option<&str>, deps: &mut Vec<Json>) -> Option<()> {
+    for &(section, kind) in &[("dependencies", "normal"), ("build-dependencies", "build"),
+                              ("dev-dependencies", "dev")] {
+        let section = match table.get(section).and_then(|s| s.as_table()) {
+            Some(section) => section,
+            None => continue,
+        };
+        for (key, dep) in section {
+            let (req, dep) = match *dep {
+                Toml::String(ref req) => (req.as_str(), None),
+                // Dependencies on crates from other sources (like paths) aren't in the registry.
+                Toml::Table(ref dep) if dep.contains_key("path") || dep.contains_key("git") => continue,
+                Toml::Table(ref dep) => (dep.get("version").and_then(|v| v.as_str()).unwrap_or("*"), Some(dep)),
+                _ => return None,
+            };
+            let get = |k: &str| dep.and_then(|d| d.get(k));
+            let mut entry = json!({
+                "name": key,
+                "req": req,
+                "features": get("features").map(toml_to_json).unwrap_or_else(|| json!([])),
+                "optional": get("optional").and_then(|o| o.as_bool()).unwrap_or(false),
+                "default_features": get("default-features").or_else(|| get("default_features"))
+                    .and_then(|d| d.as_bool()).unwrap_or(true),
+                "target": target,
+                "kind": kind,
+            });
+            // Only renamed dependencies have the actual crate name under "package".
+            if let Some(package) = get("package").and_then(|p| p.as_str()) {
+                entry["package"] = package.into();
+            }
+            deps.push(entry);
+        }
+    }
+    Some(())
+}
+
+/// Convert a TOML value into JSON.
+fn toml_to_json(value: &Toml) -> Json {
+    serde_json::to_value(value).unwrap_or(Json::Null)
+}
+
+/// Pack the vendored crate in given directory into a gzipped archive,
+/// with the files under the `$CRATE-$VERSION` directory like in the published archives.
+fn pack(name: &str, version: &Version, dir: &Path) -> io::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(GzEncoder::new(vec![], flate2::Compression::Default));
+    append_dir(&mut builder, dir, &PathBuf::from(format!("{}-{}", name, version)), true)?;
+    builder.into_inner()?.finish()
+}
+
+/// Append the files in given directory to the archive under given path, in a stable order,
+/// leaving out the checksum file (if at the top level).
+fn append_dir<W: io::Write>(builder: &mut tar::Builder<W>, dir: &Path, path: &Path,
+                            top_level: bool) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let file_name = entry.file_name();
+        if top_level && file_name == CHECKSUM_FILE {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            append_dir(builder, &entry.path(), &path.join(&file_name), false)?;
+        } else if file_type.is_file() {
+            builder.append_path_with_name(entry.path(), path.join(&file_name))?;
+        }
+    }
+    Ok(())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use semver::Version;
+    use cargo_download::{Dependency, ResolvedVersion, decompress};
+    use tar;
+    use super::LocalSource;
+
+    #[test]
+    fn vendored_directory() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-local-source-{}", std::process::id()));
+        let crate_dir = dir.join("foo");
+        fs::create_dir_all(crate_dir.join("src")).unwrap();
+        fs::write(crate_dir.join("Cargo.toml"), concat!(
+            "[package]\nname = \"foo\"\nversion = \"1.2.3\"\n",
+            "[dependencies.bar]\nversion = \"^0.4\"\noptional = true\n",
+            "[dependencies.local]\npath = \"../local\"\n",
+            "[target.'cfg(unix)'.build-dependencies]\ncc = \"1\"\n")).unwrap();
+        fs::write(crate_dir.join("src").join("lib.rs"), "").unwrap();
+        fs::write(crate_dir.join(".cargo-checksum.json"), r#"{"files":{},"package":"abc"}"#).unwrap();
+        // Crates named with the same prefix aren't mixed up with it.
+        fs::create_dir_all(dir.join("foobar")).unwrap();
+        let source = LocalSource::Directory(dir.clone());
+
+        let (_, content) = source.index_file("foo").unwrap().unwrap();
+        let versions = ResolvedVersion::list_from_index(&content).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].checksum.as_deref(), Some("abc"));
+        let version = Version::parse("1.2.3").unwrap();
+        let deps = Dependency::list_from_index(&content, &version).unwrap();
+        assert_eq!(deps.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["bar", "cc"]);
+        assert!(deps[0].optional);
+        assert!(source.index_file("baz").unwrap().is_none());
+
+        let archive = source.read_crate("foo", &version).unwrap().unwrap();
+        let mut archive = tar::Archive::new(decompress(&archive[..]).unwrap());
+        let mut paths = archive.entries().unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, vec!["foo-1.2.3/Cargo.toml", "foo-1.2.3/src/lib.rs"]);
+        assert!(source.read_crate("foo", &Version::parse("1.0.0").unwrap()).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+//! Module implementing advisory locking of output directories.
+//!
+//! This prevents concurrent invocations of the program
+//! from interleaving their writes to the same directory.
+//!
+//! The lock files are kept in the `locks` directory of the download cache (named after
+//! the locked directories), so that nothing but the crates is left in the output.
+
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+
+use cache::Cache;
+use checksum;
+
+
+/// Name of the directory inside of the cache that holds the lock files.
+const LOCKS_DIR: &str = "locks";
+
+
+/// Exclusive advisory lock on a directory, held until the object is dropped.
+#[derive(Debug)]
+pub struct DirLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl DirLock {
+    /// Lock given directory, waiting for any other process that holds the lock.
+    pub fn acquire<P: AsRef<Path>>(dir: P) -> io::Result<DirLock> {
+        DirLock::acquire_in(dir, &locks_dir())
+    }
+
+    /// Lock given directory with a lock file in the other directory given.
+    fn acquire_in<P: AsRef<Path>>(dir: P, locks_dir: &Path) -> io::Result<DirLock> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        fs::create_dir_all(locks_dir)?;
+        let path = locks_dir.join(lock_name(&dir.canonicalize()?)?);
+        let file = fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(false)
+            .open(&path)?;
+
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e);
+            }
+            info!("Waiting for another process to release the lock on {}/", dir.display());
+            file.lock_exclusive()?;
+        }
+        trace!("Acquired lock {} on {}/", path.display(), dir.display());
+        Ok(DirLock{file, path})
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // The lock file itself is left in place, because removing it
+        // would race with other processes that have it open.
+        match self.file.unlock() {
+            Ok(_) => trace!("Released lock {}", self.path.display()),
+            Err(e) => warn!("Failed to release lock {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+
+/// Return the directory containing given path, suitable for locking.
+pub fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Determine the directory of the lock files: inside of the cache if it can be written to,
+/// and in the system's temporary directory otherwise.
+fn locks_dir() -> PathBuf {
+    match Cache::open_default() {
+        Some(ref cache) if !cache.is_read_only() => cache.root().join(LOCKS_DIR),
+        _ => env::temp_dir().join("cargo-download-locks"),
+    }
+}
+
+/// Name of the lock file of given (canonical) directory path,
+/// i.e. the name of the directory followed by a hash of its path.
+fn lock_name(dir: &Path) -> io::Result<String> {
+    let hash = checksum::sha256_reader(dir.to_string_lossy().as_bytes())?;
+    let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(format!("{}-{}.lock", name, &hash[..16]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::{Path, PathBuf};
+    use super::{DirLock, parent_dir};
+
+    #[test]
+    fn parent_of_bare_name() {
+        assert_eq!(parent_dir(Path::new("foo.crate")), PathBuf::from("."));
+    }
+
+    #[test]
+    fn parent_of_nested_path() {
+        assert_eq!(parent_dir(Path::new("vendor/foo-1.0.0")), PathBuf::from("vendor"));
+    }
+
+    #[test]
+    fn lock_files_kept_out_of_locked_dirs() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-lock-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let locks = dir.join("locks");
+        {
+            let _vendor = DirLock::acquire_in(dir.join("vendor"), &locks).unwrap();
+            let _mirror = DirLock::acquire_in(dir.join("mirror"), &locks).unwrap();
+            assert_eq!(fs::read_dir(&locks).unwrap().count(), 2);
+        }
+        assert_eq!(fs::read_dir(dir.join("vendor")).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(dir.join("mirror")).unwrap().count(), 0);
+
+        // The same directory is locked with the same file, however it's referred to.
+        let _lock = DirLock::acquire_in(dir.join("vendor/../vendor"), &locks).unwrap();
+        assert_eq!(fs::read_dir(&locks).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+//! Module for reading the crates to download from a Cargo.lock file,
+//! along with the checksums that the lockfile pins them to,
+//! and for writing the crates that have been resolved as one.
+//!
+//! Lockfiles of other build systems that fetch crates from a registry can be read too,
+//! namely that of Bazel's crate_universe rules (`cargo-bazel-lock.json`).
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use semver::Version;
+use serde::de;
+use serde_json::{self, Value as Json};
+use toml;
+
+use spec::{Crate, CrateVersion};
+
+
+/// Prefixes of the `source` of packages that come from a registry
+/// (as opposed to git repositories or local paths).
+const REGISTRY_SOURCES: &[&str] = &["registry+", "sparse+"];
+
+/// Source of the crates.io packages in lockfiles, whichever protocol its index is accessed with.
+pub const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// Version of the lockfile format that's written.
+const LOCKFILE_VERSION: u32 = 3;
+
+
+/// Format of a lockfile that crates are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockfileFormat {
+    /// Cargo.lock.
+    Cargo,
+    /// Lockfile of Bazel's crate_universe rules, in JSON.
+    Bazel,
+}
+
+
+/// Contents of a lockfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lockfile {
+    /// Exact versions of the packages that come from a registry.
+    crates: Vec<Crate>,
+    /// SHA-256 checksums of the registry packages, by their names and versions.
+    checksums: BTreeMap<(String, String), String>,
+}
+
+impl Lockfile {
+    /// Load the lockfile in given format from given path.
+    pub fn load<P: AsRef<Path>>(path: P, format: LockfileFormat) -> Result<Lockfile, LockfileError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| LockfileError::Io(path.to_owned(), e))?;
+        match format {
+            LockfileFormat::Cargo => content.parse().map_err(|e| LockfileError::Parse(path.to_owned(), e)),
+            LockfileFormat::Bazel => Lockfile::from_bazel(&content)
+                .map_err(|e| LockfileError::ParseJson(path.to_owned(), e)),
+        }
+    }
+
+    /// Read the lockfile of Bazel's crate_universe rules from its content.
+    ///
+    /// The crates that are fetched over HTTP (i.e. from a registry) are included,
+    /// while those from git repositories and the local ones (like the direct dependencies'
+    /// placeholder package) are not.
+    pub fn from_bazel(content: &str) -> Result<Lockfile, serde_json::Error> {
+        use serde::de::Error;
+
+        let raw: RawBazelLockfile = serde_json::from_str(content)?;
+        let mut crates = vec![];
+        let mut checksums = BTreeMap::new();
+        for package in raw.crates.into_values() {
+            let http = match package.repository.as_ref().and_then(|r| r.get("Http")) {
+                Some(http) => http,
+                None => continue,
+            };
+            let version = Version::parse(&package.version).map_err(|e| serde_json::Error::custom(
+                format!("invalid version `{}` of crate `{}`: {}", package.version, package.name, e)))?;
+            if let Some(checksum) = http.get("sha256").and_then(|c| c.as_str()) {
+                checksums.insert((package.name.clone(), version.to_string()), checksum.to_owned());
+            }
+            let crate_ = Crate::new(package.name, CrateVersion::Exact(version))
+                .map_err(serde_json::Error::custom)?;
+            crates.push(crate_);
+        }
+        Ok(Lockfile{crates, checksums})
+    }
+
+    /// The registry packages of the lockfile, as crates with exact versions.
+    ///
+    /// Packages from git repositories and local paths (such as workspace members)
+    /// are not included.
+    #[inline]
+    pub fn registry_crates(&self) -> &[Crate] {
+        &self.crates
+    }
+
+    #[inline]
+    pub fn into_registry_crates(self) -> Vec<Crate> {
+        self.crates
+    }
+
+    /// The SHA-256 checksum (as a hex string) that the lockfile records for given package, if any.
+    ///
+    /// Only lockfiles of version 2 and later record checksums with the packages.
+    pub fn checksum(&self, name: &str, version: &Version) -> Option<&str> {
+        self.checksums.get(&(name.to_owned(), version.to_string())).map(|c| c.as_str())
+    }
+}
+
+impl FromStr for Lockfile {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw: RawLockfile = toml::from_str(s)?;
+        let mut crates = vec![];
+        let mut checksums = BTreeMap::new();
+        for package in raw.package {
+            let from_registry = package.source.as_ref()
+                .is_some_and(|s| REGISTRY_SOURCES.iter().any(|p| s.starts_with(p)));
+            if !from_registry {
+                continue;
+            }
+            let version = Version::parse(&package.version).map_err(|e| de::Error::custom(
+                format!("invalid version `{}` of package `{}`: {}", package.version, package.name, e)))?;
+            if let Some(checksum) = package.checksum {
+                checksums.insert((package.name.clone(), version.to_string()), checksum);
+            }
+            let crate_ = Crate::new(package.name, CrateVersion::Exact(version))
+                .map_err(de::Error::custom)?;
+            crates.push(crate_);
+        }
+        Ok(Lockfile{crates, checksums})
+    }
+}
+
+/// Package from a registry, to be written to a lockfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Version,
+    /// SHA-256 checksum of the package's archive, as a hex string, if known.
+    pub checksum: Option<String>,
+    /// Names and versions of the packages that the package depends on.
+    pub dependencies: Vec<(String, Version)>,
+}
+
+/// Format given registry packages (all from given source) as the content of a Cargo.lock file.
+///
+/// Like Cargo does, the packages are sorted, and dependencies are referred to by name alone,
+/// unless the lockfile has more than one version of the package.
+pub fn format_lockfile(packages: &[LockedPackage], source: &str) -> String {
+    let mut packages: Vec<_> = packages.iter().collect();
+    packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    packages.dedup_by(|a, b| a.name == b.name && a.version.to_string() == b.version.to_string());
+    let ambiguous = |name: &str| packages.iter().filter(|p| p.name == name).count() > 1;
+
+    let mut content = String::from(concat!(
+        "# This file is automatically @generated by Cargo.\n",
+        "# It is not intended for manual editing.\n"));
+    writeln!(content, "version = {}", LOCKFILE_VERSION).unwrap();
+    for package in packages.iter() {
+        writeln!(content, "\n[[package]]").unwrap();
+        writeln!(content, "name = {:?}", package.name).unwrap();
+        writeln!(content, "version = \"{}\"", package.version).unwrap();
+        writeln!(content, "source = {:?}", source).unwrap();
+        if let Some(ref checksum) = package.checksum {
+            writeln!(content, "checksum = {:?}", checksum).unwrap();
+        }
+        if package.dependencies.is_empty() {
+            continue;
+        }
+        let mut deps: Vec<_> = package.dependencies.iter().map(|(name, version)| {
+            if ambiguous(name) { format!("{} {}", name, version) } else { name.clone() }
+        }).collect();
+        deps.sort();
+        deps.dedup();
+        writeln!(content, "dependencies = [").unwrap();
+        for dep in deps {
+            writeln!(content, " {:?},", dep).unwrap();
+        }
+        writeln!(content, "]").unwrap();
+    }
+    content
+}
+
+/// Subset of the Cargo.lock format that we're interested in.
+#[derive(Deserialize)]
+struct RawLockfile {
+    #[serde(default)]
+    package: Vec<RawPackage>,
+}
+
+#[derive(Deserialize)]
+struct RawPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    checksum: Option<String>,
+}
+
+/// Subset of the crate_universe lockfile format that we're interested in.
+#[derive(Deserialize)]
+struct RawBazelLockfile {
+    /// Crates keyed by their name and version.
+    #[serde(default)]
+    crates: BTreeMap<String, RawBazelCrate>,
+}
+
+#[derive(Deserialize)]
+struct RawBazelCrate {
+    name: String,
+    version: String,
+    /// Where the crate is fetched from, e.g. `{"Http": {"url": ..., "sha256": ...}}`,
+    /// or null for the local ones.
+    repository: Option<Json>,
+}
+
+
+/// Error that can occur while loading a lockfile.
+#[derive(Debug)]
+pub enum LockfileError {
+    /// Error reading the lockfile.
+    Io(PathBuf, io::Error),
+    /// Syntax error or invalid package in the lockfile.
+    Parse(PathBuf, toml::de::Error),
+    /// Syntax error or invalid crate in a lockfile in JSON.
+    ParseJson(PathBuf, serde_json::Error),
+}
+impl Error for LockfileError {
+    fn description(&self) -> &str { "invalid lockfile" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            LockfileError::Io(_, ref e) => Some(e),
+            LockfileError::Parse(_, ref e) => Some(e),
+            LockfileError::ParseJson(_, ref e) => Some(e),
+        }
+    }
+}
+impl fmt::Display for LockfileError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockfileError::Io(ref p, ref e) =>
+                write!(fmt, "failed to read lockfile {}: {}", p.display(), e),
+            LockfileError::Parse(ref p, ref e) =>
+                write!(fmt, "failed to parse lockfile {}: {}", p.display(), e),
+            LockfileError::ParseJson(ref p, ref e) =>
+                write!(fmt, "failed to parse lockfile {}: {}", p.display(), e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use super::{CRATES_IO_SOURCE, LockedPackage, Lockfile, format_lockfile};
+
+    #[test]
+    fn registry_packages() {
+        let lockfile: Lockfile = concat!(
+            "version = 3\n\n",
+            "[[package]]\nname = \"app\"\nversion = \"0.1.0\"\ndependencies = [\"foo\"]\n\n",
+            "[[package]]\nname = \"foo\"\nversion = \"1.2.3\"\n",
+            "source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+            "checksum = \"abcd\"\n\n",
+            "[[package]]\nname = \"bar\"\nversion = \"0.4.0\"\n",
+            "source = \"git+https://example.com/bar#0123abcd\"\n\n",
+            "[[package]]\nname = \"baz\"\nversion = \"2.0.0-rc.1\"\n",
+            "source = \"sparse+https://index.crates.io/\"\n",
+        ).parse().unwrap();
+        let crates: Vec<_> = lockfile.registry_crates().iter().map(|c| c.to_string()).collect();
+        assert_eq!(crates, vec!["foo==1.2.3", "baz==2.0.0-rc.1"]);
+        assert_eq!(lockfile.checksum("foo", &Version::parse("1.2.3").unwrap()), Some("abcd"));
+        assert_eq!(lockfile.checksum("baz", &Version::parse("2.0.0-rc.1").unwrap()), None);
+
+        assert!("[[package]]\nname = \"foo\"\nversion = \"x\"\nsource = \"registry+r\"\n"
+            .parse::<Lockfile>().is_err());
+    }
+
+    #[test]
+    fn bazel_crates() {
+        let lockfile = Lockfile::from_bazel(r#"{
+            "checksum": "0123",
+            "crates": {
+                "direct-cargo-bazel-deps 0.0.1": {"name": "direct-cargo-bazel-deps", "version": "0.0.1",
+                    "repository": null},
+                "foo 1.2.3": {"name": "foo", "version": "1.2.3", "repository": {"Http": {
+                    "url": "https://static.crates.io/crates/foo/1.2.3/download", "sha256": "abcd"}}},
+                "bar 0.4.0": {"name": "bar", "version": "0.4.0", "repository": {"Git": {
+                    "remote": "https://example.com/bar.git", "commitish": {"Rev": "0123abcd"}}}}
+            }
+        }"#).unwrap();
+        let crates: Vec<_> = lockfile.registry_crates().iter().map(|c| c.to_string()).collect();
+        assert_eq!(crates, vec!["foo==1.2.3"]);
+        assert_eq!(lockfile.checksum("foo", &Version::parse("1.2.3").unwrap()), Some("abcd"));
+
+        assert!(Lockfile::from_bazel(r#"{"crates": {"foo x": {"name": "foo", "version": "x",
+            "repository": {"Http": {"url": "https://example.com"}}}}}"#).is_err());
+    }
+
+    #[test]
+    fn formatting() {
+        let package = |name: &str, version: &str, deps: &[(&str, &str)]| LockedPackage{
+            name: name.into(),
+            version: Version::parse(version).unwrap(),
+            checksum: Some(format!("{}-checksum", name)),
+            dependencies: deps.iter().map(|&(n, v)| (n.into(), Version::parse(v).unwrap())).collect(),
+        };
+        let content = format_lockfile(&[
+            package("foo", "1.0.0", &[("bar", "2.0.0"), ("baz", "0.1.0")]),
+            package("baz", "0.1.0", &[("bar", "1.0.0")]),
+            package("bar", "2.0.0", &[]),
+            package("bar", "1.0.0", &[]),
+        ], CRATES_IO_SOURCE);
+        assert!(content.contains(concat!(
+            "\n[[package]]\nname = \"foo\"\nversion = \"1.0.0\"\n",
+            "source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+            "checksum = \"foo-checksum\"\n",
+            "dependencies = [\n \"bar 2.0.0\",\n \"baz\",\n]\n")));
+
+        let lockfile: Lockfile = content.parse().unwrap();
+        let crates: Vec<_> = lockfile.registry_crates().iter().map(|c| c.to_string()).collect();
+        assert_eq!(crates, vec!["bar==1.0.0", "bar==2.0.0", "baz==0.1.0", "foo==1.0.0"]);
+        assert_eq!(lockfile.checksum("baz", &Version::parse("0.1.0").unwrap()), Some("baz-checksum"));
+    }
+}
@@ -24,12 +24,12 @@ const DEFAULT_LEVEL: Level = Level::Info;
 const DEFAULT_FILTER_LEVEL: FilterLevel = FilterLevel::Info;
 
 // Arrays of log levels, indexed by verbosity.
-const POSITIVE_VERBOSITY_LEVELS: &'static [FilterLevel] = &[
+const POSITIVE_VERBOSITY_LEVELS: &[FilterLevel] = &[
     DEFAULT_FILTER_LEVEL,
     FilterLevel::Debug,
     FilterLevel::Trace,
 ];
-const NEGATIVE_VERBOSITY_LEVELS: &'static [FilterLevel] = &[
+const NEGATIVE_VERBOSITY_LEVELS: &[FilterLevel] = &[
     DEFAULT_FILTER_LEVEL,
     FilterLevel::Warning,
     FilterLevel::Error,
@@ -3,22 +3,32 @@
 //!
 
              extern crate ansi_term;
-#[macro_use] extern crate clap;
+             extern crate cargo_download;
+             extern crate clap;
              extern crate conv;
-#[macro_use] extern crate derive_error;
              extern crate exitcode;
+             extern crate filetime;
              extern crate flate2;
+             extern crate fs2;
+             extern crate glob;
+             extern crate http as http_crate;
+             extern crate httparse;
              extern crate isatty;
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate maplit;
+             extern crate percent_encoding;
              extern crate reqwest;
              extern crate semver;
-             extern crate serde_json;
+#[macro_use] extern crate serde_json;
+             extern crate sha2;
              extern crate slog_envlogger;
              extern crate slog_stdlog;
              extern crate slog_stream;
              extern crate time;
              extern crate tar;
+             extern crate toml;
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "ios")))]
+             extern crate openssl;
 
 // `slog` must precede `log` in declarations here, because we want to simultaneously:
 // * use the standard `log` macros
@@ -28,22 +38,82 @@
 
 
 mod args;
+mod bundle;
+mod cache;
+mod cargo_config;
+mod cargo_registry;
+mod checksum;
+mod checksum_db;
+mod closure;
+mod compare;
+mod digest;
+mod filter;
+mod git_index;
+mod hashed;
+mod http;
+mod index_sync;
+mod json_output;
+mod listing;
+mod local_source;
+mod lock;
 mod logging;
+mod output_template;
+mod prefetch;
+mod progress;
+mod rate_limit;
+mod registries;
+mod registry;
+mod registry_index;
+mod scaffold;
+mod self_check;
+mod source_check;
+mod source_replacement;
+mod sparse_index;
+mod space;
+mod state;
+mod summary;
+mod timing;
+mod units;
+mod unix_socket;
+mod vendor;
+mod warnings;
 
 
 use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, Read, Write};
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use log::LogLevel::*;
-use reqwest::header::CONTENT_LENGTH;
 use semver::Version;
-use serde_json::Value as Json;
 
-use args::{ArgsError, Crate, Output};
+use cargo_download::{Crate, CrateVersion, DependencyKind, Lockfile, ManifestIssue, Output,
+                     ResolvedVersion, VersionFilter};
+
+use args::{ArgsError, Command, GcOptions, OnCollision, Options, StatsOptions};
+use bundle::BundleWriter;
+use cache::Cache;
+use cargo_config::CargoConfig;
+use cargo_registry::CargoRegistry;
+use checksum_db::{Check, ChecksumDb};
+use compare::Report;
+use digest::FileDigest;
+use http::{Client, HttpConfig, HttpError};
+use lock::DirLock;
+use prefetch::Prefetch;
+use progress::{Progress, ProgressReader};
+use registry::{ChecksumMismatch, Registry};
+use registry_index::{Index, RegistryIndex};
+use state::ExtractState;
+use summary::{FailureClass, Outcome};
+use timing::{Phase, Timings};
+use warnings::{DeniedWarning, Warning, Warnings};
 
 
 lazy_static! {
@@ -55,6 +125,12 @@ lazy_static! {
     static ref VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 }
 
+/// Size of crate archives above which they're considered unusually large.
+const LARGE_ARCHIVE_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Exit code for a downloaded archive whose checksum doesn't match the registry's.
+const CHECKSUM_MISMATCH_EXIT_CODE: i32 = exitcode::PROTOCOL;
+
 
 fn main() {
     let opts = args::parse().unwrap_or_else(|e| {
@@ -63,67 +139,1375 @@ fn main() {
     });
 
     logging::init(opts.verbosity).unwrap();
+    progress::init(opts.verbosity != 0 || opts.jobs > 1);
+    if opts.json {
+        json_output::enable();
+    }
+    if let Some(ref dir) = opts.cache_dir {
+        cache::set_dir(dir.clone());
+    }
+    if let Some(rate) = opts.limit_rate {
+        rate_limit::set_limit(rate);
+    }
     log_signature();
+    match opts.command {
+        Command::Download(_) | Command::List(_) | Command::SyncFromIndex(_) | Command::Scaffold(..)
+            | Command::Import(_) => check_cache(),
+        _ => {}
+    }
 
-    let version = match opts.crate_.exact_version() {
-        Some(v) => {
-            debug!("Exact crate version given in arguments, not querying crates.io");
-            Cow::Borrowed(v)
+    if let Some(ref path) = opts.save_profile {
+        opts.to_config().save(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            exit(exitcode::CANTCREAT)
+        });
+        info!("Settings saved to profile {}", path.display());
+    }
+
+    summary::start(summary::Files{summary: opts.summary_file.clone(), metrics: opts.metrics.clone()},
+                   opts.quiet());
+    match opts.command {
+        Command::Download(ref crates) =>
+            download(&opts, crates, crates.len() > 1 || opts.lockfile.is_some()
+                || opts.from_binary.is_some() || opts.build_closure || opts.deps || opts.each_matching
+                || opts.all_versions),
+        Command::NormalizeSpec(ref crates) => for crate_ in crates {
+            println!("{}", crate_);
+        },
+        Command::ValidateSpec(ref specs) => if !validate_specs(specs) {
+            exit(exitcode::DATAERR);
+        },
+        Command::ListVersions(ref crates) => list_versions(&opts, crates),
+        Command::Info(ref crates) => crate_info(&opts, crates),
+        Command::List(ref crates) => list_contents(&opts, crates),
+        Command::CacheGc(ref gc_opts) => cache_gc(gc_opts),
+        Command::CacheStats(ref stats_opts) => cache_stats(stats_opts),
+        Command::SelfCheck => if !self_check::run(&opts) {
+            exit(exitcode::UNAVAILABLE);
+        },
+        Command::SyncFromIndex(ref index) => sync_from_index(&opts, index),
+        Command::Scaffold(ref crate_, ref dir) => scaffold(&opts, crate_, dir),
+        Command::Import(ref path) => import_bundle(&opts, path),
+    }
+    summary::report();
+    write_summary();
+}
+
+/// Print the outcome of validating each of given crate specifications as JSON,
+/// returning whether they're all valid.
+fn validate_specs(specs: &[String]) -> bool {
+    let mut all_valid = true;
+    for spec in specs {
+        let outcome = match cargo_download::validate_spec(spec) {
+            Ok(crates) => json!({
+                "spec": spec,
+                "valid": true,
+                "crates": crates.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            }),
+            Err(e) => {
+                all_valid = false;
+                json!({
+                    "spec": spec,
+                    "valid": false,
+                    "error": {
+                        "message": e.error.to_string(),
+                        "position": e.position,
+                        "expected": e.expected,
+                    },
+                })
+            }
+        };
+        println!("{}", outcome);
+    }
+    all_valid
+}
+
+/// Download given crates, as specified by the options.
+///
+/// In a batch, each crate is written under a versioned name.
+fn download(opts: &Options, crates: &[Crate], batch: bool) {
+    // A deadline too far in the future to be represented is as good as none.
+    let deadline = opts.deadline.and_then(|d| Instant::now().checked_add(d));
+
+    // Concatenated lists of crates often have some in common, which are downloaded only once.
+    let (unique_crates, repeated) = cargo_download::dedup_crates(crates);
+    for (crate_, count) in repeated {
+        warn!("Crate {} is given {} times, downloading it only once", crate_, count);
+    }
+    let crates = &unique_crates[..];
+    let registry = open_registry(opts);
+    let previous_report = opts.compare_report.as_ref().map(|path| {
+        Report::load(path).unwrap_or_else(|e| {
+            fail(exitcode::NOINPUT, format!("Failed to load the previous report {}: {}", path.display(), e))
+        })
+    });
+
+    // Exact versions given as arguments are downloaded even if they've been yanked, but not silently.
+    // Those from lockfiles and binaries have been pinned before, so they aren't checked.
+    let pinned = if opts.lockfile.is_none() && opts.from_binary.is_none() {
+        crates.iter()
+            .filter_map(|c| c.exact_version().map(|v| format!("{}=={}", c.name(), v)))
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let checksum_crates: Vec<_>;
+    let crates = match opts.by_checksum {
+        Some(ref checksum) => {
+            checksum_crates = crates.iter().map(|c| with_checksum(&registry, c, checksum)).collect();
+            &checksum_crates[..]
         }
-        None => Cow::Owned(get_newest_version(&opts.crate_).unwrap_or_else(|e| {
-            error!("Failed to get the newest version of crate {}: {}", opts.crate_, e);
-            exit(exitcode::TEMPFAIL);
-        })),
+        None => crates,
+    };
+    let matching_crates;
+    let crates = if opts.each_matching {
+        matching_crates = with_each_matching(&registry, crates, opts.version_filter(), None);
+        &matching_crates[..]
+    } else if opts.all_versions {
+        // Archives are complete with the yanked versions and pre-releases too.
+        let filter = VersionFilter{yanked: true, prerelease: true};
+        matching_crates = with_each_matching(&registry, crates, filter, opts.last);
+        &matching_crates[..]
+    } else {
+        crates
+    };
+    let closure_crates;
+    let mut dependents = closure::Dependents::new();
+    let crates = if opts.build_closure || opts.deps {
+        let (closure, closure_dependents) = with_closures(opts, &registry, crates);
+        closure_crates = closure;
+        dependents = closure_dependents;
+        &closure_crates[..]
+    } else {
+        crates
     };
-    let crate_bytes = download_crate(&opts.crate_.name(), &version).unwrap_or_else(|e| {
-        error!("Failed to download crate `{}=={}`: {}", opts.crate_.name(), version, e);
-        exit(exitcode::TEMPFAIL);
+
+    let lockfile = opts.lockfile.as_ref().map(|path| {
+        Lockfile::load(path, opts.input_format).unwrap_or_else(|e| fail(exitcode::NOINPUT, e.to_string()))
     });
+    let mut known_checksums = KnownChecksums{
+        db: opts.checksum_db.as_ref().map(|path| {
+            ChecksumDb::load(path).unwrap_or_else(|e| {
+                fail(exitcode::IOERR, format!("Failed to load the checksum database {}: {}", path.display(), e))
+            })
+        }),
+        lockfile: lockfile.as_ref(),
+    };
 
-    if opts.extract {
-        // Extract to a directory named $CRATE-$VERSION
-        // Due to how crate archives are structured (they contain
-        // single top-level directory) this is done automatically
-        // if you simply extract them in $CWD.
-        let dir: PathBuf = format!("./{}-{}", opts.crate_.name(), version).into();
-        debug!("Extracting crate archive to {}/", dir.display());
-        let gzip = flate2::read::GzDecoder::new(&crate_bytes[..]).unwrap();
-        let mut archive = tar::Archive::new(gzip);
-        match archive.unpack(".") {
-            Ok(_) => {
-                // If -x option was passed, we need to move the extracted directory
-                // to wherever the user wanted.
-                let mut dir = dir;
-                if let Some(&Output::Path(ref p)) = opts.output.as_ref() {
-                    fs::rename(&dir, p).unwrap_or_else(|e| {
-                        error!("Failed to move extracted archive from {} to {}: {}",
-                            dir.display(), p.display(), e);
-                        exit(exitcode::IOERR)
-                    });
-                    dir = p.clone();
+    let bundle = opts.bundle.as_ref().map(|path| {
+        BundleWriter::create(path).unwrap_or_else(|e| {
+            fail(exitcode::CANTCREAT, format!("Failed to create the bundle {}: {}", path.display(), e))
+        })
+    });
+    let batch = Batch::new(crates, batch, pinned, bundle);
+    let mut timings = vec![];
+    let mut files = vec![];
+    let mut unfinished = 0;
+    let mut failure_code = None;
+    let mut process = |mut prefetch: Option<&mut Prefetch>| for (i, crate_) in crates.iter().enumerate() {
+        summary::begin(&crate_.to_string());
+        if let Some(version) = crate_.exact_version() {
+            summary::set_version(crate_.name(), version);
+            json_output::set_version(version, None);
+        }
+        if let Some(dependents) = dependents.get(&crate_.to_string()) {
+            summary::set_dependents(dependents);
+            json_output::set_dependents(dependents);
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            summary::record(Outcome::Unfinished);
+            json_output::crate_processed(crate_.name(), Outcome::Unfinished);
+            unfinished += 1;
+            continue;
+        }
+        if opts.missing_only && is_present(opts, &registry, crate_, &batch, lockfile.as_ref()) {
+            summary::record(Outcome::Skipped);
+            json_output::crate_processed(crate_.name(), Outcome::Skipped);
+            continue;
+        }
+        if let Some(ref mut prefetch) = prefetch {
+            prefetch.wait(i);
+        }
+        let mut crate_timings = Timings::default();
+        // A crate that fails doesn't stop the others, but the run fails once they've all been processed.
+        match download_one(opts, &registry, crate_, &batch, &mut crate_timings, &mut files, &mut known_checksums) {
+            Ok(outcome) => {
+                summary::record(outcome);
+                json_output::crate_processed(crate_.name(), outcome);
+            }
+            Err(failure) => {
+                failure.record();
+                failure_code = failure_code.or(Some(failure.code));
+            }
+        }
+        timings.push((crate_, crate_timings));
+    };
+    // The archives fetched ahead are handed over through the download cache.
+    let mut jobs = opts.jobs;
+    if jobs > 1 && Cache::open_default().is_none() {
+        warn!("Cannot fetch crates concurrently without the download cache, fetching them one by one");
+        jobs = 1;
+    }
+    if jobs > 1 && crates.len() > 1 {
+        debug!("Fetching the archives of {} crates with {} jobs", crates.len(), jobs);
+        let wanted = |crate_: &Crate| {
+            !(opts.missing_only && is_present(opts, &registry, crate_, &batch, lockfile.as_ref()))
+        };
+        prefetch::run(&registry, crates, jobs, opts.version_filter(), wanted,
+                      |prefetch| process(Some(prefetch)));
+    } else {
+        process(None);
+    }
+    if let Some(bundle) = batch.bundle {
+        let path = opts.bundle.as_ref().unwrap();
+        let bundled = bundle.into_inner().unwrap().finish().unwrap_or_else(|e| {
+            fail(exitcode::IOERR, format!("Failed to write the bundle {}: {}", path.display(), e))
+        });
+        info!("Bundle of {} crate(s) written to {}", bundled.len(), path.display());
+    }
+    if let Some(ref path) = opts.digest_files {
+        digest::write_listing(path, &files).unwrap_or_else(|e| {
+            fail(exitcode::IOERR, format!("Failed to write file listing to {}: {}", path.display(), e))
+        });
+        info!("Listing of {} file(s) written to {}", files.len(), path.display());
+    }
+    if opts.timing {
+        print_timings(&timings).unwrap();
+    }
+    if let Some(ref previous) = previous_report {
+        print_changes(opts.compare_report.as_ref().unwrap(), previous).unwrap();
+    }
+    if unfinished > 0 {
+        warn!("The deadline of the run has passed, {} of {} crate(s) left out", unfinished, crates.len());
+    }
+    let code = failure_code.or(if unfinished > 0 { Some(exitcode::TEMPFAIL) } else { None });
+    if let Some(code) = code {
+        summary::report();
+        write_summary();
+        exit(code);
+    }
+}
+
+/// Resolve given crate to the version whose archive has given SHA-256 checksum in the registry index.
+fn with_checksum(registry: &Registry, crate_: &Crate, checksum: &str) -> Crate {
+    let versions = registry.fetch_index_versions(crate_.name()).unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+        format!("Failed to get the versions of crate `{}` from the index: {}", crate_.name(), e)));
+    // The checksum identifies the version exactly, so there's no reason to exclude any.
+    let filter = VersionFilter{yanked: true, prerelease: true};
+    let found = cargo_download::resolve_versions(crate_, versions, filter)
+        .find(|v| v.checksum.as_ref().is_some_and(|c| c.eq_ignore_ascii_case(checksum)));
+    match found {
+        Some(v) => {
+            info!("Version {} of crate `{}` has checksum {}", v.version, crate_.name(), checksum);
+            Crate::new(crate_.name(), CrateVersion::Exact(v.version)).unwrap()
+        }
+        None => fail(exitcode::DATAERR, format!(
+            "No version of crate {} has checksum {} in the registry index", crate_, checksum)),
+    }
+}
+
+/// Expand given crates into all their versions that satisfy the requirements
+/// (or only the given number of the newest ones), from the oldest.
+fn with_each_matching(registry: &Registry, crates: &[Crate], filter: VersionFilter,
+                      last: Option<usize>) -> Vec<Crate> {
+    let mut result = vec![];
+    for crate_ in crates {
+        if crate_.exact_version().is_some() {
+            result.push(crate_.clone());
+            continue;
+        }
+        let versions = fetch_versions(registry, crate_).unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+            format!("Failed to get the versions of crate {}: {}", crate_, e)));
+        let mut matching: Vec<_> = cargo_download::resolve_versions(crate_, versions, filter)
+            .map(|v| Crate::new(crate_.name(), CrateVersion::Exact(v.version)).unwrap())
+            .collect();
+        if matching.is_empty() {
+            fail(exitcode::TEMPFAIL, format!("No version of crate {} found", crate_));
+        }
+        info!("{} version(s) of crate {} match", matching.len(), crate_);
+        if let Some(last) = last {
+            matching.truncate(last);
+        }
+        matching.reverse();
+        result.extend(matching);
+    }
+    result
+}
+
+/// Resolve given crates to exact versions, adding the crates in their closures:
+/// the whole dependency closures with `--deps`, or the build closures otherwise.
+///
+/// With `--deps`, the crates are put in dependency order, i.e. every crate comes after its dependencies.
+/// The dependents of the crates in the closures are returned along with them.
+fn with_closures(opts: &Options, registry: &Registry, crates: &[Crate]) -> (Vec<Crate>, closure::Dependents) {
+    let kind = if opts.deps { "dependency" } else { "build" };
+    let mut result: Vec<Crate> = vec![];
+    let mut graph = vec![];
+    let mut dependents = closure::Dependents::new();
+    for crate_ in crates {
+        let version = match crate_.exact_version() {
+            Some(v) => v.clone(),
+            None => get_newest_version(registry, crate_, opts.version_filter(), &opts.warnings)
+                .unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+                    format!("Failed to get the newest version of crate {}: {}", crate_, e))),
+        };
+        let closure = if opts.deps {
+            closure::dependency_graph(registry, crate_.name(), &version).map(|(packages, dependents)| {
+                let closure = packages.iter().skip(1)
+                    .map(|p| Crate::new(p.name.clone(), CrateVersion::Exact(p.version.clone())).unwrap())
+                    .collect();
+                graph.extend(packages);
+                (closure, dependents)
+            })
+        } else {
+            closure::build_closure(registry, crate_.name(), &version)
+        };
+        let (closure, closure_dependents) = closure.unwrap_or_else(|e| {
+            fail(exitcode::TEMPFAIL, format!("Failed to determine the {} closure of crate `{}=={}`: {}",
+                kind, crate_.name(), version, e))
+        });
+        debug!("The {} closure of crate `{}=={}` has {} crate(s)", kind, crate_.name(), version, closure.len());
+        // The closures of several crates may overlap, with the same dependents in each of them.
+        for (dep, list) in closure_dependents {
+            let all = dependents.entry(dep).or_default();
+            for dependent in list {
+                if !all.contains(&dependent) {
+                    all.push(dependent);
                 }
-                info!("Crate content extracted to {}/", dir.display());
             }
-            Err(e) => {
-                error!("Couldn't extract crate to {}/: {}", dir.display(), e);
-                exit(exitcode::TEMPFAIL)
+        }
+
+        let root = Crate::new(crate_.name(), CrateVersion::Exact(version)).unwrap();
+        for c in Some(root).into_iter().chain(closure) {
+            if !result.iter().any(|r| r.to_string() == c.to_string()) {
+                result.push(c);
             }
         }
+    }
+    if opts.deps {
+        // Leaf dependencies are downloaded first, so that the crates can be consumed as they come.
+        result = closure::leaves_first(&graph).into_iter()
+            .map(|p| Crate::new(p.name.clone(), CrateVersion::Exact(p.version.clone())).unwrap())
+            .collect();
+    }
+    if let Some(ref path) = opts.order_file {
+        let order: String = result.iter().map(|c| format!("{}\n", c)).collect();
+        fs::write(path, order).unwrap_or_else(|e| {
+            fail(exitcode::CANTCREAT, format!("Failed to write the download order to {}: {}", path.display(), e))
+        });
+        info!("Download order of {} crate(s) written to {}", result.len(), path.display());
+    }
+    if let Some(ref path) = opts.emit_lockfile {
+        // Like Cargo, refer to crates.io by its canonical source whatever the protocol.
+        let source = registry.index().source_id();
+        fs::write(path, cargo_download::format_lockfile(&graph, &source)).unwrap_or_else(|e| {
+            fail(exitcode::CANTCREAT, format!("Failed to write the lockfile {}: {}", path.display(), e))
+        });
+        info!("Lockfile of {} crate(s) written to {}", result.len(), path.display());
+    }
+    (result, dependents)
+}
+
+/// Download the crate versions added to the git registry index at given path
+/// since the previous sync of the output directory.
+fn sync_from_index(opts: &Options, index: &Path) {
+    let dir = match opts.output {
+        Some(Output::Path(ref p)) => p.clone(),
+        _ => PathBuf::from("."),
+    };
+    let head = index_sync::head_commit(index).unwrap_or_else(|e| {
+        fail(exitcode::NOINPUT, format!("Failed to read the registry index at {}: {}",
+            index.display(), e))
+    });
+    let last = index_sync::last_synced_commit(&dir).unwrap_or_else(|e| {
+        fail(exitcode::IOERR, format!("Failed to read {} in {}/: {}",
+            index_sync::STATE_FILE, dir.display(), e))
+    });
+
+    match last {
+        None => info!("No previous sync of {}/, starting from index commit {}", dir.display(), head),
+        Some(ref last) if *last == head => info!("Already synced to index commit {}", head),
+        Some(ref last) => {
+            let crates = index_sync::added_crates(index, last, &head).unwrap_or_else(|e| {
+                fail(exitcode::NOINPUT, format!("Failed to diff the registry index at {}: {}",
+                    index.display(), e))
+            });
+            info!("{} crate version(s) added since index commit {}", crates.len(), last);
+            download(opts, &crates, true);
+        }
+    }
+
+    // Individual downloads exit on failure, so getting here means everything is synced.
+    index_sync::record_synced_commit(&dir, &head).unwrap_or_else(|e| {
+        fail(exitcode::IOERR, format!("Failed to write {} in {}/: {}",
+            index_sync::STATE_FILE, dir.display(), e))
+    });
+}
+
+/// Assemble a project skeleton in given directory that builds given crate offline.
+fn scaffold(opts: &Options, crate_: &Crate, dir: &Path) {
+    let registry = open_registry(opts);
+
+    summary::begin(&crate_.to_string());
+    let version = match crate_.exact_version() {
+        Some(v) => v.clone(),
+        None => get_newest_version(&registry, crate_, opts.version_filter(), &opts.warnings)
+            .unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+                format!("Failed to get the newest version of crate {}: {}", crate_, e))),
+    };
+    let count = scaffold::write_skeleton(&registry, crate_.name(), &version, dir).unwrap_or_else(|e| {
+        fail(exitcode::TEMPFAIL, format!("Failed to assemble the project skeleton for crate `{}=={}` in {}/: {}",
+            crate_.name(), version, dir.display(), e))
+    });
+    summary::record(Outcome::Succeeded(0));
+    info!("Project skeleton with {} vendored crate(s) written to {}/, build it there with `cargo build --offline`",
+        count, dir.display());
+}
+
+/// Import the crates from the bundle at given path into the download cache,
+/// and into a local registry in the output directory, if one is given.
+fn import_bundle(opts: &Options, path: &Path) {
+    let cache = Cache::open_default().filter(|c| !c.is_read_only());
+    let dir = match opts.output {
+        Some(Output::Path(ref p)) => Some(p.as_path()),
+        _ => None,
+    };
+    if cache.is_none() && dir.is_none() {
+        fail(exitcode::CANTCREAT, "Cannot import the bundle without a writable download cache, \
+                                   give --output to import it into a local registry".to_owned());
+    }
+
+    let crates = bundle::import(path, cache.as_ref(), dir).unwrap_or_else(|e| {
+        let exit_code = if e.kind() == io::ErrorKind::InvalidData { exitcode::DATAERR } else { exitcode::IOERR };
+        fail(exit_code, format!("Failed to import the bundle {}: {}", path.display(), e))
+    });
+    for crate_ in &crates {
+        debug!("Imported crate `{}=={}`", crate_.name, crate_.version);
+    }
+    if let Some(ref cache) = cache {
+        info!("{} crate(s) from {} imported into the download cache at {}/",
+            crates.len(), path.display(), cache.root().display());
+    }
+    if let Some(dir) = dir {
+        info!("{} crate(s) from {} imported into the local registry at {}/",
+            crates.len(), path.display(), dir.display());
+    }
+}
+
+/// Set up access to the registry that crates are downloaded from, as specified by the options.
+///
+/// Exits the program if that's not possible.
+fn open_registry(opts: &Options) -> Registry {
+    let mut registry = connect_registry(opts);
+    registry.set_verify(!opts.no_verify);
+    // Crates of other registries are kept apart from those of crates.io in the download cache.
+    cache::set_registry(&registry.source_id());
+    registry
+}
+
+/// Connect to the registry specified by the options.
+fn connect_registry(opts: &Options) -> Registry {
+    let http_config = http_config(opts).unwrap_or_else(|e| {
+        fail(exitcode::CONFIG, e.to_string())
+    });
+    let client = Client::new(&http_config).unwrap_or_else(|e| {
+        fail(exitcode::CONFIG, format!("Failed to initialize HTTP client: {}", e))
+    });
+    // Git indices are fetched by git rather than the HTTP client, so it's given the timeout too
+    // (or the client's default of 30s).
+    index_sync::set_stall_timeout(http_config.timeout.unwrap_or(Duration::from_secs(30)));
+    if let Some(ref index) = opts.index {
+        let index = RegistryIndex::new(Index::Sparse(index.clone()));
+        let dl_url = match opts.dl_url {
+            Some(ref url) => url.clone(),
+            None if client.is_offline() => String::new(),
+            None => index.fetch_dl(&client, None).unwrap_or_else(|e| {
+                fail(exitcode::CONFIG, format!("Failed to read the configuration of index {}: {}", index, e))
+            }),
+        };
+        return Registry::alternative(client, index, &dl_url, None);
+    }
+    // Explicit crates.io endpoints take precedence over the source replacement, like --index does.
+    if opts.registry.is_none() && (opts.index_url.is_some() || opts.dl_url.is_some()) {
+        return Registry::new(client, opts.index_url.as_deref(), opts.dl_url.as_deref());
+    }
+    let name = opts.registry.as_deref().unwrap_or(registries::CRATES_IO);
+    let cargo_config = CargoConfig::load().unwrap_or_else(|e| fail(exitcode::CONFIG, e.to_string()));
+    let replacement = source_replacement::find(&cargo_config, name).unwrap_or_else(|e| {
+        fail(exitcode::CONFIG, format!("Failed to set up registry `{}`: {}", name, e))
+    });
+    let registry = match replacement {
+        Some((replacement_name, replacement)) => {
+            info!("Registry `{}` is replaced with source `{}`", name, replacement_name);
+            registries::open_replacement(client, &cargo_config, name, &replacement_name, replacement)
+        }
+        None if opts.registry.is_none() => return Registry::new(client, None, None),
+        None => registries::open(client, &cargo_config, name),
+    };
+    registry.unwrap_or_else(|e| fail(exitcode::CONFIG, format!("Failed to set up registry `{}`: {}", name, e)))
+}
+
+/// Determine the network configuration.
+///
+/// Network settings come from Cargo's config files and environment variables,
+/// with our command line flags taking precedence.
+fn http_config(opts: &Options) -> Result<HttpConfig, Box<dyn Error>> {
+    let cargo_config = CargoConfig::load()?;
+    let mut http_config = HttpConfig::load(&cargo_config)?;
+    http_config.offline |= opts.offline;
+    if let Some(ref proxy) = opts.proxy {
+        http_config.proxy = Some(proxy.clone());
+    }
+    if let Some(timeout) = opts.timeout {
+        http_config.timeout = Some(timeout);
+    }
+    http_config.connect_timeout = opts.connect_timeout;
+    http_config.dns_timeout = opts.dns_timeout;
+    http_config.max_redirects = opts.max_redirects;
+    http_config.client_identity = opts.client_identity.clone();
+    if let Some(retries) = opts.retries {
+        http_config.retries = Some(retries);
+    }
+    http_config.retry_delay = opts.retry_delay;
+    Ok(http_config)
+}
+
+/// Download a single crate, as specified by the options.
+///
+/// If the crate is a part of a batch, it's written under a versioned name
+/// (inside the output directory, if one was given).
+/// Time spent in each phase is added to `timings`,
+/// and the files of the archive are added to `files` if they're being listed.
+/// The archive is checked against the checksum database, if there is one.
+fn download_one(opts: &Options, registry: &Registry, crate_: &Crate, batch: &Batch,
+                timings: &mut Timings, files: &mut Vec<FileDigest>,
+                known_checksums: &mut KnownChecksums) -> Result<Outcome, CrateFailure> {
+    let version = match crate_.exact_version() {
+        Some(v) => {
+            debug!("Exact crate version given in arguments, not querying crates.io");
+            Cow::Borrowed(v)
+        }
+        None => {
+            let newest = timings.measure(Phase::Resolve,
+                || get_newest_version(registry, crate_, opts.version_filter(), &opts.warnings));
+            Cow::Owned(newest.map_err(|e| {
+                // A denied warning (about yanking) isn't a transient failure.
+                let code = if e.is::<DeniedWarning>() { exitcode::DATAERR } else { exitcode::TEMPFAIL };
+                CrateFailure::new(code, format!("Failed to get the newest version of crate {}: {}", crate_, e))
+            })?)
+        }
+    };
+    if !batch.claim(crate_.name(), &version) {
+        warn!("Crate {} resolves to `{}=={}`, which has been processed already", crate_, crate_.name(), version);
+        return Ok(Outcome::Skipped);
+    }
+    summary::set_version(crate_.name(), &version);
+    let pinned = batch.pinned.contains(&format!("{}=={}", crate_.name(), version));
+    if json_output::is_enabled() || pinned {
+        let yanked = timings.measure(Phase::Resolve, || is_yanked(registry, crate_, &version));
+        if pinned && yanked == Some(true) {
+            opts.warnings.emit(Warning::YankedVersion, format!(
+                "Version {} of crate `{}` has been yanked", version, crate_.name()))?;
+        }
+        json_output::set_version(&version, yanked);
+    }
+    if opts.warn_older_than.is_some() || opts.warn_newer_major {
+        timings.measure(Phase::Resolve, || warn_if_stale(opts, registry, crate_.name(), &version))?;
+    }
+    if opts.cross_verify {
+        timings.measure(Phase::Verify, || cross_verify(registry, crate_.name(), &version))?;
+    }
+    if opts.name_by_hash {
+        return download_by_hash(opts, registry, crate_.name(), &version, timings, files, known_checksums);
+    }
+    if opts.cargo_cache {
+        return download_to_cargo_cache(opts, registry, crate_.name(), &version, timings, files, known_checksums);
+    }
+    // Bundled crates aren't output anywhere else.
+    let output = if batch.bundle.is_some() { None } else { output_for(opts, registry, crate_, &version, batch)? };
+    let output = output.map(|o| claim_output(opts, registry, crate_.name(), &version, o, batch)).transpose()?;
+    if opts.extract {
+        json_output::set_path(&extract_dir(output.as_ref(), crate_, &version));
+    } else if let Some(Output::Path(ref p)) = output {
+        json_output::set_path(p);
+    }
+
+    // Lock the directory we'll be writing to, so that concurrent runs
+    // don't trample on each other's partially written files.
+    let lock_dir = if opts.extract {
+        Some(lock::parent_dir(&extract_dir(output.as_ref(), crate_, &version)))
     } else {
-        let output = opts.output.as_ref().unwrap_or(&Output::Stdout);
         match output {
-            &Output::Stdout => { io::stdout().write_all(&crate_bytes).unwrap(); }
-            &Output::Path(ref p) => {
-                let mut file = fs::OpenOptions::new()
-                    .write(true).create(true)
-                    .open(p).unwrap_or_else(|e| {
-                        error!("Failed to open output file {}: {}", p.display(), e);
-                        exit(exitcode::IOERR)
-                    });
-                file.write_all(&crate_bytes).unwrap();
-                info!("Crate's archive written to {}", p.display());
+            Some(Output::Path(ref p)) => Some(lock::parent_dir(p)),
+            _ => None,
+        }
+    };
+    let _lock = lock_dir.map(|dir| DirLock::acquire(&dir).map_err(|e| lock_failed(&dir, e))).transpose()?;
+
+    // If the crate is already where we would put it
+    // and its checksum agrees with crates.io, there is nothing to do
+    // (except maybe inspecting its archive).
+    let mut up_to_date = false;
+    if !opts.force {
+        if opts.vendor {
+            let dir = extract_dir(output.as_ref(), crate_, &version);
+            if timings.measure(Phase::Verify, || is_vendored(registry, &dir, crate_.name(), &version)) {
+                info!("Vendored crate at {}/ is up to date", dir.display());
+                up_to_date = true;
+            }
+        } else if opts.extract {
+            let dir = extract_dir(output.as_ref(), crate_, &version);
+            let extracted = timings.measure(Phase::Verify,
+                || is_extracted(registry, &dir, crate_.name(), &version, &opts.filter));
+            if extracted {
+                info!("Crate content at {}/ is up to date", dir.display());
+                up_to_date = true;
+            }
+        } else if let Some(Output::Path(ref p)) = output {
+            if timings.measure(Phase::Verify, || is_up_to_date(registry, p, crate_.name(), &version)) {
+                info!("Crate's archive at {} is up to date", p.display());
+                up_to_date = true;
+            }
+        }
+    }
+    if up_to_date && !inspects_archive(opts) {
+        return Ok(Outcome::Skipped);
+    }
+    // Unless the whole archive is needed for something else, it's extracted as it's being received.
+    let needs_archive = opts.vendor || inspects_archive(opts) || batch.bundle.is_some() ||
+        known_checksums.db.is_some() || known_checksums.lockfile.is_some();
+    if opts.extract && !needs_archive && registry.verifies() && !registry.is_local() && !registry.is_offline() {
+        let (size, checksum) = timings.measure(Phase::Download,
+            || download_extracting(opts, registry, crate_, &version, output.as_ref()))?;
+        json_output::set_archive_digest(size, &checksum);
+        summary::set_archive_checksum(&checksum);
+        return Ok(Outcome::Succeeded(size));
+    }
+
+    let crate_bytes = timings.measure(Phase::Download, || download_crate(registry, crate_.name(), &version))
+        .map_err(|e| download_failed(crate_.name(), &version, e))?;
+    json_output::set_archive(&crate_bytes);
+    summary::set_archive(&crate_bytes);
+    check_known_checksums(known_checksums, crate_.name(), &version, &crate_bytes)?;
+    inspect_archive(opts, crate_.name(), &version, &crate_bytes, files)?;
+
+    if up_to_date {
+        return Ok(Outcome::Skipped);
+    }
+    if let Some(ref bundle) = batch.bundle {
+        timings.measure(Phase::Extract,
+            || add_to_bundle(registry, crate_.name(), &version, &crate_bytes, bundle))?;
+        return Ok(Outcome::Succeeded(crate_bytes.len() as u64));
+    }
+    timings.measure(Phase::Extract, || write_output(opts, crate_, &version, output, &crate_bytes))?;
+    Ok(Outcome::Succeeded(crate_bytes.len() as u64))
+}
+
+/// Download a single crate version into the output directory,
+/// naming the archive after its SHA-256 digest.
+fn download_by_hash(opts: &Options, registry: &Registry, name: &str, version: &Version,
+                    timings: &mut Timings, files: &mut Vec<FileDigest>,
+                    known_checksums: &mut KnownChecksums) -> Result<Outcome, CrateFailure> {
+    let dir = match opts.output {
+        Some(Output::Path(ref p)) => p.clone(),
+        _ => PathBuf::from("."),
+    };
+    let _lock = DirLock::acquire(&dir).map_err(|e| lock_failed(&dir, e))?;
+
+    // If the archive with the expected digest is already there, there is nothing to do
+    // (except maybe inspecting its archive).
+    let mut up_to_date = false;
+    if !opts.force {
+        let existing = timings.measure(Phase::Verify, || {
+            let checksum = get_checksum(registry, name, version).ok()?;
+            let path = dir.join(hashed::file_name(&checksum));
+            checksum::file_matches(&path, &checksum).ok().filter(|&m| m).map(|_| path)
+        });
+        if let Some(path) = existing {
+            info!("Crate's archive at {} is up to date", path.display());
+            json_output::set_path(&path);
+            up_to_date = true;
+        }
+    }
+    if up_to_date && !inspects_archive(opts) {
+        return Ok(Outcome::Skipped);
+    }
+
+    let crate_bytes = timings.measure(Phase::Download, || download_crate(registry, name, version))
+        .map_err(|e| download_failed(name, version, e))?;
+    json_output::set_archive(&crate_bytes);
+    summary::set_archive(&crate_bytes);
+    check_known_checksums(known_checksums, name, version, &crate_bytes)?;
+    inspect_archive(opts, name, version, &crate_bytes, files)?;
+    if up_to_date {
+        return Ok(Outcome::Skipped);
+    }
+
+    timings.measure(Phase::Extract, || -> Result<(), CrateFailure> {
+        let digest = checksum::sha256_reader(&crate_bytes[..]).unwrap();
+        let file_name = hashed::file_name(&digest);
+        let path = dir.join(&file_name);
+        json_output::set_path(&path);
+        cache::write_archive(name, version, &crate_bytes, &path).map_err(|e| {
+            CrateFailure::new(exitcode::IOERR, format!("Failed to write output file {}: {}", path.display(), e))
+        })?;
+        if opts.verify_decompress {
+            verify_decompress(name, version, &path)?;
+        }
+        hashed::record(&dir, &file_name, name, version).map_err(|e| {
+            CrateFailure::new(exitcode::IOERR, format!("Failed to update {} in {}/: {}",
+                hashed::MAPPING_FILE, dir.display(), e))
+        })?;
+        info!("Crate's archive written to {}", path.display());
+        Ok(())
+    })?;
+    Ok(Outcome::Succeeded(crate_bytes.len() as u64))
+}
+
+/// Download a single crate version into Cargo's own registry cache (`--cache`),
+/// along with its index file, and unpack it there too if requested.
+fn download_to_cargo_cache(opts: &Options, registry: &Registry, name: &str, version: &Version,
+                           timings: &mut Timings, files: &mut Vec<FileDigest>,
+                           known_checksums: &mut KnownChecksums) -> Result<Outcome, CrateFailure> {
+    // Cargo keeps the index files of git registries in a checkout of the index, which isn't populated.
+    let index = match *registry.index().configured() {
+        Index::Sparse(ref index) => index,
+        Index::Git(_) => return Err(CrateFailure::new(exitcode::CONFIG, format!(
+            "Cannot put crates into Cargo's cache for the git index {} (only sparse indices are supported)",
+            registry.index()))),
+    };
+    let cargo_registry = CargoRegistry::locate(index).ok_or_else(|| {
+        CrateFailure::new(exitcode::CONFIG, "Cannot determine Cargo's home directory (set CARGO_HOME)".to_owned())
+    })?;
+    let _lock = cargo_registry.lock().map_err(|e| {
+        CrateFailure::new(exitcode::IOERR, format!("Failed to lock Cargo's package cache: {}", e))
+    })?;
+
+    // If Cargo has the archive (and its sources, if asked for) already, there is nothing to do
+    // (except maybe inspecting its archive).
+    let path = cargo_registry.crate_path(name, version);
+    json_output::set_path(&path);
+    let mut up_to_date = false;
+    if !opts.force && (!opts.cargo_cache_src || cargo_registry.has_src(name, version)) &&
+            timings.measure(Phase::Verify, || is_up_to_date(registry, &path, name, version)) {
+        info!("Crate's archive at {} is up to date", path.display());
+        up_to_date = true;
+    }
+    if up_to_date && !inspects_archive(opts) {
+        return Ok(Outcome::Skipped);
+    }
+
+    let crate_bytes = timings.measure(Phase::Download, || download_crate(registry, name, version))
+        .map_err(|e| download_failed(name, version, e))?;
+    json_output::set_archive(&crate_bytes);
+    summary::set_archive(&crate_bytes);
+    check_known_checksums(known_checksums, name, version, &crate_bytes)?;
+    inspect_archive(opts, name, version, &crate_bytes, files)?;
+    if up_to_date {
+        return Ok(Outcome::Skipped);
+    }
+
+    timings.measure(Phase::Extract, || -> Result<(), CrateFailure> {
+        // Cargo resolves the dependencies from its copy of the index when it's offline,
+        // so the crate is of no use to it without its index file (and the index's config).
+        if !cargo_registry.has_index_config() {
+            match registry.fetch_index_config() {
+                Ok(content) => cargo_registry.put_index_config(&content).map_err(|e| {
+                    CrateFailure::new(exitcode::IOERR, format!(
+                        "Failed to write the index configuration into {}: {}",
+                        cargo_registry.index_dir().display(), e))
+                })?,
+                Err(e) => warn!("Couldn't fetch the index configuration for Cargo's cache: {}", e),
+            }
+        }
+        match registry.fetch_index_file(name) {
+            Ok((_, content)) => cargo_registry.put_index_file(name, &content).map_err(|e| {
+                CrateFailure::new(exitcode::IOERR, format!(
+                    "Failed to write the index file of crate `{}` into {}: {}",
+                    name, cargo_registry.index_dir().display(), e))
+            })?,
+            Err(e) => warn!("Couldn't fetch the index file of crate `{}` for Cargo's cache: {}", name, e),
+        }
+        let path = cargo_registry.put_crate(name, version, &crate_bytes).map_err(|e| {
+            CrateFailure::new(exitcode::IOERR, format!("Failed to write output file {}: {}", path.display(), e))
+        })?;
+        info!("Crate's archive written to {}", path.display());
+        if opts.verify_decompress {
+            verify_decompress(name, version, &path)?;
+        }
+        if opts.cargo_cache_src {
+            check_space(name, version, &crate_bytes, &cargo_registry.src_dir())?;
+            let dir = cargo_registry.put_src(name, version, &crate_bytes).map_err(|e| {
+                CrateFailure::new(exitcode::IOERR, format!(
+                    "Failed to unpack crate `{}=={}` into Cargo's sources: {}", name, version, e))
+            })?;
+            info!("Crate's content extracted to {}/", dir.display());
+        }
+        Ok(())
+    })?;
+    Ok(Outcome::Succeeded(crate_bytes.len() as u64))
+}
+
+/// Add given crate version to the bundle, along with its entry from the registry index.
+fn add_to_bundle(registry: &Registry, name: &str, version: &Version, crate_bytes: &[u8],
+                 bundle: &Mutex<BundleWriter>) -> Result<(), CrateFailure> {
+    let (_, index_file) = registry.fetch_index_file(name).map_err(|e| {
+        CrateFailure::new(exitcode::TEMPFAIL, format!("Failed to fetch the index file of crate `{}`: {}", name, e))
+    })?;
+    bundle.lock().unwrap().add(name, version, crate_bytes, &index_file).map_err(|e| {
+        CrateFailure::new(exitcode::IOERR, format!("Failed to add crate `{}=={}` to the bundle: {}",
+            name, version, e))
+    })?;
+    info!("Crate `{}=={}` added to the bundle", name, version);
+    Ok(())
+}
+
+/// Checksums known beforehand that the downloaded archives are checked against,
+/// in addition to those from the registry.
+struct KnownChecksums<'l> {
+    /// Database of the checksums of crates downloaded before (`--checksum-db`).
+    db: Option<ChecksumDb>,
+    /// Lockfile that the crates were read from (`--lockfile`), which pins their checksums.
+    lockfile: Option<&'l Lockfile>,
+}
+
+/// Check given crate archive against the known checksums,
+/// recording its digest in the checksum database if the crate version is new to it.
+///
+/// Fails if the archive doesn't match the lockfile, or differs from the one recorded in the database before.
+fn check_known_checksums(known: &mut KnownChecksums, name: &str, version: &Version,
+                         crate_bytes: &[u8]) -> Result<(), CrateFailure> {
+    if known.db.is_none() && known.lockfile.is_none() {
+        return Ok(());
+    }
+    let digest = checksum::sha256_reader(crate_bytes).unwrap();
+    if let Some(lockfile) = known.lockfile {
+        match lockfile.checksum(name, version) {
+            Some(pinned) if pinned.eq_ignore_ascii_case(&digest) =>
+                debug!("Crate `{}=={}` matches its checksum in the lockfile", name, version),
+            Some(pinned) => return Err(CrateFailure::new(CHECKSUM_MISMATCH_EXIT_CODE, format!(
+                "Crate `{}=={}` doesn't match the lockfile: its SHA-256 checksum is {}, but the lockfile pins {}",
+                name, version, digest, pinned))),
+            None => debug!("Lockfile has no checksum of crate `{}=={}`", name, version),
+        }
+    }
+    let checksum_db = match known.db {
+        Some(ref mut db) => db,
+        None => return Ok(()),
+    };
+    match checksum_db.check(name, version, &digest) {
+        Check::Matches => debug!("Crate `{}=={}` matches the checksum database", name, version),
+        Check::Recorded => {
+            checksum_db.save().map_err(|e| {
+                CrateFailure::new(exitcode::IOERR, format!("Failed to update the checksum database {}: {}",
+                    checksum_db.path().display(), e))
+            })?;
+            debug!("Checksum of crate `{}=={}` recorded in the checksum database", name, version);
+        }
+        Check::Differs(known) => return Err(CrateFailure::new(exitcode::DATAERR, format!(concat!(
+            "Crate `{}=={}` differs from when it was first downloaded: ",
+            "its SHA-256 checksum is {}, but {} records {}"),
+            name, version, digest, checksum_db.path().display(), known))),
+    }
+    Ok(())
+}
+
+/// Whether the options ask for inspecting the crate archives even if they're up to date.
+fn inspects_archive(opts: &Options) -> bool {
+    opts.lint_manifest || opts.allowed_licenses.is_some() || opts.digest_files.is_some() || opts.verify_source
+}
+
+/// Inspect the content of given crate archive as requested by the options:
+/// lint its manifest, compare it against its repository, and/or add its files to the listing.
+fn inspect_archive(opts: &Options, name: &str, version: &Version, crate_bytes: &[u8],
+                   files: &mut Vec<FileDigest>) -> Result<(), CrateFailure> {
+    if crate_bytes.len() as u64 > LARGE_ARCHIVE_SIZE {
+        opts.warnings.emit(Warning::LargeArchive, format!("Archive of crate `{}=={}` is unusually large ({})",
+            name, version, units::format_size(crate_bytes.len() as u64)))?;
+    }
+    if opts.lint_manifest {
+        lint_manifest(name, version, crate_bytes, &opts.warnings)?;
+    }
+    if let Some(ref allowed) = opts.allowed_licenses {
+        check_license(name, version, crate_bytes, allowed, &opts.warnings)?;
+    }
+    if opts.verify_source {
+        verify_source(name, version, crate_bytes, &opts.warnings)?;
+    }
+    if opts.digest_files.is_some() {
+        let crate_files = digest::list_files(name, version, crate_bytes).map_err(|e| {
+            CrateFailure::new(exitcode::DATAERR, format!("Failed to list the files of crate `{}=={}`: {}",
+                name, version, e))
+        })?;
+        files.extend(crate_files);
+    }
+    Ok(())
+}
+
+/// Decode the whole archive of given crate that's been saved at given path (`--verify-decompress`),
+/// removing it and failing if it isn't a well-formed gzipped tarball.
+fn verify_decompress(name: &str, version: &Version, path: &Path) -> Result<(), CrateFailure> {
+    match fs::File::open(path).and_then(decode_archive) {
+        Ok(count) => {
+            debug!("Archive of crate `{}=={}` at {} decompressed cleanly ({} entries)",
+                name, version, path.display(), count);
+            Ok(())
+        }
+        Err(e) => {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Failed to remove the malformed archive {}: {}", path.display(), e);
+            }
+            Err(CrateFailure::new(exitcode::DATAERR, format!(
+                "Archive of crate `{}=={}` written to {} is malformed: {}", name, version, path.display(), e)))
+        }
+    }
+}
+
+/// Read through a crate archive without unpacking it, returning the number of its entries.
+///
+/// The compressed stream is read to its very end, so that its checksum (if any) is verified too.
+fn decode_archive<R: Read>(reader: R) -> io::Result<usize> {
+    let mut archive = tar::Archive::new(cargo_download::decompress(reader)?);
+    let mut count = 0;
+    for entry in archive.entries()? {
+        io::copy(&mut entry?, &mut io::sink())?;
+        count += 1;
+    }
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(count)
+}
+
+/// Report dependency hygiene red flags in the manifest of given crate archive.
+fn lint_manifest(name: &str, version: &Version, crate_bytes: &[u8],
+                 warnings: &Warnings) -> Result<(), DeniedWarning> {
+    let manifest = match read_manifest(name, version, crate_bytes) {
+        Ok(m) => m,
+        Err(e) => {
+            warn!("Couldn't read Cargo.toml of crate `{}=={}`: {}", name, version, e);
+            return Ok(());
+        }
+    };
+    match cargo_download::lint_manifest(&manifest) {
+        Ok(ref issues) if issues.is_empty() =>
+            info!("No red flags in Cargo.toml of crate `{}=={}`", name, version),
+        Ok(issues) => for issue in issues {
+            let warning = match issue {
+                ManifestIssue::WildcardRequirement(_) => Warning::WildcardRequirement,
+                ManifestIssue::GitDependency(_) => Warning::GitDependency,
+                ManifestIssue::PathDependency(_) => Warning::PathDependency,
+                ManifestIssue::MissingLicense => Warning::NoLicense,
+            };
+            warnings.emit(warning, format!("Cargo.toml of crate `{}=={}`: {}", name, version, issue))?;
+        },
+        Err(e) => warn!("Couldn't parse Cargo.toml of crate `{}=={}`: {}", name, version, e),
+    }
+    Ok(())
+}
+
+/// Check the license declared in given crate archive's Cargo.toml against the allowed ones,
+/// emitting a warning if it isn't among them.
+fn check_license(name: &str, version: &Version, crate_bytes: &[u8], allowed: &[String],
+                 warnings: &Warnings) -> Result<(), DeniedWarning> {
+    let license = match read_manifest(name, version, crate_bytes) {
+        Ok(manifest) => cargo_download::manifest_license(&manifest),
+        Err(e) => {
+            warn!("Couldn't read Cargo.toml of crate `{}=={}`: {}", name, version, e);
+            return Ok(());
+        }
+    };
+    match license {
+        Ok(Some(ref license)) if cargo_download::license_allowed(license, allowed) =>
+            debug!("License of crate `{}=={}` is allowed: {}", name, version, license),
+        Ok(Some(license)) => warnings.emit(Warning::DisallowedLicense, format!(
+            "Crate `{}=={}` is licensed under {}, which isn't allowed", name, version, license))?,
+        Ok(None) => warnings.emit(Warning::DisallowedLicense, format!(
+            "Crate `{}=={}` declares no SPDX license, so it isn't allowed", name, version))?,
+        Err(e) => warn!("Couldn't parse Cargo.toml of crate `{}=={}`: {}", name, version, e),
+    }
+    Ok(())
+}
+
+/// Compare the files in given crate archive against its git repository,
+/// emitting a warning for every one that differs or that's missing from the repository.
+fn verify_source(name: &str, version: &Version, crate_bytes: &[u8],
+                 warnings: &Warnings) -> Result<(), DeniedWarning> {
+    let report = match source_check::verify_source(name, version, crate_bytes) {
+        Ok(report) => report,
+        Err(e) => {
+            warn!("Couldn't verify crate `{}=={}` against its repository: {}", name, version, e);
+            return Ok(());
+        }
+    };
+    if report.matches() {
+        info!("Files of crate `{}=={}` match its repository {} at {}",
+            name, version, report.repository, report.rev);
+    }
+    for path in &report.differing {
+        warnings.emit(Warning::SourceMismatch, format!(
+            "File {} of crate `{}=={}` differs from its repository {} at {}",
+            path, name, version, report.repository, report.rev))?;
+    }
+    for path in &report.archive_only {
+        warnings.emit(Warning::SourceMismatch, format!(
+            "File {} of crate `{}=={}` isn't in its repository {} at {}",
+            path, name, version, report.repository, report.rev))?;
+    }
+    Ok(())
+}
+
+/// Read the Cargo.toml file from given crate archive.
+fn read_manifest(name: &str, version: &Version, crate_bytes: &[u8]) -> io::Result<String> {
+    let manifest_path = PathBuf::from(format!("{}-{}", name, version)).join("Cargo.toml");
+    let mut archive = tar::Archive::new(cargo_download::decompress(crate_bytes)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()? == manifest_path {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return Ok(content);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no Cargo.toml in the archive"))
+}
+
+/// Write the downloaded crate to its output, extracting it if requested.
+fn write_output(opts: &Options, crate_: &Crate, version: &Version,
+                output: Option<Output>, crate_bytes: &[u8]) -> Result<(), CrateFailure> {
+    if opts.vendor {
+        let dir = extract_dir(output.as_ref(), crate_, version);
+        debug!("Vendoring crate to {}/", dir.display());
+        check_space(crate_.name(), version, crate_bytes, &dir)?;
+        vendor::unpack(crate_.name(), version, crate_bytes, &dir).map_err(|e| {
+            CrateFailure::new(exitcode::IOERR, format!("Couldn't vendor crate to {}/: {}", dir.display(), e))
+        })?;
+        info!("Crate vendored to {}/", dir.display());
+    } else if opts.extract {
+        // Only some of the files may be extracted, so they're not worth the check.
+        if opts.filter.is_empty() {
+            let parent_dir = lock::parent_dir(&extract_dir(output.as_ref(), crate_, version));
+            check_space(crate_.name(), version, crate_bytes, &parent_dir)?;
+        }
+        extract(opts, crate_, version, output.as_ref(), crate_bytes, Some(crate_bytes.len() as u64), None)?;
+    } else {
+        // A tarball is the crate's archive decompressed, rather than the archive as it's been downloaded.
+        let tarball;
+        let (crate_bytes, what) = if opts.tar {
+            tarball = decompress_archive(crate_.name(), version, crate_bytes)?;
+            (&tarball[..], "tarball")
+        } else {
+            (crate_bytes, "archive")
+        };
+        match output.unwrap_or(Output::Stdout) {
+            Output::Stdout => { io::stdout().write_all(crate_bytes).unwrap(); }
+            Output::Path(ref p) => {
+                let written = if opts.tar {
+                    fs::write(p, crate_bytes)
+                } else {
+                    cache::write_archive(crate_.name(), version, crate_bytes, p)
+                };
+                written.map_err(|e| {
+                    CrateFailure::new(exitcode::IOERR, format!("Failed to write output file {}: {}",
+                        p.display(), e))
+                })?;
+                info!("Crate's {} written to {}", what, p.display());
+                if opts.verify_decompress {
+                    verify_decompress(crate_.name(), version, p)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extract the crate archive from given reader (of given size, if known) as it's being read,
+/// returning the size and SHA-256 checksum of the whole archive.
+///
+/// If the checksum that the archive should have is given, what's been extracted is only kept if it matches.
+fn extract<R: Read>(opts: &Options, crate_: &Crate, version: &Version, output: Option<&Output>, reader: R,
+                    size: Option<u64>, expected: Option<&str>) -> Result<(u64, String), CrateFailure> {
+    // Extract to a directory named $CRATE-$VERSION.
+    // Due to how crate archives are structured (they contain
+    // single top-level directory) this is done automatically
+    // if you simply extract them in the parent directory of the target.
+    // With --strip-prefix, the files go straight into the target directory instead.
+    let target_dir = extract_dir(output, crate_, version);
+    let parent_dir = lock::parent_dir(&target_dir);
+    let dir = if opts.strip_prefix {
+        target_dir.clone()
+    } else {
+        parent_dir.join(format!("{}-{}", crate_.name(), version))
+    };
+    // An earlier extraction (or with --force, whatever is there) is replaced rather than merged into,
+    // and only removed once the new one is in place.
+    let replaced = state::move_aside(&target_dir, opts.force).map_err(|e| {
+        CrateFailure::new(exitcode::IOERR, format!("Failed to move {}/ out of the way: {}",
+            target_dir.display(), e))
+    })?;
+    let restore = |replaced: &Option<PathBuf>| if let Some(ref old) = *replaced {
+        let _ = fs::remove_dir_all(&target_dir);
+        if let Err(e) = fs::rename(old, &target_dir) {
+            warn!("Failed to move {}/ back to {}/: {}", old.display(), target_dir.display(), e);
+        }
+    };
+    debug!("Extracting crate archive to {}/", dir.display());
+    let progress = Progress::start(format!("Extracting {} {}", crate_.name(), version), size);
+    let reader = ProgressReader::new(reader, progress);
+    // The archive (and its progress bar) is done with before anything is logged.
+    let unpacked = cargo_download::unpack_hashed(reader, |archive| {
+        if opts.filter.is_empty() && !opts.strip_prefix {
+            archive.unpack(&parent_dir).map(|()| 0)
+        } else {
+            // Directory of the crate is there even if no file matches.
+            fs::create_dir_all(&dir)?;
+            let unpack_dir = if opts.strip_prefix { &dir } else { &parent_dir };
+            filter::unpack(archive, unpack_dir, &opts.filter, opts.strip_prefix)
+        }
+    });
+    match unpacked {
+        // The archive is only known to be the right one once all of it has been read.
+        Ok((_, _, ref checksum)) if expected.is_some_and(|e| !e.eq_ignore_ascii_case(checksum)) => {
+            let _ = fs::remove_dir_all(&dir);
+            restore(&replaced);
+            Err(CrateFailure::new(CHECKSUM_MISMATCH_EXIT_CODE, ChecksumMismatch{
+                name: crate_.name().to_owned(), version: version.clone(),
+                expected: expected.unwrap().to_owned(), actual: checksum.clone(),
+            }.to_string()))
+        }
+        Ok((count, size, checksum)) => {
+            if !opts.filter.is_empty() {
+                match count {
+                    0 => warn!("No file of crate `{}=={}` matches the --filter", crate_.name(), version),
+                    _ => debug!("Extracted {} file(s) matching the --filter", count),
+                }
+            }
+            // If -o option was passed, we need to move the extracted directory
+            // to wherever the user wanted.
+            let mut dir = dir;
+            if dir != target_dir {
+                if let Err(e) = fs::rename(&dir, &target_dir) {
+                    let _ = fs::remove_dir_all(&dir);
+                    restore(&replaced);
+                    let hint = if target_dir.exists() { " (use --force to replace it)" } else { "" };
+                    return Err(CrateFailure::new(exitcode::IOERR, format!(
+                        "Failed to move extracted archive from {} to {}: {}{}",
+                        dir.display(), target_dir.display(), e, hint)));
+                }
+                dir = target_dir.clone();
             }
+            if let Some(ref old) = replaced {
+                if let Err(e) = fs::remove_dir_all(old) {
+                    warn!("Failed to remove the replaced {}/: {}", old.display(), e);
+                }
+            }
+
+            // Remember what we've extracted, so that a re-run can skip it.
+            let state = ExtractState{
+                name: crate_.name().to_owned(),
+                version: version.clone(),
+                checksum: checksum.clone(),
+                filter: opts.filter.iter().map(|p| p.to_string()).collect(),
+            };
+            if let Err(e) = state.write(&dir) {
+                warn!("Failed to write {} to {}/: {}", state::STATE_FILE, dir.display(), e);
+            }
+            info!("Crate content extracted to {}/", dir.display());
+            Ok((size, checksum))
+        }
+        Err(e) => {
+            let _ = fs::remove_dir_all(&dir);
+            restore(&replaced);
+            Err(CrateFailure::new(exitcode::TEMPFAIL, format!("Couldn't extract crate to {}/: {}",
+                dir.display(), e)))
+        }
+    }
+}
+
+/// Failure because given output directory couldn't be locked,
+/// pointing out when that's because it's not writable at all.
+fn lock_failed(dir: &Path, e: io::Error) -> CrateFailure {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem =>
+            CrateFailure::new(exitcode::CANTCREAT, format!("Output directory {}/ is not writable ({}); \
+                use --output to write elsewhere", dir.display(), e)),
+        _ => CrateFailure::new(exitcode::IOERR, format!("Failed to lock output directory {}/: {}",
+            dir.display(), e)),
+    }
+}
+
+/// Decompress given crate archive into a plain tarball.
+fn decompress_archive(name: &str, version: &Version, crate_bytes: &[u8]) -> Result<Vec<u8>, CrateFailure> {
+    let mut tarball = vec![];
+    cargo_download::decompress(crate_bytes).and_then(|mut r| r.read_to_end(&mut tarball))
+        .map_err(|e| {
+            CrateFailure::new(exitcode::DATAERR, format!("Couldn't decompress the archive of crate `{}=={}`: {}",
+                name, version, e))
+        })?;
+    Ok(tarball)
+}
+
+/// Fail early if there isn't enough disk space to unpack given crate into given directory.
+fn check_space(name: &str, version: &Version, crate_bytes: &[u8], dir: &Path) -> Result<(), CrateFailure> {
+    space::check(crate_bytes, dir).map_err(|e| {
+        CrateFailure::new(exitcode::CANTCREAT, format!("Cannot unpack crate `{}=={}` into {}/: {}",
+            name, version, dir.display(), e))
+    })
+}
+
+/// Make sure that downloaded crates can be stored in the cache,
+/// or else only read from it, so that e.g. a read-only home directory doesn't fail every download.
+fn check_cache() {
+    let cache = match Cache::open_default() {
+        Some(c) => c,
+        None => return,
+    };
+    if let Err(e) = cache.check_writable() {
+        warn!("Cache directory {}/ is not writable ({}), so downloaded crates won't be stored in it; \
+               use --cache-dir (or ${}) to put it elsewhere", cache.root().display(), e, cache::CACHE_DIR_VAR);
+        cache::set_read_only();
+    }
+}
+
+/// Remove stale entries from the local cache of crate archives.
+fn cache_gc(opts: &GcOptions) {
+    let cache = open_cache();
+    let entries = read_cache_entries(&cache);
+    if opts.older_than.is_none() && opts.max_size.is_none() {
+        warn!("Neither --older-than nor --max-size given, no cached crates will be removed");
+    }
+
+    let garbage = cache::select_garbage(entries, SystemTime::now(), opts.older_than, opts.max_size);
+    let mut freed = 0;
+    for entry in &garbage {
+        if opts.dry_run {
+            println!("{}\t{}", entry.name(), units::format_size(entry.size));
+        } else if let Err(e) = cache.remove(entry) {
+            warn!("Failed to remove {}: {}", entry.path.display(), e);
+            continue;
+        } else {
+            debug!("Removed {}", entry.path.display());
         }
+        freed += entry.size;
+    }
+    if opts.dry_run {
+        info!("Would remove {} cached crate(s), freeing {}", garbage.len(), units::format_size(freed));
+    } else {
+        info!("Removed {} cached crate(s), freeing {}", garbage.len(), units::format_size(freed));
+    }
+
+    // Downloads that have been abandoned midway won't be resumed, so their parts are garbage too.
+    let parts = cache.stale_parts(SystemTime::now()).unwrap_or_else(|e| {
+        warn!("Failed to look for abandoned downloads in the cache at {}/: {}", cache.root().display(), e);
+        vec![]
+    });
+    for part in &parts {
+        if opts.dry_run {
+            println!("{}\t{}", part.path.display(), units::format_size(part.size));
+        } else if let Err(e) = cache.remove(part) {
+            warn!("Failed to remove {}: {}", part.path.display(), e);
+        } else {
+            debug!("Removed abandoned download {}", part.path.display());
+        }
+    }
+}
+
+/// Print statistics about the local cache of crate archives.
+fn cache_stats(opts: &StatsOptions) {
+    let cache = open_cache();
+    let mut entries = read_cache_entries(&cache);
+    let counters = cache.counters();
+    let total_size: u64 = entries.iter().map(|e| e.size).sum();
+
+    println!("Cache directory: {}", cache.root().display());
+    println!("Entries:         {}", entries.len());
+    println!("Total size:      {}", units::format_size(total_size));
+    println!("Hits:            {}", counters.hits);
+    println!("Misses:          {}", counters.misses);
+    if let Some(rate) = counters.hit_rate() {
+        println!("Hit rate:        {:.1}%", rate * 100.0);
+    }
+
+    if opts.top > 0 && !entries.is_empty() {
+        entries.sort_by_key(|e| Reverse(e.size));
+        println!("Largest crates:");
+        for entry in entries.iter().take(opts.top) {
+            println!("  {:>10}  {}", units::format_size(entry.size), entry.name());
+        }
+    }
+}
+
+/// Open the local cache, or exit if its location cannot be determined.
+fn open_cache() -> Cache {
+    Cache::open_default().unwrap_or_else(|| {
+        error!("Couldn't determine the location of the cache");
+        exit(exitcode::CONFIG)
+    })
+}
+
+/// List the entries of given cache, or exit if it cannot be read.
+fn read_cache_entries(cache: &Cache) -> Vec<cache::CacheEntry> {
+    cache.entries().unwrap_or_else(|e| {
+        error!("Failed to read the cache at {}/: {}", cache.root().display(), e);
+        exit(exitcode::IOERR)
+    })
+}
+
+/// Log given error and exit the program with given code,
+/// recording the failure in the summary of the run first.
+fn fail(code: i32, message: String) -> ! {
+    CrateFailure::new(code, message).record();
+    summary::report();
+    write_summary();
+    exit(code)
+}
+
+/// Failure of processing a single crate, which doesn't stop the others in a batch.
+#[derive(Debug)]
+struct CrateFailure {
+    /// Exit code that the run ends with because of the failure.
+    code: i32,
+    message: String,
+}
+
+impl CrateFailure {
+    fn new(code: i32, message: String) -> CrateFailure {
+        CrateFailure{code, message}
+    }
+
+    /// Log the failure and record it for the current crate in the summary of the run.
+    fn record(&self) {
+        error!("{}", self.message);
+        json_output::failure(summary::current().as_deref(), failure_class(self.code), &self.message);
+        summary::record_failure(failure_class(self.code), &self.message);
+    }
+
+    /// Exit the program because of the failure, when there are no other crates to process.
+    fn exit(self) -> ! {
+        fail(self.code, self.message)
+    }
+}
+
+impl From<DeniedWarning> for CrateFailure {
+    fn from(warning: DeniedWarning) -> CrateFailure {
+        CrateFailure::new(exitcode::DATAERR, warning.0)
+    }
+}
+
+/// Classify a failure by the exit code that it terminates the program with.
+fn failure_class(code: i32) -> FailureClass {
+    match code {
+        exitcode::TEMPFAIL | exitcode::UNAVAILABLE => FailureClass::Network,
+        CHECKSUM_MISMATCH_EXIT_CODE | exitcode::DATAERR => FailureClass::Integrity,
+        exitcode::IOERR | exitcode::CANTCREAT => FailureClass::Io,
+        exitcode::CONFIG | exitcode::USAGE | exitcode::NOINPUT => FailureClass::Config,
+        _ => FailureClass::Other,
+    }
+}
+
+/// Write the summary and the metrics of the run, if they have been requested.
+fn write_summary() {
+    if let Err(e) = summary::write() {
+        error!("Failed to write the summary of the run: {}", e);
     }
 }
 
@@ -140,6 +1524,33 @@ fn print_args_error(e: ArgsError) -> io::Result<()> {
     }
 }
 
+/// Print the time spent in each phase of downloading the crates, and in total.
+fn print_timings(timings: &[(&Crate, Timings)]) -> io::Result<()> {
+    let mut stderr = io::stderr();
+    let mut total = Timings::default();
+    for &(crate_, crate_timings) in timings {
+        writeln!(&mut stderr, "Timing of {}: {}", crate_, crate_timings)?;
+        total += crate_timings;
+    }
+    if timings.len() > 1 {
+        writeln!(&mut stderr, "Timing of {} crates: {}", timings.len(), total)?;
+    }
+    Ok(())
+}
+
+/// Print how the crates of the run differ from those in the report of a previous one.
+fn print_changes(path: &Path, previous: &Report) -> io::Result<()> {
+    let current = summary::to_json().map(|json| Report::from_json(&json).unwrap()).unwrap_or_default();
+    let changes = compare::compare(previous, &current);
+    let mut stderr = io::stderr();
+    if changes.is_empty() {
+        writeln!(&mut stderr, "No changes since {}", path.display())
+    } else {
+        writeln!(&mut stderr, "Changes since {}:", path.display())?;
+        write!(&mut stderr, "{}", changes)
+    }
+}
+
 /// Log the program name, version, and other metadata.
 #[inline]
 fn log_signature() {
@@ -151,52 +1562,691 @@ fn log_signature() {
 }
 
 
-const CRATES_API_ROOT: &'static str = "https://crates.io/api/v1/crates";
-
-/// Talk to crates.io to get the newest version of given crate
+/// Talk to the registry to get the newest version of given crate
 /// that matches specified version requirements.
-fn get_newest_version(crate_: &Crate) -> Result<Version, Box<dyn Error>> {
-    let versions_url = format!("{}/{}/versions", CRATES_API_ROOT, crate_.name());
-    debug!("Fetching latest matching version of crate `{}` from {}", crate_, versions_url);
-    let response: Json = reqwest::get(&versions_url)?.json()?;
-
-    // TODO: rather that silently skipping over incorrect versions,
-    // report them as malformed response from crates.io
-    let mut versions = response.pointer("/versions").and_then(|vs| vs.as_array()).map(|vs| {
-        vs.iter().filter_map(|v| {
-            v.as_object().and_then(|v| v.get("num")).and_then(|n| n.as_str())
-        })
-        .filter_map(|v| Version::parse(v).ok())
-        .collect::<Vec<_>>()
-    }).ok_or_else(|| format!("malformed response from {}", versions_url))?;
+///
+/// In offline mode, the newest matching version from the local cache is used instead.
+fn get_newest_version(registry: &Registry, crate_: &Crate, filter: VersionFilter,
+                      warnings: &Warnings) -> Result<Version, Box<dyn Error>> {
+    let versions = fetch_versions(registry, crate_)?;
+    // Whether any version would match if the filter were relaxed, to suggest doing that.
+    let any_matching = |filter: VersionFilter| versions.iter().any(|v| filter.accepts(crate_, v));
+
+    let newest = cargo_download::resolve_versions(crate_, versions.iter().cloned(), filter).next();
+    match newest {
+        Some(v) => {
+            info!("Latest version of crate {} is {}", crate_, v.version);
+            if v.yanked {
+                warnings.emit(Warning::YankedVersion, format!(
+                    "Version {} of crate `{}` has been yanked", v.version, crate_.name()))?;
+            }
+            Ok(v.version)
+        }
+        None if registry.is_offline() => Err(Box::new(HttpError::Offline(
+            format!("find a version of crate {} (none matching in the caches)", crate_)))),
+        None if !filter.yanked && any_matching(VersionFilter{yanked: true, ..filter}) =>
+            Err("only yanked versions match (use --allow-yanked to download them)".into()),
+        None if !filter.prerelease && any_matching(VersionFilter{prerelease: true, ..filter}) =>
+            Err("only pre-releases match (use --pre to download them)".into()),
+        None => Err("no matching version found".into()),
+    }
+}
 
-    if versions.is_empty() {
-        return Err("no valid versions found".into());
+/// Print the published versions of given crates that satisfy their version requirements,
+/// with their publication dates and yanked status.
+fn list_versions(opts: &Options, crates: &[Crate]) {
+    let registry = open_registry(opts);
+    for crate_ in crates {
+        let versions = fetch_versions(&registry, crate_).unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+            format!("Failed to get the versions of crate {}: {}", crate_, e)));
+        let filter = VersionFilter{yanked: true, prerelease: true};
+        let matching: Vec<_> = cargo_download::resolve_versions(crate_, versions, filter).collect();
+        if matching.is_empty() {
+            fail(exitcode::TEMPFAIL, format!("No version of crate {} found", crate_));
+        }
+        let width = matching.iter().map(|v| v.version.to_string().len()).max().unwrap_or(0);
+        for v in matching {
+            let published = v.published.map_or_else(|| "-".to_owned(), format_date);
+            let line = format!("{} {:<width$}  {:<10}  {}", crate_.name(), v.version.to_string(), published,
+                if v.yanked { "yanked" } else { "" }, width = width);
+            println!("{}", line.trim_end());
+        }
     }
+}
+
+/// Print the metadata of the versions that given crates resolve to,
+/// from the registry index and (where available) its API.
+fn crate_info(opts: &Options, crates: &[Crate]) {
+    let registry = open_registry(opts);
+    for (i, crate_) in crates.iter().enumerate() {
+        let version = match crate_.exact_version() {
+            Some(v) => v.clone(),
+            None => get_newest_version(&registry, crate_, opts.version_filter(), &opts.warnings)
+                .unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+                    format!("Failed to get the newest version of crate {}: {}", crate_, e))),
+        };
+        let name = crate_.name();
+        let (index_url, content) = registry.fetch_index_file(name).unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+            format!("Failed to fetch the index file of crate `{}`: {}", name, e)));
+        let deps = cargo_download::Dependency::list_from_index(&content, &version).unwrap_or_else(|| fail(
+            exitcode::TEMPFAIL, format!("Crate `{}=={}` not found or malformed in the index file at {}",
+                name, version, index_url)));
+        let mut features: Vec<_> = cargo_download::features_from_index(&content, &version)
+            .unwrap_or_default().into_iter().collect();
+        features.sort();
+        let rust_version = cargo_download::rust_version_from_index(&content, &version);
+        let metadata = registry.fetch_metadata(name, &version).unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+            format!("Failed to get the metadata of crate `{}=={}`: {}", name, version, e)));
 
-    let version_req = crate_.version_requirement();
-    versions.sort_by(|a, b| b.cmp(a));
-    versions.into_iter().find(|v| version_req.matches(v))
-        .map(|v| { info!("Latest version of crate {} is {}", crate_, v); v.to_owned() })
-        .ok_or_else(|| "no matching version found".into())
+        let unknown = || "-".to_owned();
+        if i > 0 {
+            println!();
+        }
+        println!("{} {}", name, version);
+        println!("license: {}", metadata.license.unwrap_or_else(unknown));
+        println!("repository: {}", metadata.repository.unwrap_or_else(unknown));
+        println!("rust-version: {}", rust_version.unwrap_or_else(unknown));
+        println!("archive size: {}", metadata.size.map(units::format_size).unwrap_or_else(unknown));
+        if !features.is_empty() {
+            println!("features:");
+        }
+        for (feature, enables) in features {
+            println!("    {} = {:?}", feature, enables);
+        }
+        for &(kind, section) in &[(DependencyKind::Normal, "dependencies"),
+                                  (DependencyKind::Build, "build-dependencies"),
+                                  (DependencyKind::Dev, "dev-dependencies")] {
+            let deps: Vec<_> = deps.iter().filter(|d| d.kind == kind).collect();
+            if deps.is_empty() {
+                continue;
+            }
+            println!("{}:", section);
+            for dep in deps {
+                let mut notes = vec![];
+                if dep.rename.is_some() {
+                    notes.push(format!("package = {:?}", dep.name));
+                }
+                if dep.optional {
+                    notes.push("optional".to_owned());
+                }
+                if !dep.default_features {
+                    notes.push("no default features".to_owned());
+                }
+                if !dep.features.is_empty() {
+                    notes.push(format!("features = {:?}", dep.features));
+                }
+                let line = format!("    {} = \"{}\"", dep.used_name(), dep.req);
+                if notes.is_empty() {
+                    println!("{}", line);
+                } else {
+                    println!("{} ({})", line, notes.join(", "));
+                }
+            }
+        }
+    }
 }
 
-/// Download given crate and return it as a vector of gzipped bytes.
-fn download_crate(name: &str, version: &Version) -> Result<Vec<u8>, Box<dyn Error>> {
-    let download_url = format!("{}/{}/{}/download", CRATES_API_ROOT, name, version);
-    debug!("Downloading crate `{}=={}` from {}", name, version, download_url);
-    let mut response = reqwest::get(&download_url)?;
+/// Print the files in the archives of the versions that given crates resolve to.
+fn list_contents(opts: &Options, crates: &[Crate]) {
+    let registry = open_registry(opts);
+    for crate_ in crates {
+        let version = match crate_.exact_version() {
+            Some(v) => v.clone(),
+            None => get_newest_version(&registry, crate_, opts.version_filter(), &opts.warnings)
+                .unwrap_or_else(|e| fail(exitcode::TEMPFAIL,
+                    format!("Failed to get the newest version of crate {}: {}", crate_, e))),
+        };
+        let name = crate_.name();
+        let crate_bytes = download_crate(&registry, name, &version)
+            .unwrap_or_else(|e| download_failed(name, &version, e).exit());
+        let entries = listing::read_entries(&crate_bytes).unwrap_or_else(|e| fail(exitcode::DATAERR,
+            format!("Failed to read the archive of crate `{}=={}`: {}", name, version, e)));
+        if opts.json {
+            println!("{}", json!({
+                "name": name,
+                "version": version.to_string(),
+                "files": entries.iter().map(|e| e.to_json()).collect::<Vec<_>>(),
+            }));
+        } else {
+            for entry in entries {
+                println!("{}", entry);
+            }
+        }
+    }
+}
+
+/// Format given point in time as a date in UTC, e.g. 2021-01-01.
+fn format_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("{}", time::at_utc(time::Timespec::new(secs as i64, 0)).strftime("%Y-%m-%d").unwrap())
+}
+
+/// Check whether given version of the crate has been yanked, if that can be found out.
+fn is_yanked(registry: &Registry, crate_: &Crate, version: &Version) -> Option<bool> {
+    let versions = fetch_versions(registry, crate_).ok()?;
+    versions.into_iter().find(|v| v.version == *version).map(|v| v.yanked)
+}
 
-    let content_length: Option<usize> = response.headers().get(CONTENT_LENGTH)
-        .and_then(|ct_len| ct_len.to_str().ok())
-        .and_then(|ct_len| ct_len.parse().ok());
-    trace!("Download size: {}", content_length.map_or("<unknown>".into(), |cl| format!("{} bytes", cl)));
-    let mut bytes = match content_length {
-        Some(cl) => Vec::with_capacity(cl),
-        None => Vec::new(),
+/// Locate Cargo's own cache of given registry, to read the crates from offline.
+///
+/// Only the caches of sparse indices are read (Cargo keeps git ones in a checkout).
+fn cargo_registry_of(registry: &Registry) -> Option<CargoRegistry> {
+    match *registry.index().configured() {
+        Index::Sparse(ref index) => CargoRegistry::locate(index),
+        Index::Git(_) => None,
+    }
+}
+
+/// Talk to the registry to get the list of all published versions of given crate.
+///
+/// In offline mode, the versions present in the local cache or in Cargo's are listed instead.
+fn fetch_versions(registry: &Registry, crate_: &Crate) -> Result<Vec<ResolvedVersion>, Box<dyn Error>> {
+    if registry.is_offline() {
+        debug!("Looking for the versions of crate `{}` in the caches", crate_.name());
+        let mut versions: Vec<_> = match Cache::open_default() {
+            Some(cache) => cache.versions(crate_.name())?
+                .into_iter().map(ResolvedVersion::new).collect(),
+            None => vec![],
+        };
+        // Cargo's cache of the index has the details of the versions (like whether they're yanked),
+        // but only those whose archives Cargo has cached can be downloaded.
+        if let (Some(cargo_registry), Ok(indexed)) = (cargo_registry_of(registry),
+                                                      registry.fetch_index_versions(crate_.name())) {
+            for version in indexed {
+                if cargo_registry.crate_path(crate_.name(), &version.version).is_file() {
+                    versions.retain(|v| v.version != version.version);
+                    versions.push(version);
+                }
+            }
+        }
+        return Ok(versions);
+    }
+    debug!("Looking for the versions of crate `{}` in the registry", crate_.name());
+    registry.fetch_versions(crate_.name())
+}
+
+/// Talk to the registry to get the SHA-256 checksum of given crate version.
+///
+/// In offline mode, the checksum from Cargo's cache of the index is used instead,
+/// or that of the crate's archive in the local cache.
+fn get_checksum(registry: &Registry, name: &str, version: &Version) -> Result<String, Box<dyn Error>> {
+    if registry.is_offline() {
+        if let Ok(checksum) = registry.fetch_index_checksum(name, version) {
+            return Ok(checksum);
+        }
+        let cached = match Cache::open_default() {
+            Some(cache) => cache.get(name, version)?,
+            None => None,
+        };
+        if let Some(bytes) = cached {
+            return Ok(checksum::sha256_reader(&bytes[..])?);
+        }
+    }
+    registry.fetch_checksum(name, version)
+}
+
+/// Warn if given crate version is older than the configured threshold,
+/// or if there's a newer release of the crate that isn't semver-compatible with it.
+fn warn_if_stale(opts: &Options, registry: &Registry, name: &str, version: &Version) -> Result<(), DeniedWarning> {
+    if registry.is_offline() {
+        debug!("Not checking the staleness of crate `{}=={}` in offline mode", name, version);
+        return Ok(());
+    }
+    let versions = match registry.fetch_versions(name) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Couldn't check the staleness of crate `{}=={}`: {}", name, version, e);
+            return Ok(());
+        }
+    };
+
+    if let Some(max_age) = opts.warn_older_than {
+        let published = versions.iter()
+            .find(|v| v.version == *version && v.version.build == version.build)
+            .and_then(|v| v.published);
+        match published.and_then(|p| SystemTime::now().duration_since(p).ok()) {
+            Some(age) if age > max_age => opts.warnings.emit(Warning::OldVersion, format!(
+                "Crate `{}=={}` was published {} days ago", name, version, age.as_secs() / (24 * 60 * 60)))?,
+            Some(_) => {}
+            None => debug!("Publication time of crate `{}=={}` is unknown", name, version),
+        }
+    }
+    if opts.warn_newer_major {
+        if let Some(newer) = cargo_download::newer_major_version(version, &versions) {
+            opts.warnings.emit(Warning::NewerMajorVersion, format!(
+                "Crate `{}=={}` is outdated: version {} is available", name, version, newer))?;
+        }
+    }
+    Ok(())
+}
+
+/// Failure of given crate version to download.
+///
+/// An archive that doesn't match its checksum in the registry has an exit code of its own,
+/// so that it can be told apart from transient network failures.
+fn download_failed(name: &str, version: &Version, e: Box<dyn Error>) -> CrateFailure {
+    let code = if e.downcast_ref::<ChecksumMismatch>().is_some() {
+        CHECKSUM_MISMATCH_EXIT_CODE
+    } else {
+        exitcode::TEMPFAIL
+    };
+    CrateFailure::new(code, format!("Failed to download crate `{}=={}`: {}", name, version, e))
+}
+
+/// Check that the registry index and the API agree on the checksum of given crate version.
+///
+/// Fails if they don't, or if either of them can't be queried.
+fn cross_verify(registry: &Registry, name: &str, version: &Version) -> Result<(), CrateFailure> {
+    let fetch_failed = |source: &str, e: Box<dyn Error>| {
+        CrateFailure::new(exitcode::TEMPFAIL, format!(
+            "Failed to fetch the checksum of crate `{}=={}` from the {}: {}", name, version, source, e))
+    };
+    let index_checksum = registry.fetch_index_checksum(name, version)
+        .map_err(|e| fetch_failed("registry index", e))?;
+    let api_checksum = registry.fetch_checksum(name, version)
+        .map_err(|e| fetch_failed("crates.io API", e))?;
+    if !index_checksum.eq_ignore_ascii_case(&api_checksum) {
+        return Err(CrateFailure::new(exitcode::DATAERR, format!(
+            "Checksums of crate `{}=={}` disagree: {} in the registry index, {} from the crates.io API",
+            name, version, index_checksum, api_checksum)));
+    }
+    debug!("Registry index and crates.io API agree on the checksum of crate `{}=={}`: {}",
+        name, version, index_checksum);
+    Ok(())
+}
+
+/// Check whether the file at given path is a verified archive of given crate version.
+fn is_up_to_date(registry: &Registry, path: &Path, name: &str, version: &Version) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    let checksum = match get_checksum(registry, name, version) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Couldn't verify existing file {}, downloading again: {}", path.display(), e);
+            return false;
+        }
     };
-    response.read_to_end(&mut bytes)?;
+    match checksum::file_matches(path, &checksum) {
+        Ok(true) => true,
+        Ok(false) => {
+            debug!("Existing file {} doesn't match checksum {}", path.display(), checksum);
+            false
+        }
+        Err(e) => {
+            warn!("Couldn't read existing file {}, downloading again: {}", path.display(), e);
+            false
+        }
+    }
+}
 
+/// Check whether given crate (with an exact version) is already present
+/// in Cargo's cache or where we would output it.
+///
+/// If the crates have been read from a lockfile, what's present has to match the checksum it pins
+/// (which can only be checked for archives and vendored crates, not for the plainly extracted ones).
+fn is_present(opts: &Options, registry: &Registry, crate_: &Crate, batch: &Batch,
+              lockfile: Option<&Lockfile>) -> bool {
+    let version = match crate_.exact_version() {
+        Some(v) => v,
+        None => return false,
+    };
+    let pinned = lockfile.and_then(|l| l.checksum(crate_.name(), version));
+    if cache::in_cargo_cache(crate_.name(), version, pinned) {
+        debug!("Crate `{}=={}` is in Cargo's cache, skipping", crate_.name(), version);
+        return true;
+    }
+    if opts.name_by_hash {
+        return false;
+    }
+    // If the output can't be determined, the crate fails when it's processed.
+    let output = match output_for(opts, registry, crate_, version, batch) {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+    let path = if opts.extract {
+        Some(extract_dir(output.as_ref(), crate_, version))
+    } else {
+        match output {
+            Some(Output::Path(p)) => Some(p),
+            _ => None,
+        }
+    };
+    let path = match path {
+        Some(p) => p,
+        None => return false,
+    };
+    let present = match pinned {
+        // Vendored crates record the checksum of the archive that they've been extracted from.
+        Some(pinned) if opts.vendor =>
+            vendor::package_checksum(&path).is_some_and(|c| c.eq_ignore_ascii_case(pinned)),
+        _ if opts.extract => path.exists(),
+        _ => checksum::file_present(&path, pinned).unwrap_or_else(|e| {
+            warn!("Couldn't read existing file {}, downloading again: {}", path.display(), e);
+            false
+        }),
+    };
+    if present {
+        debug!("Crate `{}=={}` is already at {}, skipping", crate_.name(), version, path.display());
+    } else if path.exists() {
+        debug!("Crate `{}=={}` at {} doesn't match the lockfile, downloading again",
+               crate_.name(), version, path.display());
+    }
+    present
+}
+
+/// Crates downloaded together, which determines where each of them is output.
+struct Batch {
+    /// Whether there's more than one crate, in which case each is written under a versioned name.
+    several: bool,
+    /// Newest versions of the crates that are given in more than one exact version.
+    newest: HashMap<String, Version>,
+    /// Crate versions that have been processed, as `name==version`.
+    processed: Mutex<HashSet<String>>,
+    /// Paths that crates have been output to, along with the crates (as `name==version`).
+    outputs: Mutex<HashMap<PathBuf, String>>,
+    /// Exact crate versions that have been given explicitly, as `name==version`.
+    pinned: HashSet<String>,
+    /// Bundle that the crates are put into instead of being output (`--bundle`).
+    bundle: Option<Mutex<BundleWriter>>,
+}
+
+impl Batch {
+    fn new(crates: &[Crate], several: bool, pinned: HashSet<String>, bundle: Option<BundleWriter>) -> Batch {
+        let mut versions: HashMap<&str, Vec<&Version>> = HashMap::new();
+        for crate_ in crates {
+            if let Some(version) = crate_.exact_version() {
+                versions.entry(crate_.name()).or_default().push(version);
+            }
+        }
+        let newest = versions.into_iter()
+            .filter(|(_, versions)| versions.len() > 1)
+            .map(|(name, versions)| (name.to_owned(), versions.into_iter().max().unwrap().clone()))
+            .collect();
+        Batch{
+            several, newest, pinned,
+            processed: Mutex::new(HashSet::new()),
+            outputs: Mutex::new(HashMap::new()),
+            bundle: bundle.map(Mutex::new),
+        }
+    }
+
+    /// Note that given crate version is being processed,
+    /// returning false if it's been processed already (having been resolved from another spec).
+    fn claim(&self, name: &str, version: &Version) -> bool {
+        self.processed.lock().unwrap().insert(format!("{}=={}", name, version))
+    }
+}
+
+/// Determine where the crate should be output to.
+fn output_for(opts: &Options, registry: &Registry, crate_: &Crate, version: &Version,
+              batch: &Batch) -> Result<Option<Output>, CrateFailure> {
+    if opts.vendor {
+        let dir = match opts.output {
+            Some(Output::Path(ref p)) => p.clone(),
+            _ => PathBuf::from(vendor::DEFAULT_DIR),
+        };
+        let name = vendor::dir_name(crate_.name(), version, batch.newest.get(crate_.name()));
+        return Ok(Some(Output::Path(dir.join(name))));
+    }
+    if let Some(ref template) = opts.output_template {
+        let dir = match opts.output {
+            Some(Output::Path(ref p)) => p.clone(),
+            _ => PathBuf::from("."),
+        };
+        let checksum = if template.needs_checksum() {
+            Some(get_checksum(registry, crate_.name(), version).map_err(|e| {
+                CrateFailure::new(exitcode::TEMPFAIL, format!("Failed to get the checksum of crate `{}=={}` \
+                    for --output-template: {}", crate_.name(), version, e))
+            })?)
+        } else {
+            None
+        };
+        let path = template.render(crate_.name(), version, checksum.as_deref(), &registry_name(opts, registry));
+        return Ok(Some(Output::Path(dir.join(path))));
+    }
+    if !batch.several {
+        return Ok(opts.output.clone());
+    }
+    let mut dir = match opts.output {
+        Some(Output::Path(ref p)) => p.clone(),
+        _ => PathBuf::from("."),
+    };
+    if opts.all_versions {
+        dir.push(crate_.name());
+    }
+    // Build metadata is escaped like in the download URLs.
+    let version = cargo_download::url_version(version);
+    let name = if opts.extract {
+        format!("{}-{}", crate_.name(), version)
+    } else if opts.tar {
+        format!("{}-{}.tar", crate_.name(), version)
+    } else {
+        format!("{}-{}.crate", crate_.name(), version)
+    };
+    Ok(Some(Output::Path(dir.join(name))))
+}
+
+/// Claim given output for given crate version, returning where it should be output,
+/// which is elsewhere (or nowhere, failing) if another crate has been output there before,
+/// as `--on-collision` says.
+fn claim_output(opts: &Options, registry: &Registry, name: &str, version: &Version, output: Output,
+                batch: &Batch) -> Result<Output, CrateFailure> {
+    let path = match output {
+        Output::Path(p) => p,
+        Output::Stdout => return Ok(Output::Stdout),
+    };
+    let crate_id = format!("{}=={}", name, version);
+    let mut outputs = batch.outputs.lock().unwrap();
+    let other = match outputs.get(&path) {
+        Some(other) => other.clone(),
+        None => {
+            outputs.insert(path.clone(), crate_id);
+            return Ok(Output::Path(path));
+        }
+    };
+    let claimed = match opts.on_collision {
+        OnCollision::Error => return Err(CrateFailure::new(exitcode::CANTCREAT, format!(
+            "Crate `{}` would be output to {}, like crate `{}` before it (see --on-collision)",
+            crate_id, path.display(), other))),
+        OnCollision::Overwrite => {
+            warn!("Crate `{}` overwrites crate `{}` at {}", crate_id, other, path.display());
+            path.clone()
+        }
+        OnCollision::SuffixRegistry => {
+            let registry_name = registry_name(opts, registry);
+            let suffixed = [registry_name.clone(),
+                            format!("{}-{}", registry_name, cargo_download::url_version(version))].iter()
+                .map(|suffix| with_suffix(&path, suffix))
+                .find(|p| !outputs.contains_key(p))
+                .ok_or_else(|| CrateFailure::new(exitcode::CANTCREAT, format!(
+                    "Crate `{}` would be output to {}, like crate `{}` before it, \
+                     and suffixing it doesn't tell them apart", crate_id, path.display(), other)))?;
+            warn!("Crate `{}` would be output to {}, like crate `{}` before it, so it's output to {} instead",
+                crate_id, path.display(), other, suffixed.display());
+            suffixed
+        }
+    };
+    summary::record_collision(&path, &other, &opts.on_collision.to_string(), &claimed);
+    outputs.insert(claimed.clone(), crate_id);
+    Ok(Output::Path(claimed))
+}
+
+/// Suffix the file name of given output path (before its extension, if it's that of an archive).
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    // Versions have dots in them, so only the known extensions are split off.
+    let (stem, extension) = [".crate", ".tar"].iter()
+        .find_map(|ext| file_name.strip_suffix(ext).map(|stem| (stem, *ext)))
+        .unwrap_or((&file_name, ""));
+    path.with_file_name(format!("{}-{}{}", stem, suffix, extension))
+}
+
+/// Name of the registry that the crates are downloaded from, as used in `--output-template`:
+/// the name given by `--registry`, or else `crates-io` or the host of the registry's index.
+fn registry_name(opts: &Options, registry: &Registry) -> String {
+    if let Some(ref name) = opts.registry {
+        return name.clone();
+    }
+    if registry.index().is_crates_io() {
+        return registries::CRATES_IO.to_owned();
+    }
+    let index = registry.index().to_string();
+    let url = index.split("://").nth(1).unwrap_or(&index);
+    url.split('/').next().unwrap_or(url).to_owned()
+}
+
+/// Determine the directory where the crate would be extracted to.
+fn extract_dir(output: Option<&Output>, crate_: &Crate, version: &Version) -> PathBuf {
+    match output {
+        Some(Output::Path(p)) => p.clone(),
+        _ => format!("./{}-{}", crate_.name(), cargo_download::url_version(version)).into(),
+    }
+}
+
+/// Check whether given directory contains a verified vendored copy of given crate version.
+fn is_vendored(registry: &Registry, dir: &Path, name: &str, version: &Version) -> bool {
+    let package_checksum = match vendor::package_checksum(dir) {
+        Some(c) => c,
+        None => return false,
+    };
+    match get_checksum(registry, name, version) {
+        Ok(checksum) => checksum.eq_ignore_ascii_case(&package_checksum),
+        Err(e) => {
+            warn!("Couldn't verify vendored crate at {}/, vendoring again: {}", dir.display(), e);
+            false
+        }
+    }
+}
+
+/// Check whether given directory contains a verified extraction of given crate version,
+/// of the files that match given patterns (or of all of them).
+fn is_extracted(registry: &Registry, dir: &Path, name: &str, version: &Version, filter: &[glob::Pattern]) -> bool {
+    let state = match ExtractState::read(dir) {
+        Some(s) => s,
+        None => return false,
+    };
+    if !state.is_for(name, version) || !state.filter.iter().eq(filter.iter().map(|p| p.as_str())) {
+        return false;
+    }
+    match get_checksum(registry, name, version) {
+        Ok(checksum) => checksum.eq_ignore_ascii_case(&state.checksum),
+        Err(e) => {
+            warn!("Couldn't verify existing directory {}/, extracting again: {}", dir.display(), e);
+            false
+        }
+    }
+}
+
+/// Download given crate and extract it as it's being received (or read from the download cache),
+/// rather than holding its whole archive in memory, returning the size and checksum of the archive.
+///
+/// Like with `download_crate`, the archive is taken from the cache if it's there (and valid),
+/// or else stored in the cache once it's been verified.
+fn download_extracting(opts: &Options, registry: &Registry, crate_: &Crate, version: &Version,
+                       output: Option<&Output>) -> Result<(u64, String), CrateFailure> {
+    let name = crate_.name();
+    let expected = get_checksum(registry, name, version).map_err(|e| download_failed(name, version, e))?;
+    let cache = Cache::open_default();
+    if let Some(ref cache) = cache {
+        match cache.open_entry(name, version) {
+            Ok(Some(file)) => {
+                let size = file.metadata().ok().map(|m| m.len());
+                match extract(opts, crate_, version, output, file, size, Some(&expected)) {
+                    Ok(extracted) => {
+                        info!("Crate `{}=={}` found in the cache", name, version);
+                        return Ok(extracted);
+                    }
+                    Err(failure) => {
+                        warn!("Couldn't extract crate `{}=={}` from the cache, ignoring it: {}",
+                            name, version, failure.message);
+                        if !cache.is_read_only() {
+                            if let Err(e) = cache.evict(name, version) {
+                                warn!("Failed to remove crate `{}=={}` from the cache: {}", name, version, e);
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read crate `{}=={}` from the cache: {}", name, version, e),
+        }
+    }
+
+    // The archive is written into a partial file in the cache as well, and moved into place once it's verified.
+    let cache = cache.filter(|c| !c.is_read_only());
+    let part_path = cache.as_ref().map(|c| c.part_path(name, version));
+    let (reader, size) = registry.open_download(name, version, part_path.as_deref())
+        .map_err(|e| download_failed(name, version, e))?;
+    let extracted = extract(opts, crate_, version, output, reader, size, Some(&expected));
+    if let (Some(cache), Some(part_path)) = (cache, part_path) {
+        if extracted.is_err() {
+            let _ = fs::remove_file(&part_path);
+        } else if let Err(e) = cache.commit_part(name, version) {
+            warn!("Failed to store crate `{}=={}` in the cache: {}", name, version, e);
+        }
+    }
+    if extracted.is_ok() {
+        info!("Crate `{}=={}` downloaded successfully", name, version);
+    }
+    extracted
+}
+
+/// Download given crate and return it as a vector of gzipped bytes.
+///
+/// If the crate is present in the local cache, it is taken from there instead
+/// (once it's verified like a download, or else evicted), and in offline mode,
+/// it may be taken from Cargo's cache too.
+fn download_crate(registry: &Registry, name: &str, version: &Version) -> Result<Vec<u8>, Box<dyn Error>> {
+    let cache = Cache::open_default();
+    if let Some(ref cache) = cache {
+        match cache.get(name, version) {
+            Ok(Some(bytes)) => match registry.verify_cached(name, version, &bytes) {
+                Ok(()) => {
+                    info!("Crate `{}=={}` found in the cache", name, version);
+                    return Ok(bytes);
+                }
+                Err(e) => {
+                    warn!("Crate `{}=={}` in the cache is invalid, ignoring it: {}", name, version, e);
+                    if !cache.is_read_only() {
+                        if let Err(e) = cache.evict(name, version) {
+                            warn!("Failed to remove crate `{}=={}` from the cache: {}", name, version, e);
+                        }
+                    }
+                }
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read crate `{}=={}` from the cache: {}", name, version, e),
+        }
+    }
+    if registry.is_offline() {
+        return match cargo_registry_of(registry).map(|r| r.read_crate(name, version)).transpose()?.flatten() {
+            Some(bytes) => {
+                info!("Crate `{}=={}` found in Cargo's cache", name, version);
+                Ok(bytes)
+            }
+            None => Err(Box::new(HttpError::Offline(format!(
+                "download crate `{}=={}`, which is neither in the download cache nor in Cargo's",
+                name, version)))),
+        };
+    }
+
+    // The archive is received into a partial file in the cache, so that if the download is cut short,
+    // it resumes from there (possibly in a later run), and moved into place once it's verified.
+    // Crates from local sources are at hand anyway, and unverified archives mustn't be trusted by later runs.
+    let cache = cache.filter(|c| !c.is_read_only() && !registry.is_local() && registry.verifies());
+    let part_path = cache.as_ref().map(|c| c.part_path(name, version));
+    let bytes = registry.download(name, version, part_path.as_deref())?;
     info!("Crate `{}=={}` downloaded successfully", name, version);
+
+    if let Some(ref cache) = cache {
+        let stored = cache.commit_part(name, version).or_else(|e| {
+            debug!("Failed to move the download of crate `{}=={}` into the cache: {}", name, version, e);
+            cache.put(name, version, &bytes)
+        });
+        if let Err(e) = stored {
+            warn!("Failed to store crate `{}=={}` in the cache: {}", name, version, e);
+        }
+    }
     Ok(bytes)
 }
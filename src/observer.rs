@@ -0,0 +1,162 @@
+//! Module with the hooks for observing the progress of resolving and downloading crates,
+//! so that programs embedding the library (e.g. GUI front-ends) can show it in their own way.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+
+/// Phase of processing a crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Resolving the crate to a version, from the registry index.
+    Resolving,
+    /// Downloading the crate's archive.
+    Downloading,
+    /// Downloading the crate's archive and extracting it as it's being received.
+    Extracting,
+    /// The crate has been processed successfully,
+    /// with its archive verified against its checksum from the registry index.
+    Finished,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Phase::Resolving => "resolving",
+            Phase::Downloading => "downloading",
+            Phase::Extracting => "extracting",
+            Phase::Finished => "finished",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+
+/// Observer of the progress of a downloader, notified as it's processing the crates.
+///
+/// The crates are identified as they're given to the downloader: by their specification
+/// while they're being resolved (e.g. `foo=1.0`), and as `name==version` afterwards.
+/// Notifications can come from several threads at once if the downloader is shared between them.
+///
+/// All the methods do nothing by default, so only those of interest need to be implemented.
+pub trait ProgressObserver: Send + Sync {
+    /// Processing of given crate has entered given phase.
+    fn phase(&self, _crate_: &str, _phase: Phase) {}
+
+    /// Given number of bytes of the crate's archive have been received so far,
+    /// out of given total (if the registry reported it).
+    fn bytes_transferred(&self, _crate_: &str, _transferred: u64, _total: Option<u64>) {}
+
+    /// Something about given crate deserves attention, though it doesn't stop it from being processed,
+    /// e.g. the version it's resolved to has been yanked.
+    fn warning(&self, _crate_: &str, _message: &str) {}
+}
+
+
+/// Observer that the downloader is notifying, if any.
+#[derive(Clone, Default)]
+pub struct Observer(Option<Arc<dyn ProgressObserver>>);
+
+impl Observer {
+    #[inline]
+    pub fn new(observer: Arc<dyn ProgressObserver>) -> Observer {
+        Observer(Some(observer))
+    }
+
+    pub fn phase(&self, crate_: &str, phase: Phase) {
+        if let Some(ref observer) = self.0 {
+            observer.phase(crate_, phase);
+        }
+    }
+
+    pub fn bytes_transferred(&self, crate_: &str, transferred: u64, total: Option<u64>) {
+        if let Some(ref observer) = self.0 {
+            observer.bytes_transferred(crate_, transferred, total);
+        }
+    }
+
+    pub fn warning(&self, crate_: &str, message: &str) {
+        if let Some(ref observer) = self.0 {
+            observer.warning(crate_, message);
+        }
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Observer({})", if self.0.is_some() { "..." } else { "none" })
+    }
+}
+
+
+/// Reader of a crate's archive that notifies the observer of the bytes read through it.
+pub struct ObservedReader<R> {
+    inner: R,
+    observer: Observer,
+    crate_: String,
+    transferred: u64,
+    total: Option<u64>,
+}
+
+impl<R: Read> ObservedReader<R> {
+    /// Wrap given reader of the archive of given crate, whose size is given if it's known.
+    pub fn new(inner: R, observer: Observer, crate_: String, total: Option<u64>) -> ObservedReader<R> {
+        ObservedReader{inner, observer, crate_, transferred: 0, total}
+    }
+}
+
+impl<R: Read> Read for ObservedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.transferred += n as u64;
+            self.observer.bytes_transferred(&self.crate_, self.transferred, self.total);
+        }
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use super::{ObservedReader, Observer, Phase, ProgressObserver};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ProgressObserver for Recorder {
+        fn phase(&self, crate_: &str, phase: Phase) {
+            self.events.lock().unwrap().push(format!("{} {}", crate_, phase));
+        }
+
+        fn bytes_transferred(&self, crate_: &str, transferred: u64, total: Option<u64>) {
+            self.events.lock().unwrap().push(format!("{} {}/{:?}", crate_, transferred, total));
+        }
+    }
+
+    #[test]
+    fn observed_reading() {
+        let recorder = Arc::new(Recorder::default());
+        let observer = Observer::new(recorder.clone());
+        observer.phase("foo==1.0.0", Phase::Downloading);
+        observer.warning("foo==1.0.0", "ignored by the recorder");
+
+        let mut reader = ObservedReader::new(&b"hello"[..], observer, "foo==1.0.0".into(), Some(5));
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"lo");
+        assert_eq!(*recorder.events.lock().unwrap(),
+                   ["foo==1.0.0 downloading", "foo==1.0.0 3/Some(5)", "foo==1.0.0 5/Some(5)"]);
+
+        // Without an observer, the notifications go nowhere.
+        Observer::default().phase("foo==1.0.0", Phase::Finished);
+    }
+}
@@ -0,0 +1,73 @@
+//! Module defining where the downloaded crate should be output to.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use conv::errors::NoError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+
+/// Defines where the program's output should go.
+///
+/// It is (de)serialized the same way it's given on the command line,
+/// i.e. as a path, or `-` for standard output.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Output {
+    /// Output should go to a file or directory of given path.
+    Path(PathBuf),
+    /// Output should be on standard output.
+    Stdout,
+}
+impl<'s> From<&'s str> for Output {
+    fn from(s: &'s str) -> Output {
+        if s == "-" {
+            Output::Stdout
+        } else {
+            Output::Path(s.into())
+        }
+    }
+}
+impl FromStr for Output {
+    type Err = NoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+impl fmt::Display for Output {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Output::Path(ref p) => write!(fmt, "{}", p.display()),
+            Output::Stdout => write!(fmt, "-"),
+        }
+    }
+}
+impl Serialize for Output {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Output {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(Output::from(s.as_str()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use serde_json;
+    use super::Output;
+
+    #[test]
+    fn serde_roundtrip() {
+        for output in &[Output::Stdout, Output::Path(PathBuf::from("vendor/foo"))] {
+            let json = serde_json::to_string(output).unwrap();
+            assert_eq!(&serde_json::from_str::<Output>(&json).unwrap(), output);
+        }
+        assert_eq!(serde_json::to_string(&Output::Stdout).unwrap(), r#""-""#);
+    }
+}
@@ -0,0 +1,139 @@
+//! Module for naming the output of crates after a template (`--output-template`),
+//! e.g. to lay out a static mirror of a registry.
+//!
+//! The template is a path with placeholders in braces: `{name}` and `{version}` of the crate,
+//! `{checksum}` (the SHA-256 checksum of its archive) or `{checksum:N}` (its first N hex digits),
+//! and `{registry}` (the name of the registry it's downloaded from).
+
+use std::fmt;
+use std::str::FromStr;
+
+use semver::Version;
+
+use cargo_download::url_version;
+
+
+/// Template of the paths that crates are output to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Name,
+    Version,
+    /// Checksum of the crate's archive, or its prefix of given length.
+    Checksum(Option<usize>),
+    Registry,
+}
+
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<OutputTemplate, String> {
+        let mut segments = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if rest[..start].contains('}') {
+                return Err(format!("unopened placeholder in `{}`", template));
+            }
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..].find('}')
+                .ok_or_else(|| format!("unclosed placeholder in `{}`", template))?;
+            let placeholder = &rest[start + 1..start + end];
+            segments.push(match placeholder {
+                "name" => Segment::Name,
+                "version" => Segment::Version,
+                "checksum" => Segment::Checksum(None),
+                "registry" => Segment::Registry,
+                _ => match placeholder.strip_prefix("checksum:").map(|n| n.parse::<usize>()) {
+                    Some(Ok(len)) if len > 0 => Segment::Checksum(Some(len)),
+                    _ => return Err(format!("unknown placeholder `{{{}}}` (expected one of {{name}}, {{version}}, \
+                                             {{checksum}}, {{checksum:N}} or {{registry}})", placeholder)),
+                },
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unopened placeholder in `{}`", template));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+        if !segments.contains(&Segment::Name) {
+            return Err("the template has to contain the {name} of the crate".into());
+        }
+        Ok(OutputTemplate{template: template.to_owned(), segments})
+    }
+}
+
+impl OutputTemplate {
+    /// Whether the template refers to the checksums of the crates' archives,
+    /// which have to be looked up before the crates are output.
+    pub fn needs_checksum(&self) -> bool {
+        self.segments.iter().any(|s| matches!(*s, Segment::Checksum(_)))
+    }
+
+    /// Fill in the template for given crate version.
+    ///
+    /// The checksum is only needed if the template refers to it (see `needs_checksum`).
+    pub fn render(&self, name: &str, version: &Version, checksum: Option<&str>, registry: &str) -> String {
+        let mut path = String::new();
+        for segment in &self.segments {
+            match *segment {
+                Segment::Literal(ref s) => path.push_str(s),
+                Segment::Name => path.push_str(name),
+                Segment::Version => path.push_str(&url_version(version)),
+                Segment::Checksum(len) => {
+                    let checksum = checksum.expect("checksum needed by the output template").to_lowercase();
+                    let len = len.unwrap_or(checksum.len()).min(checksum.len());
+                    path.push_str(&checksum[..len]);
+                }
+                Segment::Registry => path.push_str(registry),
+            }
+        }
+        path
+    }
+}
+
+impl fmt::Display for OutputTemplate {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.template)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use super::OutputTemplate;
+
+    #[test]
+    fn rendering() {
+        let version = Version::parse("1.2.3").unwrap();
+        let template: OutputTemplate = "{registry}/{name}/{version}/{name}-{version}.crate".parse().unwrap();
+        assert!(!template.needs_checksum());
+        assert_eq!(template.render("foo", &version, None, "crates-io"), "crates-io/foo/1.2.3/foo-1.2.3.crate");
+
+        let template: OutputTemplate = "by-checksum/{checksum:2}/{checksum}-{name}".parse().unwrap();
+        assert!(template.needs_checksum());
+        assert_eq!(template.render("foo", &version, Some("ABCDEF"), "crates-io"), "by-checksum/ab/abcdef-foo");
+
+        // Build metadata is escaped like in the download URLs.
+        let template: OutputTemplate = "{name}-{version}.crate".parse().unwrap();
+        assert_eq!(template.render("foo", &Version::parse("1.2.3+meta").unwrap(), None, "crates-io"),
+                   "foo-1.2.3%2Bmeta.crate");
+
+        assert!("{name}/{size}".parse::<OutputTemplate>().is_err());
+        assert!("{name}/{checksum:0}".parse::<OutputTemplate>().is_err());
+        assert!("{name}/{version".parse::<OutputTemplate>().is_err());
+        assert!("{name}/version}".parse::<OutputTemplate>().is_err());
+        assert!("name}/{name}".parse::<OutputTemplate>().is_err());
+        assert!("{version}.crate".parse::<OutputTemplate>().is_err());
+    }
+}
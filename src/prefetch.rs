@@ -0,0 +1,107 @@
+//! Module for fetching the archives of a batch of crates concurrently (`--jobs`).
+//!
+//! Worker threads fetch the archives into the download cache ahead of the crates
+//! being processed one by one, so the processing then finds them there.
+//! All workers share the registry's HTTP client, and so its pool of connections.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use semver::Version;
+
+use cargo_download::{self, Crate, VersionFilter};
+
+use registry::Registry;
+use super::{download_crate, fetch_versions};
+
+
+/// Archives of a batch of crates that are being fetched in the background.
+pub struct Prefetch {
+    receiver: Receiver<usize>,
+    /// Indices of the crates whose archives have been fetched (or failed to be).
+    done: HashSet<usize>,
+}
+
+impl Prefetch {
+    /// Wait until the archive of the crate with given index in the batch has been fetched.
+    ///
+    /// Failures aren't reported here, since fetching the archive again
+    /// while processing the crate fails the same way, and is reported then.
+    pub fn wait(&mut self, index: usize) {
+        while !self.done.contains(&index) {
+            match self.receiver.recv() {
+                Ok(i) => { self.done.insert(i); }
+                // All the workers are finished.
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+/// Fetch the archives of given crates into the download cache with given number of workers,
+/// while `process` is handling the crates in order (waiting for each one's archive first).
+///
+/// Crates for which `wanted` returns false aren't fetched,
+/// and the crates are resolved to the versions that pass given filter.
+/// Once `process` returns, workers stop fetching the remaining archives.
+pub fn run<W, P, R>(registry: &Registry, crates: &[Crate], jobs: usize, filter: VersionFilter,
+                    wanted: W, process: P) -> R
+    where W: Fn(&Crate) -> bool + Sync, P: FnOnce(&mut Prefetch) -> R
+{
+    let (sender, receiver) = mpsc::channel();
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (next, finished, stopped, wanted) = (&next, &finished, &stopped, &wanted);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= crates.len() || stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let crate_ = &crates[index];
+                if wanted(crate_) {
+                    let result = fetch(registry, crate_, filter);
+                    let count = finished.fetch_add(1, Ordering::SeqCst) + 1;
+                    match result {
+                        Ok(version) => info!("Fetched crate `{}=={}` ({}/{})",
+                            crate_.name(), version, count, crates.len()),
+                        Err(e) => debug!("Failed to fetch crate {} ahead: {}", crate_, e),
+                    }
+                } else {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }
+                if sender.send(index).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let result = process(&mut Prefetch{receiver, done: HashSet::new()});
+        stopped.store(true, Ordering::SeqCst);
+        result
+    })
+}
+
+/// Fetch the archive of given crate into the download cache, returning its version.
+fn fetch(registry: &Registry, crate_: &Crate, filter: VersionFilter) -> Result<Version, Box<dyn Error>> {
+    let version = match crate_.exact_version() {
+        Some(v) => v.clone(),
+        None => {
+            // Same as when the crate is processed, but without the warnings (e.g. about yanking),
+            // which are emitted then.
+            let versions = fetch_versions(registry, crate_)?;
+            cargo_download::resolve_versions(crate_, versions, filter).next()
+                .map(|v| v.version)
+                .ok_or("no matching version found")?
+        }
+    };
+    download_crate(registry, crate_.name(), &version)?;
+    Ok(version)
+}
@@ -0,0 +1,179 @@
+//! Module defining the serializable configuration of a download run.
+//!
+//! Such configuration can be stored in a TOML file (a "profile")
+//! to bundle together the settings that are commonly used together,
+//! e.g. for downloading in an air-gapped environment.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use toml;
+
+use output::Output;
+
+
+/// Configuration of a download run.
+///
+/// Every field is optional in the TOML representation,
+/// with the same defaults as the corresponding command line flags.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DownloadConfig {
+    /// Verbosity of the logging output (negative values mean quieter).
+    pub verbosity: isize,
+    /// Whether to extract the crate's archive.
+    pub extract: bool,
+    /// Whether to redo the work even if the output appears up to date.
+    pub force: bool,
+    /// Whether to forbid all network access.
+    pub offline: bool,
+    /// Where to output the crate's archive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+    /// Whether to name the archives after their SHA-256 digest.
+    pub name_by_hash: bool,
+    /// Whether to check that the registry index and API agree on the checksums.
+    pub cross_verify: bool,
+    /// Whether to report dependency hygiene red flags in the crate's manifest.
+    pub lint_manifest: bool,
+    /// Root URL of the registry's crate metadata endpoints.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_url: Option<String>,
+    /// Root URL of the registry's download endpoints.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dl_url: Option<String>,
+    /// URL of the HTTP proxy to use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Timeout for the HTTP requests, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Name of the alternative registry (from Cargo's config) to download from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// Directory of the download cache to use instead of the default one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    /// Whether to skip verifying the checksums of downloaded archives against the registry.
+    pub no_verify: bool,
+    /// Whether to decode the saved archives in full, to check that they're well-formed.
+    pub verify_decompress: bool,
+    /// Whether to compare the crates against their git repositories.
+    pub verify_source: bool,
+    /// Path of the database with the checksums of all crates downloaded before.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_db: Option<PathBuf>,
+    /// Licenses that the crates are allowed to have, if they're being checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_licenses: Option<Vec<String>>,
+    /// Age of the selected crate version above which a warning is logged, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_older_than: Option<u64>,
+    /// Whether to warn if a newer, semver-incompatible version of the crate exists.
+    pub warn_newer_major: bool,
+    /// Names of the warnings that are suppressed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Names of the warnings that are escalated to errors.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl DownloadConfig {
+    /// Load the configuration from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DownloadConfig, ProfileError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| ProfileError::Io(path.to_owned(), e))?;
+        content.parse().map_err(|e| ProfileError::Parse(path.to_owned(), e))
+    }
+
+    /// Save the configuration to a TOML file, creating its directory if necessary.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ProfileError> {
+        let path = path.as_ref();
+        let content = self.to_toml().map_err(ProfileError::Serialize)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| ProfileError::Io(dir.to_owned(), e))?;
+        }
+        fs::write(path, content).map_err(|e| ProfileError::Io(path.to_owned(), e))
+    }
+
+    /// Format the configuration as TOML.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+}
+impl FromStr for DownloadConfig {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+
+/// Error that can occur while loading or saving a profile.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// Error reading or writing the profile file.
+    Io(PathBuf, io::Error),
+    /// Syntax error or unknown setting in the profile file.
+    Parse(PathBuf, toml::de::Error),
+    /// Error formatting the configuration as TOML.
+    Serialize(toml::ser::Error),
+}
+impl Error for ProfileError {
+    fn description(&self) -> &str { "invalid profile" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            ProfileError::Io(_, ref e) => Some(e),
+            ProfileError::Parse(_, ref e) => Some(e),
+            ProfileError::Serialize(ref e) => Some(e),
+        }
+    }
+}
+impl fmt::Display for ProfileError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProfileError::Io(ref p, ref e) =>
+                write!(fmt, "failed to access profile {}: {}", p.display(), e),
+            ProfileError::Parse(ref p, ref e) =>
+                write!(fmt, "failed to parse profile {}: {}", p.display(), e),
+            ProfileError::Serialize(ref e) => write!(fmt, "failed to serialize profile: {}", e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use output::Output;
+    use super::DownloadConfig;
+
+    #[test]
+    fn toml_roundtrip() {
+        let config = DownloadConfig{
+            extract: true,
+            offline: true,
+            output: Some(Output::Path(PathBuf::from("vendor"))),
+            timeout: Some(30),
+            ..DownloadConfig::default()
+        };
+        let toml = config.to_toml().unwrap();
+        assert_eq!(toml.parse::<DownloadConfig>().unwrap(), config);
+    }
+
+    #[test]
+    fn partial_and_invalid_profiles() {
+        let config: DownloadConfig = "offline = true\nproxy = \"http://proxy:3128\"\n".parse().unwrap();
+        assert!(config.offline);
+        assert!(!config.extract);
+        assert_eq!(config.proxy.as_deref(), Some("http://proxy:3128"));
+        assert!("ofline = true".parse::<DownloadConfig>().is_err());
+    }
+}
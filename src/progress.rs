@@ -0,0 +1,165 @@
+//! Module for showing the progress of downloads and extraction on the terminal.
+//!
+//! The progress bar is drawn on stderr, so it's only shown when that's a terminal
+//! and nothing else is written there meanwhile: -q turns it off, and so do -v
+//! and concurrent downloads (with --jobs), whose logs would break up the bar.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use isatty;
+
+use units::format_size;
+
+
+/// Width of the bar itself, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Minimum time between redraws of the progress bar.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether progress bars are shown at all.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a progress bar is on the screen right now.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+
+/// Enable the progress bars, unless stderr isn't a terminal.
+///
+/// `quiet` should be true if anything other than errors may be logged to stderr while they're shown.
+pub fn init(quiet: bool) {
+    let enabled = !quiet && cfg!(unix) && isatty::stderr_isatty();
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+
+/// Progress of an operation that processes a number of bytes (whose total may be unknown).
+///
+/// Its bar is drawn as the progress is advanced, and cleared when it's dropped.
+/// If another bar is being shown already, this one isn't.
+#[derive(Debug)]
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    started: Instant,
+    last_drawn: Option<Instant>,
+    /// Whether this is the progress bar that's being shown.
+    shown: bool,
+}
+
+impl Progress {
+    pub fn start<L: Into<String>>(label: L, total: Option<u64>) -> Progress {
+        let shown = ENABLED.load(Ordering::SeqCst)
+            && ACTIVE.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+        Progress{label: label.into(), total, done: 0, started: Instant::now(), last_drawn: None, shown}
+    }
+
+    /// Advance the progress by given number of processed bytes.
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if !self.shown {
+            return;
+        }
+        let now = Instant::now();
+        if self.last_drawn.is_some_and(|t| now.duration_since(t) < REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_drawn = Some(now);
+        let line = render(&self.label, self.done, self.total, now.duration_since(self.started));
+        // The bar is just a nicety, so failing to draw it doesn't matter.
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if !self.shown {
+            return;
+        }
+        if self.last_drawn.is_some() {
+            let _ = write!(io::stderr(), "\r\x1b[K");
+        }
+        ACTIVE.store(false, Ordering::SeqCst);
+    }
+}
+
+
+/// Reader that advances a progress bar by the bytes that are read through it.
+#[derive(Debug)]
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: Progress) -> Self {
+        ProgressReader{inner, progress}
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.advance(n as u64);
+        Ok(n)
+    }
+}
+
+
+/// Render the line of a progress bar: its label, the bar (if the total is known),
+/// the amount processed, and the rate and ETA once they can be estimated.
+fn render(label: &str, done: u64, total: Option<u64>, elapsed: Duration) -> String {
+    let mut line = label.to_owned();
+    match total {
+        Some(total) if total > 0 => {
+            let filled = (BAR_WIDTH as u64 * done.min(total) / total) as usize;
+            line += &format!(" [{}{}] {}/{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled),
+                format_size(done), format_size(total));
+        }
+        _ => line += &format!(" {}", format_size(done)),
+    }
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 && done > 0 {
+        let rate = done as f64 / secs;
+        line += &format!(" {}/s", format_size(rate as u64));
+        if let Some(total) = total {
+            let eta = (total.saturating_sub(done) as f64 / rate).ceil() as u64;
+            line += &format!(" ETA {}", format_eta(eta));
+        }
+    }
+    line
+}
+
+/// Format the number of seconds left, e.g. "1m05s".
+fn format_eta(secs: u64) -> String {
+    match secs {
+        s if s >= 60 * 60 => format!("{}h{:02}m", s / (60 * 60), s % (60 * 60) / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::render;
+
+    #[test]
+    fn progress_line() {
+        assert_eq!(render("Downloading foo 1.0.0", 512, Some(2048), Duration::from_secs(1)),
+            "Downloading foo 1.0.0 [#######-----------------------] 512 B/2.0 KiB 512 B/s ETA 3s");
+        assert_eq!(render("Downloading foo 1.0.0", 3 * 1024 * 1024, None, Duration::from_secs(2)),
+            "Downloading foo 1.0.0 3.0 MiB 1.5 MiB/s");
+        assert_eq!(render("Extracting foo 1.0.0", 0, Some(100), Duration::from_secs(0)),
+            "Extracting foo 1.0.0 [------------------------------] 0 B/100 B");
+        assert_eq!(render("Downloading foo 1.0.0", 1, Some(3601), Duration::from_secs(1)),
+            "Downloading foo 1.0.0 [------------------------------] 1 B/3.5 KiB 1 B/s ETA 1h00m");
+    }
+}
@@ -0,0 +1,88 @@
+//! Module for limiting the bandwidth used by downloads (`--limit-rate`).
+//!
+//! There's a single limit for the whole program, which the concurrent downloads share
+//! by drawing from the same token bucket as they receive the archives.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+lazy_static! {
+    /// Bucket that the downloads draw from, if the bandwidth is limited.
+    static ref BUCKET: Mutex<Option<Bucket>> = Mutex::new(None);
+}
+
+
+/// Limit the bandwidth of all the downloads to given number of bytes per second.
+pub fn set_limit(bytes_per_sec: u64) {
+    *BUCKET.lock().unwrap() = Some(Bucket::new(bytes_per_sec, Instant::now()));
+}
+
+/// Account for given number of bytes just received,
+/// sleeping for as long as it takes to keep under the limit (if any).
+pub fn throttle(bytes: usize) {
+    let delay = match *BUCKET.lock().unwrap() {
+        Some(ref mut bucket) => bucket.take(bytes as u64, Instant::now()),
+        None => return,
+    };
+    if delay > Duration::from_millis(0) {
+        thread::sleep(delay);
+    }
+}
+
+
+/// Token bucket that's refilled at the rate of the limit, up to a second's worth of bytes.
+///
+/// Taking more than there is puts the bucket into debt, which the later takers wait out as well,
+/// so that concurrent downloads queue up behind each other rather than overshooting the limit.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    available: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(bytes_per_sec: u64, now: Instant) -> Bucket {
+        let rate = bytes_per_sec as f64;
+        Bucket{rate, available: rate, refilled: now}
+    }
+
+    /// Take given number of bytes from the bucket at given time,
+    /// returning how long to wait before they may be taken.
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.available = (self.available + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        self.available -= bytes as f64;
+        if self.available >= 0.0 {
+            Duration::from_millis(0)
+        } else {
+            Duration::from_secs_f64(-self.available / self.rate)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::Bucket;
+
+    #[test]
+    fn bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(1000, start);
+        assert_eq!(bucket.take(1000, start), Duration::from_millis(0));
+        assert_eq!(bucket.take(500, start), Duration::from_millis(500));
+        // Another download waits for the debt of the first one too.
+        assert_eq!(bucket.take(500, start), Duration::from_millis(1000));
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(bucket.take(0, later), Duration::from_millis(0));
+        // The bucket holds no more than a second's worth of bytes, however long it's been idle.
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(bucket.take(1500, much_later), Duration::from_millis(500));
+    }
+}
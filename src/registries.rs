@@ -0,0 +1,210 @@
+//! Module for resolving the alternative registries configured in Cargo's config,
+//! like a private one:
+//!
+//! ```toml
+//! [registries.my-registry]
+//! index = "sparse+https://my-intranet.example.com/api/cargo/index/"
+//! ```
+//!
+//! Just like in Cargo, the index URL and the token can also be given through
+//! `CARGO_REGISTRIES_<NAME>_INDEX` and `CARGO_REGISTRIES_<NAME>_TOKEN` variables,
+//! and the token can also come from Cargo's credentials file.
+//! The download endpoint is read from the `config.json` file of the index,
+//! which is served over either the sparse or the git protocol.
+//!
+//! Registries (including crates.io) may also be replaced with other sources,
+//! as resolved by the `source_replacement` module.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use toml;
+
+use cache::cargo_home;
+use cargo_config::{CargoConfig, ConfigError};
+use http::Client;
+use registry::Registry;
+use registry_index::{Index, RegistryIndex};
+use source_replacement::Replacement;
+use sparse_index::IndexError;
+
+
+/// Name that Cargo uses for crates.io as a registry.
+pub const CRATES_IO: &str = "crates-io";
+
+
+/// Open the alternative registry with given name, as configured in Cargo's config.
+pub fn open(client: Client, config: &CargoConfig, name: &str) -> Result<Registry, RegistryError> {
+    let index = RegistryIndex::new(index(config, name)?);
+    let token = token(config, name)?;
+    open_index(client, index, token, name)
+}
+
+/// Open the source with given name that replaces the registry with given name
+/// (crates.io or an alternative one), as found by `source_replacement::find`.
+pub fn open_replacement(client: Client, config: &CargoConfig, replaced: &str, name: &str,
+                        replacement: Replacement) -> Result<Registry, RegistryError> {
+    match replacement {
+        Replacement::Registry(index) => {
+            let token = token(config, name)?;
+            open_index(client, RegistryIndex::new(index), token, name)
+        }
+        Replacement::Local(source) => {
+            // The crates are still those of the replaced registry, just read from elsewhere.
+            let index = if replaced == CRATES_IO {
+                RegistryIndex::crates_io()
+            } else {
+                RegistryIndex::new(index(config, replaced)?)
+            };
+            Ok(Registry::local(client, index, source))
+        }
+    }
+}
+
+/// Open the registry with given index (and name), reading its download endpoint from the index.
+fn open_index(client: Client, index: RegistryIndex, token: Option<String>,
+              name: &str) -> Result<Registry, RegistryError> {
+    if client.is_offline() {
+        // Nothing will be fetched from the registry anyway.
+        return Ok(Registry::alternative(client, index, "", token));
+    }
+    let dl = index.fetch_dl(&client, token.as_deref())?;
+    debug!("Crates of registry `{}` are downloaded from {}", name, dl);
+    Ok(Registry::alternative(client, index, &dl, token))
+}
+
+/// Find the index of the registry with given name.
+pub fn index(config: &CargoConfig, name: &str) -> Result<Index, RegistryError> {
+    let url = match registry_env_var(name, "INDEX") {
+        Some(url) => url,
+        None => config.get_str(&format!("registries.{}.index", name))?
+            .ok_or_else(|| RegistryError::NotConfigured(name.to_owned()))?,
+    };
+    Ok(Index::from_url(&url)?)
+}
+
+/// Find the token of the registry with given name, if it has one.
+fn token(config: &CargoConfig, name: &str) -> Result<Option<String>, RegistryError> {
+    if let Some(token) = registry_env_var(name, "TOKEN") {
+        return Ok(Some(token));
+    }
+    if let Some(token) = config.get_str(&format!("registries.{}.token", name))? {
+        return Ok(Some(token));
+    }
+    let token = credentials_token(Some(name)).map_err(RegistryError::Credentials)?;
+    Ok(token.map(|(token, _)| token))
+}
+
+/// Read the `CARGO_REGISTRIES_<NAME>_<KEY>` environment variable of given registry.
+fn registry_env_var(name: &str, key: &str) -> Option<String> {
+    let var = format!("CARGO_REGISTRIES_{}_{}", name.to_uppercase().replace('-', "_"), key);
+    env::var(var).ok().filter(|v| !v.is_empty())
+}
+
+/// Read the token of given registry (or crates.io, if none is given) from Cargo's credentials file,
+/// returning it along with the path of the file.
+pub fn credentials_token(name: Option<&str>) -> Result<Option<(String, PathBuf)>, String> {
+    let home = match cargo_home() {
+        Some(home) => home,
+        None => return Ok(None),
+    };
+    for file in &["credentials.toml", "credentials"] {
+        let path = home.join(file);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let credentials: toml::Value = content.parse()
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+        let table = match name {
+            Some(name) => credentials.get("registries").and_then(|r| r.get(name)),
+            None => credentials.get("registry"),
+        };
+        if let Some(token) = table.and_then(|t| t.get("token")).and_then(|t| t.as_str()) {
+            return Ok(Some((token.to_owned(), path)));
+        }
+    }
+    Ok(None)
+}
+
+
+/// Error that can occur while resolving an alternative registry.
+#[derive(Debug)]
+pub enum RegistryError {
+    /// Cargo's config doesn't define the registry with given name.
+    NotConfigured(String),
+    /// Error reading Cargo's configuration.
+    Config(ConfigError),
+    /// Error reading Cargo's credentials file.
+    Credentials(String),
+    /// Error reading the registry's index.
+    Index(IndexError),
+    /// Invalid source replacement in Cargo's config.
+    Replacement(String),
+}
+impl From<ConfigError> for RegistryError {
+    fn from(input: ConfigError) -> Self {
+        RegistryError::Config(input)
+    }
+}
+impl From<IndexError> for RegistryError {
+    fn from(input: IndexError) -> Self {
+        RegistryError::Index(input)
+    }
+}
+impl Error for RegistryError {
+    fn description(&self) -> &str { "cannot resolve registry" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            RegistryError::Config(ref e) => Some(e),
+            RegistryError::Index(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for RegistryError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistryError::NotConfigured(ref name) => write!(fmt,
+                "registry `{}` is not configured (its index should be set as registries.{}.index in Cargo's config)",
+                name, name),
+            RegistryError::Config(ref e) => write!(fmt, "{}", e),
+            RegistryError::Credentials(ref e) => write!(fmt, "{}", e),
+            RegistryError::Index(ref e) => write!(fmt, "{}", e),
+            RegistryError::Replacement(ref e) => write!(fmt, "invalid source replacement: {}", e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use cargo_config::CargoConfig;
+    use super::{RegistryError, index};
+
+    #[test]
+    fn indices() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-registries-{}", std::process::id()));
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(dir.join(".cargo").join("config.toml"), concat!(
+            "[registries.test-sparse]\n",
+            "index = \"sparse+https://registry.example.com/index/\"\n",
+            "[registries.test-git]\n",
+            "index = \"https://github.com/example/index\"\n")).unwrap();
+        let config = CargoConfig::load_for(&dir, None).unwrap();
+
+        assert_eq!(index(&config, "test-sparse").unwrap().to_string(),
+                   "sparse+https://registry.example.com/index/");
+        assert_eq!(index(&config, "test-git").unwrap().protocol(), "git");
+        match index(&config, "test-missing") {
+            Err(RegistryError::NotConfigured(..)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,679 @@
+//! Module for talking to the registry that crates are downloaded from.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+
+use reqwest::{Response, StatusCode};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE};
+use semver::Version;
+use serde_json::Value as Json;
+
+use cargo_download::{Compression, Dependency, ResolvedVersion, download_url, url_version};
+use checksum;
+use http::{Client, HttpError};
+use local_source::LocalSource;
+use progress::Progress;
+use rate_limit;
+use registry_index::RegistryIndex;
+
+
+/// Root of the crates.io web API, which serves both crate metadata and downloads.
+pub const CRATES_API_ROOT: &str = "https://crates.io/api/v1/crates";
+
+
+/// Registry serving crate metadata and archives.
+#[derive(Debug)]
+pub struct Registry {
+    client: Client,
+    /// Root URL of the API endpoints with crate metadata (versions and checksums),
+    /// if the registry has them. Otherwise, the metadata is read from the index.
+    index_url: Option<String>,
+    /// Root URL of the endpoints serving crate archives,
+    /// or a template of their URLs (as in the `dl` field of the index's `config.json`).
+    dl_url: String,
+    /// Index of the registry.
+    index: RegistryIndex,
+    /// Token that the requests to the registry are authorized with.
+    token: Option<String>,
+    /// Whether to verify the checksums of downloaded archives.
+    verify: bool,
+    /// Local source that the registry is replaced with, if any,
+    /// which both the index files and the archives are read from instead.
+    local: Option<LocalSource>,
+}
+
+impl Registry {
+    /// Create a registry accessed through given client.
+    /// URLs that aren't specified default to those of crates.io.
+    pub fn new(client: Client, index_url: Option<&str>, dl_url: Option<&str>) -> Registry {
+        let root = |url: Option<&str>| url.unwrap_or(CRATES_API_ROOT).trim_end_matches('/').to_owned();
+        Registry{
+            client,
+            index_url: Some(root(index_url)),
+            dl_url: root(dl_url),
+            index: RegistryIndex::crates_io(),
+            token: None,
+            verify: true,
+            local: None,
+        }
+    }
+
+    /// Create a registry with given index and download URL template,
+    /// with the requests to it authorized by given token, if any.
+    ///
+    /// Registries other than crates.io don't have its crate metadata endpoints,
+    /// so everything about the crates is read from the index.
+    pub fn alternative(client: Client, index: RegistryIndex, dl_template: &str,
+                       token: Option<String>) -> Registry {
+        Registry{
+            client,
+            index_url: None,
+            dl_url: dl_template.to_owned(),
+            index,
+            token,
+            verify: true,
+            local: None,
+        }
+    }
+
+    /// Create a registry with given index, whose crates are read from given local source instead
+    /// (as with Cargo's source replacement).
+    pub fn local(client: Client, index: RegistryIndex, source: LocalSource) -> Registry {
+        Registry{
+            client,
+            index_url: None,
+            dl_url: String::new(),
+            index,
+            token: None,
+            verify: true,
+            local: Some(source),
+        }
+    }
+
+    /// Set whether to verify the checksums of downloaded archives against the registry.
+    #[inline]
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Whether the checksums of archives are verified against the registry.
+    #[inline]
+    pub fn verifies(&self) -> bool {
+        self.verify
+    }
+
+    #[inline]
+    pub fn index(&self) -> &RegistryIndex {
+        &self.index
+    }
+
+    /// Whether the registry can't be reached, because the client is offline
+    /// (which doesn't matter for local sources).
+    #[inline]
+    pub fn is_offline(&self) -> bool {
+        self.local.is_none() && self.client.is_offline()
+    }
+
+    #[inline]
+    pub fn is_local(&self) -> bool {
+        self.local.is_some()
+    }
+
+    /// ID of where the crates of the registry come from: its index, or the local source replacing it.
+    pub fn source_id(&self) -> String {
+        match self.local {
+            Some(LocalSource::Registry(ref dir)) => format!("local-registry+{}", dir.display()),
+            Some(LocalSource::Directory(ref dir)) => format!("directory+{}", dir.display()),
+            None => self.index.source_id(),
+        }
+    }
+
+    /// Fetch the list of all published versions of given crate.
+    pub fn fetch_versions(&self, name: &str) -> Result<Vec<ResolvedVersion>, Box<dyn Error>> {
+        let index_url = match self.index_url {
+            Some(ref url) => url,
+            None => return self.fetch_index_versions(name),
+        };
+        let versions_url = format!("{}/{}/versions", index_url, name);
+        debug!("Fetching versions of crate `{}` from {}", name, versions_url);
+        let purpose = format!("find the versions of crate `{}`", name);
+        let response: Json = self.get(&versions_url, &purpose)?.json()?;
+        ResolvedVersion::list_from_api(&response)
+            .ok_or_else(|| format!("malformed response from {}", versions_url).into())
+    }
+
+    /// Fetch the SHA-256 checksum of given crate version.
+    pub fn fetch_checksum(&self, name: &str, version: &Version) -> Result<String, Box<dyn Error>> {
+        let index_url = match self.index_url {
+            Some(ref url) => url,
+            None => return self.fetch_index_checksum(name, version),
+        };
+        let version_url = format!("{}/{}/{}", index_url, name, url_version(version));
+        debug!("Fetching checksum of crate `{}=={}` from {}", name, version, version_url);
+        let purpose = format!("fetch the checksum of crate `{}=={}`", name, version);
+        let response: Json = self.get(&version_url, &purpose)?.json()?;
+        response.pointer("/version/checksum").and_then(|c| c.as_str())
+            .map(|c| c.to_owned())
+            .ok_or_else(|| format!("malformed response from {}", version_url).into())
+    }
+
+    /// Fetch the metadata of given crate version that the registry index doesn't have,
+    /// e.g. its license, from the API.
+    ///
+    /// Without the API (for registries other than crates.io), none of it is known.
+    pub fn fetch_metadata(&self, name: &str, version: &Version) -> Result<CrateMetadata, Box<dyn Error>> {
+        let index_url = match self.index_url {
+            Some(ref url) => url,
+            None => return Ok(CrateMetadata::default()),
+        };
+        let version_url = format!("{}/{}/{}", index_url, name, url_version(version));
+        debug!("Fetching metadata of crate `{}=={}` from {}", name, version, version_url);
+        let purpose = format!("fetch the metadata of crate `{}=={}`", name, version);
+        let response: Json = self.get(&version_url, &purpose)?.json()?;
+        let version_json = response.get("version")
+            .ok_or_else(|| format!("malformed response from {}", version_url))?;
+
+        // The repository is only listed for the crate as a whole, i.e. as of its newest version.
+        let crate_url = format!("{}/{}", index_url, name);
+        debug!("Fetching metadata of crate `{}` from {}", name, crate_url);
+        let response: Json = self.get(&crate_url, &purpose)?.json()?;
+
+        Ok(CrateMetadata{
+            license: version_json.get("license").and_then(|l| l.as_str()).map(|l| l.to_owned()),
+            repository: response.pointer("/crate/repository").and_then(|r| r.as_str()).map(|r| r.to_owned()),
+            size: version_json.get("crate_size").and_then(|s| s.as_u64()),
+        })
+    }
+
+    /// Fetch the list of all published versions of given crate from the registry index.
+    pub fn fetch_index_versions(&self, name: &str) -> Result<Vec<ResolvedVersion>, Box<dyn Error>> {
+        let (index_url, content) = self.fetch_index_file(name)?;
+        ResolvedVersion::list_from_index(&content)
+            .ok_or_else(|| format!("malformed index file at {}", index_url).into())
+    }
+
+    /// Fetch the dependencies of given crate version from the registry index.
+    pub fn fetch_dependencies(&self, name: &str, version: &Version) -> Result<Vec<Dependency>, Box<dyn Error>> {
+        let (index_url, content) = self.fetch_index_file(name)?;
+        Dependency::list_from_index(&content, version).ok_or_else(|| format!(
+            "crate `{}=={}` not found or malformed in the index file at {}", name, version, index_url).into())
+    }
+
+    /// Fetch the index file of given crate, returning its URL and content.
+    pub fn fetch_index_file(&self, name: &str) -> Result<(String, String), Box<dyn Error>> {
+        if let Some(ref source) = self.local {
+            let (path, content) = source.index_file(name)?
+                .ok_or_else(|| format!("crate `{}` not found in {}", name, source))?;
+            return Ok((path.display().to_string(), content));
+        }
+        Ok(self.index.fetch_file(&self.client, self.token.as_deref(), name)?)
+    }
+
+    /// Fetch the configuration of the registry index (its `config.json`).
+    pub fn fetch_index_config(&self) -> Result<String, Box<dyn Error>> {
+        if let Some(ref source) = self.local {
+            return Err(format!("{} has no index configuration", source).into());
+        }
+        Ok(self.index.fetch_config(&self.client, self.token.as_deref())?)
+    }
+
+    /// Fetch the SHA-256 checksum of given crate version from the registry index.
+    pub fn fetch_index_checksum(&self, name: &str, version: &Version) -> Result<String, Box<dyn Error>> {
+        self.fetch_index_versions(name)?.into_iter()
+            // Version equality disregards build metadata, but it's significant here.
+            .find(|v| &v.version == version && v.version.build == version.build)
+            .and_then(|v| v.checksum)
+            .ok_or_else(|| format!("crate `{}=={}` not found in the index", name, version).into())
+    }
+
+    /// Fetch the login of the user that given API token belongs to,
+    /// which fails if the token isn't valid.
+    pub fn fetch_token_owner(&self, token: &str) -> Result<String, Box<dyn Error>> {
+        let index_url = self.index_url.as_ref()
+            .ok_or("the registry has no API that the token could be checked with")?;
+        // The user endpoint is a sibling of the crate metadata ones.
+        let api_root = index_url.trim_end_matches("/crates");
+        if api_root == index_url {
+            return Err(format!("cannot locate the user endpoint of the API at {}", index_url).into());
+        }
+        let me_url = format!("{}/me", api_root);
+        debug!("Checking the registry token at {}", me_url);
+        let mut response = self.client.get_authorized(&me_url, token, "check the registry token")?;
+        if !response.status().is_success() {
+            return Err(format!("{} responded with {}", me_url, response.status()).into());
+        }
+        let response: Json = response.json()?;
+        response.pointer("/user/login").and_then(|l| l.as_str())
+            .map(|l| l.to_owned())
+            .ok_or_else(|| format!("malformed response from {}", me_url).into())
+    }
+
+    /// Download the archive of given crate version, as a vector of gzipped bytes.
+    ///
+    /// If a path of a partial file is given, the archive is received into it,
+    /// and resumed from where it ended (with a range request) if the file exists already,
+    /// e.g. after the connection broke on a previous attempt. The file is kept if the download
+    /// fails that way, but removed if the archive turns out to be invalid.
+    ///
+    /// A resumed download is only continued if the server sends exactly the requested range,
+    /// and if the archive stitched together that way is invalid, it's downloaded again from the start.
+    ///
+    /// Unless disabled, the archive's SHA-256 checksum is verified against the registry,
+    /// failing with `ChecksumMismatch` if it doesn't match.
+    ///
+    /// If the registry is replaced with a local source, the archive is read from there instead
+    /// (and only verified if it's the published one).
+    pub fn download(&self, name: &str, version: &Version,
+                    part_path: Option<&Path>) -> Result<Vec<u8>, Box<dyn Error>> {
+        if let Some(ref source) = self.local {
+            debug!("Reading crate `{}=={}` from {}", name, version, source);
+            let bytes = source.read_crate(name, version)?
+                .ok_or_else(|| format!("crate `{}=={}` not found in {}", name, version, source))?;
+            if source.has_published_archives() {
+                self.verify_archive(name, version, &source.to_string(), &bytes, None, String::new())?;
+            } else {
+                check_archive(&bytes, None).map_err(|e| format!("content from {} {}", source, e))?;
+            }
+            return Ok(bytes);
+        }
+        let (download_url, checksum) = self.download_url(name, version)?;
+        debug!("Downloading crate `{}=={}` from {}", name, version, download_url);
+        let purpose = format!("download crate `{}=={}`", name, version);
+
+        let mut part = match part_path.map(Part::open) {
+            Some(Ok(part)) => part,
+            Some(Err(e)) => {
+                debug!("Failed to open the partial file of crate `{}=={}`: {}", name, version, e);
+                Part::in_memory()
+            }
+            None => Part::in_memory(),
+        };
+        loop {
+            let (content_type, resumed) = self.receive(name, version, &download_url, &purpose, &mut part)?;
+            let verified = self.verify_archive(name, version, &download_url, &part.bytes,
+                                               content_type.as_deref(), checksum.clone());
+            match verified {
+                Ok(()) => return Ok(part.finish()),
+                // The parts may not fit together, e.g. if the archive has been replaced in between.
+                Err(e) if resumed => {
+                    warn!("Resumed download of crate `{}=={}` is invalid ({}), \
+                           downloading it again from the start", name, version, e);
+                    part.clear()?;
+                }
+                Err(e) => {
+                    if let Some(path) = part_path {
+                        let _ = fs::remove_file(path);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Start downloading the archive of given crate version from the registry, returning the reader
+    /// to receive it from as it's read, along with its size (if known).
+    ///
+    /// If a path of a partial file is given, the archive is also written into it,
+    /// replacing whatever was there.
+    ///
+    /// Unlike with `download`, the archive is left to the caller to verify once it's been read,
+    /// and it isn't resumed if the connection breaks.
+    pub fn open_download(&self, name: &str, version: &Version,
+                         part_path: Option<&Path>) -> Result<(Receiving, Option<u64>), Box<dyn Error>> {
+        let (download_url, _) = self.download_url(name, version)?;
+        debug!("Downloading crate `{}=={}` from {}", name, version, download_url);
+        let response = self.get(&download_url, &format!("download crate `{}=={}`", name, version))?;
+        let size = response.content_length();
+        let file = match part_path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                Some(File::create(path)?)
+            }
+            None => None,
+        };
+        Ok((Receiving{response, file}, size))
+    }
+
+    /// URL to download the archive of given crate version from, along with its checksum
+    /// if it was needed for the URL (or else an empty string).
+    fn download_url(&self, name: &str, version: &Version) -> Result<(String, String), Box<dyn Error>> {
+        let checksum = if self.dl_url.contains("{sha256-checksum}") {
+            self.fetch_index_checksum(name, version)?
+        } else {
+            String::new()
+        };
+        Ok((download_url(&self.dl_url, name, version, &checksum), checksum))
+    }
+
+    /// Receive the archive from given download URL into given part, resuming from where it ends,
+    /// and returning the archive's content type along with whether the download has been resumed.
+    fn receive(&self, name: &str, version: &Version, download_url: &str, purpose: &str,
+               part: &mut Part) -> Result<(Option<String>, bool), Box<dyn Error>> {
+        // The connection may also break while the archive is being received,
+        // which is retried just like failed requests are, but from where the archive ended.
+        let mut retry = 0;
+        let mut resumed = false;
+        loop {
+            let offset = part.bytes.len() as u64;
+            if offset > 0 {
+                debug!("Resuming the download of crate `{}=={}` from byte {}", name, version, offset);
+            }
+            let mut response = self.get_from(download_url, offset, purpose)?;
+            if offset > 0 {
+                match response.status() {
+                    StatusCode::PARTIAL_CONTENT => {
+                        let range = response.headers().get(CONTENT_RANGE).and_then(|r| r.to_str().ok());
+                        match range.map(parse_content_range) {
+                            Some(Some((first, last, length)))
+                                if first == offset && length.is_none_or(|l| last + 1 == l) => resumed = true,
+                            _ => {
+                                warn!("Server didn't honor the range request for crate `{}=={}` \
+                                       (Content-Range: {}), starting over",
+                                    name, version, range.unwrap_or("none"));
+                                part.clear()?;
+                                continue;
+                            }
+                        }
+                    }
+                    // What was received before is longer than the archive, so it's something else.
+                    StatusCode::RANGE_NOT_SATISFIABLE => {
+                        debug!("Partial download of crate `{}=={}` is invalid, starting over", name, version);
+                        part.clear()?;
+                        continue;
+                    }
+                    // The server sent the whole archive anyway.
+                    _ => {
+                        part.clear()?;
+                        resumed = false;
+                    }
+                }
+            }
+            let label = format!("Downloading {} {}", name, version);
+            match part.receive(&mut response, download_url, &label) {
+                Ok(()) => return Ok((content_type(&response), resumed)),
+                Err(e) => match self.client.backoff(retry, None) {
+                    Some(delay) => {
+                        warn!("Downloading crate `{}=={}` failed ({}), retrying in {:.1}s",
+                            name, version, e, delay.as_secs_f64());
+                        thread::sleep(delay);
+                        retry += 1;
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
+    }
+
+    /// Check that the downloaded archive of given crate version is valid,
+    /// and unless disabled, that its checksum matches the registry.
+    fn verify_archive(&self, name: &str, version: &Version, download_url: &str, bytes: &[u8],
+                      content_type: Option<&str>, checksum: String) -> Result<(), Box<dyn Error>> {
+        check_archive(bytes, content_type)
+            .map_err(|e| format!("response from {} {}", download_url, e))?;
+
+        if self.verify {
+            let expected = if checksum.is_empty() { self.expected_checksum(name, version)? } else { checksum };
+            verify_checksum(name, version, bytes, expected)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the archive of given crate version taken from the download cache is valid,
+    /// and unless disabled, that its checksum matches the registry.
+    ///
+    /// In offline mode, the registry may not know the crate version, and the archive is then trusted.
+    pub fn verify_cached(&self, name: &str, version: &Version, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        check_archive(bytes, None).map_err(|e| format!("archive {}", e))?;
+        if !self.verify {
+            return Ok(());
+        }
+        let expected = match self.expected_checksum(name, version) {
+            Ok(checksum) => checksum,
+            Err(e) if self.is_offline() => {
+                debug!("Couldn't verify crate `{}=={}` from the cache offline: {}", name, version, e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        verify_checksum(name, version, bytes, expected)
+    }
+
+    /// Fetch the SHA-256 checksum that the archive of given crate version should have.
+    fn expected_checksum(&self, name: &str, version: &Version) -> Result<String, Box<dyn Error>> {
+        match self.index_url {
+            // Custom metadata endpoints may serve crates that aren't in the crates.io index.
+            Some(ref url) if url != CRATES_API_ROOT => self.fetch_checksum(name, version),
+            _ => self.fetch_index_checksum(name, version),
+        }
+    }
+
+    /// Make a GET request to given URL of the registry, authorized if there's a token.
+    fn get(&self, url: &str, purpose: &str) -> Result<Response, HttpError> {
+        self.get_from(url, 0, purpose)
+    }
+
+    /// Make a GET request for the content at given URL of the registry from given byte offset on,
+    /// authorized if there's a token.
+    fn get_from(&self, url: &str, offset: u64, purpose: &str) -> Result<Response, HttpError> {
+        self.client.get_from(url, self.token.as_deref(), offset, purpose)
+    }
+}
+
+
+/// Archive being downloaded, which is also written to a partial file (if there's one)
+/// so that the download can be resumed from there.
+struct Part {
+    bytes: Vec<u8>,
+    file: Option<File>,
+}
+
+impl Part {
+    /// Open the partial file at given path, reading what it has received already.
+    fn open(path: &Path) -> io::Result<Part> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        Ok(Part{bytes, file: Some(file)})
+    }
+
+    /// Download that's received into memory only.
+    fn in_memory() -> Part {
+        Part{bytes: vec![], file: None}
+    }
+
+    /// Receive the body of given response, appending it to what's been received already,
+    /// and showing the progress with given label.
+    ///
+    /// Whatever arrives is kept even if the body is cut short, which is an error.
+    fn receive(&mut self, response: &mut Response, url: &str, label: &str) -> Result<(), Box<dyn Error>> {
+        let content_length: Option<usize> = response.headers().get(CONTENT_LENGTH)
+            .and_then(|ct_len| ct_len.to_str().ok())
+            .and_then(|ct_len| ct_len.parse().ok());
+        trace!("Download size: {}", content_length.map_or("<unknown>".into(), |cl| format!("{} bytes", cl)));
+        if let Some(cl) = content_length {
+            self.bytes.reserve(cl);
+        }
+        let start = self.bytes.len();
+        let mut progress = Progress::start(label, content_length.map(|cl| cl as u64));
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let n = match response.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            rate_limit::throttle(n);
+            self.bytes.extend_from_slice(&buf[..n]);
+            progress.advance(n as u64);
+            if let Some(ref mut file) = self.file {
+                file.write_all(&buf[..n])?;
+            }
+        }
+        let received = self.bytes.len() - start;
+        match content_length {
+            Some(cl) if received < cl => Err(format!("response from {} is truncated: received {} of {} bytes",
+                url, received, cl).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Discard what's been received, to start over.
+    fn clear(&mut self) -> io::Result<()> {
+        self.bytes.clear();
+        match self.file {
+            Some(ref file) => file.set_len(0),
+            None => Ok(()),
+        }
+    }
+
+    /// Finish the download (closing the partial file), returning the received bytes.
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Archive being received from the registry as it's read,
+/// which is also written to a partial file (if there's one) like with `Part`.
+pub struct Receiving {
+    response: Response,
+    file: Option<File>,
+}
+
+impl Read for Receiving {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.response.read(buf)?;
+        rate_limit::throttle(n);
+        if let Some(ref mut file) = self.file {
+            file.write_all(&buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+/// Content type of given response, if any.
+fn content_type(response: &Response) -> Option<String> {
+    response.headers().get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.to_owned())
+}
+
+/// Metadata of a crate version from the registry API.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrateMetadata {
+    /// SPDX license expression, if the crate declares one (rather than a license file).
+    pub license: Option<String>,
+    /// URL of the crate's source repository.
+    pub repository: Option<String>,
+    /// Size of the crate's archive in bytes.
+    pub size: Option<u64>,
+}
+
+/// Error of a downloaded archive not matching the checksum of its crate version in the registry.
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub name: String,
+    pub version: Version,
+    /// Checksum from the registry, as a hex string.
+    pub expected: String,
+    /// Checksum of the downloaded archive, as a hex string.
+    pub actual: String,
+}
+impl Error for ChecksumMismatch {
+    fn description(&self) -> &str { "checksum mismatch" }
+}
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "checksum of the downloaded archive of crate `{}=={}` is {}, but the registry says {}",
+            self.name, self.version, self.actual, self.expected)
+    }
+}
+
+
+/// Check that the SHA-256 checksum of given archive of a crate version is the expected one.
+fn verify_checksum(name: &str, version: &Version, bytes: &[u8], expected: String) -> Result<(), Box<dyn Error>> {
+    let actual = checksum::sha256_reader(bytes)?;
+    if !actual.eq_ignore_ascii_case(&expected) {
+        return Err(Box::new(ChecksumMismatch{name: name.to_owned(), version: version.clone(), expected, actual}));
+    }
+    trace!("Checksum of crate `{}=={}` verified: {}", name, version, actual);
+    Ok(())
+}
+
+/// Check that given downloaded content looks like a crate archive (compressed in a recognized way),
+/// rather than e.g. a maintenance page served by the registry or a captive proxy
+/// with a successful HTTP status.
+///
+/// The content type that the content was served with, if any, is included in the error.
+fn check_archive(bytes: &[u8], content_type: Option<&str>) -> Result<(), String> {
+    if Compression::detect(bytes).is_some() {
+        return Ok(());
+    }
+    let served_as = content_type.map_or_else(String::new, |ct| format!(", served as {}", ct));
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).trim_start().to_lowercase();
+    let is_html = start.starts_with("<!doctype html") || start.starts_with("<html") || start.contains("<body")
+        || content_type.is_some_and(|ct| ct.to_lowercase().starts_with("text/html"));
+    if is_html {
+        return Err(format!(concat!("is not a crate archive but an HTML page{} ",
+            "(the registry may be down for maintenance, or a proxy may be intercepting the requests)"), served_as));
+    }
+    Err(format!("is not a crate archive ({} bytes without a gzip, zstd, or tar header{})", bytes.len(), served_as))
+}
+
+/// Parse the value of a Content-Range header, like `bytes 100-199/200`,
+/// into the first and the last byte of the range, and the complete length (if it's known).
+fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (bytes, length) = range.split_once('/')?;
+    let (first, last) = bytes.split_once('-')?;
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    let length = match length.trim() {
+        "*" => None,
+        length => Some(length.parse().ok()?),
+    };
+    if last < first || length.is_some_and(|l| last >= l) {
+        return None;
+    }
+    Some((first, last, length))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{check_archive, parse_content_range};
+
+    #[test]
+    fn archive_sniffing() {
+        assert!(check_archive(&[0x1f, 0x8b, 0x08, 0x00], None).is_ok());
+        // Registries may serve the archives with any content type.
+        assert!(check_archive(&[0x1f, 0x8b, 0x08, 0x00], Some("text/html")).is_ok());
+        assert!(check_archive(&[0x28, 0xb5, 0x2f, 0xfd, 0x60], None).is_ok());
+        let html = check_archive(b"\n  <!DOCTYPE html>\n<html><body>Down for maintenance</body></html>", None);
+        assert!(html.unwrap_err().contains("HTML page"));
+        let html = check_archive(b"Down for maintenance", Some("text/html; charset=utf-8"));
+        assert!(html.unwrap_err().contains("HTML page, served as text/html; charset=utf-8"));
+        assert!(check_archive(b"", None).is_err());
+        let text = check_archive(b"Not Found", Some("text/plain"));
+        assert_eq!(text.unwrap_err(),
+                   "is not a crate archive (9 bytes without a gzip, zstd, or tar header, served as text/plain)");
+    }
+
+    #[test]
+    fn content_ranges() {
+        assert_eq!(parse_content_range("bytes 100-199/200"), Some((100, 199, Some(200))));
+        assert_eq!(parse_content_range("bytes 0-9/*"), Some((0, 9, None)));
+        assert_eq!(parse_content_range("bytes 100-199/150"), None);
+        assert_eq!(parse_content_range("bytes 20-10/100"), None);
+        assert_eq!(parse_content_range("bytes */200"), None);
+        assert_eq!(parse_content_range("items 0-9/10"), None);
+    }
+}
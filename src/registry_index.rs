@@ -0,0 +1,245 @@
+//! Module for reading a registry's index over whichever protocol works.
+//!
+//! The index is read over the protocol it's configured with (sparse or git), but if its endpoint
+//! turns out to be missing or misconfigured, the same index is read over the other protocol instead:
+//! the git repository at the sparse index's URL (without the `sparse+` prefix), or vice versa
+//! (crates.io serves its index at different URLs for each protocol, which are known).
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cargo_download::CRATES_IO_SOURCE;
+use git_index::GitIndex;
+use http::Client;
+use serde_json::{self, Value as Json};
+use sparse_index::{IndexError, SPARSE_PREFIX, SparseIndex};
+
+
+/// Index of a registry, served over either protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Index {
+    Sparse(SparseIndex),
+    Git(GitIndex),
+}
+
+impl Index {
+    /// Create the index with given URL, as used in Cargo's config
+    /// (i.e. starting with `sparse+` if it's served over the sparse protocol).
+    pub fn from_url(url: &str) -> Result<Index, IndexError> {
+        if url.starts_with(SPARSE_PREFIX) {
+            SparseIndex::from_url(url).map(Index::Sparse)
+        } else {
+            Ok(Index::Git(GitIndex::new(url)))
+        }
+    }
+
+    /// Name of the protocol that the index is served over.
+    pub fn protocol(&self) -> &'static str {
+        match *self {
+            Index::Sparse(_) => "sparse",
+            Index::Git(_) => "git",
+        }
+    }
+
+    /// The same index, served over the other protocol, if it's known where.
+    fn counterpart(&self) -> Option<Index> {
+        match *self {
+            Index::Sparse(ref index) if *index == SparseIndex::crates_io() =>
+                Some(Index::Git(GitIndex::crates_io())),
+            Index::Sparse(ref index) => Some(Index::Git(GitIndex::new(index.root()))),
+            Index::Git(ref index) if *index == GitIndex::crates_io() =>
+                Some(Index::Sparse(SparseIndex::crates_io())),
+            // Only HTTP servers can serve the sparse protocol.
+            Index::Git(ref index) if index.url().starts_with("https://") || index.url().starts_with("http://") =>
+                SparseIndex::from_url(&format!("{}{}", SPARSE_PREFIX, index.url())).ok().map(Index::Sparse),
+            Index::Git(_) => None,
+        }
+    }
+
+    fn fetch_file(&self, client: &Client, token: Option<&str>,
+                  name: &str) -> Result<(String, String), IndexError> {
+        match *self {
+            Index::Sparse(ref index) => index.fetch_file(client, token, name),
+            Index::Git(ref index) => index.fetch_file(client.is_offline(), name),
+        }
+    }
+
+    fn fetch_config(&self, client: &Client, token: Option<&str>) -> Result<String, IndexError> {
+        match *self {
+            Index::Sparse(ref index) => index.fetch_config(client, token),
+            Index::Git(ref index) => index.fetch_config(client.is_offline()),
+        }
+    }
+
+    fn fetch_dl(&self, client: &Client, token: Option<&str>) -> Result<String, IndexError> {
+        let url = match *self {
+            Index::Sparse(ref index) => format!("{}/config.json", index.root()),
+            Index::Git(ref index) => format!("{}/config.json", index.url()),
+        };
+        let malformed = || IndexError::Malformed(url.clone());
+        let config: Json = serde_json::from_str(&self.fetch_config(client, token)?).map_err(|_| malformed())?;
+        config.get("dl").and_then(|d| d.as_str())
+            .map(|d| d.to_owned())
+            .ok_or_else(malformed)
+    }
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Index::Sparse(ref index) => write!(fmt, "{}", index),
+            Index::Git(ref index) => write!(fmt, "{}", index),
+        }
+    }
+}
+
+
+/// Index of a registry, as configured, along with the same index over the other protocol
+/// that it's read from instead if the configured one doesn't work.
+#[derive(Debug)]
+pub struct RegistryIndex {
+    configured: Index,
+    fallback: Option<Index>,
+    /// Whether the configured index has turned out not to work, so the fallback is used.
+    fell_back: AtomicBool,
+    /// Whether the fallback has turned out not to work either, so it's not tried again.
+    fallback_failed: AtomicBool,
+}
+
+impl RegistryIndex {
+    /// The index of crates.io.
+    pub fn crates_io() -> RegistryIndex {
+        RegistryIndex::new(Index::Sparse(SparseIndex::crates_io()))
+    }
+
+    pub fn new(configured: Index) -> RegistryIndex {
+        RegistryIndex{
+            fallback: configured.counterpart(),
+            configured,
+            fell_back: AtomicBool::new(false),
+            fallback_failed: AtomicBool::new(false),
+        }
+    }
+
+    /// The index as it's configured (whichever protocol it's actually read over).
+    #[inline]
+    pub fn configured(&self) -> &Index {
+        &self.configured
+    }
+
+    /// Whether this is the index of crates.io (over either protocol).
+    pub fn is_crates_io(&self) -> bool {
+        match self.configured {
+            Index::Sparse(ref index) => *index == SparseIndex::crates_io(),
+            Index::Git(ref index) => *index == GitIndex::crates_io(),
+        }
+    }
+
+    /// ID of the index as a source of packages in Cargo (e.g. in Cargo.lock).
+    pub fn source_id(&self) -> String {
+        match self.configured {
+            _ if self.is_crates_io() => CRATES_IO_SOURCE.to_owned(),
+            Index::Sparse(ref index) => index.to_string(),
+            Index::Git(ref index) => format!("registry+{}", index),
+        }
+    }
+
+    /// Fetch the file with the index entries of given crate, returning its URL and content.
+    ///
+    /// The request is authorized with given token, if any.
+    pub fn fetch_file(&self, client: &Client, token: Option<&str>,
+                      name: &str) -> Result<(String, String), IndexError> {
+        self.with_fallback(client, token, |index| index.fetch_file(client, token, name))
+    }
+
+    /// Fetch the index's `config.json`, returning its content.
+    ///
+    /// The request is authorized with given token, if any.
+    pub fn fetch_config(&self, client: &Client, token: Option<&str>) -> Result<String, IndexError> {
+        self.with_fallback(client, token, |index| index.fetch_config(client, token))
+    }
+
+    /// Fetch the download URL (or URL template) of the crates from the index's `config.json`.
+    ///
+    /// The request is authorized with given token, if any.
+    pub fn fetch_dl(&self, client: &Client, token: Option<&str>) -> Result<String, IndexError> {
+        self.with_fallback(client, token, |index| index.fetch_dl(client, token))
+    }
+
+    /// Fetch something from the index, from its counterpart over the other protocol
+    /// if the configured index turns out not to work.
+    fn with_fallback<T, F>(&self, client: &Client, token: Option<&str>, fetch: F) -> Result<T, IndexError>
+        where F: Fn(&Index) -> Result<T, IndexError>
+    {
+        let fallback = match self.fallback {
+            Some(ref fallback) if !client.is_offline() && !self.fallback_failed.load(Ordering::SeqCst) => fallback,
+            // Offline, the index over the other protocol wouldn't be reachable either.
+            _ => return fetch(&self.configured),
+        };
+        if self.fell_back.load(Ordering::SeqCst) {
+            return fetch(fallback);
+        }
+        let error = match fetch(&self.configured) {
+            Ok(result) => return Ok(result),
+            Err(e) => e,
+        };
+        let broken = match error {
+            IndexError::Request(..) | IndexError::Malformed(_) | IndexError::Git(..) => true,
+            // The token isn't accepted, which has nothing to do with the protocol.
+            IndexError::Status(_, 401) | IndexError::Status(_, 403) => false,
+            // Files of crates that don't exist are missing from a working index too,
+            // but its configuration isn't.
+            IndexError::Status(..) | IndexError::NotFound(_) =>
+                self.configured.fetch_config(client, token).is_err(),
+            IndexError::NotSparse(_) => false,
+        };
+        if !broken {
+            return Err(error);
+        }
+        match fetch(fallback) {
+            Ok(result) => {
+                if !self.fell_back.swap(true, Ordering::SeqCst) {
+                    warn!("The {} index at {} doesn't work ({}), using the {} index at {} instead",
+                        self.configured.protocol(), self.configured, error, fallback.protocol(), fallback);
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                debug!("The {} index at {} doesn't work either: {}", fallback.protocol(), fallback, e);
+                self.fallback_failed.store(true, Ordering::SeqCst);
+                Err(error)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RegistryIndex {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.configured)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use git_index::GitIndex;
+    use sparse_index::SparseIndex;
+    use super::{Index, RegistryIndex};
+
+    #[test]
+    fn counterparts() {
+        let sparse = Index::from_url("sparse+https://registry.example.com/index/").unwrap();
+        assert_eq!(sparse.counterpart(), Some(Index::Git(GitIndex::new("https://registry.example.com/index"))));
+
+        let git = Index::from_url("https://registry.example.com/index").unwrap();
+        assert_eq!(git.protocol(), "git");
+        assert_eq!(git.counterpart(), Some(sparse));
+        assert_eq!(Index::from_url("ssh://git@example.com/index.git").unwrap().counterpart(), None);
+
+        let crates_io = RegistryIndex::crates_io();
+        assert_eq!(crates_io.fallback, Some(Index::Git(GitIndex::crates_io())));
+        assert_eq!(Index::Git(GitIndex::crates_io()).counterpart(), Some(Index::Sparse(SparseIndex::crates_io())));
+        assert!(RegistryIndex::new(Index::Git(GitIndex::crates_io())).is_crates_io());
+        assert_eq!(RegistryIndex::new(git).source_id(), "registry+https://registry.example.com/index");
+    }
+}
@@ -0,0 +1,212 @@
+//! Module for resolving a crate specification against the versions published in a registry.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use semver::{Version, VersionReq};
+use serde_json::{self, Value as Json};
+use time;
+
+use spec::Crate;
+
+
+/// Version of a crate as listed in a registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedVersion {
+    /// The exact version.
+    pub version: Version,
+    /// Whether the version has been yanked from the registry.
+    pub yanked: bool,
+    /// SHA-256 checksum of the version's archive, as a hex string, if known.
+    pub checksum: Option<String>,
+    /// When the version has been published, if known.
+    pub published: Option<SystemTime>,
+}
+
+impl ResolvedVersion {
+    /// Create a version that isn't yanked and has no known checksum or publication time.
+    pub fn new(version: Version) -> ResolvedVersion {
+        ResolvedVersion{version, yanked: false, checksum: None, published: None}
+    }
+
+    /// Read the list of versions from the response of crates.io's
+    /// `/api/v1/crates/$CRATE/versions` endpoint.
+    ///
+    /// Returns `None` if the response is malformed.
+    pub fn list_from_api(response: &Json) -> Option<Vec<ResolvedVersion>> {
+        let versions = response.pointer("/versions")?.as_array()?;
+        versions.iter().map(|v| {
+            let version = v.get("num")?.as_str()
+                .and_then(|n| Version::parse(n).ok())?;
+            Some(ResolvedVersion{
+                version,
+                yanked: v.get("yanked").and_then(|y| y.as_bool()).unwrap_or(false),
+                checksum: v.get("checksum").and_then(|c| c.as_str()).map(|c| c.to_owned()),
+                published: v.get("created_at").and_then(|c| c.as_str()).and_then(parse_timestamp),
+            })
+        }).collect()
+    }
+
+    /// Read the list of versions from a crate's file in the registry index,
+    /// which has a JSON object per line.
+    ///
+    /// Returns `None` if the file is malformed.
+    pub fn list_from_index(content: &str) -> Option<Vec<ResolvedVersion>> {
+        content.lines().filter(|l| !l.trim().is_empty()).map(|line| {
+            let entry: Json = serde_json::from_str(line).ok()?;
+            let version = entry.get("vers")?.as_str()
+                .and_then(|v| Version::parse(v).ok())?;
+            Some(ResolvedVersion{
+                version,
+                yanked: entry.get("yanked").and_then(|y| y.as_bool()).unwrap_or(false),
+                checksum: entry.get("cksum").and_then(|c| c.as_str()).map(|c| c.to_owned()),
+                // Only present in entries published since the field was introduced.
+                published: entry.get("pubtime").and_then(|p| p.as_str()).and_then(parse_timestamp),
+            })
+        }).collect()
+    }
+}
+
+/// Parse an RFC 3339 timestamp like `2021-05-04T12:34:56.789+00:00`, as used by crates.io.
+///
+/// Only UTC timestamps are supported, and the fractional seconds are ignored.
+fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let (datetime, rest) = (s.get(..19)?, &s[19..]);
+    let rest = rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    if !["Z", "z", "+00:00", ""].contains(&rest) {
+        return None;
+    }
+    let secs = time::strptime(datetime, "%Y-%m-%dT%H:%M:%S").ok()?.to_timespec().sec;
+    if secs < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+
+/// Which kinds of versions should be considered during resolution,
+/// in addition to those matching the version requirement the regular way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionFilter {
+    /// Whether yanked versions are allowed.
+    pub yanked: bool,
+    /// Whether pre-release versions are allowed to satisfy requirements
+    /// that don't mention any pre-release explicitly.
+    pub prerelease: bool,
+}
+
+impl VersionFilter {
+    /// Check whether given version passes the filter
+    /// and satisfies the version requirement of given crate.
+    pub fn accepts(&self, crate_: &Crate, version: &ResolvedVersion) -> bool {
+        if version.yanked && !self.yanked {
+            return false;
+        }
+        let req = crate_.version_requirement();
+        if version.version.is_prerelease() && !self.prerelease && *req == VersionReq::any() {
+            // semver lets anything match the wildcard requirement, pre-releases included.
+            return false;
+        }
+        if req.matches(&version.version) {
+            return true;
+        }
+        // semver only lets pre-releases match requirements that refer to them,
+        // so check the corresponding release version instead.
+        if self.prerelease && version.version.is_prerelease() {
+            let mut release = version.version.clone();
+            release.pre.clear();
+            return req.matches(&release);
+        }
+        false
+    }
+}
+
+
+/// Yield all versions from the registry's list that satisfy the requirement of given crate
+/// and pass the filter, from the newest to the oldest.
+///
+/// Picking one of these is up to the caller;
+/// the command line tool simply takes the first (i.e. newest) one.
+pub fn resolve_versions<'c, I>(crate_: &'c Crate, versions: I,
+                               filter: VersionFilter) -> impl Iterator<Item = ResolvedVersion> + 'c
+    where I: IntoIterator<Item = ResolvedVersion>
+{
+    let mut versions: Vec<_> = versions.into_iter().collect();
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+    versions.into_iter().filter(move |v| filter.accepts(crate_, v))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
+    use semver::Version;
+    use spec::Crate;
+    use super::{ResolvedVersion, VersionFilter, parse_timestamp, resolve_versions};
+
+    fn index() -> Vec<ResolvedVersion> {
+        let mut yanked = ResolvedVersion::new(Version::parse("1.3.0").unwrap());
+        yanked.yanked = true;
+        vec![
+            ResolvedVersion::new(Version::parse("1.0.0").unwrap()),
+            ResolvedVersion::new(Version::parse("1.2.0").unwrap()),
+            yanked,
+            ResolvedVersion::new(Version::parse("1.4.0-beta.1").unwrap()),
+            ResolvedVersion::new(Version::parse("2.0.0").unwrap()),
+        ]
+    }
+
+    fn resolve(spec: &str, filter: VersionFilter) -> Vec<String> {
+        let crate_ = Crate::from_str(spec).unwrap();
+        resolve_versions(&crate_, index(), filter).map(|v| v.version.to_string()).collect()
+    }
+
+    #[test]
+    fn newest_first_without_yanked_or_prerelease() {
+        assert_eq!(resolve("foo=^1", VersionFilter::default()), vec!["1.2.0", "1.0.0"]);
+        assert_eq!(resolve("foo", VersionFilter::default()), vec!["2.0.0", "1.2.0", "1.0.0"]);
+    }
+
+    #[test]
+    fn filters() {
+        let filter = VersionFilter{yanked: true, prerelease: true};
+        assert_eq!(resolve("foo=^1", filter), vec!["1.4.0-beta.1", "1.3.0", "1.2.0", "1.0.0"]);
+        assert_eq!(resolve("foo=^1.4.0-beta", VersionFilter::default()), vec!["1.4.0-beta.1"]);
+    }
+
+    #[test]
+    fn versions_from_api() {
+        let response = json!({"versions": [
+            {"num": "1.0.0", "yanked": false, "checksum": "abcd",
+             "created_at": "2021-01-01T00:00:10.123456+00:00"},
+            {"num": "0.9.0", "yanked": true},
+        ]});
+        let versions = ResolvedVersion::list_from_api(&response).unwrap();
+        assert_eq!(versions[0].checksum.as_deref(), Some("abcd"));
+        assert_eq!(versions[0].published, Some(UNIX_EPOCH + Duration::from_secs(1609459210)));
+        assert!(versions[1].yanked);
+        assert_eq!(versions[1].published, None);
+        assert!(ResolvedVersion::list_from_api(&json!({"versions": [{"num": "x"}]})).is_none());
+    }
+
+    #[test]
+    fn versions_from_index() {
+        let content = concat!(
+            r#"{"name": "foo", "vers": "0.1.0", "deps": [], "cksum": "aa", "yanked": false}"#, "\n",
+            r#"{"name": "foo", "vers": "0.2.0", "deps": [], "cksum": "bb", "yanked": true}"#, "\n");
+        let versions = ResolvedVersion::list_from_index(content).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[1].version, Version::parse("0.2.0").unwrap());
+        assert_eq!(versions[1].checksum.as_deref(), Some("bb"));
+        assert!(versions[1].yanked);
+        assert!(ResolvedVersion::list_from_index("not json").is_none());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1970-01-02T00:00:00Z"), Some(UNIX_EPOCH + Duration::from_secs(86400)));
+        assert_eq!(parse_timestamp("1970-01-02T00:00:00.5+00:00"), parse_timestamp("1970-01-02T00:00:00Z"));
+        assert_eq!(parse_timestamp("1970-01-02T00:00:00+02:00"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
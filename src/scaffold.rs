@@ -0,0 +1,99 @@
+//! Module for assembling a buildable offline project skeleton around a crate.
+//!
+//! The skeleton is a minimal workspace that depends on the crate, with the crate
+//! and its dependency closure vendored into a directory source that replaces crates.io,
+//! so that `cargo build --offline` works right away.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use semver::Version;
+
+use closure;
+use registry::Registry;
+use space;
+use vendor;
+use super::download_crate;
+
+
+/// Name of the directory (inside the skeleton) with the vendored crates.
+pub const VENDOR_DIR: &str = "vendor";
+
+/// Cargo configuration of the skeleton, which makes it use the vendored crates.
+const CARGO_CONFIG: &str = concat!(
+    "[source.crates-io]\n",
+    "replace-with = \"vendored-sources\"\n",
+    "\n",
+    "[source.vendored-sources]\n",
+    "directory = \"vendor\"\n");
+
+
+/// Write the project skeleton for given crate version into given directory.
+///
+/// Returns the number of vendored crates.
+pub fn write_skeleton(registry: &Registry, name: &str, version: &Version,
+                      dir: &Path) -> Result<usize, Box<dyn Error>> {
+    let closure = closure::dependency_closure(registry, name, version)?;
+    debug!("Dependency closure of crate `{}=={}` has {} crate(s)", name, version, closure.len());
+
+    let vendor_dir = dir.join(VENDOR_DIR);
+    fs::create_dir_all(&vendor_dir)?;
+    vendor(registry, name, version, &vendor_dir)?;
+    for crate_ in &closure {
+        vendor(registry, crate_.name(), crate_.exact_version().unwrap(), &vendor_dir)?;
+    }
+
+    fs::create_dir_all(dir.join(".cargo"))?;
+    fs::write(dir.join(".cargo").join("config.toml"), CARGO_CONFIG)?;
+    fs::write(dir.join("Cargo.toml"), manifest(name, version))?;
+    fs::create_dir_all(dir.join("src"))?;
+    fs::write(dir.join("src").join("lib.rs"),
+        format!("//! Skeleton for building crate `{}=={}` offline.\n", name, version))?;
+    Ok(closure.len() + 1)
+}
+
+/// Manifest of the skeleton's workspace, depending on given crate version.
+fn manifest(name: &str, version: &Version) -> String {
+    format!(concat!(
+        "[package]\n",
+        "name = \"{name}-skeleton\"\n",
+        "version = \"0.0.0\"\n",
+        "edition = \"2018\"\n",
+        "publish = false\n",
+        "\n",
+        "[dependencies]\n",
+        "{name} = \"={version}\"\n",
+        "\n",
+        "[workspace]\n"), name = name, version = version)
+}
+
+/// Extract given crate version into the vendor directory,
+/// along with the checksums that Cargo expects of a directory source.
+fn vendor(registry: &Registry, name: &str, version: &Version, vendor_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let crate_bytes = download_crate(registry, name, version)?;
+    let crate_dir = vendor_dir.join(format!("{}-{}", name, version));
+    space::check(&crate_bytes, &crate_dir)?;
+    vendor::unpack(name, version, &crate_bytes, &crate_dir)?;
+    trace!("Crate `{}=={}` vendored to {}/", name, version, crate_dir.display());
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use toml::Value as Toml;
+    use super::{CARGO_CONFIG, manifest};
+
+    #[test]
+    fn skeleton_files() {
+        let manifest: Toml = manifest("foo-bar", &Version::parse("1.2.3").unwrap()).parse().unwrap();
+        assert_eq!(manifest["package"]["name"].as_str(), Some("foo-bar-skeleton"));
+        assert_eq!(manifest["dependencies"]["foo-bar"].as_str(), Some("=1.2.3"));
+        assert!(manifest.get("workspace").is_some());
+
+        let config: Toml = CARGO_CONFIG.parse().unwrap();
+        assert_eq!(config["source"]["vendored-sources"]["directory"].as_str(), Some(super::VENDOR_DIR));
+    }
+}
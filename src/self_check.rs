@@ -0,0 +1,211 @@
+//! Module implementing the `self check` subcommand,
+//! which diagnoses common problems with the program's setup.
+
+use std::env;
+use std::fmt;
+
+use args::Options;
+use cache::Cache;
+use cargo_config::CargoConfig;
+use http::{Client, HttpConfig};
+use registries;
+use registry::Registry;
+use super::http_config;
+
+
+/// Crate whose versions are fetched to check that the registry is reachable.
+const PROBE_CRATE: &str = "cargo-download";
+
+
+/// Run all the checks, printing their results to standard output.
+///
+/// Returns whether all of them have passed.
+pub fn run(opts: &Options) -> bool {
+    let mut diagnostics = vec![];
+    let http_config = check_config(opts, &mut diagnostics);
+    let client = http_config.as_ref().and_then(|c| check_proxy(c, &mut diagnostics));
+    if let Some(client) = client {
+        let registry = Registry::new(client, opts.index_url.as_deref(), opts.dl_url.as_deref());
+        diagnostics.push(check_registry(&registry));
+        diagnostics.push(check_token(&registry));
+    }
+    diagnostics.push(check_cache());
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    diagnostics.iter().all(|d| d.status != Status::Failed)
+}
+
+
+/// Check that Cargo's configuration (and our overrides of it) is valid.
+fn check_config(opts: &Options, diagnostics: &mut Vec<Diagnostic>) -> Option<HttpConfig> {
+    match http_config(opts) {
+        Ok(config) => {
+            let mut message = String::from("Cargo configuration and environment are valid");
+            if config.offline {
+                message.push_str(" (offline mode is enabled)");
+            }
+            diagnostics.push(Diagnostic::ok("configuration", message));
+            Some(config)
+        }
+        Err(e) => {
+            diagnostics.push(Diagnostic::failed("configuration", e.to_string(), concat!(
+                "fix the setting named above in Cargo's config file ",
+                "or the corresponding CARGO_* environment variable")));
+            None
+        }
+    }
+}
+
+/// Check the proxy settings by creating the HTTP client that uses them.
+fn check_proxy(config: &HttpConfig, diagnostics: &mut Vec<Diagnostic>) -> Option<Client> {
+    let client = match Client::new(config) {
+        Ok(client) => client,
+        Err(e) => {
+            diagnostics.push(Diagnostic::failed("proxy", e.to_string(), concat!(
+                "check the proxy URL given by --proxy, http.proxy in Cargo's config, ",
+                "CARGO_HTTP_PROXY, or the http_proxy and https_proxy variables, as well as http.cainfo")));
+            return None;
+        }
+    };
+    // Don't print the URLs themselves, since they may contain credentials.
+    let mut message = match config.proxy {
+        Some(_) => String::from("HTTP proxy is configured"),
+        None if config.has_proxy() => String::from("HTTP proxy is set in the environment"),
+        None => String::from("no HTTP proxy is configured"),
+    };
+    if config.has_proxy() && !config.no_proxy.is_empty() {
+        message.push_str(&format!(" (except for {} host(s) in no_proxy)", config.no_proxy.len()));
+    }
+    let diagnostic = Diagnostic::ok("proxy", message);
+    diagnostics.push(diagnostic);
+    Some(client)
+}
+
+/// Check that the registry can be reached.
+fn check_registry(registry: &Registry) -> Diagnostic {
+    if registry.is_offline() {
+        return Diagnostic::warning("registry", "not checked in offline mode".into(),
+            "run the check without --offline (or net.offline in Cargo's config)");
+    }
+    match registry.fetch_versions(PROBE_CRATE) {
+        Ok(_) => Diagnostic::ok("registry", "registry is reachable".into()),
+        Err(e) => Diagnostic::failed("registry", e.to_string(), concat!(
+            "check the network connection, proxy settings, ",
+            "and the URL given by --index-url (if any)")),
+    }
+}
+
+/// Check that the registry token, if one is configured, is accepted by the registry.
+fn check_token(registry: &Registry) -> Diagnostic {
+    let (token, source) = match find_token() {
+        Ok(Some(found)) => found,
+        Ok(None) => return Diagnostic::ok("token",
+            "no registry token is configured (none is needed for downloads)".into()),
+        Err(e) => return Diagnostic::failed("token", e,
+            "fix the syntax of the file, or remove it and run `cargo login` again"),
+    };
+    if registry.is_offline() {
+        return Diagnostic::warning("token", format!("token from {} not checked in offline mode", source),
+            "run the check without --offline (or net.offline in Cargo's config)");
+    }
+    match registry.fetch_token_owner(&token) {
+        Ok(login) => Diagnostic::ok("token",
+            format!("token from {} is valid (user `{}`)", source, login)),
+        Err(e) => Diagnostic::failed("token", format!("token from {} was rejected: {}", source, e),
+            "generate a new token in the registry's account settings and run `cargo login`"),
+    }
+}
+
+/// Find the registry token the same way Cargo does, returning it along with its source.
+fn find_token() -> Result<Option<(String, String)>, String> {
+    if let Some(token) = env::var("CARGO_REGISTRY_TOKEN").ok().filter(|t| !t.is_empty()) {
+        return Ok(Some((token, "$CARGO_REGISTRY_TOKEN".into())));
+    }
+    if let Ok(config) = CargoConfig::load() {
+        if let Ok(Some(token)) = config.get_str("registry.token") {
+            return Ok(Some((token, "registry.token in Cargo's config".into())));
+        }
+    }
+
+    let token = registries::credentials_token(None)?;
+    Ok(token.map(|(token, path)| (token, path.display().to_string())))
+}
+
+/// Check that archives can be stored in the local cache.
+fn check_cache() -> Diagnostic {
+    let cache = match Cache::open_default() {
+        Some(cache) => cache,
+        None => return Diagnostic::failed("cache", "cannot determine the cache directory".into(),
+            "set $CARGO_HOME (or $HOME)"),
+    };
+    let dir = cache.root();
+    match cache.check_writable() {
+        Ok(_) => Diagnostic::ok("cache", format!("cache directory {}/ is writable", dir.display())),
+        // Crates can still be taken from the cache, they just won't be stored in it.
+        Err(e) => Diagnostic::warning("cache",
+            format!("cache directory {}/ is not writable: {}", dir.display(), e),
+            "fix the permissions of the directory, or point --cache-dir ($CARGO_DOWNLOAD_CACHE_DIR) elsewhere"),
+    }
+}
+
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
+    Failed,
+}
+
+/// Result of a single check, with a hint on how to fix the problem (if any).
+#[derive(Debug)]
+struct Diagnostic {
+    status: Status,
+    subject: &'static str,
+    message: String,
+    hint: Option<&'static str>,
+}
+
+impl Diagnostic {
+    fn ok(subject: &'static str, message: String) -> Diagnostic {
+        Diagnostic{status: Status::Ok, subject, message, hint: None}
+    }
+
+    fn warning(subject: &'static str, message: String, hint: &'static str) -> Diagnostic {
+        Diagnostic{status: Status::Warning, subject, message, hint: Some(hint)}
+    }
+
+    fn failed(subject: &'static str, message: String, hint: &'static str) -> Diagnostic {
+        Diagnostic{status: Status::Failed, subject, message, hint: Some(hint)}
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "WARNING",
+            Status::Failed => "FAILED",
+        };
+        write!(fmt, "[{:^7}] {}: {}", status, self.subject, self.message)?;
+        if let Some(hint) = self.hint {
+            write!(fmt, "\n          hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+
+    #[test]
+    fn formatting() {
+        assert_eq!(Diagnostic::ok("cache", "fine".into()).to_string(), "[  ok   ] cache: fine");
+        assert_eq!(Diagnostic::failed("token", "rejected".into(), "log in again").to_string(),
+            "[FAILED ] token: rejected\n          hint: log in again");
+    }
+}
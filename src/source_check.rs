@@ -0,0 +1,246 @@
+//! Module for checking crate archives against the git repositories they've been published from
+//! (`--verify-source`), as a supply-chain check that the published code is the one in the repository.
+//!
+//! The repository is the one in the crate's `package.repository`. It's fetched (shallowly)
+//! at the commit that Cargo recorded in `.cargo_vcs_info.json` when publishing the crate,
+//! or at the tag of the crate's version if there's no such record.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+use tar;
+use toml::{self, Value as Toml};
+
+use cargo_download::decompress;
+use index_sync::git;
+
+
+/// File with the details of the commit that Cargo has published a crate from.
+const VCS_INFO_FILE: &str = ".cargo_vcs_info.json";
+
+/// Files that Cargo generates into the archives when publishing crates, rather than copying them.
+/// (Cargo.toml is normalized, while the original is kept as Cargo.toml.orig.)
+const GENERATED_FILES: &[&str] = &[VCS_INFO_FILE, "Cargo.toml", "Cargo.lock"];
+
+
+/// Result of comparing a crate archive against its repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceReport {
+    /// URL of the repository.
+    pub repository: String,
+    /// Commit or tag that the archive has been compared against.
+    pub rev: String,
+    /// Files in the archive whose content differs from the repository's.
+    pub differing: Vec<String>,
+    /// Files in the archive that aren't in the repository at all.
+    pub archive_only: Vec<String>,
+}
+
+impl SourceReport {
+    /// Whether the archive matches the repository.
+    pub fn matches(&self) -> bool {
+        self.differing.is_empty() && self.archive_only.is_empty()
+    }
+}
+
+
+/// Compare the files in given crate archive against the crate's repository,
+/// which is fetched into a temporary directory for the comparison.
+pub fn verify_source(name: &str, version: &Version, crate_bytes: &[u8]) -> io::Result<SourceReport> {
+    let files = read_files(name, version, crate_bytes)?;
+    let manifest = files.iter().find(|f| f.0 == "Cargo.toml")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no Cargo.toml in the archive"))?;
+    let repository = repository_url(&String::from_utf8_lossy(&manifest.1))?;
+    let (sha1, path_in_vcs) = match files.iter().find(|f| f.0 == VCS_INFO_FILE) {
+        Some(vcs_info) => parse_vcs_info(&vcs_info.1)?,
+        None => (None, PathBuf::new()),
+    };
+    let revs = match sha1 {
+        Some(sha1) => vec![sha1],
+        None => vec![format!("v{}", version), version.to_string(),
+                     format!("{}-v{}", name, version), format!("{}-{}", name, version)],
+    };
+
+    let dir = env::temp_dir().join(format!("cargo-download-source-{}-{}-{}", process::id(), name, version));
+    let _ = fs::remove_dir_all(&dir);
+    let fetched = fetch_repository(&repository, &revs, &dir);
+    let report = fetched.map(|rev| {
+        let (differing, archive_only) = compare_files(&files, &dir, &path_in_vcs);
+        SourceReport{repository, rev, differing, archive_only}
+    });
+    let _ = fs::remove_dir_all(&dir);
+    report
+}
+
+/// Read the regular files in given crate archive, with their paths relative to the crate's directory.
+fn read_files(name: &str, version: &Version, crate_bytes: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let crate_dir = PathBuf::from(format!("{}-{}", name, version));
+    let mut archive = tar::Archive::new(decompress(crate_bytes)?);
+    let mut files = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = match entry.path()?.strip_prefix(&crate_dir) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => continue,
+        };
+        let mut content = vec![];
+        entry.read_to_end(&mut content)?;
+        files.push((path, content));
+    }
+    Ok(files)
+}
+
+/// URL of the repository from given content of a crate's Cargo.toml.
+fn repository_url(manifest: &str) -> io::Result<String> {
+    let manifest: Toml = toml::from_str(manifest)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("malformed Cargo.toml: {}", e)))?;
+    let url = manifest.get("package").and_then(|p| p.get("repository")).and_then(|r| r.as_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Cargo.toml has no package.repository"))?;
+    // Only URLs that git can fetch from anonymously (which also keeps them from passing as git's options).
+    if !["https://", "http://", "git://"].iter().any(|scheme| url.starts_with(scheme)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported repository URL {}", url)));
+    }
+    Ok(url.trim_end_matches('/').to_owned())
+}
+
+/// Commit (if recorded) and the crate's path in the repository from given content of `.cargo_vcs_info.json`.
+fn parse_vcs_info(content: &[u8]) -> io::Result<(Option<String>, PathBuf)> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", VCS_INFO_FILE));
+    let vcs_info: Json = serde_json::from_slice(content).map_err(|_| malformed())?;
+    let sha1 = vcs_info.get("git").and_then(|g| g.get("sha1")).and_then(|s| s.as_str());
+    if sha1.is_some_and(|s| s.is_empty() || !s.chars().all(|c| c.is_ascii_hexdigit())) {
+        return Err(malformed());
+    }
+    let path_in_vcs = PathBuf::from(vcs_info.get("path_in_vcs").and_then(|p| p.as_str()).unwrap_or(""));
+    // The path is joined to the local clone, so it must not be able to point outside of it.
+    if !path_in_vcs.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(malformed());
+    }
+    Ok((sha1.map(|s| s.to_owned()), path_in_vcs))
+}
+
+/// Fetch the first of given revisions of the repository at given URL that exists,
+/// and check it out into given directory, returning the revision.
+fn fetch_repository(url: &str, revs: &[String], dir: &Path) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    git(dir, &["init", "--quiet"])?;
+    let mut error = None;
+    for rev in revs {
+        debug!("Fetching {} from the repository at {}", rev, url);
+        match git(dir, &["fetch", "--quiet", "--depth", "1", url, rev]) {
+            Ok(_) => {
+                git(dir, &["-c", "advice.detachedHead=false", "checkout", "--quiet", "FETCH_HEAD"])?;
+                return Ok(rev.clone());
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| io::Error::other("no revision to fetch")))
+}
+
+/// Compare given files from a crate archive against a checkout of its repository,
+/// with the crate at given path in there, returning the paths of the files that differ
+/// and of those that aren't in the repository.
+///
+/// Files that Cargo generates when publishing are skipped, except for Cargo.toml.orig,
+/// which is compared against the repository's Cargo.toml. Files at the top of the crate
+/// may also come from the top of the repository (like a README or a license shared in a workspace).
+fn compare_files(files: &[(String, Vec<u8>)], repo: &Path, path_in_vcs: &Path) -> (Vec<String>, Vec<String>) {
+    let (mut differing, mut archive_only) = (vec![], vec![]);
+    for (path, content) in files {
+        if GENERATED_FILES.contains(&path.as_str()) {
+            continue;
+        }
+        let repo_path = if path == "Cargo.toml.orig" { "Cargo.toml" } else { path.as_str() };
+        let mut candidates = vec![repo.join(path_in_vcs).join(repo_path)];
+        if !repo_path.contains('/') && path_in_vcs != Path::new("") {
+            candidates.push(repo.join(repo_path));
+        }
+        let repo_contents: Vec<_> = candidates.iter().filter_map(|p| fs::read(p).ok()).collect();
+        if repo_contents.is_empty() {
+            archive_only.push(path.clone());
+        } else if !repo_contents.contains(content) {
+            differing.push(path.clone());
+        }
+    }
+    (differing, archive_only)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::slice;
+
+    use index_sync::git;
+    use super::{compare_files, fetch_repository, parse_vcs_info, repository_url};
+
+    #[test]
+    fn comparison() {
+        let repo = env::temp_dir().join(format!("cargo-download-test-source-check-{}", process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(repo.join("foo/src")).unwrap();
+        fs::write(repo.join("foo/Cargo.toml"), "[package]\nname = \"foo\"\n").unwrap();
+        fs::write(repo.join("foo/src/lib.rs"), "pub fn foo() {}\n").unwrap();
+        fs::write(repo.join("LICENSE"), "MIT\n").unwrap();
+
+        let file = |path: &str, content: &str| (path.to_owned(), content.as_bytes().to_vec());
+        let files = vec![
+            file("Cargo.toml", "# normalized\n"),
+            file("Cargo.toml.orig", "[package]\nname = \"foo\"\n"),
+            file(".cargo_vcs_info.json", "{}"),
+            file("LICENSE", "MIT\n"),
+            file("src/lib.rs", "pub fn foo() { evil() }\n"),
+            file("src/build_helper.rs", "fn evil() {}\n"),
+        ];
+        let (differing, archive_only) = compare_files(&files, &repo, Path::new("foo"));
+        assert_eq!(differing, ["src/lib.rs"]);
+        assert_eq!(archive_only, ["src/build_helper.rs"]);
+        fs::remove_dir_all(&repo).unwrap();
+
+        let vcs_info = br#"{"git":{"sha1":"0123abcd"},"path_in_vcs":"crates/foo"}"#;
+        assert_eq!(parse_vcs_info(vcs_info).unwrap(), (Some("0123abcd".into()), PathBuf::from("crates/foo")));
+        assert!(parse_vcs_info(br#"{"git":{"sha1":"abc"},"path_in_vcs":"../elsewhere"}"#).is_err());
+        assert!(parse_vcs_info(br#"{"git":{"sha1":"--upload-pack=evil"}}"#).is_err());
+
+        let manifest = "[package]\nname = \"foo\"\nrepository = \"https://github.com/foo/foo/\"\n";
+        assert_eq!(repository_url(manifest).unwrap(), "https://github.com/foo/foo");
+        assert!(repository_url("[package]\nname = \"foo\"\n").is_err());
+        assert!(repository_url("[package]\nrepository = \"--upload-pack=evil\"\n").is_err());
+    }
+
+    #[test]
+    fn fetching() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-source-fetch-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = dir.join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        fs::write(repo.join("lib.rs"), "pub fn foo() {}\n").unwrap();
+        git(&repo, &["init", "--quiet"]).unwrap();
+        git(&repo, &["add", "."]).unwrap();
+        git(&repo, &["-c", "user.name=test", "-c", "user.email=test@example.com",
+                     "commit", "--quiet", "-m", "foo"]).unwrap();
+        git(&repo, &["tag", "v1.0.0"]).unwrap();
+        let sha1 = git(&repo, &["rev-parse", "HEAD"]).unwrap().trim().to_owned();
+
+        let url = format!("file://{}", repo.display());
+        let revs = ["v2.0.0".to_owned(), "v1.0.0".to_owned()];
+        assert_eq!(fetch_repository(&url, &revs, &dir.join("by-tag")).unwrap(), "v1.0.0");
+        assert_eq!(fs::read(dir.join("by-tag/lib.rs")).unwrap(), b"pub fn foo() {}\n");
+        assert_eq!(fetch_repository(&url, slice::from_ref(&sha1), &dir.join("by-commit")).unwrap(), sha1);
+        assert!(dir.join("by-commit/lib.rs").is_file());
+        assert!(fetch_repository(&url, &revs[..1], &dir.join("missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+//! Module for resolving the source replacement configured in Cargo's config,
+//! which points Cargo at a mirror (or at vendored crates) instead of crates.io or another registry:
+//!
+//! ```toml
+//! [source.crates-io]
+//! replace-with = "mirror"
+//!
+//! [source.mirror]
+//! registry = "sparse+https://mirror.example.com/index/"
+//! ```
+//!
+//! Just like in Cargo, the replacement can be a remote registry (`registry = URL`),
+//! an alternative registry from `[registries]`, a local registry (`local-registry = DIR`),
+//! or a directory of vendored crates (`directory = DIR`), and it can be replaced in turn.
+
+use cargo_config::CargoConfig;
+use local_source::LocalSource;
+use registries::{self, RegistryError};
+use registry_index::Index;
+
+
+/// Source that another one is replaced with.
+#[derive(Debug)]
+pub enum Replacement {
+    /// Remote registry with given index.
+    Registry(Index),
+    /// Local registry or directory of vendored crates.
+    Local(LocalSource),
+}
+
+
+/// Find what the source with given name (`crates-io` or that of an alternative registry) is replaced with,
+/// returning the name of the replacement source along with it, or `None` if it isn't replaced.
+pub fn find(config: &CargoConfig, name: &str) -> Result<Option<(String, Replacement)>, RegistryError> {
+    let mut chain = vec![name.to_owned()];
+    while let Some(replace_with) = config.get_str(&format!("source.{}.replace-with", chain[chain.len() - 1]))? {
+        if chain.contains(&replace_with) {
+            chain.push(replace_with);
+            return Err(RegistryError::Replacement(format!("sources are replaced in a cycle: {}",
+                chain.join(" -> "))));
+        }
+        chain.push(replace_with);
+    }
+    if chain.len() == 1 {
+        return Ok(None);
+    }
+    let replacement = chain.pop().unwrap();
+    let source = source(config, &replacement)?;
+    Ok(Some((replacement, source)))
+}
+
+/// Read the definition of the source with given name.
+fn source(config: &CargoConfig, name: &str) -> Result<Replacement, RegistryError> {
+    let key = |field: &str| format!("source.{}.{}", name, field);
+    if let Some(url) = config.get_str(&key("registry"))? {
+        return Ok(Replacement::Registry(Index::from_url(&url)?));
+    }
+    if let Some(dir) = config.get_path(&key("local-registry"))? {
+        return Ok(Replacement::Local(LocalSource::Registry(dir)));
+    }
+    if let Some(dir) = config.get_path(&key("directory"))? {
+        return Ok(Replacement::Local(LocalSource::Directory(dir)));
+    }
+    if config.get(&key("git")).is_some() {
+        return Err(RegistryError::Replacement(format!("source `{}` is a git repository, which isn't supported",
+            name)));
+    }
+    match registries::index(config, name) {
+        Ok(index) => Ok(Replacement::Registry(index)),
+        Err(RegistryError::NotConfigured(_)) => Err(RegistryError::Replacement(format!(
+            "source `{}` is not defined (it should be set as source.{} or registries.{} in Cargo's config)",
+            name, name, name))),
+        Err(e) => Err(e),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use cargo_config::CargoConfig;
+    use local_source::LocalSource;
+    use super::{Replacement, find};
+
+    #[test]
+    fn replacements() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-source-replacement-{}", std::process::id()));
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(dir.join(".cargo").join("config.toml"), concat!(
+            "[source.crates-io]\n",
+            "replace-with = \"vendored\"\n",
+            "[source.vendored]\n",
+            "replace-with = \"local\"\n",
+            "[source.local]\n",
+            "local-registry = \"registry\"\n",
+            "[source.test-mirrored]\n",
+            "replace-with = \"test-mirror\"\n",
+            "[registries.test-mirror]\n",
+            "index = \"sparse+https://mirror.example.com/index/\"\n",
+            "[source.test-cycle]\n",
+            "replace-with = \"test-cycle2\"\n",
+            "[source.test-cycle2]\n",
+            "replace-with = \"test-cycle\"\n",
+            "[source.test-undefined]\n",
+            "replace-with = \"test-nowhere\"\n")).unwrap();
+        let config = CargoConfig::load_for(&dir, None).unwrap();
+
+        match find(&config, "crates-io").unwrap() {
+            Some((ref name, Replacement::Local(LocalSource::Registry(ref path)))) => {
+                assert_eq!(name, "local");
+                assert_eq!(path, &dir.join("registry"));
+            }
+            other => panic!("unexpected replacement: {:?}", other),
+        }
+        match find(&config, "test-mirrored").unwrap() {
+            Some((_, Replacement::Registry(index))) =>
+                assert_eq!(index.to_string(), "sparse+https://mirror.example.com/index/"),
+            other => panic!("unexpected replacement: {:?}", other),
+        }
+        assert!(find(&config, "test-mirror").unwrap().is_none());
+        assert!(find(&config, "test-cycle").unwrap_err().to_string()
+            .contains("test-cycle -> test-cycle2 -> test-cycle"));
+        assert!(find(&config, "test-undefined").unwrap_err().to_string()
+            .contains("`test-nowhere` is not defined"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+//! Module for checking that there's enough disk space to unpack crate archives,
+//! so that running out of it is reported upfront rather than halfway through a write.
+
+use std::io;
+use std::path::Path;
+
+use fs2;
+use tar;
+
+use cargo_download::decompress;
+use units;
+
+
+/// Size of the filesystem blocks that every unpacked file is assumed to be rounded up to.
+const BLOCK_SIZE: u64 = 4096;
+
+
+/// Estimate how much disk space the content of given crate archive takes when unpacked,
+/// with every entry rounded up to a whole number of filesystem blocks.
+pub fn unpacked_size(crate_bytes: &[u8]) -> io::Result<u64> {
+    let mut archive = tar::Archive::new(decompress(crate_bytes)?);
+    let mut size = 0;
+    for entry in archive.entries()? {
+        let entry_size = entry?.header().size()?;
+        size += entry_size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+    Ok(size.max(BLOCK_SIZE))
+}
+
+/// Check that there's enough space available to unpack given crate archive into given directory
+/// (which doesn't have to exist yet), returning a message that says otherwise.
+///
+/// If either the space needed or the space available cannot be determined,
+/// the check passes, leaving any problem to the unpacking itself.
+pub fn check(crate_bytes: &[u8], dir: &Path) -> Result<(), String> {
+    let needed = match unpacked_size(crate_bytes) {
+        Ok(n) => n,
+        Err(e) => {
+            debug!("Couldn't determine the unpacked size of the crate archive: {}", e);
+            return Ok(());
+        }
+    };
+    let existing = match dir.ancestors().find(|p| p.exists()) {
+        Some(p) => if p.as_os_str().is_empty() { Path::new(".") } else { p },
+        None => return Ok(()),
+    };
+    let available = match fs2::available_space(existing) {
+        Ok(a) => a,
+        Err(e) => {
+            debug!("Couldn't determine the space available in {}: {}", existing.display(), e);
+            return Ok(());
+        }
+    };
+    trace!("Unpacking needs about {}, with {} available in {}",
+        units::format_size(needed), units::format_size(available), existing.display());
+    if needed > available {
+        return Err(format!("not enough disk space in {} (about {} needed, {} available)",
+            existing.display(), units::format_size(needed), units::format_size(available)));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tar;
+    use super::{BLOCK_SIZE, unpacked_size};
+
+    #[test]
+    fn size_of_unpacked_archive() {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::Default));
+        for &(path, size) in &[("foo-1.0.0/Cargo.toml", 100), ("foo-1.0.0/src/lib.rs", 5000)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(path).unwrap();
+            header.set_size(size);
+            header.set_cksum();
+            builder.append(&header, &vec![b'x'; size as usize][..]).unwrap();
+        }
+        let crate_bytes = builder.into_inner().unwrap().finish().unwrap();
+        assert_eq!(unpacked_size(&crate_bytes).unwrap(), 3 * BLOCK_SIZE);
+    }
+}
@@ -0,0 +1,158 @@
+//! Module for reading registry indices over the sparse HTTP protocol,
+//! where every crate's index entries are a separate file served at a well-known path
+//! (so there's no need to clone a git repository with the whole index).
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use reqwest::Response;
+
+use cargo_download::{CRATES_INDEX_ROOT, index_path};
+use cargo_registry::CargoRegistry;
+use http::{Client, HttpError};
+
+
+/// Prefix that marks the index URLs of registries served over the sparse protocol.
+pub const SPARSE_PREFIX: &str = "sparse+";
+
+
+/// Registry index served over the sparse protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseIndex {
+    /// Root URL of the index, without the `sparse+` prefix and the trailing slash.
+    root: String,
+}
+
+impl SparseIndex {
+    /// The index of crates.io.
+    pub fn crates_io() -> SparseIndex {
+        SparseIndex{root: CRATES_INDEX_ROOT.to_owned()}
+    }
+
+    /// Create the index with given URL, as used in Cargo's config (i.e. starting with `sparse+`).
+    pub fn from_url(url: &str) -> Result<SparseIndex, IndexError> {
+        let root = url.strip_prefix(SPARSE_PREFIX)
+            .ok_or_else(|| IndexError::NotSparse(url.to_owned()))?;
+        Ok(SparseIndex{root: root.trim_end_matches('/').to_owned()})
+    }
+
+    /// Root URL of the index, without the `sparse+` prefix.
+    #[inline]
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Fetch the file with the index entries of given crate, returning its URL and content.
+    ///
+    /// The request is authorized with given token, if any.
+    /// Offline, the file is read from Cargo's cache of the index instead, if it's there.
+    pub fn fetch_file(&self, client: &Client, token: Option<&str>,
+                      name: &str) -> Result<(String, String), IndexError> {
+        let url = format!("{}/{}", self.root, index_path(name));
+        if client.is_offline() {
+            match CargoRegistry::locate(self).map(|r| r.read_index_file(name)) {
+                Some(Ok(Some(content))) => {
+                    debug!("Index entries of crate `{}` found in Cargo's cache of the index", name);
+                    return Ok((url, content));
+                }
+                Some(Err(e)) =>
+                    debug!("Failed to read Cargo's cache of the index entries of crate `{}`: {}", name, e),
+                _ => {}
+            }
+        }
+        debug!("Fetching index entries of crate `{}` from {}", name, url);
+        let purpose = format!("read the index entries of crate `{}`", name);
+        let mut response = get(client, token, &url, &purpose)?;
+        let content = response.text().map_err(|e| IndexError::Request(url.clone(), e.into()))?;
+        Ok((url, content))
+    }
+
+    /// Fetch the index's `config.json`, returning its content.
+    ///
+    /// The request is authorized with given token, if any.
+    pub fn fetch_config(&self, client: &Client, token: Option<&str>) -> Result<String, IndexError> {
+        let url = format!("{}/config.json", self.root);
+        debug!("Fetching the configuration of the index from {}", url);
+        let mut response = get(client, token, &url, "read the configuration of the registry index")?;
+        response.text().map_err(|e| IndexError::Request(url, e.into()))
+    }
+}
+
+impl fmt::Display for SparseIndex {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}{}/", SPARSE_PREFIX, self.root)
+    }
+}
+
+/// Make a GET request to given URL of the index, failing unless it succeeds.
+fn get(client: &Client, token: Option<&str>, url: &str,
+       purpose: &str) -> Result<Response, IndexError> {
+    let response = match token {
+        Some(token) => client.get_authorized(url, token, purpose),
+        None => client.get(url, purpose),
+    };
+    let response = response.map_err(|e| IndexError::Request(url.to_owned(), e))?;
+    if !response.status().is_success() {
+        return Err(IndexError::Status(url.to_owned(), response.status().as_u16()));
+    }
+    Ok(response)
+}
+
+
+/// Error that can occur while reading a sparse index.
+#[derive(Debug)]
+pub enum IndexError {
+    /// Given index URL doesn't use the sparse protocol.
+    NotSparse(String),
+    /// Error while requesting given URL.
+    Request(String, HttpError),
+    /// Given URL responded with given (unsuccessful) HTTP status.
+    Status(String, u16),
+    /// Malformed response from given URL.
+    Malformed(String),
+    /// Error while fetching the git index at given URL.
+    Git(String, io::Error),
+    /// Given file doesn't exist in the git index.
+    NotFound(String),
+}
+impl Error for IndexError {
+    fn description(&self) -> &str { "cannot read registry index" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            IndexError::Request(_, ref e) => Some(e),
+            IndexError::Git(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for IndexError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IndexError::NotSparse(ref url) => write!(fmt,
+                "index URL {} doesn't use the sparse protocol (it should start with `{}`)", url, SPARSE_PREFIX),
+            IndexError::Request(ref url, ref e) => write!(fmt, "request to {} failed: {}", url, e),
+            IndexError::Status(ref url, 401) => write!(fmt,
+                "{} responded with 401 (the registry requires a token)", url),
+            IndexError::Status(ref url, 404) => write!(fmt, "{} not found", url),
+            IndexError::Status(ref url, status) => write!(fmt, "{} responded with {}", url, status),
+            IndexError::Malformed(ref url) => write!(fmt, "malformed response from {}", url),
+            IndexError::Git(ref url, ref e) => write!(fmt, "failed to fetch the git index at {}: {}", url, e),
+            IndexError::NotFound(ref url) => write!(fmt, "{} not found", url),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::SparseIndex;
+
+    #[test]
+    fn index_urls() {
+        let index = SparseIndex::from_url("sparse+https://registry.example.com/index/").unwrap();
+        assert_eq!(index.to_string(), "sparse+https://registry.example.com/index/");
+        assert!(SparseIndex::from_url("https://github.com/rust-lang/crates.io-index").is_err());
+        assert_eq!(SparseIndex::crates_io().to_string(), "sparse+https://index.crates.io/");
+    }
+}
@@ -0,0 +1,418 @@
+//! Module defining the specification of a crate to download.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use semver::{Version, VersionReq, ReqParseError, SemVerError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+
+
+/// Parse a crate specification (`CRATE[=VERSION]`)
+/// and return it in its canonical, normalized form.
+///
+/// The normalized form always includes the version part
+/// (`*` if none was given), with the version requirement formatted
+/// the way the `semver` crate formats it, e.g. `foo=>= 1.0.0, < 2.0.0`.
+/// Parsing the normalized form again yields an identical `Crate`.
+pub fn normalize_spec(spec: &str) -> Result<String, CrateError> {
+    Crate::from_str(spec).map(|c| c.to_string())
+}
+
+
+/// Parse a crate specification that may list several exact versions
+/// (like `foo=1.0.0,1.2.0,2.0.1`), returning a `Crate` for each one.
+///
+/// Since commas also separate the parts of a version requirement
+/// (like `foo=>=1.0, <2.0`), the specification is treated as a list
+/// only if every comma-separated part is a complete version without an operator.
+/// Otherwise, this is equivalent to parsing a single `Crate`.
+pub fn expand_spec(spec: &str) -> Result<Vec<Crate>, CrateError> {
+    let parts: Vec<_> = spec.splitn(2, '=').map(|p| p.trim()).collect();
+    if parts.len() == 2 && parts[1].contains(',') {
+        let versions: Result<Vec<_>, _> = parts[1].split(',')
+            .map(|v| Version::parse(v.trim())).collect();
+        if let Ok(versions) = versions {
+            let name = parse_name(parts[0])?;
+            return Ok(versions.into_iter()
+                .map(|v| Crate{name: name.clone(), version: CrateVersion::Exact(v)})
+                .collect());
+        }
+    }
+    Crate::from_str(spec).map(|c| vec![c])
+}
+
+/// Parse a crate specification like `expand_spec` does,
+/// but on failure, locate the error within the specification.
+pub fn validate_spec(spec: &str) -> Result<Vec<Crate>, SpecError> {
+    expand_spec(spec).map_err(|error| {
+        let name_part = spec.split('=').next().unwrap();
+        let name_start = name_part.len() - name_part.trim_start().len();
+        let (position, expected) = match error {
+            CrateError::Name(ref name) => {
+                // Point at the first character that's not allowed in crate names.
+                let offset = name.char_indices()
+                    .find(|&(_, c)| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                    .map(|(i, _)| i).unwrap_or(0);
+                (name_start + offset, EXPECTED_NAME)
+            }
+            CrateError::Version(_) => {
+                let version_part = &spec[name_part.len() + 1..];
+                (name_part.len() + 1 + version_part.len() - version_part.trim_start().len(), EXPECTED_VERSION)
+            }
+        };
+        SpecError{position: spec[..position].chars().count(), expected, error}
+    })
+}
+
+/// What's expected in place of an invalid crate name.
+const EXPECTED_NAME: &str = "crate name made of letters, digits, `-` and `_`";
+/// What's expected in place of an invalid crate version.
+const EXPECTED_VERSION: &str = concat!(
+    "version requirement (like `1.2`, `~1.2.3` or `>=1.0, <2`), exact version (like `=1.2.3`), ",
+    "or a list of exact versions (like `1.0.0,1.2.0`)");
+
+
+/// Remove the repeated crate specifications from given list, keeping the first occurrence of each.
+///
+/// Returns the remaining crates, along with the ones that have been repeated
+/// and how many times each of those occurred.
+/// Specifications are the same if their normalized forms are, e.g. `foo=1.0` and `foo = ^1.0`.
+pub fn dedup_crates(crates: &[Crate]) -> (Vec<Crate>, Vec<(Crate, usize)>) {
+    let mut unique: Vec<Crate> = vec![];
+    let mut counts: Vec<usize> = vec![];
+    for crate_ in crates {
+        let spec = crate_.to_string();
+        match unique.iter().position(|c| c.to_string() == spec) {
+            Some(i) => counts[i] += 1,
+            None => {
+                unique.push(crate_.clone());
+                counts.push(1);
+            }
+        }
+    }
+    let repeated = unique.iter().zip(&counts)
+        .filter(|&(_, &count)| count > 1)
+        .map(|(c, &count)| (c.clone(), count))
+        .collect();
+    (unique, repeated)
+}
+
+
+/// Specification of a crate to download.
+///
+/// It is (de)serialized as its textual form, i.e. `CRATE=VERSION`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Crate {
+    name: String,
+    version: CrateVersion,
+}
+impl FromStr for Crate {
+    type Err = CrateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.splitn(2, '=').map(|p| p.trim()).collect();
+        let name = parse_name(parts[0])?;
+        if parts.len() < 2 {
+            Ok(Crate{
+                name,
+                version: CrateVersion::Other(VersionReq::any()),
+            })
+        } else {
+            let version = CrateVersion::from_str(parts[1])?;
+            Ok(Crate{name, version})
+        }
+    }
+}
+impl Crate {
+    /// Create a crate specification from its name and version.
+    pub fn new<N: Into<String>>(name: N, version: CrateVersion) -> Result<Crate, CrateError> {
+        let name = parse_name(&name.into())?;
+        Ok(Crate{name, version})
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn version(&self) -> &CrateVersion {
+        &self.version
+    }
+
+    pub fn exact_version(&self) -> Option<&Version> {
+        match self.version {
+            CrateVersion::Exact(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn version_requirement(&self) -> Cow<'_, VersionReq> {
+        match self.version {
+            CrateVersion::Exact(ref v) => Cow::Owned(VersionReq::exact(v)),
+            CrateVersion::Other(ref r) => Cow::Borrowed(r),
+        }
+    }
+}
+impl fmt::Display for Crate {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}={}", self.name, self.version)
+    }
+}
+impl Serialize for Crate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Crate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Crate::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// Validate a crate name.
+fn parse_name(name: &str) -> Result<String, CrateError> {
+    let valid_name = !name.is_empty() &&
+        name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if valid_name {
+        Ok(name.to_owned())
+    } else {
+        Err(CrateError::Name(name.to_owned()))
+    }
+}
+
+/// Crate version.
+///
+/// Build metadata (like the `+build.5` in `1.2.3+build.5`) is preserved
+/// in exact versions, since it's part of the version as published.
+/// In version requirements it is ignored, which is consistent with Cargo.
+///
+/// It is (de)serialized as its textual form, e.g. `=1.0.0` or `^1.2`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CrateVersion {
+    /// Exact version, like =1.0.0.
+    Exact(Version),
+    /// Non-exact version, like ^1.0.0.
+    Other(VersionReq)
+}
+impl FromStr for CrateVersion {
+    type Err = CrateVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(v) = s.strip_prefix('=') {
+            let version = Version::from_str(v)?;
+            Ok(CrateVersion::Exact(version))
+        } else if s.trim() == "*" {
+            // Same as giving no version at all.
+            Ok(CrateVersion::Other(VersionReq::any()))
+        } else {
+            let version_req = VersionReq::from_str(s)?;
+            Ok(CrateVersion::Other(version_req))
+        }
+    }
+}
+impl fmt::Display for CrateVersion {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CrateVersion::Exact(ref v) => write!(fmt, "={}", v),
+            CrateVersion::Other(ref r) => write!(fmt, "{}", r),
+        }
+    }
+}
+impl Serialize for CrateVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for CrateVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        CrateVersion::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+
+/// Error that can occur while parsing CRATE argument.
+#[derive(Debug)]
+pub enum CrateError {
+    /// General syntax error of the crate specification.
+    Name(String),
+    /// Error parsing the semver spec of the crate.
+    Version(CrateVersionError),
+}
+impl From<CrateVersionError> for CrateError {
+    fn from(input: CrateVersionError) -> Self {
+        CrateError::Version(input)
+    }
+}
+impl Error for CrateError {
+    fn description(&self) -> &str { "invalid crate specification" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            CrateError::Version(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for CrateError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CrateError::Name(ref n) => write!(fmt, "invalid crate name `{}`", n),
+            CrateError::Version(ref e) => write!(fmt, "invalid crate version: {}", e),
+        }
+    }
+}
+
+/// Error in a crate specification, along with where it is and what's expected there instead.
+#[derive(Debug)]
+pub struct SpecError {
+    /// Position of the invalid part in the specification, in characters from its start.
+    pub position: usize,
+    /// Description of the syntax expected at that position.
+    pub expected: &'static str,
+    pub error: CrateError,
+}
+impl Error for SpecError {
+    fn description(&self) -> &str { "invalid crate specification" }
+    fn cause(&self) -> Option<&dyn Error> {
+        Some(&self.error)
+    }
+}
+impl fmt::Display for SpecError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} at position {} (expected {})", self.error, self.position, self.expected)
+    }
+}
+
+/// Error that can occur while parsing crate version.
+#[derive(Debug, Error)]
+pub enum CrateVersionError {
+    Syntax(SemVerError),
+    Semantics(ReqParseError),
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use semver::Version;
+    use serde_json;
+    use super::{Crate, CrateVersion, EXPECTED_NAME, EXPECTED_VERSION,
+                dedup_crates, expand_spec, normalize_spec, validate_spec};
+
+    #[test]
+    fn crate_name_only() {
+        let crate_ = Crate::from_str("foo").unwrap();
+        assert_eq!(crate_.name(), "foo");
+        assert_eq!(crate_.exact_version(), None);
+        assert_eq!(crate_.to_string(), "foo=*");
+    }
+
+    #[test]
+    fn crate_exact_version() {
+        let crate_ = Crate::from_str("foo==1.2.3").unwrap();
+        assert_eq!(crate_.exact_version(), Some(&Version::parse("1.2.3").unwrap()));
+        assert_eq!(crate_.to_string(), "foo==1.2.3");
+    }
+
+    #[test]
+    fn crate_exact_version_with_build_metadata() {
+        let crate_ = Crate::from_str("foo==1.2.3+build.5").unwrap();
+        let version = crate_.exact_version().unwrap();
+        assert_eq!(version.to_string(), "1.2.3+build.5");
+        assert_eq!(crate_.to_string(), "foo==1.2.3+build.5");
+        assert!(crate_.version_requirement().matches(version));
+    }
+
+    #[test]
+    fn crate_requirement_ignores_build_metadata() {
+        let crate_ = Crate::from_str("foo=1.2.3+build.5").unwrap();
+        assert_eq!(crate_.exact_version(), None);
+        let req = crate_.version_requirement();
+        assert!(req.matches(&Version::parse("1.2.3").unwrap()));
+        assert!(req.matches(&Version::parse("1.4.0+other").unwrap()));
+    }
+
+    #[test]
+    fn crate_invalid_name() {
+        assert!(Crate::from_str("").is_err());
+        assert!(Crate::from_str("foo+bar").is_err());
+        assert!(Crate::from_str("foo+bar==1.0.0").is_err());
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize_spec(" foo ").unwrap(), "foo=*");
+        assert_eq!(normalize_spec("foo = 1.2").unwrap(), "foo=^1.2");
+        assert_eq!(normalize_spec("foo==1.2.3").unwrap(), "foo==1.2.3");
+        assert_eq!(normalize_spec("foo=>=1.0,<2").unwrap(), "foo=>= 1.0, < 2");
+        assert!(normalize_spec("foo=bar").is_err());
+    }
+
+    #[test]
+    fn normalization_roundtrip() {
+        for spec in &["foo", "foo=1", "foo=~1.2", "foo==0.1.0-alpha+b1", "foo=>=1.0,<2"] {
+            let normalized = normalize_spec(spec).unwrap();
+            assert_eq!(Crate::from_str(&normalized).unwrap(), Crate::from_str(spec).unwrap());
+            assert_eq!(normalize_spec(&normalized).unwrap(), normalized);
+        }
+    }
+
+    #[test]
+    fn version_list() {
+        let crates = expand_spec("foo=1.0.0, 1.2.0,2.0.1").unwrap();
+        let specs: Vec<_> = crates.iter().map(|c| c.to_string()).collect();
+        assert_eq!(specs, vec!["foo==1.0.0", "foo==1.2.0", "foo==2.0.1"]);
+    }
+
+    #[test]
+    fn version_requirement_with_commas() {
+        let crates = expand_spec("foo=>=1.0, <2.0").unwrap();
+        assert_eq!(crates.len(), 1);
+        assert_eq!(crates[0].exact_version(), None);
+
+        let crates = expand_spec("foo=1.0.0").unwrap();
+        assert_eq!(crates.len(), 1);
+        assert!(expand_spec("foo+=1.0.0,2.0.0").is_err());
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(validate_spec("foo=1.0.0,1.2.0").unwrap().len(), 2);
+
+        let error = validate_spec(" foo+bar=1").unwrap_err();
+        assert_eq!((error.position, error.expected), (4, EXPECTED_NAME));
+        let error = validate_spec("").unwrap_err();
+        assert_eq!((error.position, error.expected), (0, EXPECTED_NAME));
+        let error = validate_spec("foo= 1.x.y").unwrap_err();
+        assert_eq!((error.position, error.expected), (5, EXPECTED_VERSION));
+    }
+
+    #[test]
+    fn deduplication() {
+        let crates: Vec<_> = ["foo==1.0.0", "bar", "foo = 1.0", "foo==1.0.0", "bar=*", "foo=1.0"].iter()
+            .map(|s| Crate::from_str(s).unwrap()).collect();
+        let (unique, repeated) = dedup_crates(&crates);
+        let specs: Vec<_> = unique.iter().map(|c| c.to_string()).collect();
+        assert_eq!(specs, vec!["foo==1.0.0", "bar=*", "foo=^1.0"]);
+        let repeated: Vec<_> = repeated.iter().map(|(c, n)| (c.to_string(), *n)).collect();
+        assert_eq!(repeated, vec![("foo==1.0.0".to_owned(), 2), ("bar=*".to_owned(), 2),
+                                  ("foo=^1.0".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        let crate_ = Crate::from_str("foo=>=1.0,<2").unwrap();
+        let json = serde_json::to_string(&crate_).unwrap();
+        assert_eq!(json, r#""foo=>= 1.0, < 2""#);
+        assert_eq!(serde_json::from_str::<Crate>(&json).unwrap(), crate_);
+
+        let version: CrateVersion = serde_json::from_str(r#""=1.2.3+b1""#).unwrap();
+        assert_eq!(serde_json::to_string(&version).unwrap(), r#""=1.2.3+b1""#);
+        assert!(serde_json::from_str::<Crate>(r#""foo+bar""#).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Module for noticing when the selected version of a crate is stale,
+//! e.g. because a newer, semver-incompatible release line exists.
+
+use semver::Version;
+
+use resolve::ResolvedVersion;
+
+
+/// Find the newest release of a crate that's semver-incompatible with given version
+/// and newer than it, if there is any.
+///
+/// Yanked versions and pre-releases aren't taken into account.
+pub fn newer_major_version(version: &Version, versions: &[ResolvedVersion]) -> Option<Version> {
+    versions.iter()
+        .filter(|v| !v.yanked && !v.version.is_prerelease())
+        .map(|v| &v.version)
+        .filter(|v| *v > version && compatibility_line(v) != compatibility_line(version))
+        .max()
+        .cloned()
+}
+
+/// Determine the release line of given version, within which all versions are semver-compatible.
+///
+/// Like in Cargo, the leftmost non-zero component decides, so e.g. 0.3.x is a line of its own.
+fn compatibility_line(version: &Version) -> (u64, u64, u64) {
+    match (version.major, version.minor) {
+        (0, 0) => (0, 0, version.patch),
+        (0, minor) => (0, minor, 0),
+        (major, _) => (major, 0, 0),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use resolve::ResolvedVersion;
+    use super::newer_major_version;
+
+    #[test]
+    fn newer_majors() {
+        let versions: Vec<_> = ["0.1.0", "0.1.5", "0.2.0", "1.0.0", "1.2.0", "2.0.0-rc.1"].iter()
+            .map(|v| ResolvedVersion::new(Version::parse(v).unwrap()))
+            .collect();
+        let newer = |v: &str| newer_major_version(&Version::parse(v).unwrap(), &versions)
+            .map(|v| v.to_string());
+
+        assert_eq!(newer("0.1.0"), Some("1.2.0".into()));
+        assert_eq!(newer("1.0.0"), None);
+        assert_eq!(newer("1.2.0"), None);
+    }
+}
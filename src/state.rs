@@ -0,0 +1,117 @@
+//! Module for keeping track of crates that have been extracted.
+//!
+//! After a successful extraction, a small state file is written
+//! into the crate's directory, recording what exactly has been put there.
+//! This allows subsequent runs to detect that the work is already done.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+
+
+/// Name of the state file placed inside of an extracted crate's directory.
+pub const STATE_FILE: &str = ".cargo-download.json";
+
+
+/// Record of a crate that has been extracted to a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractState {
+    /// Name of the extracted crate.
+    pub name: String,
+    /// Exact version of the extracted crate.
+    pub version: Version,
+    /// SHA-256 checksum of the crate's archive, as a hex string.
+    pub checksum: String,
+    /// Patterns of the paths of the files that have been extracted (all of them if there are none).
+    pub filter: Vec<String>,
+}
+
+impl ExtractState {
+    /// Read the state file from given directory, if there is a valid one.
+    pub fn read<P: AsRef<Path>>(dir: P) -> Option<ExtractState> {
+        let path = dir.as_ref().join(STATE_FILE);
+        let file = fs::File::open(&path).ok()?;
+        let json: Json = serde_json::from_reader(io::BufReader::new(file)).ok()?;
+
+        let name = json.get("name").and_then(|n| n.as_str())?;
+        let version = json.get("version").and_then(|v| v.as_str())
+            .and_then(|v| Version::parse(v).ok())?;
+        let checksum = json.get("checksum").and_then(|c| c.as_str())?;
+        // State files written before filtering was possible don't have the patterns.
+        let filter = json.get("filter").and_then(|f| f.as_array()).map_or_else(Vec::new, |f| {
+            f.iter().filter_map(|p| p.as_str()).map(|p| p.to_owned()).collect()
+        });
+        Some(ExtractState{name: name.to_owned(), version, checksum: checksum.to_owned(), filter})
+    }
+
+    /// Write the state file into given directory.
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let path = dir.as_ref().join(STATE_FILE);
+        let json = json!({
+            "name": self.name,
+            "version": self.version.to_string(),
+            "checksum": self.checksum,
+            "filter": self.filter,
+        });
+        let file = fs::File::create(&path)?;
+        serde_json::to_writer_pretty(file, &json).map_err(io::Error::from)
+    }
+
+    /// Whether this state describes given version of given crate.
+    pub fn is_for(&self, name: &str, version: &Version) -> bool {
+        // Version equality disregards build metadata, but we care about it here.
+        self.name == name && &self.version == version && self.version.build == version.build
+    }
+}
+
+
+/// Move the directory at given path out of the way of a new extraction into it,
+/// returning where it's been moved to (next to it), or `None` if there's nothing to move.
+///
+/// Only earlier extractions (with a state file) are moved, unless forced to,
+/// so that unrelated directories aren't disturbed.
+pub fn move_aside(dir: &Path, force: bool) -> io::Result<Option<PathBuf>> {
+    if !dir.is_dir() || !(force || dir.join(STATE_FILE).is_file()) {
+        return Ok(None);
+    }
+    let file_name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let aside = dir.with_file_name(format!(".{}.old-{}", file_name, process::id()));
+    fs::rename(dir, &aside)?;
+    Ok(Some(aside))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use super::{STATE_FILE, move_aside};
+
+    #[test]
+    fn moving_aside() {
+        let dir = env::temp_dir().join(format!("cargo-download-test-state-{}", process::id()));
+        let extracted = dir.join("foo-1.0.0");
+        fs::create_dir_all(&extracted).unwrap();
+        fs::write(extracted.join("lib.rs"), "").unwrap();
+        // A directory that hasn't been extracted before is left alone, unless forced.
+        assert!(move_aside(&extracted, false).unwrap().is_none());
+        assert!(extracted.join("lib.rs").is_file());
+        assert!(move_aside(&dir.join("bar-1.0.0"), true).unwrap().is_none());
+
+        fs::write(extracted.join(STATE_FILE), "{}").unwrap();
+        let aside = move_aside(&extracted, false).unwrap().unwrap();
+        assert!(!extracted.exists());
+        assert!(aside.join("lib.rs").is_file());
+        assert_eq!(aside.parent(), Some(dir.as_path()));
+        fs::remove_dir_all(&aside).unwrap();
+
+        // Re-extracting over it works, since it's out of the way.
+        fs::create_dir_all(&extracted).unwrap();
+        let aside = move_aside(&extracted, true).unwrap().unwrap();
+        assert!(!extracted.exists() && aside.is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+//! Module for streaming crate archives straight from crates.io.
+//!
+//! The expected checksum of the archive comes from the registry index,
+//! and it's verified as the stream is consumed, so that the crate's bytes
+//! can be processed as they arrive, without going through temporary files.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+use reqwest;
+use serde_json::{self, Value as Json};
+use sha2::{Digest, Sha256};
+
+use index::{CRATES_INDEX_ROOT, download_url, index_path};
+use resolve::{ResolvedVersion, VersionFilter, resolve_versions};
+use spec::Crate;
+
+
+/// Open a stream of the archive of given crate, as a gzipped tarball.
+///
+/// The crate is resolved to its newest matching version, like the command line tool does.
+/// Reading the stream fails with an `InvalidData` error at its end
+/// if the archive's SHA-256 checksum doesn't match the registry index.
+pub fn open_crate_stream(crate_: &Crate) -> Result<impl Read, StreamError> {
+    let client = reqwest::Client::new();
+
+    let index_url = format!("{}/{}", CRATES_INDEX_ROOT, index_path(crate_.name()));
+    let index_file = get(&client, &index_url)?.text()?;
+    let versions = ResolvedVersion::list_from_index(&index_file)
+        .ok_or_else(|| StreamError::Malformed(index_url.clone()))?;
+    // Yanked versions can still be downloaded if they're asked for explicitly.
+    let filter = VersionFilter{yanked: crate_.exact_version().is_some(), prerelease: false};
+    let resolved = resolve_versions(crate_, versions, filter).next()
+        .ok_or_else(|| StreamError::NoMatchingVersion(crate_.to_string()))?;
+    let checksum = resolved.checksum.ok_or(StreamError::Malformed(index_url))?;
+
+    let config_url = format!("{}/config.json", CRATES_INDEX_ROOT);
+    let config: Json = serde_json::from_str(&get(&client, &config_url)?.text()?)
+        .map_err(|_| StreamError::Malformed(config_url.clone()))?;
+    let template = config.get("dl").and_then(|d| d.as_str())
+        .ok_or(StreamError::Malformed(config_url))?;
+    let download_url = download_url(template, crate_.name(), &resolved.version, &checksum);
+
+    let response = get(&client, &download_url)?;
+    Ok(VerifyingReader::new(response, checksum))
+}
+
+/// Make a GET request to given URL, failing unless it succeeds.
+fn get(client: &reqwest::Client, url: &str) -> Result<reqwest::Response, StreamError> {
+    let response = client.get(url).send()?;
+    if !response.status().is_success() {
+        return Err(StreamError::Status(url.to_owned(), response.status().as_u16()));
+    }
+    Ok(response)
+}
+
+
+/// Reader that computes the SHA-256 digest of everything read through it,
+/// failing at the end of the input if the digest doesn't match the expected one.
+pub struct VerifyingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    /// Expected digest, as a hex string.
+    expected: String,
+    verified: bool,
+}
+
+impl<R: Read> VerifyingReader<R> {
+    /// Wrap given reader, expecting given SHA-256 digest (as a hex string) of its content.
+    pub fn new<S: Into<String>>(inner: R, expected: S) -> VerifyingReader<R> {
+        VerifyingReader{inner, hasher: Sha256::new(), expected: expected.into(), verified: false}
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.hasher.update(&buf[..n]);
+        } else if !buf.is_empty() && !self.verified {
+            let actual: String = self.hasher.clone().finalize().iter()
+                .map(|b| format!("{:02x}", b)).collect();
+            if !actual.eq_ignore_ascii_case(&self.expected) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "checksum mismatch: expected {}, got {}", self.expected, actual)));
+            }
+            self.verified = true;
+        }
+        Ok(n)
+    }
+}
+
+
+/// Error that can occur while opening a crate stream.
+#[derive(Debug)]
+pub enum StreamError {
+    /// Error while performing a request.
+    Request(reqwest::Error),
+    /// Given URL responded with given (unsuccessful) HTTP status.
+    Status(String, u16),
+    /// Malformed response from given URL.
+    Malformed(String),
+    /// No version of given crate matches the requirement.
+    NoMatchingVersion(String),
+}
+impl From<reqwest::Error> for StreamError {
+    fn from(input: reqwest::Error) -> Self {
+        StreamError::Request(input)
+    }
+}
+impl Error for StreamError {
+    fn description(&self) -> &str { "failed to open crate stream" }
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            StreamError::Request(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for StreamError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamError::Request(ref e) => write!(fmt, "{}", e),
+            StreamError::Status(ref url, status) => write!(fmt, "{} responded with {}", url, status),
+            StreamError::Malformed(ref url) => write!(fmt, "malformed response from {}", url),
+            StreamError::NoMatchingVersion(ref crate_) =>
+                write!(fmt, "no version of crate {} found", crate_),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use super::VerifyingReader;
+
+    // SHA-256 of "hello\n".
+    const HELLO_SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    #[test]
+    fn verification() {
+        let mut content = vec![];
+        VerifyingReader::new(&b"hello\n"[..], HELLO_SHA256).read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"hello\n");
+
+        let err = VerifyingReader::new(&b"hell0\n"[..], HELLO_SHA256).read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,539 @@
+//! Module for the summary of a download run.
+//!
+//! With `--summary-file`, the summary is written at the end of the run,
+//! including when it's cut short by a failure, so that it can be uploaded
+//! as a CI artifact or parsed by schedulers.
+//!
+//! With `--metrics`, the same numbers are also written as metrics
+//! in the Prometheus text format, for the textfile collector of node_exporter
+//! to pick up from scheduled runs.
+//!
+//! When several crates are downloaded, the outcome for each of them
+//! is also reported in the log at the end of the run, followed by a table with the totals
+//! (unless the run is quiet).
+//!
+//! The crates that have been output are listed in the summary with their versions and checksums,
+//! so that a later run can be compared against it (`--compare-report`).
+//! With `--deps` and `--build-closure`, they're listed with their dependents too.
+//! Crates that would have been output to the same path are listed too, with how that's been resolved.
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+
+use checksum;
+use closure::Dependent;
+use units;
+
+
+lazy_static! {
+    /// Summary of the current run, along with the files to write it to.
+    ///
+    /// It's global, because failures terminate the program from deep within the call stack.
+    static ref SUMMARY: Mutex<Option<(Files, Summary)>> = Mutex::new(None);
+}
+
+/// Whether the table with the totals is printed when the outcomes are reported.
+static TABLE: AtomicBool = AtomicBool::new(false);
+
+/// Upper bounds (in seconds) of the buckets of the histogram of crate processing times.
+const DURATION_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+
+/// Files that the summary of the run is written to, if they've been requested.
+#[derive(Debug, Clone, Default)]
+pub struct Files {
+    /// File with the summary in JSON.
+    pub summary: Option<PathBuf>,
+    /// File with the metrics in the Prometheus text format.
+    pub metrics: Option<PathBuf>,
+}
+
+
+/// Outcome of processing a single crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The crate has been output, from an archive of given size in bytes.
+    Succeeded(u64),
+    /// The crate has been skipped because it was already present.
+    Skipped,
+    /// The crate hasn't been processed, because the deadline of the run has passed.
+    Unfinished,
+}
+
+/// Class of a failure, which the metrics are broken down by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// The registry couldn't be reached or didn't respond as expected.
+    Network,
+    /// A downloaded archive didn't match its checksum, or was malformed.
+    Integrity,
+    /// Reading or writing local files failed.
+    Io,
+    /// The configuration or the input was invalid.
+    Config,
+    /// Any other failure.
+    Other,
+}
+
+impl FailureClass {
+    const ALL: &'static [FailureClass] = &[FailureClass::Network, FailureClass::Integrity,
+                                           FailureClass::Io, FailureClass::Config, FailureClass::Other];
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            FailureClass::Network => "network",
+            FailureClass::Integrity => "integrity",
+            FailureClass::Io => "io",
+            FailureClass::Config => "config",
+            FailureClass::Other => "other",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+
+/// Start collecting the summary of the run, to be written to given files.
+///
+/// Unless `quiet`, the totals of the run are printed too when the outcomes are reported.
+pub fn start(files: Files, quiet: bool) {
+    *SUMMARY.lock().unwrap() = Some((files, Summary::new()));
+    TABLE.store(!quiet, Ordering::SeqCst);
+}
+
+/// Note that processing of given crate has started.
+pub fn begin(crate_: &str) {
+    with_summary(|s| {
+        s.current = Some(crate_.to_owned());
+        s.current_started = Some(Instant::now());
+        s.current_version = None;
+        s.current_checksum = None;
+        s.current_dependents.clear();
+    });
+}
+
+/// The crate that's currently being processed, if any.
+pub fn current() -> Option<String> {
+    SUMMARY.lock().unwrap().as_ref().and_then(|(_, s)| s.current.clone())
+}
+
+/// Note the version that the current crate has been resolved to.
+pub fn set_version(name: &str, version: &Version) {
+    with_summary(|s| s.current_version = Some((name.to_owned(), version.clone())));
+}
+
+/// Note the downloaded archive of the current crate.
+pub fn set_archive(crate_bytes: &[u8]) {
+    with_summary(|s| s.current_checksum = checksum::sha256_reader(crate_bytes).ok());
+}
+
+/// Note the SHA-256 checksum of the archive of the current crate, which has been extracted as it was received.
+pub fn set_archive_checksum(checksum: &str) {
+    with_summary(|s| s.current_checksum = Some(checksum.to_owned()));
+}
+
+/// Note the crates that depend on the current crate, which is part of the closure of another one.
+pub fn set_dependents(dependents: &[Dependent]) {
+    with_summary(|s| s.current_dependents = dependents.to_vec());
+}
+
+/// Record that the current crate would have been output to given path, like given crate before it,
+/// and that it's been output to the other path given instead (unless it's the same path, overwritten).
+pub fn record_collision(path: &Path, other: &str, resolution: &str, output: &Path) {
+    with_summary(|s| s.record_collision(path, other, resolution, output));
+}
+
+/// The summary of the run so far, in the same JSON as it's written to the file.
+pub fn to_json() -> Option<Json> {
+    SUMMARY.lock().unwrap().as_ref().map(|(_, s)| s.to_json())
+}
+
+/// Record the outcome of processing the current crate.
+pub fn record(outcome: Outcome) {
+    with_summary(|s| s.record(outcome));
+}
+
+/// Record the failure of processing the current crate (or of the run as a whole).
+pub fn record_failure(class: FailureClass, message: &str) {
+    with_summary(|s| s.record_failure(class, message));
+}
+
+/// Write the summary and the metrics to their files, if they have been requested.
+pub fn write() -> io::Result<()> {
+    let summary = SUMMARY.lock().unwrap();
+    let (files, summary) = match *summary {
+        Some((ref files, ref summary)) => (files, summary),
+        None => return Ok(()),
+    };
+    if let Some(ref path) = files.summary {
+        let json = serde_json::to_string_pretty(&summary.to_json())?;
+        fs::write(path, json + "\n")?;
+    }
+    if let Some(ref path) = files.metrics {
+        write_atomically(path, &summary.to_metrics())?;
+    }
+    Ok(())
+}
+
+/// Write given content to a file by renaming a temporary one over it,
+/// so that it's never read while only partially written.
+fn write_atomically(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Log the outcome for each crate processed so far, if there's been more than one,
+/// and print a table with their totals to stderr.
+pub fn report() {
+    if let Some((_, ref summary)) = *SUMMARY.lock().unwrap() {
+        if summary.crates.len() < 2 {
+            return;
+        }
+        info!("Outcome for {} crate(s):", summary.crates.len());
+        for (crate_, outcome) in &summary.crates {
+            match *outcome {
+                Some(Outcome::Succeeded(bytes)) =>
+                    info!("  {}: succeeded ({})", crate_, units::format_size(bytes)),
+                Some(Outcome::Skipped) => info!("  {}: skipped (already present)", crate_),
+                Some(Outcome::Unfinished) => warn!("  {}: left out (the deadline has passed)", crate_),
+                None => error!("  {}: failed", crate_),
+            }
+        }
+        if TABLE.load(Ordering::SeqCst) {
+            eprint!("{}", summary.to_table(summary.started.elapsed()));
+        }
+    }
+}
+
+fn with_summary<F: FnOnce(&mut Summary)>(f: F) {
+    if let Some((_, ref mut summary)) = *SUMMARY.lock().unwrap() {
+        f(summary);
+    }
+}
+
+
+/// Summary of a download run.
+#[derive(Debug)]
+struct Summary {
+    started: Instant,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    unfinished: usize,
+    bytes: u64,
+    /// Crate that's currently being processed.
+    current: Option<String>,
+    /// When processing of the current crate started.
+    current_started: Option<Instant>,
+    /// Name of the current crate and the version it's been resolved to, once that's known.
+    current_version: Option<(String, Version)>,
+    /// SHA-256 checksum of the archive of the current crate, if it's been downloaded.
+    current_checksum: Option<String>,
+    /// Crates that depend on the current crate, if it's part of a closure.
+    current_dependents: Vec<Dependent>,
+    /// Crates that failed (if known), along with the classes of the failures and the error messages.
+    failures: Vec<(Option<String>, FailureClass, String)>,
+    /// Crates processed so far, along with their outcomes (none if they failed).
+    crates: Vec<(String, Option<Outcome>)>,
+    /// Crate versions that have been output or found present.
+    versions: Vec<ListedVersion>,
+    /// Time it took to process each crate (apart from those left out).
+    durations: Vec<Duration>,
+    /// Crates that would have been output to the same path as others.
+    collisions: Vec<Collision>,
+}
+
+/// Crate that would have been output to the same path as another one.
+#[derive(Debug)]
+struct Collision {
+    crate_: Option<String>,
+    path: PathBuf,
+    /// The other crate, as `name==version`.
+    other: String,
+    /// How the collision has been resolved (see `--on-collision`).
+    resolution: String,
+    /// Where the crate has been output instead.
+    output: PathBuf,
+}
+
+/// Crate version that's been output or found present.
+#[derive(Debug)]
+struct ListedVersion {
+    name: String,
+    version: Version,
+    /// SHA-256 checksum of its archive, if it's been downloaded.
+    checksum: Option<String>,
+    outcome: Outcome,
+    /// Crates that depend on it, if it's part of a closure.
+    dependents: Vec<Dependent>,
+}
+
+impl Summary {
+    fn new() -> Summary {
+        Summary{
+            started: Instant::now(),
+            succeeded: 0, failed: 0, skipped: 0, unfinished: 0, bytes: 0,
+            current: None,
+            current_started: None,
+            current_version: None,
+            current_checksum: None,
+            current_dependents: vec![],
+            failures: vec![],
+            crates: vec![],
+            versions: vec![],
+            durations: vec![],
+            collisions: vec![],
+        }
+    }
+
+    fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Succeeded(bytes) => {
+                self.succeeded += 1;
+                self.bytes += bytes;
+            }
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Unfinished => self.unfinished += 1,
+        }
+        if let Some(crate_) = self.current.take() {
+            self.crates.push((crate_, Some(outcome)));
+        }
+        if let Some((name, version)) = self.current_version.take() {
+            if outcome != Outcome::Unfinished {
+                self.versions.push(ListedVersion{
+                    name, version, checksum: self.current_checksum.take(), outcome,
+                    dependents: mem::take(&mut self.current_dependents),
+                });
+            }
+        }
+        if let Some(started) = self.current_started.take() {
+            if outcome != Outcome::Unfinished {
+                self.durations.push(started.elapsed());
+            }
+        }
+    }
+
+    fn record_failure(&mut self, class: FailureClass, message: &str) {
+        self.failed += 1;
+        if let Some(ref crate_) = self.current {
+            self.crates.push((crate_.clone(), None));
+        }
+        if let Some(started) = self.current_started.take() {
+            self.durations.push(started.elapsed());
+        }
+        self.failures.push((self.current.take(), class, message.to_owned()));
+    }
+
+    fn record_collision(&mut self, path: &Path, other: &str, resolution: &str, output: &Path) {
+        self.collisions.push(Collision{
+            crate_: self.current.clone(),
+            path: path.to_owned(),
+            other: other.to_owned(),
+            resolution: resolution.to_owned(),
+            output: output.to_owned(),
+        });
+    }
+
+    fn to_json(&self) -> Json {
+        json!({
+            "succeeded": self.succeeded,
+            "failed": self.failed,
+            "skipped": self.skipped,
+            "unfinished": self.unfinished,
+            "bytes": self.bytes,
+            "duration_secs": self.started.elapsed().as_secs_f64(),
+            "failures": self.failures.iter().map(|(crate_, class, error)| json!({
+                "crate": crate_,
+                "class": class.to_string(),
+                "error": error,
+            })).collect::<Vec<_>>(),
+            "crates": self.versions.iter().map(|v| json!({
+                "name": v.name,
+                "version": v.version.to_string(),
+                "checksum": v.checksum,
+                "outcome": if let Outcome::Succeeded(_) = v.outcome { "downloaded" } else { "skipped" },
+                "dependents": v.dependents.iter().map(|d| d.to_json()).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+            "collisions": self.collisions.iter().map(|c| json!({
+                "crate": c.crate_,
+                "path": c.path.display().to_string(),
+                "collided_with": c.other,
+                "resolution": c.resolution,
+                "output": c.output.display().to_string(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Format the totals of the run as a table, with given time that the run has taken.
+    fn to_table(&self, elapsed: Duration) -> String {
+        let mut columns = vec![
+            ("Downloaded", self.succeeded.to_string()),
+            ("Skipped", self.skipped.to_string()),
+            ("Failed", self.failed.to_string()),
+        ];
+        if self.unfinished > 0 {
+            columns.push(("Left out", self.unfinished.to_string()));
+        }
+        columns.push(("Size", units::format_size(self.bytes)));
+        columns.push(("Elapsed", format!("{:.1}s", elapsed.as_secs_f64())));
+
+        let (mut header, mut values) = (String::new(), String::new());
+        for (name, value) in columns {
+            let width = name.len().max(value.len()) + 2;
+            header += &format!("{:>1$}", name, width);
+            values += &format!("{:>1$}", value, width);
+        }
+        format!("{}\n{}\n", header, values)
+    }
+
+    /// Format the metrics of the run in the Prometheus text format.
+    fn to_metrics(&self) -> String {
+        let mut out = String::new();
+        self.write_metrics(&mut out).unwrap();
+        out
+    }
+
+    fn write_metrics(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "# HELP cargo_download_downloads_total Crates processed by the run, by outcome.")?;
+        writeln!(out, "# TYPE cargo_download_downloads_total counter")?;
+        for &(outcome, count) in &[("succeeded", self.succeeded), ("failed", self.failed),
+                                   ("skipped", self.skipped), ("unfinished", self.unfinished)] {
+            writeln!(out, "cargo_download_downloads_total{{outcome=\"{}\"}} {}", outcome, count)?;
+        }
+
+        writeln!(out, "# HELP cargo_download_bytes_total Total size of the downloaded crate archives.")?;
+        writeln!(out, "# TYPE cargo_download_bytes_total counter")?;
+        writeln!(out, "cargo_download_bytes_total {}", self.bytes)?;
+
+        writeln!(out, "# HELP cargo_download_failures_total Failures of the run, by class.")?;
+        writeln!(out, "# TYPE cargo_download_failures_total counter")?;
+        for class in FailureClass::ALL {
+            let count = self.failures.iter().filter(|(_, c, _)| c == class).count();
+            writeln!(out, "cargo_download_failures_total{{class=\"{}\"}} {}", class, count)?;
+        }
+
+        writeln!(out, "# HELP cargo_download_crate_duration_seconds Time it took to process each crate.")?;
+        writeln!(out, "# TYPE cargo_download_crate_duration_seconds histogram")?;
+        let secs: Vec<_> = self.durations.iter().map(|d| d.as_secs_f64()).collect();
+        for bound in DURATION_BUCKETS {
+            let count = secs.iter().filter(|&&s| s <= *bound).count();
+            writeln!(out, "cargo_download_crate_duration_seconds_bucket{{le=\"{}\"}} {}", bound, count)?;
+        }
+        writeln!(out, "cargo_download_crate_duration_seconds_bucket{{le=\"+Inf\"}} {}", secs.len())?;
+        writeln!(out, "cargo_download_crate_duration_seconds_sum {}", secs.iter().sum::<f64>())?;
+        writeln!(out, "cargo_download_crate_duration_seconds_count {}", secs.len())?;
+
+        writeln!(out, "# HELP cargo_download_run_duration_seconds Time the whole run took.")?;
+        writeln!(out, "# TYPE cargo_download_run_duration_seconds gauge")?;
+        writeln!(out, "cargo_download_run_duration_seconds {}", self.started.elapsed().as_secs_f64())?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        writeln!(out, "# HELP cargo_download_last_run_timestamp_seconds When the run finished, as a Unix time.")?;
+        writeln!(out, "# TYPE cargo_download_last_run_timestamp_seconds gauge")?;
+        writeln!(out, "cargo_download_last_run_timestamp_seconds {}", now.as_secs())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+    use semver::Version;
+    use cargo_download::DependencyKind;
+    use closure::Dependent;
+    use super::{FailureClass, Outcome, Summary};
+
+    #[test]
+    fn counts() {
+        let mut summary = Summary::new();
+        summary.current = Some("foo==1.0.0".into());
+        summary.current_version = Some(("foo".into(), Version::parse("1.0.0").unwrap()));
+        summary.current_checksum = Some("abc".into());
+        summary.record(Outcome::Succeeded(100));
+        summary.current = Some("cc==1.0.1".into());
+        summary.current_version = Some(("cc".into(), Version::parse("1.0.1").unwrap()));
+        summary.current_dependents = vec![Dependent{
+            crate_: "foo==1.0.0".into(), kind: DependencyKind::Build, optional: false,
+        }];
+        summary.record(Outcome::Skipped);
+        summary.record(Outcome::Unfinished);
+        summary.current = Some("bar==0.1.0".into());
+        summary.record_collision(Path::new("out/bar.crate"), "bar==0.0.1", "suffix-registry",
+                                 Path::new("out/bar-crates-io.crate"));
+        summary.record_failure(FailureClass::Network, "not found");
+
+        let json = summary.to_json();
+        assert_eq!(json["succeeded"], 1);
+        assert_eq!(json["skipped"], 1);
+        assert_eq!(json["unfinished"], 1);
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["bytes"], 100);
+        assert_eq!(json["failures"][0]["crate"], "bar==0.1.0");
+        assert_eq!(json["failures"][0]["class"], "network");
+        assert_eq!(json["failures"][0]["error"], "not found");
+        assert_eq!(json["crates"], json!([
+            {"name": "foo", "version": "1.0.0", "checksum": "abc", "outcome": "downloaded", "dependents": []},
+            {"name": "cc", "version": "1.0.1", "checksum": null, "outcome": "skipped", "dependents": [
+                {"crate": "foo==1.0.0", "kind": "build", "optional": false},
+            ]},
+        ]));
+        assert_eq!(json["collisions"], json!([{
+            "crate": "bar==0.1.0", "path": "out/bar.crate", "collided_with": "bar==0.0.1",
+            "resolution": "suffix-registry", "output": "out/bar-crates-io.crate",
+        }]));
+        assert_eq!(summary.crates, vec![("foo==1.0.0".to_owned(), Some(Outcome::Succeeded(100))),
+                                        ("cc==1.0.1".to_owned(), Some(Outcome::Skipped)),
+                                        ("bar==0.1.0".to_owned(), None)]);
+    }
+
+    #[test]
+    fn table() {
+        let mut summary = Summary::new();
+        summary.record(Outcome::Succeeded(1536));
+        summary.record(Outcome::Succeeded(512));
+        summary.record(Outcome::Skipped);
+        assert_eq!(summary.to_table(Duration::from_millis(12345)), concat!(
+            "  Downloaded  Skipped  Failed     Size  Elapsed\n",
+            "           2        1       0  2.0 KiB    12.3s\n"));
+
+        summary.record(Outcome::Unfinished);
+        summary.record_failure(FailureClass::Network, "timed out");
+        assert_eq!(summary.to_table(Duration::from_secs(60)), concat!(
+            "  Downloaded  Skipped  Failed  Left out     Size  Elapsed\n",
+            "           2        1       1         1  2.0 KiB    60.0s\n"));
+    }
+
+    #[test]
+    fn metrics() {
+        let mut summary = Summary::new();
+        summary.record(Outcome::Succeeded(2048));
+        summary.record_failure(FailureClass::Integrity, "checksum mismatch");
+        summary.durations = vec![Duration::from_millis(50), Duration::from_secs(3)];
+
+        let metrics = summary.to_metrics();
+        let lines: Vec<_> = metrics.lines().collect();
+        assert!(lines.contains(&"cargo_download_downloads_total{outcome=\"succeeded\"} 1"));
+        assert!(lines.contains(&"cargo_download_downloads_total{outcome=\"failed\"} 1"));
+        assert!(lines.contains(&"cargo_download_bytes_total 2048"));
+        assert!(lines.contains(&"cargo_download_failures_total{class=\"integrity\"} 1"));
+        assert!(lines.contains(&"cargo_download_failures_total{class=\"network\"} 0"));
+        assert!(lines.contains(&"cargo_download_crate_duration_seconds_bucket{le=\"0.1\"} 1"));
+        assert!(lines.contains(&"cargo_download_crate_duration_seconds_bucket{le=\"2.5\"} 1"));
+        assert!(lines.contains(&"cargo_download_crate_duration_seconds_bucket{le=\"5\"} 2"));
+        assert!(lines.contains(&"cargo_download_crate_duration_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(lines.contains(&"cargo_download_crate_duration_seconds_count 2"));
+    }
+}
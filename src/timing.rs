@@ -0,0 +1,103 @@
+//! Module for measuring how much time is spent in the phases of a download.
+
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+
+/// Phase of downloading a crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Resolving the version requirement to an exact version.
+    Resolve,
+    /// Downloading the crate's archive (or retrieving it from the cache).
+    Download,
+    /// Verifying checksums of existing outputs.
+    Verify,
+    /// Extracting the archive, or writing it out if it's not being extracted.
+    Extract,
+}
+
+impl Phase {
+    const ALL: &'static [Phase] = &[Phase::Resolve, Phase::Download, Phase::Verify, Phase::Extract];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Phase::Resolve => "resolve",
+            Phase::Download => "download",
+            Phase::Verify => "verify",
+            Phase::Extract => "extract",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+
+/// Wall-clock time spent in each phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    phases: [Duration; 4],
+}
+
+impl Timings {
+    /// Run given function, adding the time it took to given phase.
+    pub fn measure<T, F: FnOnce() -> T>(&mut self, phase: Phase, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.phases[phase.index()] += start.elapsed();
+        result
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.phases[phase.index()]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().sum()
+    }
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Timings) {
+        for (this, other) in self.phases.iter_mut().zip(other.phases.iter()) {
+            *this += *other;
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for phase in Phase::ALL {
+            write!(fmt, "{} {:.2}s, ", phase, self.get(*phase).as_secs_f64())?;
+        }
+        write!(fmt, "total {:.2}s", self.total().as_secs_f64())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Phase, Timings};
+
+    #[test]
+    fn accumulation() {
+        let mut first = Timings::default();
+        first.phases[Phase::Download as usize] = Duration::from_millis(1500);
+        let mut second = Timings::default();
+        second.phases[Phase::Resolve as usize] = Duration::from_millis(250);
+        second.phases[Phase::Download as usize] = Duration::from_millis(500);
+
+        first += second;
+        assert_eq!(first.get(Phase::Download), Duration::from_secs(2));
+        assert_eq!(first.total(), Duration::from_millis(2250));
+        assert_eq!(first.to_string(),
+            "resolve 0.25s, download 2.00s, verify 0.00s, extract 0.00s, total 2.25s");
+    }
+}
@@ -0,0 +1,114 @@
+//! Module for parsing human-friendly quantities, like durations and sizes.
+
+use std::time::Duration;
+
+
+/// Parse a duration like "90s", "15m", "12h", "30d" or "2w".
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = split_unit(s);
+    let value: u64 = number.parse()
+        .map_err(|_| format!("invalid duration `{}`", s))?;
+    let multiplier = match unit.to_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit `{}` (expected s, m, h, d or w)", unit)),
+    };
+    value.checked_mul(multiplier).map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration `{}`", s))
+}
+
+/// Parse a size in bytes like "512", "100K", "20M" or "5G".
+/// Suffixes are binary, i.e. 1K is 1024 bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = split_unit(s);
+    let value: u64 = number.parse()
+        .map_err(|_| format!("invalid size `{}`", s))?;
+    let unit = unit.to_uppercase();
+    let unit = unit.trim_end_matches("IB").trim_end_matches('B');
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit `{}` (expected K, M, G or T)", unit)),
+    };
+    value.checked_mul(multiplier).ok_or_else(|| format!("invalid size `{}`", s))
+}
+
+/// Format a size in bytes in a human-friendly way, e.g. "1.5 MiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for u in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = u;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+
+/// Split a string like "30d" into its numeric part and the unit suffix.
+fn split_unit(s: &str) -> (&str, &str) {
+    let idx = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (&s[..idx], s[idx..].trim())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{format_size, parse_duration, parse_size};
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(15 * 60));
+        assert_eq!(parse_duration("30d").unwrap(), Duration::from_secs(30 * 24 * 3600));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+
+    #[test]
+    fn overflowing_duration() {
+        assert_eq!(parse_duration("9999999999999999w").unwrap_err(), "invalid duration `9999999999999999w`");
+        assert!(parse_duration("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("100K").unwrap(), 100 * 1024);
+        assert_eq!(parse_size("5G").unwrap(), 5 << 30);
+        assert_eq!(parse_size("20MiB").unwrap(), 20 << 20);
+        assert!(parse_size("5X").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn overflowing_size() {
+        assert_eq!(parse_size("99999999999T").unwrap_err(), "invalid size `99999999999T`");
+        assert_eq!(parse_size("16777215T").unwrap(), 16777215 << 40);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_size(10), "10 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 << 30), "5.0 GiB");
+    }
+}
@@ -0,0 +1,209 @@
+//! Module for making HTTP requests to servers listening on Unix domain sockets.
+//!
+//! Such servers are addressed with `http+unix://` URLs, where the percent-encoded
+//! path of the socket is used in place of the host, e.g.
+//! `http+unix://%2Frun%2Fregistry.sock/api/v1/crates`.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use http_crate as http;
+use httparse;
+use percent_encoding::percent_decode;
+use reqwest;
+
+use http::HttpError;
+
+
+/// Prefix of the URLs that address Unix domain sockets.
+const URL_PREFIX: &str = "http+unix://";
+
+/// Maximum number of headers we expect in a response.
+const MAX_HEADERS: usize = 64;
+
+
+/// Whether given URL addresses a Unix domain socket.
+pub fn is_unix_url(url: &str) -> bool {
+    url.starts_with(URL_PREFIX)
+}
+
+/// Make a GET request to given `http+unix://` URL.
+#[cfg(unix)]
+pub fn get(url: &str, timeout: Option<Duration>) -> Result<reqwest::Response, HttpError> {
+    use std::os::unix::net::UnixStream;
+
+    let (socket, path) = split_url(url).map_err(HttpError::Config)?;
+    let socket_err = |e| HttpError::Socket(socket.clone(), e);
+    let mut stream = UnixStream::connect(&socket).map_err(socket_err)?;
+    stream.set_read_timeout(timeout).map_err(socket_err)?;
+    stream.set_write_timeout(timeout).map_err(socket_err)?;
+
+    let request = format!(concat!(
+        "GET {} HTTP/1.1\r\n",
+        "Host: localhost\r\n",
+        "User-Agent: cargo-download\r\n",
+        "Accept: */*\r\n",
+        "Connection: close\r\n\r\n"), path);
+    stream.write_all(request.as_bytes()).map_err(socket_err)?;
+
+    // Since we've asked the server to close the connection,
+    // the response ends where the stream does.
+    let mut bytes = vec![];
+    stream.read_to_end(&mut bytes).map_err(socket_err)?;
+    let response = parse_response(&bytes).map_err(|e| HttpError::Response(url.to_owned(), e))?;
+    Ok(reqwest::Response::from(response))
+}
+
+/// Make a GET request to given `http+unix://` URL.
+#[cfg(not(unix))]
+pub fn get(_url: &str, _timeout: Option<Duration>) -> Result<reqwest::Response, HttpError> {
+    Err(HttpError::Config("Unix domain sockets are not supported on this platform".into()))
+}
+
+
+/// Split an `http+unix://` URL into the path of the socket and the path of the request.
+fn split_url(url: &str) -> Result<(PathBuf, String), String> {
+    let rest = url.strip_prefix(URL_PREFIX)
+        .ok_or_else(|| format!("`{}` is not an {} URL", url, URL_PREFIX))?;
+    let (socket, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    if socket.is_empty() {
+        return Err(format!("missing socket path in `{}`", url));
+    }
+    let socket = percent_decode(socket.as_bytes()).decode_utf8_lossy().into_owned();
+    Ok((PathBuf::from(socket), path.to_owned()))
+}
+
+/// Parse a complete HTTP/1.x response.
+fn parse_response(bytes: &[u8]) -> Result<http::Response<Vec<u8>>, String> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let body_start = match parsed.parse(bytes) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Err("truncated response headers".into()),
+        Err(e) => return Err(format!("invalid response: {}", e)),
+    };
+
+    let mut builder = http::Response::builder();
+    builder.status(parsed.code.unwrap_or(0));
+    let mut chunked = false;
+    let mut content_length = None;
+    for header in parsed.headers.iter() {
+        let value = String::from_utf8_lossy(header.value);
+        if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.to_lowercase().contains("chunked");
+            // The body we pass on is already decoded.
+            continue;
+        }
+        if header.name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+        builder.header(header.name, header.value);
+    }
+
+    let body = &bytes[body_start..];
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        match content_length {
+            Some(len) if len > body.len() => return Err("truncated response body".into()),
+            Some(len) => body[..len].to_vec(),
+            None => body.to_vec(),
+        }
+    };
+    builder.body(body).map_err(|e| format!("invalid response: {}", e))
+}
+
+/// Decode a response body sent with the chunked transfer encoding.
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = find_crlf(body).ok_or("truncated chunk size")?;
+        let size_line = String::from_utf8_lossy(&body[..line_end]);
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16)
+            .map_err(|_| format!("invalid chunk size `{}`", size_hex))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        if body.len() < size + 2 {
+            return Err("truncated chunk".into());
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+fn find_crlf(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(2).position(|w| w == b"\r\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{decode_chunked, parse_response, split_url};
+
+    #[test]
+    fn urls() {
+        let (socket, path) = split_url("http+unix://%2Frun%2Freg.sock/api/v1/crates").unwrap();
+        assert_eq!(socket, PathBuf::from("/run/reg.sock"));
+        assert_eq!(path, "/api/v1/crates");
+        assert_eq!(split_url("http+unix://%2Frun%2Freg.sock").unwrap().1, "/");
+        assert!(split_url("http+unix:///api").is_err());
+    }
+
+    #[test]
+    fn responses() {
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\nhello").unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), b"hello");
+
+        let response = parse_response(concat!(
+            "HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\n\r\n").as_bytes()).unwrap();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.body(), b"abcde");
+        assert!(response.headers().get("transfer-encoding").is_none());
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort").is_err());
+        assert!(decode_chunked(b"zz\r\n").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn request_over_socket() {
+        use std::env;
+        use std::fs;
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+        use super::get;
+
+        let socket = env::temp_dir().join(format!("cargo-download-test-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let len = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+
+        let url = format!("http+unix://{}/api/v1/crates/foo",
+            socket.to_str().unwrap().replace('/', "%2F"));
+        let mut response = get(&url, None).unwrap();
+        let mut body = String::new();
+        response.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "{}");
+        assert!(server.join().unwrap().starts_with("GET /api/v1/crates/foo HTTP/1.1\r\n"));
+
+        fs::remove_file(&socket).unwrap();
+    }
+}
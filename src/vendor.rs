@@ -0,0 +1,144 @@
+//! Module for extracting crates in the layout of `cargo vendor`,
+//! which a `[source.*]` entry with `directory = ...` in Cargo's config can point to.
+//!
+//! Every vendored crate has a `.cargo-checksum.json` file with the SHA-256 digests
+//! of its files and of its archive, which Cargo verifies before building it.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use semver::Version;
+use serde_json::{self, Value as Json};
+use tar;
+
+use cargo_download::decompress;
+use checksum;
+
+
+/// Directory that crates are vendored into by default, like with `cargo vendor`.
+pub const DEFAULT_DIR: &str = "vendor";
+
+/// Name of the file with the checksums of a vendored crate.
+pub const CHECKSUM_FILE: &str = ".cargo-checksum.json";
+
+/// Top-level files of the crate that `cargo vendor` leaves out.
+const SKIPPED_FILES: &[&str] = &[".gitattributes", ".gitignore", ".git", ".cargo-ok"];
+
+
+/// Name of the directory that given crate version is vendored into.
+///
+/// Like with `cargo vendor`, the directory is named after the crate only,
+/// unless a newer version of the crate is vendored too (whose directory that is).
+pub fn dir_name(name: &str, version: &Version, newest: Option<&Version>) -> String {
+    match newest {
+        // Version equality disregards build metadata, so compare the full strings.
+        Some(newest) if newest.to_string() != version.to_string() => format!("{}-{}", name, version),
+        _ => name.to_owned(),
+    }
+}
+
+/// Extract the archive of given crate version into given directory (replacing it if it exists),
+/// computing the digests of its files along the way and writing them to the checksum file.
+pub fn unpack(name: &str, version: &Version, crate_bytes: &[u8], dir: &Path) -> io::Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+
+    // Archives have a single top-level directory named $CRATE-$VERSION.
+    let prefix = format!("{}-{}/", name, version);
+    let mut files = serde_json::Map::new();
+    let mut archive = tar::Archive::new(decompress(crate_bytes)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let relative = match path.strip_prefix(&prefix) {
+            Some(p) if is_safe_path(p) => p.to_owned(),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("archive entry {} is outside of the crate's directory", path))),
+        };
+        if is_skipped(&relative) {
+            trace!("Skipping {} of crate `{}=={}`", relative, name, version);
+            continue;
+        }
+
+        let target = dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::File::create(&target)?;
+        let sha256 = checksum::copy_with_sha256(&mut entry, &mut file)?;
+        set_mode(&target, entry.header().mode()?)?;
+        files.insert(relative, sha256.into());
+    }
+
+    let checksums = json!({
+        "files": files,
+        "package": checksum::sha256_reader(crate_bytes)?,
+    });
+    fs::write(dir.join(CHECKSUM_FILE), checksums.to_string())
+}
+
+/// Read the SHA-256 checksum of the archive that the crate in given directory was vendored from,
+/// if it has a valid checksum file.
+pub fn package_checksum(dir: &Path) -> Option<String> {
+    let content = fs::read(dir.join(CHECKSUM_FILE)).ok()?;
+    let json: Json = serde_json::from_slice(&content).ok()?;
+    json.get("package").and_then(|p| p.as_str()).map(|p| p.to_owned())
+}
+
+/// Check whether given relative path (from an archive) stays inside of the directory.
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty() && !path.starts_with('/') && path.split('/').all(|c| c != ".." && !c.is_empty())
+}
+
+/// Check whether `cargo vendor` would leave out the file at given relative path.
+fn is_skipped(path: &str) -> bool {
+    if SKIPPED_FILES.contains(&path) {
+        return true;
+    }
+    // Leftovers of applying patches, but crates.io archives have their original manifest
+    // as Cargo.toml.orig, which is kept.
+    (path.ends_with(".orig") || path.ends_with(".rej")) && path != "Cargo.toml.orig"
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: u32) -> io::Result<()> {
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use super::{dir_name, is_safe_path, is_skipped};
+
+    #[test]
+    fn vendored_paths() {
+        let v1 = Version::parse("1.0.0").unwrap();
+        let v2 = Version::parse("2.0.0").unwrap();
+        assert_eq!(dir_name("foo", &v1, None), "foo");
+        assert_eq!(dir_name("foo", &v2, Some(&v2)), "foo");
+        assert_eq!(dir_name("foo", &v1, Some(&v2)), "foo-1.0.0");
+
+        assert!(is_safe_path("src/lib.rs"));
+        assert!(!is_safe_path("../evil.rs"));
+        assert!(!is_safe_path("src//lib.rs"));
+        assert!(!is_safe_path(""));
+
+        assert!(is_skipped(".gitignore"));
+        assert!(is_skipped("src/lib.rs.orig"));
+        assert!(!is_skipped("Cargo.toml.orig"));
+        assert!(!is_skipped("src/.gitignore"));
+    }
+}
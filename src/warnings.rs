@@ -0,0 +1,172 @@
+//! Module for the named warnings that can be suppressed or escalated,
+//! like rustc lints, with `--allow` and `--deny`.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+
+/// Warning about a potential problem with a crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Warning {
+    /// The selected version has been yanked from the registry.
+    YankedVersion,
+    /// The crate's manifest has no license or license-file.
+    NoLicense,
+    /// The crate's manifest has a wildcard ("*") version requirement.
+    WildcardRequirement,
+    /// The crate's manifest has a git dependency.
+    GitDependency,
+    /// The crate's manifest has a path dependency.
+    PathDependency,
+    /// The crate's archive is unusually large.
+    LargeArchive,
+    /// The selected version has been published longer ago than the `--warn-older-than` threshold.
+    OldVersion,
+    /// A newer, semver-incompatible version of the crate exists.
+    NewerMajorVersion,
+    /// The crate's license isn't in the `--allowed-licenses` list.
+    DisallowedLicense,
+    /// A file in the crate's archive differs from its repository, or isn't there (`--verify-source`).
+    SourceMismatch,
+}
+
+impl Warning {
+    /// All the warnings.
+    pub const ALL: &'static [Warning] = &[
+        Warning::YankedVersion,
+        Warning::NoLicense,
+        Warning::WildcardRequirement,
+        Warning::GitDependency,
+        Warning::PathDependency,
+        Warning::LargeArchive,
+        Warning::OldVersion,
+        Warning::NewerMajorVersion,
+        Warning::DisallowedLicense,
+        Warning::SourceMismatch,
+    ];
+
+    /// Names of all the warnings, as given on the command line.
+    pub const NAMES: &'static [&'static str] = &[
+        "yanked-version",
+        "no-license",
+        "wildcard-requirement",
+        "git-dependency",
+        "path-dependency",
+        "large-archive",
+        "old-version",
+        "newer-major-version",
+        "disallowed-license",
+        "source-mismatch",
+    ];
+
+    /// Name of the warning, as given on the command line.
+    pub fn name(self) -> &'static str {
+        let index = Warning::ALL.iter().position(|&w| w == self).unwrap();
+        Warning::NAMES[index]
+    }
+
+    /// Command line flag that enables the check reporting the warning, unless it's always checked.
+    pub fn enabled_by(self) -> Option<&'static str> {
+        match self {
+            Warning::YankedVersion | Warning::LargeArchive => None,
+            Warning::NoLicense | Warning::WildcardRequirement
+                | Warning::GitDependency | Warning::PathDependency => Some("--lint-manifest"),
+            Warning::OldVersion => Some("--warn-older-than"),
+            Warning::NewerMajorVersion => Some("--warn-newer-major"),
+            Warning::DisallowedLicense => Some("--allowed-licenses"),
+            Warning::SourceMismatch => Some("--verify-source"),
+        }
+    }
+}
+
+impl FromStr for Warning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Warning::NAMES.iter().position(|&n| n == s)
+            .map(|i| Warning::ALL[i])
+            .ok_or_else(|| format!("unknown warning `{}`", s))
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+
+/// Which warnings have been suppressed or escalated to errors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Warnings {
+    allowed: Vec<Warning>,
+    denied: Vec<Warning>,
+}
+
+impl Warnings {
+    /// Create the configuration, with denying a warning taking precedence over allowing it.
+    pub fn new(allowed: Vec<Warning>, denied: Vec<Warning>) -> Warnings {
+        Warnings{allowed, denied}
+    }
+
+    /// The warnings that have been suppressed.
+    pub fn allowed(&self) -> &[Warning] {
+        &self.allowed
+    }
+
+    /// The warnings that have been escalated to errors.
+    pub fn denied(&self) -> &[Warning] {
+        &self.denied
+    }
+
+    /// Report given warning with given message, according to the configuration:
+    /// only log it in verbose mode if it's allowed, or fail with `DeniedWarning` if it's denied.
+    pub fn emit(&self, warning: Warning, message: String) -> Result<(), DeniedWarning> {
+        if self.denied.contains(&warning) {
+            return Err(DeniedWarning(format!("{} (denied by --deny {})", message, warning)));
+        } else if self.allowed.contains(&warning) {
+            debug!("{} (allowed by --allow {})", message, warning);
+        } else {
+            warn!("{} [{}]", message, warning);
+        }
+        Ok(())
+    }
+}
+
+
+/// Error of a warning that's been escalated with `--deny`, with the warning's message.
+#[derive(Debug)]
+pub struct DeniedWarning(pub String);
+impl Error for DeniedWarning {
+    fn description(&self) -> &str { "denied warning" }
+}
+impl fmt::Display for DeniedWarning {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Warning, Warnings};
+
+    #[test]
+    fn names() {
+        assert_eq!(Warning::ALL.len(), Warning::NAMES.len());
+        for &warning in Warning::ALL {
+            assert_eq!(warning.name().parse::<Warning>(), Ok(warning));
+        }
+        assert!("unused-variables".parse::<Warning>().is_err());
+    }
+
+    #[test]
+    fn denied() {
+        let warnings = Warnings::new(vec![Warning::LargeArchive, Warning::NoLicense], vec![Warning::NoLicense]);
+        assert!(warnings.emit(Warning::LargeArchive, "large".into()).is_ok());
+        assert!(warnings.emit(Warning::YankedVersion, "yanked".into()).is_ok());
+        let denied = warnings.emit(Warning::NoLicense, "no license".into()).unwrap_err();
+        assert_eq!(denied.to_string(), "no license (denied by --deny no-license)");
+    }
+}